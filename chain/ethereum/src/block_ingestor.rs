@@ -37,6 +37,33 @@ impl BlockIngestorMetrics {
     }
 }
 
+/// Metrics for the reorgs that block ingestors observe. They are shared
+/// by the block ingestors of all networks.
+pub struct ReorgMetrics {
+    reorg_depth: Box<HistogramVec>,
+}
+
+impl ReorgMetrics {
+    pub fn new(registry: Arc<dyn MetricsRegistry>) -> Self {
+        Self {
+            reorg_depth: registry
+                .new_histogram_vec(
+                    "ethereum_chain_reorg_depth",
+                    "Number of blocks of the old chain that a chain reorg replaced",
+                    vec![String::from("network")],
+                    vec![1.0, 2.0, 3.0, 5.0, 10.0, 20.0, 50.0],
+                )
+                .unwrap(),
+        }
+    }
+
+    pub fn observe_reorg_depth(&self, network_name: &str, depth: u64) {
+        self.reorg_depth
+            .with_label_values(vec![network_name].as_slice())
+            .observe(depth as f64);
+    }
+}
+
 pub struct BlockIngestor<S>
 where
     S: ChainStore,
@@ -44,9 +71,10 @@ where
     chain_store: Arc<S>,
     eth_adapter: Arc<dyn EthereumAdapter>,
     ancestor_count: u64,
-    _network_name: String,
+    network_name: String,
     logger: Logger,
     polling_interval: Duration,
+    reorg_metrics: Arc<ReorgMetrics>,
 }
 
 impl<S> BlockIngestor<S>
@@ -60,6 +88,7 @@ where
         network_name: String,
         logger_factory: &LoggerFactory,
         polling_interval: Duration,
+        reorg_metrics: Arc<ReorgMetrics>,
     ) -> Result<BlockIngestor<S>, Error> {
         let logger = logger_factory.component_logger(
             "BlockIngestor",
@@ -76,9 +105,10 @@ where
             chain_store,
             eth_adapter,
             ancestor_count,
-            _network_name: network_name,
+            network_name,
             logger,
             polling_interval,
+            reorg_metrics,
         })
     }
 
@@ -189,7 +219,7 @@ where
 
         let latest_block = self
             .eth_adapter
-            .load_full_block(&self.logger, latest_block)
+            .load_full_block(&self.logger, self.chain_store.clone(), latest_block)
             .compat()
             .await?;
 
@@ -222,6 +252,37 @@ where
             let missing_blocks = self.get_blocks(&missing_block_hashes);
             missing_block_hashes = self.ingest_blocks(missing_blocks).await?;
         }
+
+        if let Some(head_block_ptr) = head_block_ptr_opt {
+            self.check_for_reorg(head_block_ptr)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether moving the chain head away from `old_head` was a
+    /// reorg, and if it was, record it in the reorg log and alert about it
+    fn check_for_reorg(&self, old_head: EthereumBlockPointer) -> Result<(), Error> {
+        let new_head = match self.chain_store.chain_head_ptr()? {
+            Some(new_head) if new_head != old_head => new_head,
+            _ => return Ok(()),
+        };
+
+        if let Some(reorg) =
+            self.chain_store
+                .record_reorg(old_head, new_head, self.ancestor_count)?
+        {
+            warn!(
+                self.logger,
+                "Chain reorg of {} blocks detected",
+                reorg.depth;
+                "old_head" => format!("{}", reorg.old_head),
+                "new_head" => format!("{}", reorg.new_head),
+                "depth" => reorg.depth,
+                "code" => LogCode::ChainReorg,
+            );
+            self.reorg_metrics
+                .observe_reorg_depth(&self.network_name, reorg.depth);
+        }
         Ok(())
     }
 
@@ -252,10 +313,12 @@ where
     ) -> Box<dyn Stream<Item = EthereumBlock, Error = EthereumAdapterError> + Send + 'static> {
         let logger = self.logger.clone();
         let eth_adapter = self.eth_adapter.clone();
+        let chain_store: Arc<dyn ChainStore> = self.chain_store.clone();
 
         let block_futures = block_hashes.iter().map(move |&block_hash| {
             let logger = logger.clone();
             let eth_adapter = eth_adapter.clone();
+            let chain_store = chain_store.clone();
 
             eth_adapter
                 .block_by_hash(&logger, block_hash)
@@ -263,7 +326,7 @@ where
                 .and_then(move |block_opt| {
                     block_opt.ok_or_else(|| EthereumAdapterError::BlockUnavailable(block_hash))
                 })
-                .and_then(move |block| eth_adapter.load_full_block(&logger, block))
+                .and_then(move |block| eth_adapter.load_full_block(&logger, chain_store, block))
         });
 
         Box::new(stream::futures_unordered(block_futures))
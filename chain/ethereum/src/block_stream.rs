@@ -1,14 +1,18 @@
 use std::cmp;
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
 use std::mem;
 use std::time::Duration;
 
-use graph::components::ethereum::{
-    blocks_with_triggers, triggers_in_block, EthereumNetworks, NodeCapabilities,
-};
-use graph::prelude::{
-    BlockStream as BlockStreamTrait, BlockStreamBuilder as BlockStreamBuilderTrait, *,
+use graph::blockchain::{
+    BlockStream as BlockStreamTrait, BlockStreamEvent, BlockStreamMetrics, BlockWithTriggers,
 };
+use graph::components::ethereum::{blocks_with_triggers, triggers_in_block};
+use graph::prelude::futures03::compat::Compat;
+use graph::prelude::futures03::future::{abortable, AbortHandle};
+use graph::prelude::tokio::sync::mpsc;
+use graph::prelude::*;
+
+use crate::{Chain, FlatBlock, FlatFileBlockSource};
 
 #[cfg(debug_assertions)]
 use fail::fail_point;
@@ -25,6 +29,14 @@ lazy_static! {
         .unwrap_or("100".into())
         .parse::<u64>()
         .expect("invalid GRAPH_ETHEREUM_TARGET_TRIGGERS_PER_BLOCK_RANGE");
+
+    /// Once fewer than this many blocks are left to be processed, start fetching and
+    /// materializing the blocks after them in the background, keeping at most this many
+    /// blocks that wait to be processed. Set to 0 to disable prefetching.
+    static ref PREFETCH_BLOCKS: usize = std::env::var("GRAPH_ETHEREUM_PREFETCH_BLOCKS")
+        .unwrap_or("100".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_ETHEREUM_PREFETCH_BLOCKS");
 }
 
 enum BlockStreamState {
@@ -41,9 +53,15 @@ enum BlockStreamState {
     /// The BlockStream is emitting blocks that must be processed in order to bring the subgraph
     /// store up to date with the chain store.
     ///
-    /// Valid next states: BeginReconciliation
+    /// Valid next states: BeginReconciliation, YieldingPrefetchedBlocks
     YieldingBlocks(VecDeque<EthereumBlockWithTriggers>),
 
+    /// The BlockStream is emitting the blocks that follow the ones reconciliation found,
+    /// which a background task fetches until it reaches the reorg threshold.
+    ///
+    /// Valid next states: BeginReconciliation
+    YieldingPrefetchedBlocks(Prefetch),
+
     /// The BlockStream experienced an error and is pausing before attempting to produce
     /// blocks again.
     ///
@@ -83,16 +101,20 @@ struct BlockStreamContext<S, C> {
     subgraph_store: Arc<S>,
     chain_store: Arc<C>,
     eth_adapter: Arc<dyn EthereumAdapter>,
+    flat_files: Option<Arc<FlatFileBlockSource>>,
     node_id: NodeId,
     subgraph_id: SubgraphDeploymentId,
     reorg_threshold: u64,
     log_filter: EthereumLogFilter,
     call_filter: EthereumCallFilter,
     block_filter: EthereumBlockFilter,
+    declared_calls: EthereumDeclaredCalls,
     start_blocks: Vec<u64>,
     include_calls_in_blocks: bool,
     logger: Logger,
     metrics: Arc<BlockStreamMetrics>,
+    ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+    call_cache: Arc<dyn EthereumCallCache>,
     previous_triggers_per_block: f64,
     previous_block_range_size: u64,
     max_block_range_size: u64,
@@ -104,16 +126,20 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             subgraph_store: self.subgraph_store.cheap_clone(),
             chain_store: self.chain_store.cheap_clone(),
             eth_adapter: self.eth_adapter.cheap_clone(),
+            flat_files: self.flat_files.clone(),
             node_id: self.node_id.clone(),
             subgraph_id: self.subgraph_id.clone(),
             reorg_threshold: self.reorg_threshold,
             log_filter: self.log_filter.clone(),
             call_filter: self.call_filter.clone(),
             block_filter: self.block_filter.clone(),
+            declared_calls: self.declared_calls.clone(),
             start_blocks: self.start_blocks.clone(),
             include_calls_in_blocks: self.include_calls_in_blocks,
             logger: self.logger.clone(),
             metrics: self.metrics.clone(),
+            ethrpc_metrics: self.ethrpc_metrics.clone(),
+            call_cache: self.call_cache.cheap_clone(),
             previous_triggers_per_block: self.previous_triggers_per_block,
             previous_block_range_size: self.previous_block_range_size,
             max_block_range_size: self.max_block_range_size,
@@ -126,6 +152,27 @@ pub struct BlockStream<S, C> {
     consecutive_err_count: u32,
     chain_head_update_stream: ChainHeadUpdateStream,
     ctx: BlockStreamContext<S, C>,
+    /// The blocks after the ones that are being yielded, fetched while those are processed
+    prefetch: Option<Prefetch>,
+}
+
+/// Blocks that a background task fetches and materializes, starting with block `from`,
+/// while the blocks before them are processed. The task stops at the reorg threshold and
+/// is aborted when this is dropped.
+struct Prefetch {
+    /// The first block the task fetches
+    from: u64,
+    /// The blocks that wait to be processed; sending blocks waits while this is full
+    blocks: Compat<mpsc::Receiver<Result<EthereumBlockWithTriggers, Error>>>,
+    abort: AbortHandle,
+    metrics: Arc<BlockStreamMetrics>,
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        self.abort.abort();
+        self.metrics.buffered_blocks.set(0.0);
+    }
 }
 
 // This is the same as `ReconciliationStep` but without retries.
@@ -147,16 +194,20 @@ where
         subgraph_store: Arc<S>,
         chain_store: Arc<C>,
         eth_adapter: Arc<dyn EthereumAdapter>,
+        flat_files: Option<Arc<FlatFileBlockSource>>,
         node_id: NodeId,
         subgraph_id: SubgraphDeploymentId,
         log_filter: EthereumLogFilter,
         call_filter: EthereumCallFilter,
         block_filter: EthereumBlockFilter,
+        declared_calls: EthereumDeclaredCalls,
         start_blocks: Vec<u64>,
         include_calls_in_blocks: bool,
         reorg_threshold: u64,
         logger: Logger,
         metrics: Arc<BlockStreamMetrics>,
+        ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+        call_cache: Arc<dyn EthereumCallCache>,
     ) -> Self {
         BlockStream {
             state: BlockStreamState::BeginReconciliation,
@@ -166,6 +217,7 @@ where
                 subgraph_store,
                 chain_store,
                 eth_adapter,
+                flat_files,
                 node_id,
                 subgraph_id,
                 reorg_threshold,
@@ -173,15 +225,19 @@ where
                 log_filter,
                 call_filter,
                 block_filter,
+                declared_calls,
                 start_blocks,
                 include_calls_in_blocks,
                 metrics,
+                ethrpc_metrics,
+                call_cache,
 
                 // A high number here forces a slow start, with a range of 1.
                 previous_triggers_per_block: 1_000_000.0,
                 previous_block_range_size: 1,
                 max_block_range_size: *MAX_BLOCK_RANGE_SIZE,
             },
+            prefetch: None,
         }
     }
 }
@@ -226,8 +282,6 @@ where
         let log_filter = self.log_filter.clone();
         let call_filter = self.call_filter.clone();
         let block_filter = self.block_filter.clone();
-        let start_blocks = self.start_blocks.clone();
-        let max_block_range_size = self.max_block_range_size;
 
         // Get pointers from database for comparison
         let head_ptr_opt = ctx.chain_store.chain_head_ptr().unwrap();
@@ -318,7 +372,7 @@ where
                         |ptr| {
                             ctx.eth_adapter.is_on_main_chain(
                                 &ctx.logger,
-                                ctx.metrics.ethrpc_metrics.clone(),
+                                ctx.ethrpc_metrics.clone(),
                                 ctx.chain_store.clone(),
                                 ptr,
                             )
@@ -356,72 +410,13 @@ where
                             // then we start with the genesis block
                             let from = subgraph_ptr.map_or(0, |ptr| ptr.number + 1);
 
-                            // Get the next subsequent data source start block to ensure the block range
-                            // is aligned with data source.
-                            let next_start_block: u64 = start_blocks
-                                .into_iter()
-                                .filter(|block_num| block_num > &from)
-                                .min()
-                                .unwrap_or(std::u64::MAX);
-
-                            // End either just before the the next data source start_block or
-                            // just prior to the reorg threshold. It isn't safe to go any farther
-                            // due to race conditions.
-                            let to_limit =
-                                cmp::min(head_ptr.number - reorg_threshold, next_start_block - 1);
-
-                            // Calculate the range size according to the target number of triggers,
-                            // respecting the global maximum and also not increasing too
-                            // drastically from the previous block range size.
-                            //
-                            // An example of the block range dynamics:
-                            // - Start with a block range of 1, target of 1000.
-                            // - Scan 1 block:
-                            //   0 triggers found, max_range_size = 10, range_size = 10
-                            // - Scan 10 blocks:
-                            //   2 triggers found, 0.2 per block, range_size = 1000 / 0.2 = 5000
-                            // - Scan 5000 blocks:
-                            //   10000 triggers found, 2 per block, range_size = 1000 / 2 = 500
-                            // - Scan 500 blocks:
-                            //   1000 triggers found, 2 per block, range_size = 1000 / 2 = 500
-                            let range_size_upper_limit =
-                                max_block_range_size.min(ctx.previous_block_range_size * 10);
-                            let range_size = if ctx.previous_triggers_per_block == 0.0 {
-                                range_size_upper_limit
-                            } else {
-                                (*TARGET_TRIGGERS_PER_BLOCK_RANGE as f64
-                                    / ctx.previous_triggers_per_block)
-                                    .max(1.0)
-                                    .min(range_size_upper_limit as f64)
-                                    as u64
-                            };
-                            let to = cmp::min(from + range_size - 1, to_limit);
-
                             let section = ctx.metrics.stopwatch.start_section("scan_blocks");
-                            info!(
-                                ctx.logger,
-                                "Scanning blocks [{}, {}]", from, to;
-                                "range_size" => range_size
-                            );
-                            Box::new(
-                                blocks_with_triggers(
-                                    ctx.eth_adapter,
-                                    ctx.logger.clone(),
-                                    ctx.chain_store.clone(),
-                                    ctx.metrics.ethrpc_metrics.clone(),
-                                    from,
-                                    to,
-                                    log_filter.clone(),
-                                    call_filter.clone(),
-                                    block_filter.clone(),
-                                )
-                                .map_ok(move |blocks| {
+                            Box::new(ctx.scan_blocks(from, head_ptr, reorg_threshold).map(
+                                move |(blocks, range_size)| {
                                     section.end();
                                     ReconciliationStep::ProcessDescendantBlocks(blocks, range_size)
-                                })
-                                .boxed()
-                                .compat(),
-                            )
+                                },
+                            ))
                         },
                     ),
             )
@@ -494,7 +489,8 @@ where
                                 ctx.eth_adapter
                                     .calls_in_block(
                                         &logger,
-                                        ctx.metrics.ethrpc_metrics.clone(),
+                                        ctx.ethrpc_metrics.clone(),
+                                        ctx.chain_store.clone(),
                                         head_ancestor.block.number.unwrap().as_u64(),
                                         head_ancestor.block.hash.unwrap(),
                                     )
@@ -512,7 +508,7 @@ where
                                         eth_adapter,
                                         logger,
                                         ctx.chain_store.clone(),
-                                        ctx.metrics.ethrpc_metrics.clone(),
+                                        ctx.ethrpc_metrics.clone(),
                                         log_filter.clone(),
                                         call_filter.clone(),
                                         block_filter.clone(),
@@ -536,6 +532,350 @@ where
         }
     }
 
+    /// Scan the block range that starts at `from` for blocks with triggers, and return
+    /// them together with the range size. All blocks in the range must be beyond the
+    /// reorg threshold.
+    fn scan_blocks(
+        &self,
+        from: u64,
+        head_ptr: EthereumBlockPointer,
+        reorg_threshold: u64,
+    ) -> Box<dyn Future<Item = (Vec<EthereumBlockWithTriggers>, u64), Error = Error> + Send> {
+        // Get the next subsequent data source start block to ensure the block range
+        // is aligned with data source.
+        let next_start_block: u64 = self
+            .start_blocks
+            .iter()
+            .cloned()
+            .filter(|block_num| *block_num > from)
+            .min()
+            .unwrap_or(std::u64::MAX);
+
+        // End either just before the the next data source start_block or
+        // just prior to the reorg threshold. It isn't safe to go any farther
+        // due to race conditions.
+        let to_limit = cmp::min(head_ptr.number - reorg_threshold, next_start_block - 1);
+
+        // Calculate the range size according to the target number of triggers,
+        // respecting the global maximum and also not increasing too
+        // drastically from the previous block range size.
+        //
+        // An example of the block range dynamics:
+        // - Start with a block range of 1, target of 1000.
+        // - Scan 1 block:
+        //   0 triggers found, max_range_size = 10, range_size = 10
+        // - Scan 10 blocks:
+        //   2 triggers found, 0.2 per block, range_size = 1000 / 0.2 = 5000
+        // - Scan 5000 blocks:
+        //   10000 triggers found, 2 per block, range_size = 1000 / 2 = 500
+        // - Scan 500 blocks:
+        //   1000 triggers found, 2 per block, range_size = 1000 / 2 = 500
+        let range_size_upper_limit = self
+            .max_block_range_size
+            .min(self.previous_block_range_size * 10);
+        let range_size = if self.previous_triggers_per_block == 0.0 {
+            range_size_upper_limit
+        } else {
+            (*TARGET_TRIGGERS_PER_BLOCK_RANGE as f64 / self.previous_triggers_per_block)
+                .max(1.0)
+                .min(range_size_upper_limit as f64) as u64
+        };
+        let to = cmp::min(from + range_size - 1, to_limit);
+
+        if let Some(flat_files) = &self.flat_files {
+            if let Some(last) = flat_files.last_block(from) {
+                let to = cmp::min(to, last);
+                info!(
+                    self.logger,
+                    "Scanning blocks [{}, {}] in flat files", from, to;
+                    "range_size" => range_size
+                );
+                return self.scan_flat_files(flat_files.cheap_clone(), from, to, range_size);
+            }
+        }
+
+        info!(
+            self.logger,
+            "Scanning blocks [{}, {}]", from, to;
+            "range_size" => range_size
+        );
+        Box::new(
+            blocks_with_triggers(
+                self.eth_adapter.cheap_clone(),
+                self.logger.clone(),
+                self.chain_store.clone(),
+                self.ethrpc_metrics.clone(),
+                from,
+                to,
+                self.log_filter.clone(),
+                self.call_filter.clone(),
+                self.block_filter.clone(),
+            )
+            .map_ok(move |blocks| (blocks, range_size))
+            .boxed()
+            .compat(),
+        )
+    }
+
+    /// Find the blocks with triggers in `from..=to` by reading the blocks from flat files
+    /// instead of asking the Ethereum node. Like `blocks_with_triggers`, the result always
+    /// contains the block `to`; that block is also put into the chain store so that checking
+    /// whether the subgraph pointer is on the main chain does not need the Ethereum node.
+    fn scan_flat_files(
+        &self,
+        flat_files: Arc<FlatFileBlockSource>,
+        from: u64,
+        to: u64,
+        range_size: u64,
+    ) -> Box<dyn Future<Item = (Vec<EthereumBlockWithTriggers>, u64), Error = Error> + Send> {
+        let ctx = self.clone();
+
+        Box::new(
+            async move {
+                let blocks = graph::spawn_blocking_allow_panic(move || flat_files.blocks(from, to))
+                    .await
+                    .map_err(|e| anyhow!("failed to read flat files: {}", e))??;
+
+                let mut relevant_blocks = Vec::new();
+                for FlatBlock { block, traces } in blocks {
+                    let number = block.block.number.unwrap().as_u64();
+                    let calls = match traces {
+                        Some(traces) => traces
+                            .iter()
+                            .filter_map(EthereumCall::try_from_trace)
+                            .collect(),
+                        None if ctx.include_calls_in_blocks => {
+                            // The subgraph needs calls, but the files do not have them
+                            debug!(
+                                ctx.logger,
+                                "Flat file for block {} has no traces, scanning blocks \
+                                 with the Ethereum node",
+                                number
+                            );
+                            let blocks = blocks_with_triggers(
+                                ctx.eth_adapter.cheap_clone(),
+                                ctx.logger.clone(),
+                                ctx.chain_store.clone(),
+                                ctx.ethrpc_metrics.clone(),
+                                from,
+                                to,
+                                ctx.log_filter.clone(),
+                                ctx.call_filter.clone(),
+                                ctx.block_filter.clone(),
+                            )
+                            .await?;
+                            return Ok((blocks, range_size));
+                        }
+                        None => vec![],
+                    };
+                    if number == to {
+                        ctx.chain_store.upsert_block(block.clone())?;
+                    }
+
+                    let block = triggers_in_block(
+                        ctx.eth_adapter.cheap_clone(),
+                        ctx.logger.cheap_clone(),
+                        ctx.chain_store.clone(),
+                        ctx.ethrpc_metrics.clone(),
+                        ctx.log_filter.clone(),
+                        ctx.call_filter.clone(),
+                        ctx.block_filter.clone(),
+                        BlockFinality::NonFinal(EthereumBlockWithCalls {
+                            ethereum_block: block,
+                            calls,
+                        }),
+                    )
+                    .await?;
+                    if !block.triggers.is_empty() || number == to {
+                        relevant_blocks.push(block);
+                    }
+                }
+                debug!(
+                    ctx.logger,
+                    "Found {} relevant block(s)",
+                    relevant_blocks.len()
+                );
+
+                Ok((relevant_blocks, range_size))
+            }
+            .boxed()
+            .compat(),
+        )
+    }
+
+    /// The chain head pointer and the reorg threshold if block `from` is far enough
+    /// behind the chain head to be prefetched. Blocks within the reorg threshold are not
+    /// prefetched since they can still change.
+    fn prefetch_head(&self, from: u64) -> Result<Option<(EthereumBlockPointer, u64)>, Error> {
+        Ok(self.chain_store.chain_head_ptr()?.and_then(|head_ptr| {
+            let reorg_threshold = self.reorg_threshold.min(head_ptr.number);
+            if from + reorg_threshold > head_ptr.number {
+                None
+            } else {
+                Some((head_ptr, reorg_threshold))
+            }
+        }))
+    }
+
+    /// Start fetching and materializing the blocks with triggers from block `from` on in
+    /// the background, keeping at most `capacity` blocks that wait to be processed.
+    fn prefetch(&self, from: u64, capacity: usize) -> Option<Prefetch> {
+        if !matches!(self.prefetch_head(from), Ok(Some(_))) {
+            return None;
+        }
+
+        debug!(self.logger, "Prefetching blocks"; "from" => from);
+        let (sender, blocks) = mpsc::channel(capacity);
+        let (task, abort) = abortable(self.clone().prefetch_blocks(from, sender));
+        graph::spawn(task);
+        Some(Prefetch {
+            from,
+            blocks: blocks.compat(),
+            abort,
+            metrics: self.metrics.cheap_clone(),
+        })
+    }
+
+    /// Scan one block range after the other, starting at `from`, until the reorg threshold
+    /// is reached, and send the materialized blocks to `sender`. Sending waits while the
+    /// channel is full, which keeps this from getting too far ahead of processing.
+    async fn prefetch_blocks(
+        mut self,
+        mut from: u64,
+        mut sender: mpsc::Sender<Result<EthereumBlockWithTriggers, Error>>,
+    ) {
+        let result: Result<(), Error> = async {
+            while let Some((head_ptr, reorg_threshold)) = self.prefetch_head(from)? {
+                let (blocks, range_size) = self
+                    .scan_blocks(from, head_ptr, reorg_threshold)
+                    .compat()
+                    .await?;
+                let next = match blocks.last() {
+                    Some(block) => block.ethereum_block.number() + 1,
+                    None => break,
+                };
+
+                let total_triggers = blocks.iter().map(|b| b.triggers.len()).sum::<usize>();
+                self.previous_triggers_per_block = total_triggers as f64 / range_size as f64;
+                self.previous_block_range_size = range_size;
+
+                for block in blocks {
+                    let block = self.materialize(block).await?;
+                    self.metrics.buffered_blocks.inc();
+                    if sender.send(Ok(block)).await.is_err() {
+                        // The block stream does not need the blocks anymore
+                        self.metrics.buffered_blocks.dec();
+                        return Ok(());
+                    }
+                }
+                from = next;
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            let _ = sender.send(Err(e)).await;
+        }
+    }
+
+    /// Load everything that processing `block` needs from the Ethereum node: the receipts
+    /// of its transactions, the calls made in it if the subgraph needs them, and the
+    /// results of the contract calls that event handlers declare.
+    async fn materialize(
+        &self,
+        block: EthereumBlockWithTriggers,
+    ) -> Result<EthereumBlockWithTriggers, Error> {
+        let ethereum_block = match block.ethereum_block {
+            BlockFinality::Final(light_block) => {
+                let number = light_block.number();
+                let full_block = self
+                    .eth_adapter
+                    .load_full_block(&self.logger, self.chain_store.clone(), light_block)
+                    .compat()
+                    .await?;
+                let calls = if !self.include_calls_in_blocks
+                    || full_block.transaction_receipts.is_empty()
+                {
+                    vec![]
+                } else {
+                    self.eth_adapter
+                        .calls_in_block(
+                            &self.logger,
+                            self.ethrpc_metrics.clone(),
+                            self.chain_store.clone(),
+                            number,
+                            full_block.block.hash.unwrap(),
+                        )
+                        .compat()
+                        .await?
+                };
+                BlockFinality::NonFinal(EthereumBlockWithCalls {
+                    ethereum_block: full_block,
+                    calls,
+                })
+            }
+            // Blocks from flat files already have everything
+            ethereum_block @ BlockFinality::NonFinal(_) => ethereum_block,
+        };
+        let block = EthereumBlockWithTriggers {
+            ethereum_block,
+            triggers: block.triggers,
+        };
+
+        self.make_declared_calls(&block).await;
+        Ok(block)
+    }
+
+    /// Make the contract calls that event handlers declare for the logs in `block` so that
+    /// their results are in the call cache when the handlers run. Calls that fail are only
+    /// logged since the handler gets the error when it makes the call itself.
+    async fn make_declared_calls(&self, block: &EthereumBlockWithTriggers) {
+        if self.declared_calls.is_empty() {
+            return;
+        }
+
+        let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+        let calls = block
+            .triggers
+            .iter()
+            .filter_map(|trigger| match trigger {
+                EthereumTrigger::Log(log) => Some(log),
+                _ => None,
+            })
+            .flat_map(|log| {
+                self.declared_calls
+                    .calls_for_log(&self.logger, block_ptr, log)
+            })
+            .map(|call| {
+                let logger = self.logger.cheap_clone();
+                self.eth_adapter
+                    .contract_call(&self.logger, call, self.call_cache.cheap_clone())
+                    .compat()
+                    .map(move |result| {
+                        if let Err(e) = result {
+                            debug!(logger, "Declared call failed"; "error" => e.to_string());
+                        }
+                    })
+            });
+        futures03::future::join_all(calls).await;
+    }
+
+    /// Whether the subgraph has processed all blocks before block `from`, which is needed to
+    /// use blocks that were prefetched from there on.
+    fn follows_subgraph_ptr(&self, from: u64) -> bool {
+        let subgraph_ptr = match self.subgraph_store.block_ptr(&self.subgraph_id) {
+            Ok(Some(subgraph_ptr)) if subgraph_ptr.number + 1 == from => subgraph_ptr,
+            _ => return false,
+        };
+        if let Ok(Some(head_ptr)) = self.chain_store.chain_head_ptr() {
+            self.metrics
+                .blocks_behind
+                .set(head_ptr.number.saturating_sub(subgraph_ptr.number) as f64);
+        }
+        true
+    }
+
     /// Set subgraph deployment entity synced flag if and only if the subgraph block pointer is
     /// caught up to the head block pointer.
     fn update_subgraph_synced_status(&self) -> Result<(), Error> {
@@ -556,10 +896,13 @@ where
     }
 }
 
-impl<S: Store, C: ChainStore> BlockStreamTrait for BlockStream<S, C> {}
+impl<S: Store + EthereumCallCache, C: ChainStore> BlockStreamTrait<Chain<S, C>>
+    for BlockStream<S, C>
+{
+}
 
-impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
-    type Item = BlockStreamEvent;
+impl<S: Store + EthereumCallCache, C: ChainStore> Stream for BlockStream<S, C> {
+    type Item = BlockStreamEvent<Chain<S, C>>;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
@@ -569,6 +912,10 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
         let result = loop {
             match state {
                 BlockStreamState::BeginReconciliation => {
+                    // A prefetched range might not follow the blocks that reconciliation
+                    // finds, e.g., after an error
+                    self.prefetch = None;
+
                     // Start the reconciliation process by asking for blocks
                     state = BlockStreamState::Reconciliation(self.ctx.next_blocks());
                 }
@@ -623,7 +970,7 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
 
                         Ok(Async::Ready(NextBlocks::Revert(block))) => {
                             state = BlockStreamState::BeginReconciliation;
-                            break Ok(Async::Ready(Some(BlockStreamEvent::Revert(block))));
+                            break Ok(Async::Ready(Some(BlockStreamEvent::Revert(block.into()))));
                         }
 
                         Ok(Async::NotReady) => {
@@ -652,17 +999,71 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
 
                 // Yielding blocks from reconciliation process
                 BlockStreamState::YieldingBlocks(mut next_blocks) => {
+                    // Fetch the blocks after these while the remaining ones are processed
+                    if self.prefetch.is_none() && next_blocks.len() < *PREFETCH_BLOCKS {
+                        if let Some(last) = next_blocks.back() {
+                            self.prefetch = self
+                                .ctx
+                                .prefetch(last.ethereum_block.number() + 1, *PREFETCH_BLOCKS);
+                        }
+                    }
+
                     match next_blocks.pop_front() {
                         // Yield one block
                         Some(next_block) => {
                             state = BlockStreamState::YieldingBlocks(next_blocks);
-                            break Ok(Async::Ready(Some(BlockStreamEvent::Block(next_block))));
+                            let next_block = BlockWithTriggers::new(
+                                next_block.ethereum_block,
+                                next_block.triggers,
+                            );
+                            break Ok(Async::Ready(Some(BlockStreamEvent::ProcessBlock(
+                                next_block,
+                            ))));
                         }
 
                         // Done yielding blocks
                         None => {
+                            state = match self.prefetch.take() {
+                                Some(prefetch) if self.ctx.follows_subgraph_ptr(prefetch.from) => {
+                                    BlockStreamState::YieldingPrefetchedBlocks(prefetch)
+                                }
+                                _ => BlockStreamState::BeginReconciliation,
+                            };
+                        }
+                    }
+                }
+
+                // Yielding the blocks that were fetched in the background
+                BlockStreamState::YieldingPrefetchedBlocks(mut prefetch) => {
+                    match prefetch.blocks.poll() {
+                        // Yield one block
+                        Ok(Async::Ready(Some(next_block))) => {
+                            self.ctx.metrics.buffered_blocks.dec();
+                            state = BlockStreamState::YieldingPrefetchedBlocks(prefetch);
+                            let next_block = BlockWithTriggers::new(
+                                next_block.ethereum_block,
+                                next_block.triggers,
+                            );
+                            break Ok(Async::Ready(Some(BlockStreamEvent::ProcessBlock(
+                                next_block,
+                            ))));
+                        }
+
+                        // The reorg threshold was reached
+                        Ok(Async::Ready(None)) => {
                             state = BlockStreamState::BeginReconciliation;
                         }
+
+                        Ok(Async::NotReady) => {
+                            state = BlockStreamState::YieldingPrefetchedBlocks(prefetch);
+                            break Ok(Async::NotReady);
+                        }
+
+                        // Reconciliation fetches the blocks again
+                        Err(e) => {
+                            state = BlockStreamState::BeginReconciliation;
+                            break Err(e);
+                        }
                     }
                 }
 
@@ -720,118 +1121,6 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
     }
 }
 
-pub struct BlockStreamBuilder<S, C, M> {
-    subgraph_store: Arc<S>,
-    chain_stores: HashMap<String, Arc<C>>,
-    eth_networks: EthereumNetworks,
-    node_id: NodeId,
-    reorg_threshold: u64,
-    metrics_registry: Arc<M>,
-}
-
-impl<S, C, M> Clone for BlockStreamBuilder<S, C, M> {
-    fn clone(&self) -> Self {
-        BlockStreamBuilder {
-            subgraph_store: self.subgraph_store.clone(),
-            chain_stores: self.chain_stores.clone(),
-            eth_networks: self.eth_networks.clone(),
-            node_id: self.node_id.clone(),
-            reorg_threshold: self.reorg_threshold,
-            metrics_registry: self.metrics_registry.clone(),
-        }
-    }
-}
-
-impl<S, C, M> BlockStreamBuilder<S, C, M>
-where
-    S: Store,
-    C: ChainStore,
-    M: MetricsRegistry,
-{
-    pub fn new(
-        subgraph_store: Arc<S>,
-        chain_stores: HashMap<String, Arc<C>>,
-        eth_networks: EthereumNetworks,
-        node_id: NodeId,
-        reorg_threshold: u64,
-        metrics_registry: Arc<M>,
-    ) -> Self {
-        BlockStreamBuilder {
-            subgraph_store,
-            chain_stores,
-            eth_networks,
-            node_id,
-            reorg_threshold,
-            metrics_registry,
-        }
-    }
-}
-
-impl<S, C, M> BlockStreamBuilderTrait for BlockStreamBuilder<S, C, M>
-where
-    S: Store,
-    C: ChainStore,
-    M: MetricsRegistry,
-{
-    type Stream = BlockStream<S, C>;
-
-    fn build(
-        &self,
-        logger: Logger,
-        deployment_id: SubgraphDeploymentId,
-        network_name: String,
-        start_blocks: Vec<u64>,
-        log_filter: EthereumLogFilter,
-        call_filter: EthereumCallFilter,
-        block_filter: EthereumBlockFilter,
-        include_calls_in_blocks: bool,
-        metrics: Arc<BlockStreamMetrics>,
-    ) -> Self::Stream {
-        let logger = logger.new(o!(
-            "component" => "BlockStream",
-        ));
-
-        let chain_store = self
-            .chain_stores
-            .get(&network_name)
-            .expect(&format!(
-                "no store that supports network: {}",
-                &network_name
-            ))
-            .clone();
-
-        let requirements = NodeCapabilities {
-            archive: false,
-            traces: include_calls_in_blocks,
-        };
-
-        let eth_adapter = self
-            .eth_networks
-            .adapter_with_capabilities(network_name.clone(), &requirements)
-            .expect(&format!(
-                "no eth adapter that supports network: {} with {}",
-                &network_name, &requirements
-            ));
-
-        // Create the actual subgraph-specific block stream
-        BlockStream::new(
-            self.subgraph_store.clone(),
-            chain_store,
-            eth_adapter.clone(),
-            self.node_id.clone(),
-            deployment_id,
-            log_filter,
-            call_filter,
-            block_filter,
-            start_blocks,
-            include_calls_in_blocks,
-            self.reorg_threshold,
-            logger,
-            metrics,
-        )
-    }
-}
-
 // This always returns `false` in a normal build. A test may configure reorg by enabling
 // "test_reorg" fail point with the number of the block that should be reorged.
 #[cfg(debug_assertions)]
@@ -855,3 +1144,309 @@ fn test_reorg(ptr: EthereumBlockPointer) -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::data::subgraph::{Mapping, Source};
+    use graph::prelude::ethabi::{self, Contract, Token};
+    use graph::prelude::web3::types::{Address, Bytes, Log, TransactionReceipt, H256};
+    use graph_mock::{MockMetricsRegistry, MockStore};
+    use std::collections::{BTreeMap, HashSet};
+    use std::fs;
+    use std::sync::Mutex;
+
+    const ABI: &str = r#"[
+        {
+            "type": "event",
+            "name": "Transfer",
+            "anonymous": false,
+            "inputs": [
+                { "name": "from", "type": "address", "indexed": true },
+                { "name": "to", "type": "address", "indexed": true },
+                { "name": "value", "type": "uint256", "indexed": false }
+            ]
+        },
+        {
+            "type": "function",
+            "name": "balanceOf",
+            "constant": true,
+            "payable": false,
+            "stateMutability": "view",
+            "inputs": [{ "name": "owner", "type": "address" }],
+            "outputs": [{ "name": "", "type": "uint256" }]
+        }
+    ]"#;
+
+    struct CallCache;
+
+    impl EthereumCallCache for CallCache {
+        fn get_call(
+            &self,
+            _: ethabi::Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+        ) -> Result<Option<Vec<u8>>, Error> {
+            Ok(None)
+        }
+
+        fn set_call(
+            &self,
+            _: ethabi::Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &[u8],
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn block_ptr(number: u64) -> EthereumBlockPointer {
+        (H256::from_low_u64_be(number), number).into()
+    }
+
+    fn context(
+        eth_adapter: MockEthereumAdapter,
+        store: MockStore,
+        flat_files: Option<Arc<FlatFileBlockSource>>,
+        block_filter: EthereumBlockFilter,
+        declared_calls: EthereumDeclaredCalls,
+    ) -> BlockStreamContext<MockStore, MockStore> {
+        let logger = Logger::root(slog::Discard, o!());
+        let registry: Arc<dyn MetricsRegistry> = Arc::new(MockMetricsRegistry::new());
+        let id = SubgraphDeploymentId::new("prefetch").unwrap();
+        let stopwatch = StopwatchMetrics::new(logger.clone(), id.clone(), registry.clone());
+        let store = Arc::new(store);
+
+        BlockStreamContext {
+            subgraph_store: store.clone(),
+            chain_store: store,
+            eth_adapter: Arc::new(eth_adapter),
+            flat_files,
+            node_id: NodeId::new("test").unwrap(),
+            subgraph_id: id.clone(),
+            reorg_threshold: 50,
+            log_filter: EthereumLogFilter::default(),
+            call_filter: EthereumCallFilter::default(),
+            block_filter,
+            declared_calls,
+            start_blocks: vec![],
+            include_calls_in_blocks: false,
+            logger,
+            metrics: Arc::new(BlockStreamMetrics::new(registry.clone(), &id, stopwatch)),
+            ethrpc_metrics: Arc::new(SubgraphEthRpcMetrics::new(registry, id.as_str())),
+            call_cache: Arc::new(CallCache),
+            previous_triggers_per_block: 0.0,
+            previous_block_range_size: 1,
+            max_block_range_size: 100,
+        }
+    }
+
+    /// Flat files with the (empty) blocks `first..=last`
+    fn flat_files(name: &str, first: u64, last: u64) -> Arc<FlatFileBlockSource> {
+        let dir = std::env::temp_dir().join(format!("graph-block-stream-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let lines: Vec<String> = (first..=last)
+            .map(|number| {
+                let mut block = EthereumBlock::default();
+                block.block.number = Some(number.into());
+                block.block.hash = Some(H256::from_low_u64_be(number));
+                serde_json::to_string(&block).unwrap()
+            })
+            .collect();
+        let compressed = zstd::encode_all(lines.join("\n").as_bytes(), 0).unwrap();
+        fs::write(
+            dir.join(format!("{}-{}.jsonl.zst", first, last)),
+            compressed,
+        )
+        .unwrap();
+
+        let logger = Logger::root(slog::Discard, o!());
+        Arc::new(FlatFileBlockSource::new(&logger, &dir).unwrap())
+    }
+
+    /// A data source for an ERC20 contract at `address` whose `Transfer`
+    /// handler declares a call of `balanceOf` for the recipient
+    fn erc20_data_source(address: Address) -> DataSource {
+        let mut calls = BTreeMap::new();
+        calls.insert(
+            "balance".to_owned(),
+            "ERC20[event.address].balanceOf(event.params.to)"
+                .parse()
+                .unwrap(),
+        );
+
+        DataSource {
+            kind: "ethereum/contract".to_owned(),
+            network: Some("mainnet".to_owned()),
+            name: "ERC20".to_owned(),
+            source: Source {
+                address: Some(address),
+                abi: "ERC20".to_owned(),
+                start_block: 0,
+            },
+            mapping: Mapping {
+                kind: "ethereum/events".to_owned(),
+                api_version: "0.0.4".to_owned(),
+                language: "wasm/assemblyscript".to_owned(),
+                entities: vec![],
+                abis: vec![MappingABI {
+                    name: "ERC20".to_owned(),
+                    contract: Contract::load(ABI.as_bytes()).unwrap(),
+                    link: Link::from(String::new()),
+                }],
+                block_handlers: vec![],
+                call_handlers: vec![],
+                event_handlers: vec![MappingEventHandler {
+                    event: "Transfer(indexed address,indexed address,uint256)".to_owned(),
+                    topic0: None,
+                    handler: "handleTransfer".to_owned(),
+                    calls,
+                }],
+                runtime: Arc::new(vec![]),
+                link: Link::from(String::new()),
+            },
+            context: None,
+            creation_block: None,
+        }
+    }
+
+    fn transfer(address: Address, to: Address) -> Log {
+        let contract = Contract::load(ABI.as_bytes()).unwrap();
+        let topic = |address| H256::from_slice(&ethabi::encode(&[Token::Address(address)]));
+        Log {
+            address,
+            topics: vec![
+                contract.event("Transfer").unwrap().signature(),
+                topic(Address::zero()),
+                topic(to),
+            ],
+            data: Bytes(ethabi::encode(&[Token::Uint(5.into())])),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn materialize_loads_receipts_and_makes_declared_calls() {
+        let token = Address::from_low_u64_be(1);
+        let other_token = Address::from_low_u64_be(2);
+        let recipient = Address::from_low_u64_be(3);
+
+        let mut eth_adapter = MockEthereumAdapter::new();
+        eth_adapter
+            .expect_load_full_block()
+            .times(1)
+            .returning(|_, _, block| {
+                Box::new(future::ok(EthereumBlock {
+                    transaction_receipts: vec![TransactionReceipt::default()],
+                    block,
+                }))
+            });
+        let made_calls = Arc::new(Mutex::new(Vec::new()));
+        let made_calls2 = made_calls.clone();
+        eth_adapter
+            .expect_contract_call()
+            .returning(move |_, call, _| {
+                made_calls2.lock().unwrap().push(call);
+                Box::new(future::ok(vec![Token::Uint(7.into())]))
+            });
+
+        let ctx = context(
+            eth_adapter,
+            MockStore::new(),
+            None,
+            EthereumBlockFilter::default(),
+            EthereumDeclaredCalls::from_data_sources(&[erc20_data_source(token)]),
+        );
+
+        let mut light_block = LightEthereumBlock::default();
+        light_block.number = Some(7.into());
+        light_block.hash = Some(H256::from_low_u64_be(7));
+        let block = EthereumBlockWithTriggers::new(
+            vec![
+                EthereumTrigger::Log(transfer(token, recipient)),
+                EthereumTrigger::Log(transfer(other_token, recipient)),
+            ],
+            BlockFinality::Final(light_block),
+        );
+
+        let block = ctx.materialize(block).await.unwrap();
+
+        match &block.ethereum_block {
+            BlockFinality::NonFinal(block) => {
+                assert_eq!(1, block.ethereum_block.transaction_receipts.len());
+                assert!(block.calls.is_empty());
+            }
+            BlockFinality::Final(_) => panic!("the block was not loaded in full"),
+        }
+        assert_eq!(2, block.triggers.len());
+
+        // Only the log of the contract the data source is for leads to a call
+        let made_calls = made_calls.lock().unwrap();
+        assert_eq!(1, made_calls.len());
+        assert_eq!(token, made_calls[0].address);
+        assert_eq!(block_ptr(7), made_calls[0].block_ptr);
+        assert_eq!("balanceOf", made_calls[0].function.name);
+        assert_eq!(vec![Token::Address(recipient)], made_calls[0].args);
+    }
+
+    #[tokio::test]
+    async fn prefetch_keeps_at_most_capacity_blocks() {
+        let mut store = MockStore::new();
+        store
+            .expect_chain_head_ptr()
+            .returning(|| Ok(Some(block_ptr(200))));
+        store.expect_upsert_block().returning(|_| Ok(()));
+        let block_filter = EthereumBlockFilter {
+            contract_addresses: HashSet::new(),
+            trigger_every_block: true,
+        };
+        let ctx = context(
+            MockEthereumAdapter::new(),
+            store,
+            Some(flat_files("prefetch", 0, 199)),
+            block_filter,
+            EthereumDeclaredCalls::default(),
+        );
+
+        // Blocks within the reorg threshold are not prefetched
+        assert!(ctx.prefetch(151, 5).is_none());
+
+        let mut prefetch = ctx.prefetch(10, 5).expect("blocks are prefetched");
+        let buffered = || ctx.metrics.buffered_blocks.get();
+        for _ in 0..100 {
+            if buffered() == 5.0 {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        }
+        // Fetching stops once the channel is full
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        assert_eq!(5.0, buffered());
+
+        let blocks = prefetch
+            .blocks
+            .by_ref()
+            .take(20)
+            .collect()
+            .compat()
+            .await
+            .unwrap();
+        assert_eq!(
+            (10..30).collect::<Vec<_>>(),
+            blocks
+                .iter()
+                .map(|block| block.ethereum_block.number())
+                .collect::<Vec<_>>()
+        );
+        for block in &blocks {
+            assert!(matches!(block.ethereum_block, BlockFinality::NonFinal(_)));
+            assert_eq!(1, block.triggers.len());
+        }
+
+        drop(prefetch);
+        assert_eq!(0.0, buffered());
+    }
+}
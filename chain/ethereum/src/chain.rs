@@ -0,0 +1,322 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use graph::blockchain::{
+    BlockPtr, BlockStreamMetrics, BlockWithTriggers, Blockchain, BlockchainKind,
+    NodeCapabilities as NodeCapabilitiesTrait, TriggerFilter as TriggerFilterTrait,
+    TriggerProcessor as TriggerProcessorTrait, TriggersAdapter as TriggersAdapterTrait,
+};
+use graph::components::ethereum::{triggers_in_block, EthereumNetworks, NodeCapabilities};
+use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::data::subgraph::required_ethereum_capabilities;
+use graph::prelude::*;
+
+use crate::{BlockStream, FlatFileBlockSource};
+
+/// An Ethereum network, e.g. `mainnet`, that subgraphs can index
+pub struct Chain<S, C> {
+    name: String,
+    node_id: NodeId,
+    subgraph_store: Arc<S>,
+    chain_store: Arc<C>,
+    eth_networks: EthereumNetworks,
+    flat_files: Option<Arc<FlatFileBlockSource>>,
+    reorg_threshold: u64,
+    metrics_registry: Arc<dyn MetricsRegistry>,
+}
+
+impl<S, C> Chain<S, C>
+where
+    S: Store,
+    C: ChainStore,
+{
+    pub fn new(
+        name: String,
+        node_id: NodeId,
+        subgraph_store: Arc<S>,
+        chain_store: Arc<C>,
+        eth_networks: EthereumNetworks,
+        flat_files: Option<Arc<FlatFileBlockSource>>,
+        reorg_threshold: u64,
+        metrics_registry: Arc<dyn MetricsRegistry>,
+    ) -> Self {
+        Chain {
+            name,
+            node_id,
+            subgraph_store,
+            chain_store,
+            eth_networks,
+            flat_files,
+            reorg_threshold,
+            metrics_registry,
+        }
+    }
+
+    fn eth_adapter(
+        &self,
+        requirements: &NodeCapabilities,
+    ) -> Result<Arc<dyn EthereumAdapter>, Error> {
+        self.eth_networks
+            .adapter_with_capabilities(self.name.clone(), requirements)
+            .map_err(|e| {
+                anyhow!(
+                    "no eth adapter that supports network {} with {}: {}",
+                    &self.name,
+                    requirements,
+                    e
+                )
+            })
+    }
+}
+
+impl<S, C> Blockchain for Chain<S, C>
+where
+    S: Store + EthereumCallCache,
+    C: ChainStore,
+{
+    const KIND: BlockchainKind = BlockchainKind::Ethereum;
+
+    type Block = BlockFinality;
+    type DataSource = DataSource;
+    type DataSourceTemplate = DataSourceTemplate;
+    type NodeCapabilities = NodeCapabilities;
+    type TriggerData = EthereumTrigger;
+    type TriggerFilter = TriggerFilter;
+    type TriggersAdapter = TriggersAdapter<C>;
+    type BlockStream = BlockStream<S, C>;
+    type TriggerProcessor = TriggerProcessor;
+
+    fn network_name(&self) -> &str {
+        &self.name
+    }
+
+    fn triggers_adapter(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        capabilities: &NodeCapabilities,
+    ) -> Result<Arc<Self::TriggersAdapter>, Error> {
+        let eth_adapter = self.eth_adapter(capabilities)?;
+        let metrics = Arc::new(SubgraphEthRpcMetrics::new(
+            self.metrics_registry.cheap_clone(),
+            deployment,
+        ));
+        Ok(Arc::new(TriggersAdapter {
+            chain_store: self.chain_store.cheap_clone(),
+            eth_adapter,
+            metrics,
+        }))
+    }
+
+    fn trigger_processor(&self) -> Arc<TriggerProcessor> {
+        Arc::new(TriggerProcessor)
+    }
+
+    fn new_block_stream(
+        &self,
+        logger: &Logger,
+        deployment: SubgraphDeploymentId,
+        start_blocks: Vec<BlockNumber>,
+        filter: TriggerFilter,
+        triggers_adapter: Arc<Self::TriggersAdapter>,
+        metrics: Arc<BlockStreamMetrics>,
+    ) -> Result<Self::BlockStream, Error> {
+        let logger = logger.new(o!(
+            "component" => "BlockStream",
+        ));
+
+        // Mappings with call handlers or block handlers with call filters
+        // need the calls in all blocks, and the block stream makes the
+        // calls that event handlers declare
+        let include_calls_in_blocks = filter.requires_traces();
+        let requirements = NodeCapabilities {
+            archive: !filter.declared_calls.is_empty(),
+            traces: include_calls_in_blocks,
+        };
+        let eth_adapter = self.eth_adapter(&requirements)?;
+
+        let start_blocks = start_blocks.into_iter().map(|block| block as u64).collect();
+
+        Ok(BlockStream::new(
+            self.subgraph_store.cheap_clone(),
+            self.chain_store.cheap_clone(),
+            eth_adapter,
+            self.flat_files.clone(),
+            self.node_id.clone(),
+            deployment,
+            filter.log,
+            filter.call,
+            filter.block,
+            filter.declared_calls,
+            start_blocks,
+            include_calls_in_blocks,
+            self.reorg_threshold,
+            logger,
+            metrics,
+            triggers_adapter.metrics.cheap_clone(),
+            self.subgraph_store.cheap_clone(),
+        ))
+    }
+}
+
+impl<S, C> NodeCapabilitiesTrait<Chain<S, C>> for NodeCapabilities
+where
+    S: Store + EthereumCallCache,
+    C: ChainStore,
+{
+    fn from_data_sources(data_sources: &[DataSource], templates: &[DataSourceTemplate]) -> Self {
+        required_ethereum_capabilities(
+            data_sources
+                .iter()
+                .map(|data_source| &data_source.mapping)
+                .chain(templates.iter().map(|template| &template.mapping)),
+        )
+    }
+}
+
+/// The log, call and block filters of a subgraph, and the contract calls
+/// its event handlers declare
+#[derive(Clone, Debug, Default)]
+pub struct TriggerFilter {
+    pub log: EthereumLogFilter,
+    pub call: EthereumCallFilter,
+    pub block: EthereumBlockFilter,
+    pub declared_calls: EthereumDeclaredCalls,
+}
+
+impl TriggerFilter {
+    /// Whether matching the filter requires the calls made in a block
+    pub fn requires_traces(&self) -> bool {
+        !self.call.is_empty() || !self.block.contract_addresses.is_empty()
+    }
+}
+
+impl<S, C> TriggerFilterTrait<Chain<S, C>> for TriggerFilter
+where
+    S: Store + EthereumCallCache,
+    C: ChainStore,
+{
+    fn extend<'a>(&mut self, data_sources: impl Iterator<Item = &'a DataSource> + Clone) {
+        self.log
+            .extend(EthereumLogFilter::from_data_sources(data_sources.clone()));
+        self.call
+            .extend(EthereumCallFilter::from_data_sources(data_sources.clone()));
+        self.block
+            .extend(EthereumBlockFilter::from_data_sources(data_sources.clone()));
+        self.declared_calls
+            .extend(EthereumDeclaredCalls::from_data_sources(data_sources));
+    }
+}
+
+pub struct TriggersAdapter<C> {
+    chain_store: Arc<C>,
+    eth_adapter: Arc<dyn EthereumAdapter>,
+    metrics: Arc<SubgraphEthRpcMetrics>,
+}
+
+#[async_trait]
+impl<S, C> TriggersAdapterTrait<Chain<S, C>> for TriggersAdapter<C>
+where
+    S: Store + EthereumCallCache,
+    C: ChainStore,
+{
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: BlockFinality,
+        filter: &TriggerFilter,
+    ) -> Result<BlockWithTriggers<Chain<S, C>>, Error> {
+        let block = triggers_in_block(
+            self.eth_adapter.cheap_clone(),
+            logger.cheap_clone(),
+            self.chain_store.cheap_clone(),
+            self.metrics.cheap_clone(),
+            filter.log.clone(),
+            filter.call.clone(),
+            filter.block.clone(),
+            block,
+        )
+        .await?;
+        Ok(BlockWithTriggers::new(block.ethereum_block, block.triggers))
+    }
+
+    async fn parent_ptr(&self, logger: &Logger, block: &BlockPtr) -> Result<BlockPtr, Error> {
+        let hash = EthereumBlockPointer::try_from(block)?.hash;
+        let blocks = self
+            .eth_adapter
+            .load_blocks(
+                logger.cheap_clone(),
+                self.chain_store.cheap_clone(),
+                HashSet::from_iter(Some(hash)),
+            )
+            .collect()
+            .compat()
+            .await?;
+        assert_eq!(blocks.len(), 1);
+
+        // Reverting the genesis block is not possible
+        let parent = blocks[0]
+            .parent_ptr()
+            .ok_or_else(|| anyhow!("genesis block {} cannot be reverted", block))?;
+        Ok(parent.into())
+    }
+}
+
+/// Matches Ethereum triggers against the event, call and block handlers of
+/// runtime hosts and runs the handlers
+pub struct TriggerProcessor;
+
+#[async_trait]
+impl<H: EthereumRuntimeHost> TriggerProcessorTrait<H> for TriggerProcessor {
+    type Block = BlockFinality;
+    type TriggerData = EthereumTrigger;
+    type MappingBlock = LightEthereumBlock;
+
+    fn mapping_block(&self, block: &BlockFinality) -> Arc<LightEthereumBlock> {
+        Arc::new(block.light_block())
+    }
+
+    fn matches(&self, host: &H, trigger: &EthereumTrigger) -> bool {
+        match trigger {
+            EthereumTrigger::Log(log) => host.matches_log(log),
+            EthereumTrigger::Call(call) => host.matches_call(call),
+            EthereumTrigger::Block(ptr, trigger_type) => {
+                host.matches_block(trigger_type, ptr.number)
+            }
+        }
+    }
+
+    async fn process_trigger(
+        &self,
+        logger: &Logger,
+        host: &H,
+        block: &Arc<LightEthereumBlock>,
+        trigger: &EthereumTrigger,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        match trigger {
+            EthereumTrigger::Log(log) => {
+                let log = Arc::new(log.clone());
+                let transaction = block
+                    .transaction_for_log(&log)
+                    .map(Arc::new)
+                    .context("Found no transaction for event")?;
+                host.process_log(logger, block, &transaction, &log, state, proof_of_indexing)
+                    .await
+            }
+            EthereumTrigger::Call(call) => {
+                let call = Arc::new(call.clone());
+                let transaction = block
+                    .transaction_for_call(&call)
+                    .map(Arc::new)
+                    .context("Found no transaction for call")?;
+                host.process_call(logger, block, &transaction, &call, state, proof_of_indexing)
+                    .await
+            }
+            EthereumTrigger::Block(_, trigger_type) => {
+                host.process_block(logger, block, trigger_type, state, proof_of_indexing)
+                    .await
+            }
+        }
+    }
+}
@@ -795,6 +795,7 @@ where
     fn load_full_block(
         &self,
         logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
         block: LightEthereumBlock,
     ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
         let logger = logger.clone();
@@ -809,7 +810,30 @@ where
                 transaction_receipts: Vec::new(),
             }));
         }
+
+        match chain_store.transaction_receipts(&block_hash) {
+            Ok(Some(transaction_receipts))
+                if transaction_receipts.len() == block.transactions.len() =>
+            {
+                trace!(
+                    logger,
+                    "Loaded receipts for block {} from the store",
+                    block_hash
+                );
+                return Box::new(future::ok(EthereumBlock {
+                    block,
+                    transaction_receipts,
+                }));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(logger, "Failed to load transaction receipts from the store";
+                      "block_hash" => format!("{:x}", block_hash),
+                      "error" => e.to_string());
+            }
+        }
         let web3 = self.web3.clone();
+        let logger_for_store = logger.clone();
 
         // Retry, but eventually give up.
         // A receipt might be missing because the block was uncled, and the
@@ -908,6 +932,13 @@ where
                         )
                         .into()
                     })
+                })
+                .inspect(move |block| {
+                    if let Err(e) = chain_store.upsert_block(block.clone()) {
+                        warn!(logger_for_store, "Failed to store transaction receipts";
+                              "block_hash" => format!("{:x}", block_hash),
+                              "error" => e.to_string());
+                    }
                 }),
         )
     }
@@ -1081,10 +1112,34 @@ where
         &self,
         logger: &Logger,
         subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
         block_number: u64,
         block_hash: H256,
     ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        match chain_store.block_traces(&block_hash) {
+            Ok(Some(traces)) => {
+                trace!(
+                    logger,
+                    "Loaded traces for block {} from the store",
+                    block_hash
+                );
+                return Box::new(future::ok(
+                    traces
+                        .iter()
+                        .filter_map(EthereumCall::try_from_trace)
+                        .collect(),
+                ));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(logger, "Failed to load traces from the store";
+                      "block_hash" => format!("{:x}", block_hash),
+                      "error" => e.to_string());
+            }
+        }
+
         let eth = self.clone();
+        let logger_for_store = logger.clone();
         let addresses = Vec::new();
         let calls = eth
             .trace_stream(
@@ -1121,6 +1176,12 @@ where
                 future::ok(traces)
             })
             .map(move |traces| {
+                let block_ptr = (block_hash, block_number).into();
+                if let Err(e) = chain_store.set_block_traces(block_ptr, &traces) {
+                    warn!(logger_for_store, "Failed to store traces";
+                          "block_hash" => format!("{:x}", block_hash),
+                          "error" => e.to_string());
+                }
                 traces
                     .iter()
                     .filter_map(EthereumCall::try_from_trace)
@@ -1159,29 +1220,91 @@ where
         &self,
         logger: &Logger,
         subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
         from: u64,
         to: u64,
         call_filter: EthereumCallFilter,
     ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
         let eth = self.clone();
 
-        let addresses: Vec<H160> = call_filter
+        let addresses: HashSet<H160> = call_filter
             .contract_addresses_function_signatures
             .iter()
             .filter(|(_addr, (start_block, _fsigs))| start_block <= &to)
             .map(|(addr, (_start_block, _fsigs))| *addr)
-            .collect::<HashSet<H160>>()
-            .into_iter()
-            .collect::<Vec<H160>>();
+            .collect();
+
+        let traces: Box<dyn Stream<Item = Trace, Error = Error> + Send> = match chain_store
+            .block_traces_in_range(from, to)
+        {
+            Ok(Some(traces)) => {
+                trace!(
+                    logger,
+                    "Loaded traces for blocks [{}, {}] from the store",
+                    from,
+                    to
+                );
+                Box::new(stream::iter_ok(traces))
+            }
+            res => {
+                if let Err(e) = res {
+                    warn!(logger, "Failed to load traces from the store";
+                              "from" => from,
+                              "to" => to,
+                              "error" => e.to_string());
+                }
+
+                // Request all traces of the blocks, not just the ones to
+                // `addresses`, so that we can cache them for every
+                // subgraph that needs them
+                let logger = logger.clone();
+                Box::new(
+                    eth.trace_stream(&logger, subgraph_metrics, from, to, vec![])
+                        .collect()
+                        .map(move |traces| {
+                            let mut blocks: Vec<(EthereumBlockPointer, Vec<Trace>)> = Vec::new();
+                            for trace in traces {
+                                match blocks
+                                    .iter_mut()
+                                    .rev()
+                                    .find(|(ptr, _)| ptr.hash == trace.block_hash)
+                                {
+                                    Some((_, block_traces)) => block_traces.push(trace),
+                                    None => blocks.push((
+                                        (trace.block_hash, trace.block_number).into(),
+                                        vec![trace],
+                                    )),
+                                }
+                            }
+                            for (block_ptr, block_traces) in &blocks {
+                                if let Err(e) =
+                                    chain_store.set_block_traces(*block_ptr, block_traces)
+                                {
+                                    warn!(logger, "Failed to store traces";
+                                              "block_hash" => format!("{:x}", block_ptr.hash),
+                                              "error" => e.to_string());
+                                }
+                            }
+                            stream::iter_ok(
+                                blocks
+                                    .into_iter()
+                                    .flat_map(|(_, block_traces)| block_traces),
+                            )
+                        })
+                        .flatten_stream(),
+                )
+            }
+        };
+
         Box::new(
-            eth.trace_stream(&logger, subgraph_metrics, from, to, addresses)
+            traces
                 .filter_map(|trace| EthereumCall::try_from_trace(&trace))
                 .filter(move |call| {
-                    // `trace_filter` can only filter by calls `to` an address and
-                    // a block range. Since subgraphs are subscribing to calls
-                    // for a specific contract function an additional filter needs
-                    // to be applied
-                    call_filter.matches(&call)
+                    // The traces contain the calls to all addresses. Since
+                    // subgraphs are subscribing to calls for a specific
+                    // contract function an additional filter needs to be
+                    // applied
+                    addresses.contains(&call.to) && call_filter.matches(&call)
                 }),
         )
     }
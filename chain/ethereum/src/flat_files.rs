@@ -0,0 +1,173 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use graph::prelude::*;
+use serde::Deserialize;
+use web3::types::Trace;
+
+/// The extension of the files that `FlatFileBlockSource` reads
+const EXTENSION: &str = ".jsonl.zst";
+
+/// A block as it is stored in flat files: the block with full transactions,
+/// its transaction receipts, and the traces of all calls made in the block.
+/// Traces can be left out for chains or exports that do not have them, in
+/// which case subgraphs that need calls can not use the flat files.
+#[derive(Deserialize)]
+pub struct FlatBlock {
+    #[serde(flatten)]
+    pub block: EthereumBlock,
+    pub traces: Option<Vec<Trace>>,
+}
+
+/// A file with the blocks `first..=last`
+#[derive(Clone, Debug)]
+struct FlatFile {
+    first: u64,
+    last: u64,
+    path: PathBuf,
+}
+
+impl FlatFile {
+    /// Return `None` if `path` does not look like a flat file
+    fn from_path(path: PathBuf) -> Option<Result<Self, Error>> {
+        let name = path.file_name()?.to_str()?;
+        let range = name.strip_suffix(EXTENSION)?;
+
+        let mut parts = range.splitn(2, '-');
+        let first = parts.next().and_then(|first| first.parse::<u64>().ok());
+        let last = parts.next().and_then(|last| last.parse::<u64>().ok());
+        match (first, last) {
+            (Some(first), Some(last)) if first <= last => Some(Ok(FlatFile { first, last, path })),
+            _ => Some(Err(anyhow!(
+                "flat file name `{}` must have the form `FIRST-LAST{}` with the numbers \
+                 of the first and last block in the file",
+                name,
+                EXTENSION
+            ))),
+        }
+    }
+
+    /// Read the blocks `from..=to` from this file
+    fn blocks(&self, from: u64, to: u64) -> Result<Vec<FlatBlock>, Error> {
+        let file = File::open(&self.path)?;
+        let reader = BufReader::new(zstd::stream::read::Decoder::new(file)?);
+
+        let mut blocks = Vec::new();
+        let mut expected = self.first;
+        for line in reader.lines() {
+            if expected > to {
+                break;
+            }
+            let block: FlatBlock = serde_json::from_str(&line?).map_err(|e| {
+                anyhow!("invalid block in flat file {}: {}", self.path.display(), e)
+            })?;
+            let number = block.block.block.number.map(|number| number.as_u64());
+            if number != Some(expected) {
+                return Err(anyhow!(
+                    "flat file {} has block {:?} where block {} was expected",
+                    self.path.display(),
+                    number,
+                    expected
+                ));
+            }
+            if expected >= from {
+                blocks.push(block);
+            }
+            expected += 1;
+        }
+
+        if expected <= to {
+            return Err(anyhow!(
+                "flat file {} ends before block {}",
+                self.path.display(),
+                expected
+            ));
+        }
+        Ok(blocks)
+    }
+}
+
+/// A source of historical blocks that reads blocks exported from an archive
+/// node from a directory of flat files, so that syncing these blocks does not
+/// need any JSON-RPC requests. Each file is a zstd compressed file with one
+/// JSON-encoded block per line and holds the consecutive blocks given by its
+/// name `FIRST-LAST.jsonl.zst`.
+///
+/// The files must only contain blocks that are final; the block stream only
+/// uses them for blocks beyond the reorg threshold.
+#[derive(Debug)]
+pub struct FlatFileBlockSource {
+    files: Vec<FlatFile>,
+}
+
+impl FlatFileBlockSource {
+    pub fn new(logger: &Logger, dir: &Path) -> Result<Self, Error> {
+        let mut files = fs::read_dir(dir)
+            .map_err(|e| anyhow!("can not read flat files in {}: {}", dir.display(), e))?
+            .map(|entry| entry.map(|entry| entry.path()).map_err(Error::from))
+            .filter_map(|path| match path {
+                Ok(path) => FlatFile::from_path(path),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        files.sort_by_key(|file| file.first);
+
+        for pair in files.windows(2) {
+            if pair[0].last >= pair[1].first {
+                return Err(anyhow!(
+                    "flat files {} and {} overlap",
+                    pair[0].path.display(),
+                    pair[1].path.display()
+                ));
+            }
+        }
+
+        info!(logger, "Using flat files for historical blocks";
+              "dir" => dir.display().to_string(),
+              "files" => files.len(),
+              "first_block" => files.first().map(|file| file.first),
+              "last_block" => files.last().map(|file| file.last));
+
+        Ok(FlatFileBlockSource { files })
+    }
+
+    /// The last block of the gapless range of blocks starting at `from`
+    /// that the files contain, or `None` if there is no file with `from`
+    pub fn last_block(&self, from: u64) -> Option<u64> {
+        let start = self
+            .files
+            .iter()
+            .position(|file| file.first <= from && from <= file.last)?;
+        let mut last = self.files[start].last;
+        for file in &self.files[start + 1..] {
+            if file.first != last + 1 {
+                break;
+            }
+            last = file.last;
+        }
+        Some(last)
+    }
+
+    /// Read the blocks `from..=to`. All of these blocks must be in the
+    /// files, which can be checked with `last_block`. This does blocking IO
+    pub fn blocks(&self, from: u64, to: u64) -> Result<Vec<FlatBlock>, Error> {
+        let mut blocks = Vec::new();
+        let mut next = from;
+        for file in self
+            .files
+            .iter()
+            .filter(|file| file.last >= from && file.first <= to)
+        {
+            if file.first > next {
+                return Err(anyhow!("flat files are missing block {}", next));
+            }
+            blocks.extend(file.blocks(next, to.min(file.last))?);
+            next = file.last + 1;
+        }
+        if next <= to {
+            return Err(anyhow!("flat files are missing block {}", next));
+        }
+        Ok(blocks)
+    }
+}
@@ -3,12 +3,16 @@ extern crate lazy_static;
 
 mod block_ingestor;
 mod block_stream;
+mod chain;
 mod config;
 mod ethereum_adapter;
+mod flat_files;
 pub mod network_indexer;
 mod transport;
 
-pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics, CLEANUP_BLOCKS};
-pub use self::block_stream::{BlockStream, BlockStreamBuilder};
+pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics, ReorgMetrics, CLEANUP_BLOCKS};
+pub use self::block_stream::BlockStream;
+pub use self::chain::{Chain, TriggerFilter, TriggerProcessor, TriggersAdapter};
 pub use self::ethereum_adapter::EthereumAdapter;
+pub use self::flat_files::{FlatBlock, FlatFileBlockSource};
 pub use self::transport::{EventLoopHandle, Transport};
@@ -172,6 +172,7 @@ fn fetch_ommers(
 fn fetch_block_and_ommers_by_number(
     logger: Logger,
     adapter: Arc<dyn EthereumAdapter>,
+    chain_store: Arc<dyn ChainStore>,
     metrics: Arc<NetworkIndexerMetrics>,
     block_number: u64,
 ) -> BlockFuture {
@@ -215,7 +216,7 @@ fn fetch_block_and_ommers_by_number(
                     fetch_full_block,
                     fetch_full_block_problems,
                     adapter_for_full_block
-                        .load_full_block(&logger_for_full_block, block)
+                        .load_full_block(&logger_for_full_block, chain_store, block)
                         .from_err()
                 )
                 .and_then(move |block| {
@@ -253,6 +254,7 @@ fn fetch_block_and_ommers_by_number(
 fn fetch_blocks(context: &Context, block_numbers: Range<u64>) -> BlockStream {
     let logger = context.logger.clone();
     let adapter = context.adapter.clone();
+    let chain_store = context.chain_store.clone();
     let metrics = context.metrics.clone();
 
     Box::new(
@@ -261,6 +263,7 @@ fn fetch_blocks(context: &Context, block_numbers: Range<u64>) -> BlockStream {
                 fetch_block_and_ommers_by_number(
                     logger.clone(),
                     adapter.clone(),
+                    chain_store.clone(),
                     metrics.clone(),
                     block_number,
                 )
@@ -448,6 +451,7 @@ pub struct Context {
     logger: Logger,
     adapter: Arc<dyn EthereumAdapter>,
     store: Arc<dyn NetworkStore>,
+    chain_store: Arc<dyn ChainStore>,
     metrics: Arc<NetworkIndexerMetrics>,
     block_writer: Arc<BlockWriter>,
     event_sink: Sender<NetworkIndexerEvent>,
@@ -1167,6 +1171,7 @@ impl NetworkIndexer {
         let state_machine = StateMachine::start(Context {
             logger,
             adapter,
+            chain_store: store.clone(),
             store,
             metrics,
             block_writer,
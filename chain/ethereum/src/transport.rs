@@ -1,35 +1,311 @@
-use jsonrpc_core::types::Call;
+use futures::sync::oneshot;
+use jsonrpc_core::types::{Call, Id, Output, Request};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
 
 pub use web3::transports::EventLoopHandle;
 use web3::transports::{http, ipc, ws};
 use web3::RequestId;
 
+use graph::components::ethereum::ProviderHealth;
 use graph::prelude::*;
 
 use super::config::ETHEREUM_CONFIG;
 
-/// Abstraction over the different web3 transports.
-#[derive(Clone, Debug)]
-pub enum Transport {
-    RPC(http::Http),
-    IPC(ipc::Ipc),
-    WS(ws::WebSocket),
+type Response = Result<Value, web3::Error>;
+
+/// A connection to an Ethereum node through one of the web3 transports.
+/// Tests use their own connections to check how requests are coalesced
+/// and batched
+trait Connection: fmt::Debug + Send + Sync + 'static {
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call);
+
+    fn send(
+        &self,
+        id: RequestId,
+        request: Call,
+    ) -> Box<dyn Future<Item = Value, Error = web3::Error> + Send>;
+
+    /// Whether the connection can send JSON-RPC batches with `send_batch`
+    fn supports_batches(&self) -> bool {
+        false
+    }
+
+    /// Send `calls` in one JSON-RPC batch. The node may send the outputs
+    /// for the calls in any order; they have to be matched to the calls by
+    /// their `id`
+    fn send_batch(
+        &self,
+        _calls: Vec<Call>,
+    ) -> Box<dyn Future<Item = Vec<Output>, Error = web3::Error> + Send> {
+        Box::new(future::err(web3::Error::Transport(
+            "JSON-RPC batches are only sent over HTTP".to_owned(),
+        )))
+    }
+}
+
+/// JSON-RPC over HTTP. Batches do not go through web3 since it pairs the
+/// outputs in a batch response with the requests by their position
+#[derive(Debug)]
+struct HttpConnection {
+    http: http::Http,
+    /// The URL of the node without any credentials
+    url: reqwest::Url,
+    /// The username and password from the URL
+    basic_auth: Option<(String, Option<String>)>,
+    headers: reqwest::header::HeaderMap,
+    client: reqwest::Client,
+}
+
+impl Connection for HttpConnection {
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        web3::Transport::prepare(&self.http, method, params)
+    }
+
+    fn send(
+        &self,
+        id: RequestId,
+        request: Call,
+    ) -> Box<dyn Future<Item = Value, Error = web3::Error> + Send> {
+        Box::new(web3::Transport::send(&self.http, id, request))
+    }
+
+    fn supports_batches(&self) -> bool {
+        true
+    }
+
+    fn send_batch(
+        &self,
+        calls: Vec<Call>,
+    ) -> Box<dyn Future<Item = Vec<Output>, Error = web3::Error> + Send> {
+        let body = serde_json::to_vec(&Request::Batch(calls))
+            .expect("JSON-RPC requests can be serialized");
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some((username, password)) = &self.basic_auth {
+            request = request.basic_auth(username, password.as_ref());
+        }
+        Box::new(send_batch_request(request).boxed().compat())
+    }
+}
+
+async fn send_batch_request(request: reqwest::RequestBuilder) -> Result<Vec<Output>, web3::Error> {
+    let transport_error = |e: reqwest::Error| web3::Error::Transport(e.to_string());
+    let response = request.send().await.map_err(transport_error)?;
+    if !response.status().is_success() {
+        return Err(web3::Error::Transport(format!(
+            "unexpected status code for JSON-RPC batch: {}",
+            response.status()
+        )));
+    }
+    let body = response.bytes().await.map_err(transport_error)?;
+    match serde_json::from_slice(&body) {
+        Ok(jsonrpc_core::Response::Batch(outputs)) => Ok(outputs),
+        Ok(jsonrpc_core::Response::Single(_)) => Err(web3::Error::Transport(
+            "expected a batch response but got a single response".to_owned(),
+        )),
+        Err(e) => Err(web3::Error::Transport(format!(
+            "invalid JSON-RPC batch response: {}",
+            e
+        ))),
+    }
+}
+
+impl Connection for ipc::Ipc {
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        web3::Transport::prepare(self, method, params)
+    }
+
+    fn send(
+        &self,
+        id: RequestId,
+        request: Call,
+    ) -> Box<dyn Future<Item = Value, Error = web3::Error> + Send> {
+        Box::new(web3::Transport::send(self, id, request))
+    }
+}
+
+impl Connection for ws::WebSocket {
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        web3::Transport::prepare(self, method, params)
+    }
+
+    fn send(
+        &self,
+        id: RequestId,
+        request: Call,
+    ) -> Box<dyn Future<Item = Value, Error = web3::Error> + Send> {
+        Box::new(web3::Transport::send(self, id, request))
+    }
+}
+
+/// Pair the `outputs` of a batch response with the requests with `ids`,
+/// and return the responses in the order of `ids`
+fn match_outputs(ids: &[RequestId], outputs: Vec<Output>) -> Vec<Response> {
+    let mut outputs: HashMap<RequestId, Output> = outputs
+        .into_iter()
+        .filter_map(|output| {
+            let id = match &output {
+                Output::Success(success) => &success.id,
+                Output::Failure(failure) => &failure.id,
+            };
+            match id {
+                Id::Num(id) => Some((*id as RequestId, output)),
+                Id::Str(_) | Id::Null => None,
+            }
+        })
+        .collect();
+    ids.iter()
+        .map(|id| match outputs.remove(id) {
+            Some(Output::Success(success)) => Ok(success.result),
+            Some(Output::Failure(failure)) => Err(web3::Error::Rpc(failure.error)),
+            None => Err(web3::Error::Transport(format!(
+                "batch response has no response for request {}",
+                id
+            ))),
+        })
+        .collect()
+}
+
+/// A request that waits to be sent as part of a batch
+struct Queued {
+    id: RequestId,
+    call: Call,
+    method: String,
+    key: String,
+}
+
+/// The requests that wait to be sent as part of the next batch
+#[derive(Default)]
+struct Queue {
+    /// Incremented whenever a batch is taken from the queue so that the
+    /// timer for a batch can tell whether its batch was sent already
+    generation: u64,
+    requests: Vec<Queued>,
+}
+
+impl Queue {
+    fn take(&mut self) -> Vec<Queued> {
+        self.generation += 1;
+        std::mem::take(&mut self.requests)
+    }
+}
+
+/// The requests that are in flight or waiting to be sent through a
+/// transport. This is shared between all clones of a transport, and
+/// therefore between all subgraphs that use the same Ethereum node.
+struct Requests {
+    /// The maximum number of requests to send in one JSON-RPC batch. With
+    /// a maximum of 1, requests are not batched
+    max_batch_size: usize,
+    /// How long to wait for more requests before a batch that is not full
+    /// is sent
+    batch_window: Duration,
+    /// Callers that wait for the response to a request, keyed by the
+    /// method and params of the request. Identical requests that are made
+    /// while one of them is in flight are only sent once
+    in_flight: Mutex<HashMap<String, Vec<oneshot::Sender<Response>>>>,
+    /// Requests that have not been sent yet because they wait for more
+    /// requests to batch with
+    queue: Mutex<Queue>,
+    metrics: Arc<ProviderEthRpcMetrics>,
+    health: Arc<ProviderHealth>,
+}
+
+/// Abstraction over the different web3 transports. Requests are coalesced
+/// with identical requests that are in flight, and sent in JSON-RPC batches
+/// over HTTP if `ETHEREUM_RPC_MAX_BATCH_SIZE` is greater than 1.
+#[derive(Clone)]
+pub struct Transport {
+    connection: Arc<dyn Connection>,
+    requests: Arc<Requests>,
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.connection.fmt(f)
+    }
 }
 
 impl Transport {
+    fn new(
+        connection: Arc<dyn Connection>,
+        metrics: Arc<ProviderEthRpcMetrics>,
+        health: Arc<ProviderHealth>,
+    ) -> Self {
+        let max_batch_size: usize = env::var_os("ETHEREUM_RPC_MAX_BATCH_SIZE")
+            .map(|s| s.to_str().unwrap().parse().unwrap())
+            .unwrap_or(1);
+        let batch_window: u64 = env::var_os("ETHEREUM_RPC_BATCH_WINDOW")
+            .map(|s| s.to_str().unwrap().parse().unwrap())
+            .unwrap_or(5);
+
+        Self::with_batches(
+            connection,
+            metrics,
+            health,
+            max_batch_size,
+            Duration::from_millis(batch_window),
+        )
+    }
+
+    fn with_batches(
+        connection: Arc<dyn Connection>,
+        metrics: Arc<ProviderEthRpcMetrics>,
+        health: Arc<ProviderHealth>,
+        max_batch_size: usize,
+        batch_window: Duration,
+    ) -> Self {
+        let max_batch_size = if connection.supports_batches() {
+            max_batch_size
+        } else {
+            1
+        };
+
+        Transport {
+            connection,
+            requests: Arc::new(Requests {
+                max_batch_size,
+                batch_window,
+                in_flight: Mutex::new(HashMap::new()),
+                queue: Mutex::new(Queue::default()),
+                metrics,
+                health,
+            }),
+        }
+    }
+
     /// Creates an IPC transport.
-    pub fn new_ipc(ipc: &str) -> (EventLoopHandle, Self) {
+    pub fn new_ipc(
+        ipc: &str,
+        metrics: Arc<ProviderEthRpcMetrics>,
+        health: Arc<ProviderHealth>,
+    ) -> (EventLoopHandle, Self) {
         ipc::Ipc::new(ipc)
-            .map(|(event_loop, transport)| (event_loop, Transport::IPC(transport)))
+            .map(|(event_loop, transport)| {
+                (event_loop, Self::new(Arc::new(transport), metrics, health))
+            })
             .expect("Failed to connect to Ethereum IPC")
     }
 
     /// Creates a WebSocket transport.
-    pub fn new_ws(ws: &str) -> (EventLoopHandle, Self) {
+    pub fn new_ws(
+        ws: &str,
+        metrics: Arc<ProviderEthRpcMetrics>,
+        health: Arc<ProviderHealth>,
+    ) -> (EventLoopHandle, Self) {
         ws::WebSocket::new(ws)
-            .map(|(event_loop, transport)| (event_loop, Transport::WS(transport)))
+            .map(|(event_loop, transport)| {
+                (event_loop, Self::new(Arc::new(transport), metrics, health))
+            })
             .expect("Failed to connect to Ethereum WS")
     }
 
@@ -37,7 +313,11 @@ impl Transport {
     ///
     /// Note: JSON-RPC over HTTP doesn't always support subscribing to new
     /// blocks (one such example is Infura's HTTP endpoint).
-    pub fn new_rpc(rpc: &str) -> (EventLoopHandle, Self) {
+    pub fn new_rpc(
+        rpc: &str,
+        metrics: Arc<ProviderEthRpcMetrics>,
+        health: Arc<ProviderHealth>,
+    ) -> (EventLoopHandle, Self) {
         let max_parallel_http: usize = env::var_os("ETHEREUM_RPC_MAX_PARALLEL_REQUESTS")
             .map(|s| s.to_str().unwrap().parse().unwrap())
             .unwrap_or(64);
@@ -45,29 +325,247 @@ impl Transport {
         let cfg = ETHEREUM_CONFIG.rpc.get(rpc);
         let headers = cfg.map(|cfg| cfg.http_headers.clone()).unwrap_or_default();
 
+        // The headers for batches, which use a newer version of the `http`
+        // crate than web3
+        let mut batch_headers = reqwest::header::HeaderMap::new();
+        for (name, value) in headers.iter() {
+            batch_headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes())
+                    .expect("header names are valid"),
+                reqwest::header::HeaderValue::from_bytes(value.as_bytes())
+                    .expect("header values are valid"),
+            );
+        }
+
+        // Like web3, send the credentials in the URL as basic auth
+        let mut url = reqwest::Url::parse(rpc).expect("Failed to parse Ethereum RPC URL");
+        let basic_auth = if url.username().is_empty() {
+            None
+        } else {
+            Some((url.username().to_owned(), url.password().map(str::to_owned)))
+        };
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+
         http::Http::with_max_parallel_and_headers(rpc, max_parallel_http, headers)
-            .map(|(event_loop, transport)| (event_loop, Transport::RPC(transport)))
+            .map(|(event_loop, http)| {
+                let connection = HttpConnection {
+                    http,
+                    url,
+                    basic_auth,
+                    headers: batch_headers,
+                    client: reqwest::Client::new(),
+                };
+                (event_loop, Self::new(Arc::new(connection), metrics, health))
+            })
             .expect("Failed to connect to Ethereum RPC")
     }
+
+    /// Send `call`, or wait for the response to an identical call that is
+    /// already in flight. The response is sent to `sender`
+    fn enqueue(&self, id: RequestId, call: Call, sender: oneshot::Sender<Response>) {
+        let method_and_key = match &call {
+            Call::MethodCall(method_call) => Some((
+                method_call.method.clone(),
+                format!(
+                    "{}:{}",
+                    method_call.method,
+                    serde_json::to_string(&method_call.params).unwrap_or_default()
+                ),
+            )),
+            Call::Notification(_) | Call::Invalid { .. } => None,
+        };
+
+        // Notifications don't have a response and invalid calls are
+        // rejected by the node; neither is worth coalescing or batching
+        let (method, key) = match method_and_key {
+            Some(method_and_key) => method_and_key,
+            None => {
+                graph::spawn(
+                    self.connection
+                        .send(id, call)
+                        .then(move |result| {
+                            let _ = sender.send(result);
+                            Ok::<_, ()>(())
+                        })
+                        .compat(),
+                );
+                return;
+            }
+        };
+
+        {
+            let mut in_flight = self.requests.in_flight.lock().unwrap();
+            if let Some(waiting) = in_flight.get_mut(&key) {
+                waiting.push(sender);
+                self.requests.metrics.add_coalesced_request(&method);
+                return;
+            }
+            in_flight.insert(key.clone(), vec![sender]);
+        }
+        self.requests.metrics.add_sent_request(&method);
+
+        if self.requests.max_batch_size <= 1 {
+            self.send_one(id, call, key);
+            return;
+        }
+
+        let full_batch = {
+            let mut queue = self.requests.queue.lock().unwrap();
+            queue.requests.push(Queued {
+                id,
+                call,
+                method,
+                key,
+            });
+            if queue.requests.len() >= self.requests.max_batch_size {
+                Some(queue.take())
+            } else {
+                if queue.requests.len() == 1 {
+                    // The first request of a batch; send the batch once the
+                    // batch window has passed, unless it fills up before
+                    let transport = self.clone();
+                    let generation = queue.generation;
+                    let batch_window = self.requests.batch_window;
+                    graph::spawn(async move {
+                        tokio::time::delay_for(batch_window).await;
+                        transport.flush_expired(generation);
+                    });
+                }
+                None
+            }
+        };
+        if let Some(batch) = full_batch {
+            self.flush(batch);
+        }
+    }
+
+    /// Send the batch of `generation` since its batch window has passed,
+    /// unless it filled up and was sent before that
+    fn flush_expired(&self, generation: u64) {
+        let batch = {
+            let mut queue = self.requests.queue.lock().unwrap();
+            if queue.generation != generation {
+                return;
+            }
+            queue.take()
+        };
+        self.flush(batch);
+    }
+
+    fn send_one(&self, id: RequestId, call: Call, key: String) {
+        let transport = self.clone();
+        graph::spawn(
+            self.connection
+                .send(id, call)
+                .then(move |result| {
+                    transport.respond(&key, result);
+                    Ok::<_, ()>(())
+                })
+                .compat(),
+        );
+    }
+
+    fn flush(&self, mut batch: Vec<Queued>) {
+        if batch.is_empty() {
+            return;
+        }
+        if batch.len() == 1 {
+            let Queued { id, call, key, .. } = batch.pop().unwrap();
+            self.send_one(id, call, key);
+            return;
+        }
+
+        self.requests.metrics.observe_batch_size(batch.len());
+        for queued in &batch {
+            self.requests.metrics.add_batched_request(&queued.method);
+        }
+
+        let calls = batch.iter().map(|queued| queued.call.clone()).collect();
+        let transport = self.clone();
+        graph::spawn(
+            self.connection
+                .send_batch(calls)
+                .then(move |result| {
+                    match result {
+                        Ok(outputs) => {
+                            let ids: Vec<_> = batch.iter().map(|queued| queued.id).collect();
+                            let responses = match_outputs(&ids, outputs);
+                            for (queued, response) in batch.into_iter().zip(responses) {
+                                transport.respond(&queued.key, response);
+                            }
+                        }
+                        Err(e) => {
+                            for queued in batch {
+                                transport.respond(&queued.key, Err(clone_error(&e)));
+                            }
+                        }
+                    }
+                    Ok::<_, ()>(())
+                })
+                .compat(),
+        );
+    }
+
+    /// Pass `response` to everybody who waits for the request with `key`
+    fn respond(&self, key: &str, response: Response) {
+        // Errors that the node reports, like reverts, show that the node
+        // works; anything else counts against its health
+        match &response {
+            Ok(_) | Err(web3::Error::Rpc(_)) => self.requests.health.record_success(),
+            Err(_) => self.requests.health.record_error(),
+        }
+
+        let waiting = self
+            .requests
+            .in_flight
+            .lock()
+            .unwrap()
+            .remove(key)
+            .unwrap_or_default();
+        for sender in waiting {
+            let response = match &response {
+                Ok(value) => Ok(value.clone()),
+                Err(e) => Err(clone_error(e)),
+            };
+            // The caller might have given up on the request
+            let _ = sender.send(response);
+        }
+    }
+}
+
+/// `web3::Error` is not `Clone`. Errors reported by the node are kept as
+/// they are since callers look at them, e.g., to detect reverts; all
+/// other errors are turned into transport errors with the same message
+fn clone_error(e: &web3::Error) -> web3::Error {
+    match e {
+        web3::Error::Rpc(rpc_error) => web3::Error::Rpc(rpc_error.clone()),
+        e => web3::Error::Transport(e.to_string()),
+    }
 }
 
 impl web3::Transport for Transport {
     type Out = Box<dyn Future<Item = Value, Error = web3::error::Error> + Send>;
 
     fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
-        match self {
-            Transport::RPC(http) => http.prepare(method, params),
-            Transport::IPC(ipc) => ipc.prepare(method, params),
-            Transport::WS(ws) => ws.prepare(method, params),
-        }
+        self.connection.prepare(method, params)
     }
 
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
-        match self {
-            Transport::RPC(http) => Box::new(http.send(id, request)),
-            Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
-            Transport::WS(ws) => Box::new(ws.send(id, request)),
-        }
+        // Only enqueue the request once the future is polled so that the
+        // background tasks that send it are spawned on the runtime that
+        // polls it
+        let transport = self.clone();
+        Box::new(future::lazy(move || {
+            let (sender, receiver) = oneshot::channel();
+            transport.enqueue(id, request, sender);
+            receiver.then(|result| match result {
+                Ok(response) => response,
+                Err(oneshot::Canceled) => Err(web3::Error::Transport(
+                    "request was dropped before it was sent".to_owned(),
+                )),
+            })
+        }))
     }
 }
 
@@ -81,10 +579,283 @@ impl web3::BatchTransport for Transport {
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
-        match self {
-            Transport::RPC(http) => Box::new(http.send_batch(requests)),
-            Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
-            Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+        // Explicit batches are sent as they are; the requests in them still
+        // count as sent requests
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        for (_, call) in &requests {
+            if let Call::MethodCall(method_call) = call {
+                self.requests.metrics.add_sent_request(&method_call.method);
+            }
+        }
+
+        if self.connection.supports_batches() {
+            let (ids, calls): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+            Box::new(
+                self.connection
+                    .send_batch(calls)
+                    .map(move |outputs| match_outputs(&ids, outputs)),
+            )
+        } else {
+            // Connections that can not send batches are multiplexed, and
+            // sending the requests one by one costs little
+            let responses = requests
+                .into_iter()
+                .map(|(id, call)| {
+                    self.connection
+                        .send(id, call)
+                        .then(|response| Ok::<_, web3::Error>(response))
+                })
+                .collect::<Vec<_>>();
+            Box::new(future::join_all(responses))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::ethereum::ProviderLimits;
+    use graph::components::metrics::Registry;
+    use graph_core::MetricsRegistry;
+    use jsonrpc_core::types::{Failure, MethodCall, Params, Success, Version};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A connection that answers each request with its first param, or
+    /// with an RPC error for `eth_fail`, and records the ids of the
+    /// requests it sends
+    #[derive(Debug, Default)]
+    struct MockConnection {
+        next_id: AtomicUsize,
+        /// The ids of the requests in each single request or batch that
+        /// was sent
+        sent: Mutex<Vec<Vec<RequestId>>>,
+        /// Return the outputs of a batch in the reverse order of the calls
+        reverse: bool,
+        /// Fail every request with a transport error
+        fail: bool,
+    }
+
+    impl MockConnection {
+        fn sent(&self) -> Vec<Vec<RequestId>> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        fn answer(&self, call: &Call) -> Output {
+            match call {
+                Call::MethodCall(MethodCall { method, id, .. }) if method == "eth_fail" => {
+                    Output::Failure(Failure {
+                        jsonrpc: Some(Version::V2),
+                        error: jsonrpc_core::Error::internal_error(),
+                        id: id.clone(),
+                    })
+                }
+                Call::MethodCall(MethodCall {
+                    params: Params::Array(params),
+                    id,
+                    ..
+                }) => Output::Success(Success {
+                    jsonrpc: Some(Version::V2),
+                    result: params[0].clone(),
+                    id: id.clone(),
+                }),
+                _ => panic!("unexpected call {:?}", call),
+            }
+        }
+    }
+
+    impl Connection for MockConnection {
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+            let call = Call::MethodCall(MethodCall {
+                jsonrpc: Some(Version::V2),
+                method: method.to_owned(),
+                params: Params::Array(params),
+                id: Id::Num(id as u64),
+            });
+            (id, call)
+        }
+
+        fn send(
+            &self,
+            id: RequestId,
+            request: Call,
+        ) -> Box<dyn Future<Item = Value, Error = web3::Error> + Send> {
+            self.sent.lock().unwrap().push(vec![id]);
+            if self.fail {
+                return Box::new(future::err(web3::Error::Transport(
+                    "node is down".to_owned(),
+                )));
+            }
+            Box::new(future::result(
+                match_outputs(&[id], vec![self.answer(&request)])
+                    .pop()
+                    .unwrap(),
+            ))
+        }
+
+        fn supports_batches(&self) -> bool {
+            true
         }
+
+        fn send_batch(
+            &self,
+            calls: Vec<Call>,
+        ) -> Box<dyn Future<Item = Vec<Output>, Error = web3::Error> + Send> {
+            let ids = calls
+                .iter()
+                .map(|call| match call {
+                    Call::MethodCall(MethodCall {
+                        id: Id::Num(id), ..
+                    }) => *id as RequestId,
+                    _ => panic!("unexpected call {:?}", call),
+                })
+                .collect();
+            self.sent.lock().unwrap().push(ids);
+            if self.fail {
+                return Box::new(future::err(web3::Error::Transport(
+                    "node is down".to_owned(),
+                )));
+            }
+            let mut outputs: Vec<_> = calls.iter().map(|call| self.answer(call)).collect();
+            if self.reverse {
+                outputs.reverse();
+            }
+            Box::new(future::ok(outputs))
+        }
+    }
+
+    fn transport(connection: Arc<MockConnection>, max_batch_size: usize) -> Transport {
+        let logger = Logger::root(slog::Discard, o!());
+        let registry = Arc::new(MetricsRegistry::new(logger, Arc::new(Registry::new())));
+        let metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
+        let health = Arc::new(ProviderHealth::new(
+            "mock".to_owned(),
+            ProviderLimits::default(),
+        ));
+        // Batches that are not full are only sent when a test flushes them
+        Transport::with_batches(
+            connection,
+            metrics,
+            health,
+            max_batch_size,
+            Duration::from_secs(3600),
+        )
+    }
+
+    fn request(transport: &Transport, method: &str, param: &str) -> oneshot::Receiver<Response> {
+        let (id, call) = web3::Transport::prepare(transport, method, vec![Value::from(param)]);
+        let (sender, receiver) = oneshot::channel();
+        transport.enqueue(id, call, sender);
+        receiver
+    }
+
+    async fn response(receiver: oneshot::Receiver<Response>) -> Response {
+        receiver.compat().await.expect("the request was answered")
+    }
+
+    #[tokio::test]
+    async fn identical_requests_are_coalesced() {
+        let connection = Arc::new(MockConnection::default());
+        let transport = transport(connection.clone(), 1);
+
+        let a1 = request(&transport, "eth_call", "a");
+        let a2 = request(&transport, "eth_call", "a");
+        let b = request(&transport, "eth_call", "b");
+        assert_eq!(vec![vec![0], vec![2]], connection.sent());
+
+        assert_eq!(Value::from("a"), response(a1).await.unwrap());
+        assert_eq!(Value::from("a"), response(a2).await.unwrap());
+        assert_eq!(Value::from("b"), response(b).await.unwrap());
+
+        // Once the response is in, the same request is sent again
+        let a3 = request(&transport, "eth_call", "a");
+        assert_eq!(Value::from("a"), response(a3).await.unwrap());
+        assert_eq!(vec![vec![0], vec![2], vec![3]], connection.sent());
+    }
+
+    #[tokio::test]
+    async fn batch_responses_are_matched_by_id() {
+        let connection = Arc::new(MockConnection {
+            reverse: true,
+            ..Default::default()
+        });
+        let transport = transport(connection.clone(), 3);
+
+        let a = request(&transport, "eth_call", "a");
+        let b = request(&transport, "eth_fail", "b");
+        assert!(connection.sent().is_empty());
+        let c = request(&transport, "eth_call", "c");
+        assert_eq!(vec![vec![0, 1, 2]], connection.sent());
+
+        assert_eq!(Value::from("a"), response(a).await.unwrap());
+        match response(b).await {
+            Err(web3::Error::Rpc(_)) => (),
+            other => panic!("expected an RPC error but got {:?}", other),
+        }
+        assert_eq!(Value::from("c"), response(c).await.unwrap());
+
+        // Explicit batches return responses in the order of the requests
+        let requests: Vec<_> = ["x", "y", "z"]
+            .iter()
+            .map(|param| {
+                web3::Transport::prepare(&transport, "eth_call", vec![Value::from(*param)])
+            })
+            .collect();
+        let responses = web3::BatchTransport::send_batch(&transport, requests)
+            .compat()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![Value::from("x"), Value::from("y"), Value::from("z")],
+            responses
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_reach_all_callers() {
+        let connection = Arc::new(MockConnection {
+            fail: true,
+            ..Default::default()
+        });
+        let transport = transport(connection.clone(), 2);
+
+        let a1 = request(&transport, "eth_call", "a");
+        let a2 = request(&transport, "eth_call", "a");
+        let b = request(&transport, "eth_call", "b");
+        assert_eq!(vec![vec![0, 2]], connection.sent());
+
+        for receiver in vec![a1, a2, b] {
+            match response(receiver).await {
+                Err(web3::Error::Transport(_)) => (),
+                other => panic!("expected a transport error but got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_window_only_flushes_its_own_batch() {
+        let connection = Arc::new(MockConnection::default());
+        let transport = transport(connection.clone(), 2);
+
+        // The first batch fills up and is sent right away
+        let a = request(&transport, "eth_call", "a");
+        let b = request(&transport, "eth_call", "b");
+        let c = request(&transport, "eth_call", "c");
+        assert_eq!(vec![vec![0, 1]], connection.sent());
+
+        // When the batch window of the first batch passes, the second
+        // batch is left alone
+        transport.flush_expired(0);
+        assert_eq!(vec![vec![0, 1]], connection.sent());
+
+        transport.flush_expired(1);
+        assert_eq!(vec![vec![0, 1], vec![2]], connection.sent());
+
+        assert_eq!(Value::from("a"), response(a).await.unwrap());
+        assert_eq!(Value::from("b"), response(b).await.unwrap());
+        assert_eq!(Value::from("c"), response(c).await.unwrap());
     }
 }
@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::PathBuf;
+
+use graph::prelude::*;
+use graph_chain_ethereum::FlatFileBlockSource;
+
+/// Create a fresh directory for the flat files of a test
+fn flat_files_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("graph-flat-files-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write a flat file with the (empty) blocks `first..=last`
+fn write_flat_file(dir: &PathBuf, first: u64, last: u64) {
+    let lines: Vec<String> = (first..=last)
+        .map(|number| {
+            let mut block = EthereumBlock::default();
+            block.block.number = Some(number.into());
+            serde_json::to_string(&block).unwrap()
+        })
+        .collect();
+    let compressed = zstd::encode_all(lines.join("\n").as_bytes(), 0).unwrap();
+    fs::write(
+        dir.join(format!("{}-{}.jsonl.zst", first, last)),
+        compressed,
+    )
+    .unwrap();
+}
+
+fn numbers(source: &FlatFileBlockSource, from: u64, to: u64) -> Vec<u64> {
+    source
+        .blocks(from, to)
+        .unwrap()
+        .into_iter()
+        .map(|block| block.block.block.number.unwrap().as_u64())
+        .collect()
+}
+
+#[test]
+fn read_blocks_across_files() {
+    let dir = flat_files_dir("read");
+    write_flat_file(&dir, 0, 9);
+    write_flat_file(&dir, 10, 19);
+    write_flat_file(&dir, 30, 39);
+    fs::write(dir.join("README"), "not a flat file").unwrap();
+
+    let source = FlatFileBlockSource::new(&Logger::root(slog::Discard, o!()), &dir).unwrap();
+
+    assert_eq!(Some(19), source.last_block(0));
+    assert_eq!(Some(19), source.last_block(15));
+    assert_eq!(None, source.last_block(20));
+    assert_eq!(Some(39), source.last_block(30));
+
+    assert_eq!(vec![3, 4, 5], numbers(&source, 3, 5));
+    assert_eq!((8..=12).collect::<Vec<_>>(), numbers(&source, 8, 12));
+    assert!(source.blocks(18, 31).is_err());
+}
+
+#[test]
+fn reject_overlapping_files() {
+    let dir = flat_files_dir("overlap");
+    write_flat_file(&dir, 0, 9);
+    write_flat_file(&dir, 5, 14);
+
+    assert!(FlatFileBlockSource::new(&Logger::root(slog::Discard, o!()), &dir).is_err());
+}
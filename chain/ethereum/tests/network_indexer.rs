@@ -252,7 +252,7 @@ fn create_mock_ethereum_adapter(
     let chains_for_load_full_block = chains.clone();
     adapter
         .expect_load_full_block()
-        .returning(move |_, block: LightEthereumBlock| {
+        .returning(move |_, _, block: LightEthereumBlock| {
             let chains = chains_for_load_full_block.lock().unwrap();
             Box::new(future::result(
                 chains
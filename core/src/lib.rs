@@ -6,5 +6,6 @@ pub mod three_box;
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{
-    DataSourceLoader, SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar,
+    ChainRunner, DataSourceLoader, SharedInstanceKeepAliveMap, SubgraphAssignmentProvider,
+    SubgraphInstance, SubgraphInstanceManager, SubgraphRegistrar, SubgraphRunner,
 };
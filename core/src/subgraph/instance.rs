@@ -1,14 +1,14 @@
-use async_trait::async_trait;
 use futures01::sync::mpsc::Sender;
 use lazy_static::lazy_static;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::str::FromStr;
 
+use graph::blockchain::TriggerProcessor;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
-use web3::types::{Log, H256};
 
 lazy_static! {
     static ref MAX_DATA_SOURCES: Option<usize> = env::var("GRAPH_SUBGRAPH_MAX_DATA_SOURCES")
@@ -20,6 +20,7 @@ lazy_static! {
 pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     subgraph_id: SubgraphDeploymentId,
     network: String,
+    features: BTreeSet<SubgraphFeature>,
     host_builder: T,
 
     /// Runtime hosts, one for each data source mapping.
@@ -37,7 +38,7 @@ impl<T> SubgraphInstance<T>
 where
     T: RuntimeHostBuilder,
 {
-    pub(crate) fn from_manifest(
+    pub fn from_manifest(
         logger: &Logger,
         manifest: SubgraphManifest,
         host_builder: T,
@@ -45,12 +46,14 @@ where
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
+        let features = manifest.features;
         let templates = Arc::new(manifest.templates);
 
         let mut this = SubgraphInstance {
             host_builder,
             subgraph_id,
             network,
+            features,
             hosts: Vec::new(),
             module_cache: HashMap::new(),
         };
@@ -83,6 +86,113 @@ where
         Ok(this)
     }
 
+    /// Split the hosts into groups such that the hosts in different groups
+    /// declare disjoint sets of entity types. Each host is listed with its
+    /// position in `hosts`, and groups are ordered by their first host
+    pub(crate) fn independent_hosts(&self) -> Vec<Vec<(usize, Arc<T::Host>)>> {
+        let mut groups: Vec<(BTreeSet<&str>, Vec<usize>)> = Vec::new();
+        for (i, host) in self.hosts.iter().enumerate() {
+            let mut types: BTreeSet<&str> =
+                host.entity_types().iter().map(String::as_str).collect();
+            let mut members = vec![i];
+
+            // Merge all groups that share an entity type with this host
+            let mut rest = Vec::with_capacity(groups.len());
+            for (group_types, group_members) in groups {
+                if group_types.is_disjoint(&types) {
+                    rest.push((group_types, group_members));
+                } else {
+                    types.extend(group_types);
+                    members.extend(group_members);
+                }
+            }
+            members.sort();
+            rest.push((types, members));
+            groups = rest;
+        }
+        groups.sort_by_key(|(_, members)| members[0]);
+
+        groups
+            .into_iter()
+            .map(|(_, members)| {
+                members
+                    .into_iter()
+                    .map(|i| (i, self.hosts[i].cheap_clone()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Run the handlers of all hosts that match `trigger`
+    pub async fn process_trigger<P>(
+        &self,
+        processor: &P,
+        logger: &Logger,
+        block: &Arc<P::MappingBlock>,
+        trigger: &P::TriggerData,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>
+    where
+        P: TriggerProcessor<T::Host>,
+    {
+        Self::process_trigger_in_hosts(
+            processor,
+            logger,
+            &self.hosts,
+            block,
+            trigger,
+            state,
+            proof_of_indexing,
+        )
+        .await
+    }
+
+    /// Like `process_trigger` but only runs the handlers of `hosts`. The
+    /// hosts that match `trigger` process it in the order in which they
+    /// appear in `hosts`
+    pub async fn process_trigger_in_hosts<P>(
+        processor: &P,
+        logger: &Logger,
+        hosts: &[Arc<T::Host>],
+        block: &Arc<P::MappingBlock>,
+        trigger: &P::TriggerData,
+        mut state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>
+    where
+        P: TriggerProcessor<T::Host>,
+    {
+        let matching_hosts = hosts
+            .iter()
+            .filter(|host| processor.matches(host.as_ref(), trigger));
+        let hosts_count = matching_hosts.clone().count();
+
+        if hosts_count > 1 {
+            info!(
+                logger,
+                "{} matching runtime hosts found for {} trigger", hosts_count, trigger.kind();
+                "trigger" => trigger.error_context().unwrap_or_default(),
+            );
+        }
+
+        for (i, host) in matching_hosts.enumerate() {
+            let host_context = format!("{}/{}", i + 1, hosts_count);
+            let logger = logger.new(o!("runtime_host" => host_context));
+            state = processor
+                .process_trigger(
+                    &logger,
+                    host.as_ref(),
+                    block,
+                    trigger,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await?;
+        }
+        Ok(state)
+    }
+
     fn new_host(
         &mut self,
         logger: Logger,
@@ -109,6 +219,7 @@ where
         self.host_builder.build(
             self.network.clone(),
             self.subgraph_id.clone(),
+            &self.features,
             data_source,
             templates,
             mapping_request_sender,
@@ -117,150 +228,10 @@ where
     }
 }
 
-#[async_trait]
 impl<T> SubgraphInstanceTrait<T::Host> for SubgraphInstance<T>
 where
     T: RuntimeHostBuilder,
 {
-    /// Returns true if the subgraph has a handler for an Ethereum event.
-    fn matches_log(&self, log: &Log) -> bool {
-        self.hosts.iter().any(|host| host.matches_log(log))
-    }
-
-    async fn process_trigger(
-        &self,
-        logger: &Logger,
-        block: &Arc<LightEthereumBlock>,
-        trigger: EthereumTrigger,
-        state: BlockState,
-        proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, MappingError> {
-        Self::process_trigger_in_runtime_hosts(
-            logger,
-            &self.hosts,
-            block,
-            trigger,
-            state,
-            proof_of_indexing,
-        )
-        .await
-    }
-
-    async fn process_trigger_in_runtime_hosts(
-        logger: &Logger,
-        hosts: &[Arc<T::Host>],
-        block: &Arc<LightEthereumBlock>,
-        trigger: EthereumTrigger,
-        mut state: BlockState,
-        proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, MappingError> {
-        match trigger {
-            EthereumTrigger::Log(log) => {
-                let log = Arc::new(log);
-
-                let transaction = block
-                    .transaction_for_log(&log)
-                    .map(Arc::new)
-                    .context("Found no transaction for event")?;
-                let matching_hosts = hosts.iter().filter(|host| host.matches_log(&log));
-                let hosts_count = matching_hosts.clone().count();
-
-                if hosts_count > 1 {
-                    info!(
-                        logger,
-                        "{} matching runtime hosts found for log trigger.", hosts_count;
-                        "address" => &log.address.to_string(),
-                        "topic0" => &log.topics.iter().next().unwrap_or(&H256::zero()).to_string(),
-                        "transaction" => log.transaction_hash.unwrap_or(H256::zero()).to_string(),
-                    );
-                }
-
-                // Process the log in each host in the same order the corresponding data
-                // sources appear in the subgraph manifest
-                let transaction = Arc::new(transaction);
-                for (i, host) in matching_hosts.enumerate() {
-                    let host_context = format!("{}/{}", i + 1, hosts_count);
-                    let logger = logger.new(o!("runtime_host" => host_context));
-                    state = host
-                        .process_log(
-                            &logger,
-                            block,
-                            &transaction,
-                            &log,
-                            state,
-                            proof_of_indexing.cheap_clone(),
-                        )
-                        .await?;
-                }
-            }
-            EthereumTrigger::Call(call) => {
-                let call = Arc::new(call);
-
-                let transaction = block
-                    .transaction_for_call(&call)
-                    .context("Found no transaction for call")?;
-                let transaction = Arc::new(transaction);
-                let matching_hosts = hosts.iter().filter(|host| host.matches_call(&call));
-                let hosts_count = matching_hosts.clone().count();
-
-                if hosts_count > 1 {
-                    info!(
-                        logger,
-                        "{} matching runtime hosts found for call trigger.", hosts_count;
-                        "from" => &call.from.to_string(),
-                        "to" => &call.to.to_string(),
-                        "transaction" => &call.transaction_hash.map(|hash| hash.to_string()).unwrap_or("unkown".to_string()),
-                    );
-                }
-
-                for (i, host) in matching_hosts.enumerate() {
-                    let host_context = format!("{}/{}", i + 1, hosts_count);
-                    let logger = logger.new(o!("runtime_host" => host_context));
-                    state = host
-                        .process_call(
-                            &logger,
-                            block,
-                            &transaction,
-                            &call,
-                            state,
-                            proof_of_indexing.cheap_clone(),
-                        )
-                        .await?;
-                }
-            }
-            EthereumTrigger::Block(ptr, trigger_type) => {
-                let matching_hosts = hosts
-                    .iter()
-                    .filter(|host| host.matches_block(&trigger_type, ptr.number));
-                let hosts_count = matching_hosts.clone().count();
-
-                if hosts_count > 1 {
-                    info!(
-                        logger,
-                        "{} matching runtime hosts found for block trigger.", hosts_count;
-                        "number" => &ptr.number,
-                        "hash" => &ptr.number,
-                    );
-                }
-
-                for (i, host) in matching_hosts.enumerate() {
-                    let host_context = format!("{}/{}", i + 1, hosts_count);
-                    let logger = logger.new(o!("runtime_host" => host_context));
-                    state = host
-                        .process_block(
-                            &logger,
-                            block,
-                            &trigger_type,
-                            state,
-                            proof_of_indexing.cheap_clone(),
-                        )
-                        .await?;
-                }
-            }
-        }
-        Ok(state)
-    }
-
     fn add_dynamic_data_source(
         &mut self,
         logger: &Logger,
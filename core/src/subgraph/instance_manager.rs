@@ -1,17 +1,18 @@
+use async_trait::async_trait;
 use atomic_refcell::AtomicRefCell;
 use fail::fail_point;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-use graph::components::store::ModificationsAndCache;
-use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
-use graph::components::{
-    ethereum::{triggers_in_block, EthereumNetworks},
-    store::EntityType,
+use graph::blockchain::{
+    BlockPtr, BlockStreamEvent, BlockStreamMetrics, BlockWithTriggers, Blockchain, TriggerData,
+    TriggerProcessor,
 };
+use graph::components::store::{EntityType, ModificationsAndCache};
+use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, SubgraphError, POI_OBJECT,
@@ -37,36 +38,33 @@ lazy_static! {
         std::env::var("GRAPH_DISABLE_FAIL_FAST").is_ok();
 }
 
-type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
+pub type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
-struct IndexingInputs<B, S> {
+struct IndexingInputs<C: Blockchain, S> {
     deployment_id: SubgraphDeploymentId,
     features: BTreeSet<SubgraphFeature>,
-    network_name: String,
-    start_blocks: Vec<u64>,
+    start_blocks: Vec<BlockNumber>,
     store: Arc<S>,
-    eth_adapter: Arc<dyn EthereumAdapter>,
-    stream_builder: B,
-    include_calls_in_blocks: bool,
+    chain: Arc<C>,
+    triggers_adapter: Arc<C::TriggersAdapter>,
+    trigger_processor: Arc<C::TriggerProcessor>,
     templates: Arc<Vec<DataSourceTemplate>>,
 }
 
-struct IndexingState<T: RuntimeHostBuilder> {
+struct IndexingState<C: Blockchain, T: RuntimeHostBuilder> {
     logger: Logger,
     instance: SubgraphInstance<T>,
     instances: SharedInstanceKeepAliveMap,
-    log_filter: EthereumLogFilter,
-    call_filter: EthereumCallFilter,
-    block_filter: EthereumBlockFilter,
+    filter: C::TriggerFilter,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
 }
 
-struct IndexingContext<B, T: RuntimeHostBuilder, S> {
+struct IndexingContext<C: Blockchain, T: RuntimeHostBuilder, S> {
     /// Read only inputs that are needed while indexing a subgraph.
-    pub inputs: IndexingInputs<B, S>,
+    pub inputs: IndexingInputs<C, S>,
 
     /// Mutable state that may be modified while indexing a subgraph.
-    pub state: IndexingState<T>,
+    pub state: IndexingState<C, T>,
 
     /// Sensors to measure the execution of the subgraph instance
     pub subgraph_metrics: Arc<SubgraphInstanceMetrics>,
@@ -74,9 +72,6 @@ struct IndexingContext<B, T: RuntimeHostBuilder, S> {
     /// Sensors to measure the execution of the subgraph's runtime hosts
     pub host_metrics: Arc<HostMetrics>,
 
-    /// Sensors to measure the execution of eth rpc calls
-    pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
-
     pub block_stream_metrics: Arc<BlockStreamMetrics>,
 }
 
@@ -102,22 +97,6 @@ impl SubgraphInstanceManagerMetrics {
     }
 }
 
-enum TriggerType {
-    Event,
-    Call,
-    Block,
-}
-
-impl TriggerType {
-    fn label_value(&self) -> &str {
-        match self {
-            TriggerType::Event => "event",
-            TriggerType::Call => "call",
-            TriggerType::Block => "block",
-        }
-    }
-}
-
 struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
@@ -170,9 +149,9 @@ impl SubgraphInstanceMetrics {
         }
     }
 
-    pub fn observe_trigger_processing_duration(&self, duration: f64, trigger: TriggerType) {
+    pub fn observe_trigger_processing_duration(&self, duration: f64, trigger_kind: &str) {
         self.trigger_processing_duration
-            .with_label_values(vec![trigger.label_value()].as_slice())
+            .with_label_values(vec![trigger_kind].as_slice())
             .observe(duration);
     }
 
@@ -185,18 +164,14 @@ impl SubgraphInstanceMetrics {
 }
 
 impl SubgraphInstanceManager {
-    /// Creates a new runtime manager.
-    pub fn new<B, S, M>(
+    /// Creates a new runtime manager that runs subgraphs on `chains`, which
+    /// are keyed by the name of their network.
+    pub fn new<M>(
         logger_factory: &LoggerFactory,
-        stores: HashMap<String, Arc<S>>,
-        eth_networks: EthereumNetworks,
-        host_builder: impl RuntimeHostBuilder,
-        block_stream_builder: B,
+        chains: HashMap<String, Arc<dyn SubgraphRunner>>,
         metrics_registry: Arc<M>,
     ) -> Self
     where
-        S: Store + ChainStore + EthereumCallCache,
-        B: BlockStreamBuilder,
         M: MetricsRegistry,
     {
         let logger = logger_factory.component_logger("SubgraphInstanceManager", None);
@@ -206,15 +181,7 @@ impl SubgraphInstanceManager {
         let (subgraph_sender, subgraph_receiver) = channel(100);
 
         // Handle incoming events from the subgraph provider.
-        Self::handle_subgraph_events(
-            logger_factory,
-            subgraph_receiver,
-            stores,
-            eth_networks,
-            host_builder,
-            block_stream_builder,
-            metrics_registry.clone(),
-        );
+        Self::handle_subgraph_events(logger_factory, subgraph_receiver, chains, metrics_registry);
 
         SubgraphInstanceManager {
             logger,
@@ -223,22 +190,15 @@ impl SubgraphInstanceManager {
     }
 
     /// Handle incoming events from subgraph providers.
-    fn handle_subgraph_events<B, S, M>(
+    fn handle_subgraph_events<M>(
         logger_factory: LoggerFactory,
         receiver: Receiver<SubgraphAssignmentProviderEvent>,
-        stores: HashMap<String, Arc<S>>,
-        eth_networks: EthereumNetworks,
-        host_builder: impl RuntimeHostBuilder,
-        block_stream_builder: B,
+        chains: HashMap<String, Arc<dyn SubgraphRunner>>,
         metrics_registry: Arc<M>,
     ) where
-        S: Store + ChainStore + EthereumCallCache,
-        B: BlockStreamBuilder,
         M: MetricsRegistry,
     {
-        let metrics_registry_for_manager = metrics_registry.clone();
-        let metrics_registry_for_subgraph = metrics_registry.clone();
-        let manager_metrics = SubgraphInstanceManagerMetrics::new(metrics_registry_for_manager);
+        let manager_metrics = SubgraphInstanceManagerMetrics::new(metrics_registry);
 
         // Subgraph instance shutdown senders
         let instances: SharedInstanceKeepAliveMap = Default::default();
@@ -261,18 +221,18 @@ impl SubgraphInstanceManager {
                         );
                         let network = manifest.network_name();
 
-                        match Self::start_subgraph(
-                            logger.clone(),
-                            instances.clone(),
-                            host_builder.clone(),
-                            block_stream_builder.clone(),
-                            stores.get(&network).cloned(),
-                            &eth_networks,
-                            manifest,
-                            metrics_registry_for_subgraph.clone(),
-                        )
-                        .await
-                        {
+                        let res = match chains.get(&network) {
+                            Some(runner) => {
+                                runner
+                                    .start_subgraph(logger.clone(), instances.clone(), manifest)
+                                    .await
+                            }
+                            None => Err(anyhow!(
+                                "expected chain that matches subgraph network: {}",
+                                &network
+                            )),
+                        };
+                        match res {
                             Ok(()) => manager_metrics.subgraph_count.inc(),
                             Err(err) => error!(
                                 logger,
@@ -294,50 +254,92 @@ impl SubgraphInstanceManager {
         });
     }
 
-    async fn start_subgraph<B, S, M>(
+    fn stop_subgraph(instances: SharedInstanceKeepAliveMap, id: SubgraphDeploymentId) {
+        // Drop the cancel guard to shut down the subgraph now
+        let mut instances = instances.write().unwrap();
+        instances.remove(&id);
+    }
+}
+
+/// Runs subgraphs on one chain. The instance manager holds a runner for
+/// each network, which hides the type of the chain and lets it run
+/// subgraphs on chains of different kinds side by side.
+#[async_trait]
+pub trait SubgraphRunner: Send + Sync + 'static {
+    /// Start indexing the subgraph `manifest`. The subgraph runs until the
+    /// cancel guard that is kept for it in `instances` is dropped.
+    async fn start_subgraph(
+        &self,
         logger: Logger,
         instances: SharedInstanceKeepAliveMap,
-        host_builder: impl RuntimeHostBuilder,
-        stream_builder: B,
-        store: Option<Arc<S>>,
-        eth_networks: &EthereumNetworks,
         manifest: SubgraphManifest,
-        registry: Arc<M>,
-    ) -> Result<(), Error>
-    where
-        B: BlockStreamBuilder,
-        S: Store + ChainStore + EthereumCallCache,
-        M: MetricsRegistry,
-    {
-        let required_capabilities = manifest.required_ethereum_capabilities();
-        let network = manifest.network_name();
+    ) -> Result<(), Error>;
+}
+
+/// Runs subgraphs on `chain` with the runtime hosts that `host_builder`
+/// creates. The chain's trigger processor decides which hosts a trigger
+/// matches and how the hosts process it.
+pub struct ChainRunner<C, S, T, M> {
+    chain: Arc<C>,
+    store: Arc<S>,
+    host_builder: T,
+    metrics_registry: Arc<M>,
+}
 
-        let store = store
-            .ok_or_else(|| anyhow!("expected store that matches subgraph network: {}", &network))?;
+impl<C, S, T, M> ChainRunner<C, S, T, M> {
+    pub fn new(chain: Arc<C>, store: Arc<S>, host_builder: T, metrics_registry: Arc<M>) -> Self {
+        Self {
+            chain,
+            store,
+            host_builder,
+            metrics_registry,
+        }
+    }
+}
 
-        let eth_adapter = eth_networks
-            .adapter_with_capabilities(network.clone(), &required_capabilities).map_err(|e|
-                anyhow!(
-                "expected eth adapter that matches subgraph network {} with required capabilities: {}: {}",
-                &network,
-                &required_capabilities, e))?.clone();
+#[async_trait]
+impl<C, S, T, M> SubgraphRunner for ChainRunner<C, S, T, M>
+where
+    C: Blockchain,
+    C::TriggerProcessor: TriggerProcessor<T::Host, Block = C::Block, TriggerData = C::TriggerData>,
+    S: Store,
+    T: RuntimeHostBuilder,
+    M: MetricsRegistry,
+{
+    async fn start_subgraph(
+        &self,
+        logger: Logger,
+        instances: SharedInstanceKeepAliveMap,
+        manifest: SubgraphManifest,
+    ) -> Result<(), Error> {
+        let store = self.store.cheap_clone();
+        let chain = self.chain.cheap_clone();
+        let registry = self.metrics_registry.cheap_clone();
 
         store.start_subgraph_deployment(&logger, &manifest.id)?;
 
         // Clone the deployment ID for later
         let deployment_id = manifest.id.clone();
-        let network_name = manifest.network_name();
 
-        // Obtain filters from the manifest
-        let log_filter = EthereumLogFilter::from_data_sources(&manifest.data_sources);
-        let call_filter = EthereumCallFilter::from_data_sources(&manifest.data_sources);
-        let block_filter = EthereumBlockFilter::from_data_sources(&manifest.data_sources);
-        let start_blocks = manifest.start_blocks();
-
-        // Identify whether there are mappings with call handlers or
-        // block handlers with call filters; in this case, we need to
-        // include calls in all blocks
-        let include_calls_in_blocks = manifest.requires_traces();
+        // Obtain the trigger filter and the capabilities that the chain
+        // needs from the data sources in the manifest
+        let data_sources = manifest
+            .data_sources
+            .iter()
+            .map(C::DataSource::from_manifest)
+            .collect::<Result<Vec<_>, _>>()?;
+        let chain_templates = manifest
+            .templates
+            .iter()
+            .map(C::DataSourceTemplate::from_manifest)
+            .collect::<Result<Vec<_>, _>>()?;
+        let filter = C::TriggerFilter::from_data_sources(data_sources.iter());
+        let capabilities = C::NodeCapabilities::from_data_sources(&data_sources, &chain_templates);
+        let start_blocks = manifest
+            .start_blocks()
+            .into_iter()
+            .map(|block| block as BlockNumber)
+            .collect();
 
         let templates = Arc::new(manifest.templates.clone());
 
@@ -355,42 +357,42 @@ impl SubgraphInstanceManager {
             deployment_id.as_str(),
             stopwatch_metrics.clone(),
         ));
-        let ethrpc_metrics = Arc::new(SubgraphEthRpcMetrics::new(registry.clone(), &deployment_id));
         let block_stream_metrics = Arc::new(BlockStreamMetrics::new(
             registry.clone(),
-            ethrpc_metrics.clone(),
             &deployment_id,
             stopwatch_metrics,
         ));
+        let triggers_adapter = chain.triggers_adapter(&deployment_id, &capabilities)?;
+        let trigger_processor = chain.trigger_processor();
         let features = manifest.features.clone();
-        let instance =
-            SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        let instance = SubgraphInstance::from_manifest(
+            &logger,
+            manifest,
+            self.host_builder.clone(),
+            host_metrics.clone(),
+        )?;
 
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
             inputs: IndexingInputs {
                 deployment_id: deployment_id.clone(),
                 features,
-                network_name,
                 start_blocks,
                 store,
-                eth_adapter,
-                stream_builder,
-                include_calls_in_blocks,
+                chain,
+                triggers_adapter,
+                trigger_processor,
                 templates,
             },
             state: IndexingState {
                 logger,
                 instance,
                 instances,
-                log_filter,
-                call_filter,
-                block_filter,
+                filter,
                 entity_lfu_cache: LfuCache::new(),
             },
             subgraph_metrics,
             host_metrics,
-            ethrpc_metrics,
             block_stream_metrics,
         };
 
@@ -409,12 +411,6 @@ impl SubgraphInstanceManager {
 
         Ok(())
     }
-
-    fn stop_subgraph(instances: SharedInstanceKeepAliveMap, id: SubgraphDeploymentId) {
-        // Drop the cancel guard to shut down the subgraph now
-        let mut instances = instances.write().unwrap();
-        instances.remove(&id);
-    }
 }
 
 impl EventConsumer<SubgraphAssignmentProviderEvent> for SubgraphInstanceManager {
@@ -429,11 +425,12 @@ impl EventConsumer<SubgraphAssignmentProviderEvent> for SubgraphInstanceManager
     }
 }
 
-async fn run_subgraph<B, T, S>(mut ctx: IndexingContext<B, T, S>) -> Result<(), ()>
+async fn run_subgraph<C, T, S>(mut ctx: IndexingContext<C, T, S>) -> Result<(), ()>
 where
-    B: BlockStreamBuilder,
+    C: Blockchain,
+    C::TriggerProcessor: TriggerProcessor<T::Host, Block = C::Block, TriggerData = C::TriggerData>,
     T: RuntimeHostBuilder,
-    S: ChainStore + Store + EthereumCallCache,
+    S: Store,
 {
     // Clone a few things for different parts of the async processing
     let subgraph_metrics = ctx.subgraph_metrics.cheap_clone();
@@ -446,20 +443,26 @@ where
 
         let block_stream_canceler = CancelGuard::new();
         let block_stream_cancel_handle = block_stream_canceler.handle();
-        let mut block_stream = ctx
-            .inputs
-            .stream_builder
-            .build(
-                logger.clone(),
-                ctx.inputs.deployment_id.clone(),
-                ctx.inputs.network_name.clone(),
-                ctx.inputs.start_blocks.clone(),
-                ctx.state.log_filter.clone(),
-                ctx.state.call_filter.clone(),
-                ctx.state.block_filter.clone(),
-                ctx.inputs.include_calls_in_blocks,
-                ctx.block_stream_metrics.clone(),
-            )
+        let block_stream = match ctx.inputs.chain.new_block_stream(
+            &logger,
+            ctx.inputs.deployment_id.clone(),
+            ctx.inputs.start_blocks.clone(),
+            ctx.state.filter.clone(),
+            ctx.inputs.triggers_adapter.cheap_clone(),
+            ctx.block_stream_metrics.clone(),
+        ) {
+            Ok(block_stream) => block_stream,
+            Err(e) => {
+                error!(
+                    &logger,
+                    "Failed to start block stream: {}", e;
+                    "id" => id_for_err.to_string(),
+                    "code" => LogCode::SubgraphSyncingFailure
+                );
+                return Err(());
+            }
+        };
+        let mut block_stream = block_stream
             .map_err(CancelableError::Error)
             .cancelable(&block_stream_canceler, || CancelableError::Cancel)
             .compat();
@@ -477,7 +480,7 @@ where
         // Process events from the stream as long as no restart is needed
         loop {
             let block = match block_stream.next().await {
-                Some(Ok(BlockStreamEvent::Block(block))) => block,
+                Some(Ok(BlockStreamEvent::ProcessBlock(block))) => block,
                 Some(Ok(BlockStreamEvent::Revert(subgraph_ptr))) => {
                     info!(
                         logger,
@@ -487,28 +490,13 @@ where
                     );
 
                     // We would like to revert the DB state to the parent of the current block.
-                    // First, load the block in order to get the parent hash.
                     if let Err(e) = ctx
                         .inputs
-                        .eth_adapter
-                        .load_blocks(
-                            logger.cheap_clone(),
-                            ctx.inputs.store.cheap_clone(),
-                            HashSet::from_iter(Some(subgraph_ptr.hash)),
-                        )
-                        .collect()
-                        .compat()
+                        .triggers_adapter
+                        .parent_ptr(&logger, &subgraph_ptr)
                         .await
-                        .map(|blocks| {
-                            assert_eq!(blocks.len(), 1);
-                            blocks.into_iter().next().unwrap()
-                        })
-                        .and_then(|block| {
-                            // Produce pointer to parent block (using parent hash).
-                            let parent_ptr = block
-                                .parent_ptr()
-                                .expect("genesis block cannot be reverted");
-
+                        .and_then(|parent_ptr| EthereumBlockPointer::try_from(&parent_ptr))
+                        .and_then(|parent_ptr| {
                             // Revert entity changes from this block, and update subgraph ptr.
                             ctx.inputs
                                 .store
@@ -556,24 +544,17 @@ where
                 None => unreachable!("The block stream stopped producing blocks"),
             };
 
-            let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+            let block_ptr = block.ptr();
 
-            if block.triggers.len() > 0 {
+            if block.trigger_count() > 0 {
                 subgraph_metrics
                     .block_trigger_count
-                    .observe(block.triggers.len() as f64);
+                    .observe(block.trigger_count() as f64);
             }
 
             let start = Instant::now();
 
-            let res = process_block(
-                &logger,
-                ctx.inputs.eth_adapter.cheap_clone(),
-                ctx,
-                block_stream_cancel_handle.clone(),
-                block,
-            )
-            .await;
+            let res = process_block(&logger, ctx, block_stream_cancel_handle.clone(), block).await;
 
             let elapsed = start.elapsed().as_secs_f64();
             subgraph_metrics.block_processing_duration.observe(elapsed);
@@ -602,6 +583,20 @@ where
                     return Err(());
                 }
 
+                // The subgraph was stopped while the block was written, for
+                // example because it is being moved to another shard which
+                // keeps the block from being written. That is not a failure
+                // of the subgraph
+                Err(e) if block_stream_cancel_handle.is_canceled() => {
+                    debug!(
+                        &logger,
+                        "Subgraph stopped while processing a block";
+                        "id" => id_for_err.to_string(),
+                        "error" => e.to_string(),
+                    );
+                    return Err(());
+                }
+
                 // Handle unexpected stream errors by marking the subgraph as failed.
                 Err(e) => {
                     error!(
@@ -614,7 +609,7 @@ where
                     let error = SubgraphError {
                         subgraph_id: id_for_err.clone(),
                         message: e.to_string(),
-                        block_ptr: Some(block_ptr),
+                        block_ptr: EthereumBlockPointer::try_from(&block_ptr).ok(),
                         handler: None,
                         deterministic: e.is_deterministic(),
                     };
@@ -665,20 +660,22 @@ impl From<Error> for BlockProcessingError {
 
 /// Processes a block and returns the updated context and a boolean flag indicating
 /// whether new dynamic data sources have been added to the subgraph.
-async fn process_block<B: BlockStreamBuilder, T: RuntimeHostBuilder, S>(
+async fn process_block<C, T: RuntimeHostBuilder, S>(
     logger: &Logger,
-    eth_adapter: Arc<dyn EthereumAdapter>,
-    mut ctx: IndexingContext<B, T, S>,
+    mut ctx: IndexingContext<C, T, S>,
     block_stream_cancel_handle: CancelHandle,
-    block: EthereumBlockWithTriggers,
-) -> Result<(IndexingContext<B, T, S>, bool), BlockProcessingError>
+    block: BlockWithTriggers<C>,
+) -> Result<(IndexingContext<C, T, S>, bool), BlockProcessingError>
 where
-    S: ChainStore + Store + EthereumCallCache,
+    C: Blockchain,
+    C::TriggerProcessor: TriggerProcessor<T::Host, Block = C::Block, TriggerData = C::TriggerData>,
+    S: Store,
 {
-    let triggers = block.triggers;
-    let block = block.ethereum_block;
+    let triggers = block.trigger_data;
+    let block = block.block;
 
-    let block_ptr = EthereumBlockPointer::from(&block);
+    let chain_block_ptr = block.ptr();
+    let block_ptr = EthereumBlockPointer::try_from(&chain_block_ptr)?;
     let logger = logger.new(o!(
         "block_number" => format!("{:?}", block_ptr.number),
         "block_hash" => format!("{:?}", block_ptr.hash)
@@ -695,8 +692,9 @@ where
     }
 
     // Obtain current and new block pointer (after this block is processed)
-    let light_block = Arc::new(block.light_block());
-    let block_ptr_after = EthereumBlockPointer::from(&block);
+    let processor = ctx.inputs.trigger_processor.cheap_clone();
+    let mapping_block = processor.mapping_block(&block);
+    let block_ptr_after = block_ptr.clone();
     let block_ptr_for_new_data_sources = block_ptr_after.clone();
 
     let metrics = ctx.subgraph_metrics.clone();
@@ -716,21 +714,43 @@ where
     };
 
     // Process events one after the other, passing in entity operations
-    // collected previously to every new event being processed
-    let mut block_state = match process_triggers(
-        &logger,
-        BlockState::new(
-            ctx.inputs.store.clone(),
-            std::mem::take(&mut ctx.state.entity_lfu_cache),
-        ),
-        proof_of_indexing.cheap_clone(),
-        ctx.subgraph_metrics.clone(),
-        &ctx.state.instance,
-        &light_block,
-        triggers,
-    )
-    .await
+    // collected previously to every new event being processed. Subgraphs
+    // that opt into it have the events for data sources that declare
+    // disjoint entity types processed concurrently
+    let entity_lfu_cache = std::mem::take(&mut ctx.state.entity_lfu_cache);
+    let triggers_result = if ctx
+        .inputs
+        .features
+        .contains(&SubgraphFeature::parallelDataSources)
     {
+        process_triggers_in_parallel(
+            &logger,
+            ctx.inputs.store.clone(),
+            entity_lfu_cache,
+            proof_of_indexing.cheap_clone(),
+            ctx.subgraph_metrics.clone(),
+            processor.as_ref(),
+            &ctx.state.instance,
+            &chain_block_ptr,
+            &mapping_block,
+            triggers,
+        )
+        .await
+    } else {
+        process_triggers(
+            &logger,
+            BlockState::new(ctx.inputs.store.clone(), entity_lfu_cache),
+            proof_of_indexing.cheap_clone(),
+            ctx.subgraph_metrics.clone(),
+            processor.as_ref(),
+            &ctx.state.instance,
+            &chain_block_ptr,
+            &mapping_block,
+            triggers,
+        )
+        .await
+    };
+    let mut block_state = match triggers_result {
         // The triggers were processed but some were skipped due to deterministic errors.
         Ok(block_state) if block_state.has_errors() => {
             // While the version is pending we fail the subgraph even if the error is deterministic.
@@ -805,19 +825,18 @@ where
         )?;
 
         // Reprocess the triggers from this block that match the new data sources
-        let block_with_triggers = triggers_in_block(
-            eth_adapter.clone(),
-            logger.cheap_clone(),
-            ctx.inputs.store.clone(),
-            ctx.ethrpc_metrics.clone(),
-            EthereumLogFilter::from_data_sources(data_sources.iter()),
-            EthereumCallFilter::from_data_sources(data_sources.iter()),
-            EthereumBlockFilter::from_data_sources(data_sources.iter()),
-            block.clone(),
-        )
-        .await?;
+        let chain_data_sources = data_sources
+            .iter()
+            .map(C::DataSource::from_manifest)
+            .collect::<Result<Vec<_>, _>>()?;
+        let filter = C::TriggerFilter::from_data_sources(chain_data_sources.iter());
+        let block_with_triggers = ctx
+            .inputs
+            .triggers_adapter
+            .triggers_in_block(&logger, block.clone(), &filter)
+            .await?;
 
-        let triggers = block_with_triggers.triggers;
+        let triggers = block_with_triggers.trigger_data;
 
         if triggers.len() == 1 {
             info!(
@@ -839,17 +858,19 @@ where
             &mut ctx,
             &mut block_state.entity_cache,
             data_sources,
+            &chain_data_sources,
             block_ptr_for_new_data_sources,
         );
 
         // Process the triggers in each host in the same order the
         // corresponding data sources have been created.
         for trigger in triggers.into_iter() {
-            block_state = SubgraphInstance::<T>::process_trigger_in_runtime_hosts(
+            block_state = SubgraphInstance::<T>::process_trigger_in_hosts(
+                processor.as_ref(),
                 &logger,
                 &runtime_hosts,
-                &light_block,
-                trigger,
+                &mapping_block,
+                &trigger,
                 block_state,
                 proof_of_indexing.cheap_clone(),
             )
@@ -876,7 +897,7 @@ where
     }
 
     if let Some(proof_of_indexing) = proof_of_indexing {
-        let proof_of_indexing = Arc::try_unwrap(proof_of_indexing).unwrap().into_inner();
+        let proof_of_indexing = into_proof_of_indexing(proof_of_indexing)?;
         update_proof_of_indexing(
             proof_of_indexing,
             &ctx.host_metrics.stopwatch,
@@ -934,6 +955,19 @@ where
     }
 }
 
+/// Take the proof of indexing out of `proof_of_indexing` once all handlers
+/// that wrote to it have finished. A handler that still holds on to it is a
+/// bug, and is reported as an error rather than a panic
+fn into_proof_of_indexing(
+    proof_of_indexing: Arc<AtomicRefCell<ProofOfIndexing>>,
+) -> Result<ProofOfIndexing, Error> {
+    Arc::try_unwrap(proof_of_indexing)
+        .map(AtomicRefCell::into_inner)
+        .map_err(|_| {
+            anyhow!("the proof of indexing is still in use after all triggers were processed")
+        })
+}
+
 /// Transform the proof of indexing changes into entity updates that will be
 /// inserted when as_modifications is called.
 async fn update_proof_of_indexing(
@@ -981,61 +1015,247 @@ async fn update_proof_of_indexing(
     Ok(())
 }
 
-async fn process_triggers(
+async fn process_triggers<T, P>(
     logger: &Logger,
     mut block_state: BlockState,
     proof_of_indexing: SharedProofOfIndexing,
     subgraph_metrics: Arc<SubgraphInstanceMetrics>,
-    instance: &SubgraphInstance<impl RuntimeHostBuilder>,
-    block: &Arc<LightEthereumBlock>,
-    triggers: Vec<EthereumTrigger>,
-) -> Result<BlockState, MappingError> {
+    processor: &P,
+    instance: &SubgraphInstance<T>,
+    block_ptr: &BlockPtr,
+    block: &Arc<P::MappingBlock>,
+    triggers: Vec<P::TriggerData>,
+) -> Result<BlockState, MappingError>
+where
+    T: RuntimeHostBuilder,
+    P: TriggerProcessor<T::Host>,
+{
     for trigger in triggers.into_iter() {
-        let block_ptr = EthereumBlockPointer::from(block.as_ref());
-        let trigger_type = match trigger {
-            EthereumTrigger::Log(_) => TriggerType::Event,
-            EthereumTrigger::Call(_) => TriggerType::Call,
-            EthereumTrigger::Block(..) => TriggerType::Block,
-        };
-        let transaction_id = match &trigger {
-            EthereumTrigger::Log(log) => log.transaction_hash,
-            EthereumTrigger::Call(call) => call.transaction_hash,
-            EthereumTrigger::Block(..) => None,
-        };
         let start = Instant::now();
         block_state = instance
             .process_trigger(
+                processor,
                 &logger,
-                &block,
-                trigger,
+                block,
+                &trigger,
                 block_state,
                 proof_of_indexing.cheap_clone(),
             )
             .await
-            .map_err(move |e| {
-                e.context(match transaction_id {
-                    Some(tx_hash) => format!(
-                        "Failed to process trigger in block {}, transaction {:x}",
-                        block_ptr, tx_hash
-                    ),
-                    None => "Failed to process trigger".to_string(),
-                })
-            })?;
+            .map_err(|e| e.context(trigger_error_context(block_ptr, &trigger)))?;
         let elapsed = start.elapsed().as_secs_f64();
-        subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
+        subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger.kind());
     }
     Ok(block_state)
 }
 
-fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
+fn trigger_error_context(block_ptr: &BlockPtr, trigger: &impl TriggerData) -> String {
+    match trigger.error_context() {
+        Some(context) => format!(
+            "Failed to process trigger in block {}, {}",
+            block_ptr, context
+        ),
+        None => "Failed to process trigger".to_string(),
+    }
+}
+
+/// What running the handlers of one host for one trigger produced, apart
+/// from entity changes. When triggers are processed in parallel, these are
+/// put back into the order in which sequential processing produces them
+struct HostOutcome {
+    /// The position of the trigger in the block and of the host in the
+    /// subgraph instance
+    order: (usize, usize),
+    proof_of_indexing: Option<ProofOfIndexing>,
+    deterministic_errors: Vec<SubgraphError>,
+    created_data_sources: Vec<DataSourceTemplateInfo>,
+}
+
+/// Like `process_triggers`, but the hosts are split into groups that
+/// declare disjoint entity types, and the groups process the triggers
+/// concurrently, each with its own entity cache. Since no group can see
+/// the entities of another group, merging the entity changes of all groups
+/// gives the same result as processing the triggers sequentially. PoI
+/// events, deterministic errors and created data sources are merged in the
+/// order of triggers and hosts so that they also match sequential
+/// processing
+async fn process_triggers_in_parallel<T, P>(
+    logger: &Logger,
+    store: Arc<dyn Store>,
+    mut entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    proof_of_indexing: SharedProofOfIndexing,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    processor: &P,
+    instance: &SubgraphInstance<T>,
+    block_ptr: &BlockPtr,
+    block: &Arc<P::MappingBlock>,
+    triggers: Vec<P::TriggerData>,
+) -> Result<BlockState, MappingError>
+where
+    T: RuntimeHostBuilder,
+    P: TriggerProcessor<T::Host>,
+{
+    let groups = instance.independent_hosts();
+    if groups.len() < 2 {
+        return process_triggers(
+            logger,
+            BlockState::new(store, entity_lfu_cache),
+            proof_of_indexing,
+            subgraph_metrics,
+            processor,
+            instance,
+            block_ptr,
+            block,
+            triggers,
+        )
+        .await;
+    }
+
+    // Give each group the cached entities of its entity types
+    let states: Vec<_> = groups
+        .iter()
+        .map(|hosts| {
+            let entity_types: BTreeSet<_> = hosts
+                .iter()
+                .flat_map(|(_, host)| host.entity_types().iter().cloned().map(EntityType::data))
+                .collect();
+            let cache = entity_lfu_cache.split_off(|key| entity_types.contains(&key.entity_type));
+            BlockState::new(store.cheap_clone(), cache)
+        })
+        .collect();
+    let mut block_state = BlockState::new(store, entity_lfu_cache);
+
+    let results =
+        futures03::future::join_all(groups.into_iter().zip(states).map(|(hosts, state)| {
+            process_triggers_in_hosts::<T, P>(
+                logger,
+                state,
+                proof_of_indexing.is_some(),
+                subgraph_metrics.cheap_clone(),
+                processor,
+                hosts,
+                block_ptr,
+                block,
+                &triggers,
+            )
+        }))
+        .await;
+
+    // Report the error that sequential processing would have run into first
+    let mut outcomes = Vec::new();
+    let mut first_error: Option<((usize, usize), MappingError)> = None;
+    for result in results {
+        match result {
+            Ok((state, group_outcomes)) => {
+                block_state.extend(state);
+                outcomes.extend(group_outcomes);
+            }
+            Err((order, e)) => {
+                if first_error
+                    .as_ref()
+                    .map_or(true, |(first, _)| order < *first)
+                {
+                    first_error = Some((order, e));
+                }
+            }
+        }
+    }
+    if let Some((_, e)) = first_error {
+        return Err(e);
+    }
+
+    outcomes.sort_by_key(|outcome| outcome.order);
+    for outcome in outcomes {
+        if let (Some(proof_of_indexing), Some(deferred)) =
+            (&proof_of_indexing, outcome.proof_of_indexing)
+        {
+            proof_of_indexing.borrow_mut().append(logger, deferred);
+        }
+        block_state
+            .deterministic_errors
+            .extend(outcome.deterministic_errors);
+        block_state.extend_created_data_sources(outcome.created_data_sources);
+    }
+    Ok(block_state)
+}
+
+/// Process the triggers in one group of hosts for
+/// `process_triggers_in_parallel`. Each host processes each trigger
+/// separately so that the outcomes can be ordered by trigger and host
+async fn process_triggers_in_hosts<T, P>(
+    logger: &Logger,
+    mut state: BlockState,
+    with_proof_of_indexing: bool,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    processor: &P,
+    hosts: Vec<(usize, Arc<T::Host>)>,
+    block_ptr: &BlockPtr,
+    block: &Arc<P::MappingBlock>,
+    triggers: &[P::TriggerData],
+) -> Result<(BlockState, Vec<HostOutcome>), ((usize, usize), MappingError)>
+where
+    T: RuntimeHostBuilder,
+    P: TriggerProcessor<T::Host>,
+{
+    let mut outcomes = Vec::new();
+
+    for (trigger_index, trigger) in triggers.iter().enumerate() {
+        let start = Instant::now();
+        let mut matched = false;
+
+        for (host_index, host) in &hosts {
+            if !processor.matches(host.as_ref(), trigger) {
+                continue;
+            }
+            matched = true;
+
+            let order = (trigger_index, *host_index);
+            let proof_of_indexing = if with_proof_of_indexing {
+                Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::deferred())))
+            } else {
+                None
+            };
+            state = processor
+                .process_trigger(
+                    logger,
+                    host.as_ref(),
+                    block,
+                    trigger,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                )
+                .await
+                .map_err(|e| (order, e.context(trigger_error_context(block_ptr, trigger))))?;
+
+            let proof_of_indexing = proof_of_indexing
+                .map(into_proof_of_indexing)
+                .transpose()
+                .map_err(|e| (order, MappingError::Unknown(e)))?;
+            outcomes.push(HostOutcome {
+                order,
+                proof_of_indexing,
+                deterministic_errors: std::mem::take(&mut state.deterministic_errors),
+                created_data_sources: state.drain_created_data_sources(),
+            });
+        }
+
+        if matched {
+            let elapsed = start.elapsed().as_secs_f64();
+            subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger.kind());
+        }
+    }
+    Ok((state, outcomes))
+}
+
+fn create_dynamic_data_sources<C, T: RuntimeHostBuilder, S>(
     logger: Logger,
-    ctx: &mut IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<C, T, S>,
     host_metrics: Arc<HostMetrics>,
     created_data_sources: Vec<DataSourceTemplateInfo>,
 ) -> Result<(Vec<DataSource>, Vec<Arc<T::Host>>), Error>
 where
-    B: BlockStreamBuilder,
-    S: ChainStore + Store + EthereumCallCache,
+    C: Blockchain,
+    S: Store,
 {
     let mut data_sources = vec![];
     let mut runtime_hosts = vec![];
@@ -1076,15 +1296,16 @@ where
     Ok((data_sources, runtime_hosts))
 }
 
-fn persist_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
+fn persist_dynamic_data_sources<C, T: RuntimeHostBuilder, S>(
     logger: Logger,
-    ctx: &mut IndexingContext<B, T, S>,
+    ctx: &mut IndexingContext<C, T, S>,
     entity_cache: &mut EntityCache,
     data_sources: Vec<DataSource>,
+    chain_data_sources: &[C::DataSource],
     block_ptr: EthereumBlockPointer,
 ) where
-    B: BlockStreamBuilder,
-    S: ChainStore + Store,
+    C: Blockchain,
+    S: Store,
 {
     if !data_sources.is_empty() {
         debug!(
@@ -1113,18 +1334,6 @@ fn persist_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
         entity_cache.append(operations);
     }
 
-    // Merge log filters from data sources into the block stream builder
-    ctx.state
-        .log_filter
-        .extend(EthereumLogFilter::from_data_sources(&data_sources));
-
-    // Merge call filters from data sources into the block stream builder
-    ctx.state
-        .call_filter
-        .extend(EthereumCallFilter::from_data_sources(&data_sources));
-
-    // Merge block filters from data sources into the block stream builder
-    ctx.state
-        .block_filter
-        .extend(EthereumBlockFilter::from_data_sources(&data_sources));
+    // Merge filters from data sources into the filter for the block stream
+    ctx.state.filter.extend(chain_data_sources.iter());
 }
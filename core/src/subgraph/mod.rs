@@ -5,7 +5,9 @@ mod provider;
 mod registrar;
 
 pub use self::instance::SubgraphInstance;
-pub use self::instance_manager::SubgraphInstanceManager;
+pub use self::instance_manager::{
+    ChainRunner, SharedInstanceKeepAliveMap, SubgraphInstanceManager, SubgraphRunner,
+};
 pub use self::loader::DataSourceLoader;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
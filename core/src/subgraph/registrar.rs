@@ -320,7 +320,7 @@ where
             &logger,
             self.store.clone(),
             chain_store.clone(),
-            ethereum_adapter.clone(),
+            ethereum_adapter,
             name.clone(),
             manifest,
             node_id,
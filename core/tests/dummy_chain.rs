@@ -0,0 +1,522 @@
+//! Run a subgraph with the instance manager on a chain that shares no
+//! types with Ethereum: blocks are numbered, triggers are messages from a
+//! sender, and each data source handles the messages of one sender by
+//! storing them as entities of the first type that its mapping declares
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::time::Instant;
+
+use futures01::sync::mpsc::{channel, Sender};
+
+use graph::blockchain::{
+    self, Block, BlockHash, BlockPtr, BlockStream, BlockStreamEvent, BlockStreamMetrics,
+    BlockWithTriggers, Blockchain, BlockchainKind, TriggerFilter, TriggersAdapter,
+};
+use graph::components::subgraph::{MappingError, ProofOfIndexingEvent, SharedProofOfIndexing};
+use graph::data::subgraph::{Link, Mapping, Source, SubgraphFeature};
+use graph::prelude::web3::types::H256;
+use graph::prelude::*;
+use graph_core::{ChainRunner, SharedInstanceKeepAliveMap, SubgraphRunner};
+use graph_mock::MockMetricsRegistry;
+use test_store::*;
+
+const SCHEMA: &str = "
+    type Message @entity { id: ID!, sender: String!, block: Int! }
+    type Reply @entity { id: ID!, sender: String!, block: Int! }
+";
+
+const NETWORK: &str = "dummy";
+
+#[derive(Clone, Debug, PartialEq)]
+struct DummyBlock {
+    number: BlockNumber,
+}
+
+fn hash(number: BlockNumber) -> BlockHash {
+    BlockHash::from(H256::from_low_u64_be(number as u64 + 1).as_bytes().to_vec())
+}
+
+impl Block for DummyBlock {
+    fn ptr(&self) -> BlockPtr {
+        BlockPtr::new(hash(self.number), self.number)
+    }
+
+    fn parent_ptr(&self) -> Option<BlockPtr> {
+        match self.number {
+            0 => None,
+            n => Some(BlockPtr::new(hash(n - 1), n - 1)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct DummyTrigger {
+    sender: String,
+}
+
+impl blockchain::TriggerData for DummyTrigger {
+    fn kind(&self) -> &'static str {
+        "message"
+    }
+
+    fn error_context(&self) -> Option<String> {
+        Some(format!("message from {}", self.sender))
+    }
+}
+
+/// Listens for the messages from the sender that the data source is named
+/// after
+struct DummyDataSource {
+    sender: String,
+}
+
+impl blockchain::DataSource for DummyDataSource {
+    fn from_manifest(data_source: &DataSource) -> Result<Self, Error> {
+        Ok(DummyDataSource {
+            sender: data_source.name.clone(),
+        })
+    }
+}
+
+struct DummyTemplate;
+
+impl blockchain::DataSourceTemplate for DummyTemplate {
+    fn from_manifest(_template: &DataSourceTemplate) -> Result<Self, Error> {
+        Ok(DummyTemplate)
+    }
+}
+
+struct DummyCapabilities;
+
+impl blockchain::NodeCapabilities<DummyChain> for DummyCapabilities {
+    fn from_data_sources(_: &[DummyDataSource], _: &[DummyTemplate]) -> Self {
+        DummyCapabilities
+    }
+}
+
+#[derive(Clone, Default)]
+struct DummyFilter {
+    senders: HashSet<String>,
+}
+
+impl DummyFilter {
+    fn matches(&self, trigger: &DummyTrigger) -> bool {
+        self.senders.contains(&trigger.sender)
+    }
+}
+
+impl TriggerFilter<DummyChain> for DummyFilter {
+    fn extend<'a>(&mut self, data_sources: impl Iterator<Item = &'a DummyDataSource> + Clone) {
+        self.senders
+            .extend(data_sources.map(|ds| ds.sender.clone()));
+    }
+}
+
+type Blocks = Vec<(DummyBlock, Vec<DummyTrigger>)>;
+
+struct DummyTriggersAdapter {
+    blocks: Blocks,
+}
+
+#[async_trait]
+impl TriggersAdapter<DummyChain> for DummyTriggersAdapter {
+    async fn triggers_in_block(
+        &self,
+        _logger: &Logger,
+        block: DummyBlock,
+        filter: &DummyFilter,
+    ) -> Result<BlockWithTriggers<DummyChain>, Error> {
+        let triggers = self
+            .blocks
+            .iter()
+            .find(|(b, _)| b == &block)
+            .map(|(_, triggers)| triggers.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|trigger| filter.matches(trigger))
+            .collect();
+        Ok(BlockWithTriggers::new(block, triggers))
+    }
+
+    async fn parent_ptr(&self, _logger: &Logger, block: &BlockPtr) -> Result<BlockPtr, Error> {
+        Ok(BlockPtr::new(hash(block.number - 1), block.number - 1))
+    }
+}
+
+/// Produces the blocks of the chain and then waits forever for new ones
+struct DummyBlockStream {
+    events: VecDeque<BlockStreamEvent<DummyChain>>,
+}
+
+impl Stream for DummyBlockStream {
+    type Item = BlockStreamEvent<DummyChain>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Error> {
+        match self.events.pop_front() {
+            Some(event) => Ok(Async::Ready(Some(event))),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl BlockStream<DummyChain> for DummyBlockStream {}
+
+/// The runtime host for a data source; it only knows the sender whose
+/// messages it handles
+#[derive(Debug, PartialEq)]
+struct DummyHost {
+    deployment: SubgraphDeploymentId,
+    sender: String,
+    entities: Vec<String>,
+}
+
+impl RuntimeHost for DummyHost {
+    fn creation_block_number(&self) -> Option<u64> {
+        None
+    }
+
+    fn entity_types(&self) -> &[String] {
+        &self.entities
+    }
+}
+
+#[derive(Clone)]
+struct DummyHostBuilder;
+
+impl RuntimeHostBuilder for DummyHostBuilder {
+    type Host = DummyHost;
+    type Req = ();
+
+    fn build(
+        &self,
+        _network_name: String,
+        subgraph_id: SubgraphDeploymentId,
+        _features: &BTreeSet<SubgraphFeature>,
+        data_source: DataSource,
+        _top_level_templates: Arc<Vec<DataSourceTemplate>>,
+        _mapping_request_sender: Sender<()>,
+        _metrics: Arc<HostMetrics>,
+    ) -> Result<DummyHost, Error> {
+        Ok(DummyHost {
+            deployment: subgraph_id,
+            sender: data_source.name,
+            entities: data_source.mapping.entities,
+        })
+    }
+
+    fn spawn_mapping(
+        _raw_module: Vec<u8>,
+        _logger: Logger,
+        _subgraph_id: SubgraphDeploymentId,
+        _metrics: Arc<HostMetrics>,
+    ) -> Result<Sender<()>, Error> {
+        Ok(channel(1).0)
+    }
+}
+
+/// Stores every message as an entity of the first type the host declares,
+/// and records that in the proof of indexing
+struct DummyProcessor;
+
+#[async_trait]
+impl blockchain::TriggerProcessor<DummyHost> for DummyProcessor {
+    type Block = DummyBlock;
+    type TriggerData = DummyTrigger;
+    type MappingBlock = DummyBlock;
+
+    fn mapping_block(&self, block: &DummyBlock) -> Arc<DummyBlock> {
+        Arc::new(block.clone())
+    }
+
+    fn matches(&self, host: &DummyHost, trigger: &DummyTrigger) -> bool {
+        host.sender == trigger.sender
+    }
+
+    async fn process_trigger(
+        &self,
+        logger: &Logger,
+        host: &DummyHost,
+        block: &Arc<DummyBlock>,
+        trigger: &DummyTrigger,
+        mut state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let entity_type = &host.entities[0];
+        let id = format!("{}-{}", block.number, trigger.sender);
+        let entity = Entity::from(vec![
+            ("id", Value::from(id.clone())),
+            ("sender", Value::from(trigger.sender.clone())),
+            ("block", Value::from(block.number)),
+        ]);
+        if let Some(proof_of_indexing) = &proof_of_indexing {
+            proof_of_indexing.borrow_mut().write(
+                logger,
+                NETWORK,
+                &ProofOfIndexingEvent::SetEntity {
+                    entity_type,
+                    id: &id,
+                    data: &entity,
+                },
+            );
+        }
+        let key = EntityKey::data(host.deployment.clone(), entity_type.clone(), id);
+        state.enter_handler();
+        state.entity_cache.set(key, entity);
+        state.exit_handler();
+        Ok(state)
+    }
+}
+
+struct DummyChain {
+    blocks: Blocks,
+}
+
+impl Blockchain for DummyChain {
+    const KIND: BlockchainKind = BlockchainKind::Arweave;
+
+    type Block = DummyBlock;
+    type DataSource = DummyDataSource;
+    type DataSourceTemplate = DummyTemplate;
+    type NodeCapabilities = DummyCapabilities;
+    type TriggerData = DummyTrigger;
+    type TriggerFilter = DummyFilter;
+    type TriggersAdapter = DummyTriggersAdapter;
+    type BlockStream = DummyBlockStream;
+    type TriggerProcessor = DummyProcessor;
+
+    fn network_name(&self) -> &str {
+        NETWORK
+    }
+
+    fn triggers_adapter(
+        &self,
+        _deployment: &SubgraphDeploymentId,
+        _capabilities: &DummyCapabilities,
+    ) -> Result<Arc<DummyTriggersAdapter>, Error> {
+        Ok(Arc::new(DummyTriggersAdapter {
+            blocks: self.blocks.clone(),
+        }))
+    }
+
+    fn trigger_processor(&self) -> Arc<DummyProcessor> {
+        Arc::new(DummyProcessor)
+    }
+
+    fn new_block_stream(
+        &self,
+        _logger: &Logger,
+        _deployment: SubgraphDeploymentId,
+        start_blocks: Vec<BlockNumber>,
+        filter: DummyFilter,
+        _triggers_adapter: Arc<DummyTriggersAdapter>,
+        _metrics: Arc<BlockStreamMetrics>,
+    ) -> Result<DummyBlockStream, Error> {
+        let start = start_blocks.into_iter().min().unwrap_or(0);
+        let events = self
+            .blocks
+            .iter()
+            .filter(|(block, _)| block.number >= start)
+            .map(|(block, triggers)| {
+                let triggers = triggers
+                    .iter()
+                    .filter(|trigger| filter.matches(trigger))
+                    .cloned()
+                    .collect();
+                BlockStreamEvent::ProcessBlock(BlockWithTriggers::new(block.clone(), triggers))
+            })
+            .collect();
+        Ok(DummyBlockStream { events })
+    }
+}
+
+fn dummy_chain() -> DummyChain {
+    let block = |number, senders: &[&str]| {
+        let triggers = senders
+            .iter()
+            .map(|sender| DummyTrigger {
+                sender: sender.to_string(),
+            })
+            .collect();
+        (DummyBlock { number }, triggers)
+    };
+    DummyChain {
+        blocks: vec![
+            block(0, &["alice"]),
+            block(1, &["bob", "carol"]),
+            block(2, &["alice", "bob"]),
+        ],
+    }
+}
+
+/// A data source for the messages from `sender` that stores them as
+/// `entity_type`
+fn data_source(sender: &str, entity_type: &str) -> DataSource {
+    DataSource {
+        kind: "arweave/messages".to_owned(),
+        network: Some(NETWORK.to_owned()),
+        name: sender.to_owned(),
+        source: Source {
+            address: None,
+            abi: String::new(),
+            start_block: 0,
+        },
+        mapping: Mapping {
+            kind: "arweave/messages".to_owned(),
+            api_version: "0.0.4".to_owned(),
+            language: "wasm/assemblyscript".to_owned(),
+            entities: vec![entity_type.to_owned()],
+            abis: vec![],
+            block_handlers: vec![],
+            call_handlers: vec![],
+            event_handlers: vec![],
+            runtime: Arc::new(vec![]),
+            link: Link::from("dummy".to_owned()),
+        },
+        context: None,
+        creation_block: None,
+    }
+}
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("dummyChain").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SCHEMA);
+    id
+}
+
+/// Run a subgraph with `data_sources` on the dummy chain until it has
+/// processed the last block
+async fn run_subgraph<S: Store>(
+    store: &Arc<S>,
+    id: &SubgraphDeploymentId,
+    features: BTreeSet<SubgraphFeature>,
+    data_sources: Vec<DataSource>,
+) {
+    let manifest = SubgraphManifest {
+        id: id.clone(),
+        location: "dummy".to_owned(),
+        spec_version: "0.0.2".to_owned(),
+        features,
+        description: None,
+        repository: None,
+        schema: Schema::parse(SCHEMA, id.clone()).unwrap(),
+        data_sources,
+        graft: None,
+        templates: vec![],
+    };
+
+    let runner = ChainRunner::new(
+        Arc::new(dummy_chain()),
+        store.clone(),
+        DummyHostBuilder,
+        Arc::new(MockMetricsRegistry::new()),
+    );
+    let instances = SharedInstanceKeepAliveMap::default();
+    runner
+        .start_subgraph(LOGGER.clone(), instances.clone(), manifest)
+        .await
+        .unwrap();
+
+    // Wait for the subgraph to process the last block
+    let start = Instant::now();
+    while store.block_ptr(id).unwrap().map(|ptr| ptr.number) != Some(2) {
+        assert!(
+            start.elapsed() < Duration::from_secs(30),
+            "the subgraph did not process all blocks"
+        );
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+    }
+    instances.write().unwrap().remove(id);
+}
+
+#[test]
+fn instance_manager_runs_subgraph_on_dummy_chain() {
+    run_test_sequentially(setup, |store, id| async move {
+        run_subgraph(
+            &store,
+            &id,
+            BTreeSet::new(),
+            vec![
+                data_source("alice", "Message"),
+                data_source("bob", "Message"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            hash(2).as_slice(),
+            store.block_ptr(&id).unwrap().unwrap().hash.as_bytes()
+        );
+
+        let message = |entity_id: &str| {
+            let key = EntityKey::data(id.clone(), "Message".to_owned(), entity_id.to_owned());
+            store.get(key).unwrap()
+        };
+        for (block, sender) in &[(0, "alice"), (1, "bob"), (2, "alice"), (2, "bob")] {
+            let message = message(&format!("{}-{}", block, sender))
+                .unwrap_or_else(|| panic!("no message from {} in block {}", sender, block));
+            assert_eq!(
+                Some(&Value::from(sender.to_string())),
+                message.get("sender")
+            );
+            assert_eq!(Some(&Value::Int(*block)), message.get("block"));
+        }
+        // No data source listens for carol
+        assert_eq!(None, message("1-carol"));
+    })
+}
+
+/// The entities that `run_subgraph` stores for the data sources of alice
+/// and bob when they store messages as `Message` and `Reply` respectively,
+/// and the proof of indexing at the last block
+async fn stored_outcome<S: Store>(
+    store: &Arc<S>,
+    id: &SubgraphDeploymentId,
+) -> (Vec<Entity>, [u8; 32]) {
+    let keys = &[
+        ("Message", "0-alice"),
+        ("Reply", "1-bob"),
+        ("Message", "2-alice"),
+        ("Reply", "2-bob"),
+    ];
+    let entities = keys
+        .iter()
+        .map(|(entity_type, entity_id)| {
+            let key = EntityKey::data(id.clone(), entity_type.to_string(), entity_id.to_string());
+            store
+                .get(key)
+                .unwrap()
+                .unwrap_or_else(|| panic!("{}[{}] was not stored", entity_type, entity_id))
+        })
+        .collect();
+    let poi = store
+        .clone()
+        .get_proof_of_indexing(id, &None, (H256::from_low_u64_be(3), 2u64).into())
+        .await
+        .unwrap()
+        .expect("the deployment has a proof of indexing");
+    (entities, poi)
+}
+
+/// Processing the triggers of data sources with disjoint entity types in
+/// parallel must produce the same entities and the same proof of indexing
+/// as processing them sequentially
+#[test]
+fn parallel_processing_matches_sequential_processing() {
+    run_test_sequentially(setup, |store, id| async move {
+        let data_sources = || vec![data_source("alice", "Message"), data_source("bob", "Reply")];
+
+        run_subgraph(&store, &id, BTreeSet::new(), data_sources()).await;
+        let sequential = stored_outcome(&store, &id).await;
+
+        remove_subgraphs();
+        create_test_subgraph(&id, SCHEMA);
+        let features = vec![SubgraphFeature::parallelDataSources]
+            .into_iter()
+            .collect();
+        run_subgraph(&store, &id, features, data_sources()).await;
+        let parallel = stored_outcome(&store, &id).await;
+
+        assert_eq!(sequential, parallel);
+    })
+}
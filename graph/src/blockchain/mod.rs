@@ -0,0 +1,346 @@
+//! The `Blockchain` trait and the types that go with it abstract over the
+//! chain that a subgraph indexes. Everything in here is independent of a
+//! particular chain; support for a chain is added by implementing
+//! `Blockchain` in a separate crate, like `graph-chain-ethereum`.
+
+use anyhow::Error;
+use async_trait::async_trait;
+use futures::Stream;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::components::metrics::{stopwatch::StopwatchMetrics, Gauge, MetricsRegistry};
+use crate::components::store::BlockNumber;
+use crate::components::subgraph::{BlockState, MappingError, SharedProofOfIndexing};
+use crate::data::subgraph::{self, SubgraphDeploymentId};
+use slog::Logger;
+
+/// The hash of a block. Different chains use hashes of different lengths,
+/// and we therefore store them as an opaque sequence of bytes
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockHash(pub Box<[u8]>);
+
+impl BlockHash {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Encodes the hash as a hexadecimal string **without** a "0x" prefix,
+    /// which is the format in which hashes are stored in the database
+    pub fn hash_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{}", self.hash_hex())
+    }
+}
+
+impl From<Vec<u8>> for BlockHash {
+    fn from(bytes: Vec<u8>) -> Self {
+        BlockHash(bytes.into_boxed_slice())
+    }
+}
+
+/// A chain-agnostic pointer to a block
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockPtr {
+    pub hash: BlockHash,
+    pub number: BlockNumber,
+}
+
+impl BlockPtr {
+    pub fn new(hash: BlockHash, number: BlockNumber) -> Self {
+        Self { hash, number }
+    }
+
+    pub fn hash_hex(&self) -> String {
+        self.hash.hash_hex()
+    }
+}
+
+impl fmt::Display for BlockPtr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} ({})", self.number, self.hash_hex())
+    }
+}
+
+/// A block of some chain; the chain defines what exactly a block contains
+pub trait Block: Send + Sync {
+    fn ptr(&self) -> BlockPtr;
+    fn parent_ptr(&self) -> Option<BlockPtr>;
+
+    fn number(&self) -> BlockNumber {
+        self.ptr().number
+    }
+}
+
+/// The kinds of chains that graph-node knows about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockchainKind {
+    Ethereum,
+    Arweave,
+}
+
+impl BlockchainKind {
+    /// Determine the chain from the `kind` of a data source in a subgraph
+    /// manifest, e.g., `ethereum/contract`
+    pub fn from_data_source_kind(kind: &str) -> Option<Self> {
+        match kind.split('/').next() {
+            Some("ethereum") => Some(BlockchainKind::Ethereum),
+            Some("arweave") => Some(BlockchainKind::Arweave),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BlockchainKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockchainKind::Ethereum => write!(f, "ethereum"),
+            BlockchainKind::Arweave => write!(f, "arweave"),
+        }
+    }
+}
+
+/// A data source of a subgraph on some chain. Subgraph manifests are the
+/// same for all chains, and each chain builds its data sources from the
+/// data sources in the manifest, including the ones that mappings create
+/// from templates
+pub trait DataSource: Send + Sync + 'static {
+    fn from_manifest(data_source: &subgraph::DataSource) -> Result<Self, Error>
+    where
+        Self: Sized;
+}
+
+/// A data source template of a subgraph on some chain, built from a
+/// template in the subgraph manifest
+pub trait DataSourceTemplate: Send + Sync + 'static {
+    fn from_manifest(template: &subgraph::DataSourceTemplate) -> Result<Self, Error>
+    where
+        Self: Sized;
+}
+
+/// What a subgraph requires from the nodes of a chain, e.g., whether they
+/// need to be archive nodes
+pub trait NodeCapabilities<C: Blockchain>: Send + Sync {
+    /// The capabilities that processing the triggers of `data_sources`,
+    /// and of the data sources created from `templates`, requires
+    fn from_data_sources(
+        data_sources: &[C::DataSource],
+        templates: &[C::DataSourceTemplate],
+    ) -> Self;
+}
+
+/// What the instance manager needs to know about a trigger without
+/// knowing the chain it comes from
+pub trait TriggerData {
+    /// The kind of trigger, like `event`. Trigger processing metrics are
+    /// labelled with it
+    fn kind(&self) -> &'static str;
+
+    /// Where the trigger came from, e.g., the transaction that caused it,
+    /// for error messages. `None` if there is nothing more to say than the
+    /// block the trigger is in
+    fn error_context(&self) -> Option<String>;
+}
+
+/// Matches the triggers of a chain against runtime hosts of type `H` and
+/// runs the handlers of hosts for them. The instance manager decides which
+/// hosts see a trigger and in what order, and leaves everything that
+/// depends on the chain to the processor
+#[async_trait]
+pub trait TriggerProcessor<H>: Send + Sync + 'static {
+    type Block: Block;
+    type TriggerData: TriggerData + Send + Sync;
+    /// What handlers see of a block. It is extracted from the block once,
+    /// before any of its triggers are processed
+    type MappingBlock: Send + Sync;
+
+    fn mapping_block(&self, block: &Self::Block) -> Arc<Self::MappingBlock>;
+
+    /// Whether `host` has a handler for `trigger`
+    fn matches(&self, host: &H, trigger: &Self::TriggerData) -> bool;
+
+    /// Run the handler of `host` for `trigger`, which `host` matches
+    async fn process_trigger(
+        &self,
+        logger: &Logger,
+        host: &H,
+        block: &Arc<Self::MappingBlock>,
+        trigger: &Self::TriggerData,
+        state: BlockState,
+        proof_of_indexing: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError>;
+}
+
+/// A block together with the triggers in it that are relevant for a
+/// subgraph
+pub struct BlockWithTriggers<C: Blockchain> {
+    pub block: C::Block,
+    pub trigger_data: Vec<C::TriggerData>,
+}
+
+impl<C: Blockchain> BlockWithTriggers<C> {
+    pub fn new(block: C::Block, trigger_data: Vec<C::TriggerData>) -> Self {
+        Self {
+            block,
+            trigger_data,
+        }
+    }
+
+    pub fn trigger_count(&self) -> usize {
+        self.trigger_data.len()
+    }
+
+    pub fn ptr(&self) -> BlockPtr {
+        self.block.ptr()
+    }
+}
+
+/// The events that a block stream produces
+pub enum BlockStreamEvent<C: Blockchain> {
+    /// Process the triggers in this block
+    ProcessBlock(BlockWithTriggers<C>),
+    /// Revert the block that this pointer points to; processing continues
+    /// from its parent
+    Revert(BlockPtr),
+}
+
+/// A stream of blocks with the triggers for one subgraph deployment
+pub trait BlockStream<C: Blockchain>:
+    Stream<Item = BlockStreamEvent<C>, Error = Error> + Send
+{
+}
+
+/// Data that decides which triggers in a block a subgraph is interested in
+pub trait TriggerFilter<C: Blockchain>: Default + Clone + Send + Sync {
+    /// Create a filter that matches the triggers of all `data_sources`
+    fn from_data_sources<'a>(
+        data_sources: impl Iterator<Item = &'a C::DataSource> + Clone,
+    ) -> Self {
+        let mut this = Self::default();
+        this.extend(data_sources);
+        this
+    }
+
+    /// Extend this filter so that it also matches the triggers of
+    /// `data_sources`
+    fn extend<'a>(&mut self, data_sources: impl Iterator<Item = &'a C::DataSource> + Clone);
+}
+
+/// Access to the parts of a chain that a subgraph needs while processing
+/// blocks, outside of the block stream
+#[async_trait]
+pub trait TriggersAdapter<C: Blockchain>: Send + Sync {
+    /// Find the triggers matching `filter` in `block`. This is used to
+    /// reprocess a block for data sources that were created while
+    /// processing it
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: C::Block,
+        filter: &C::TriggerFilter,
+    ) -> Result<BlockWithTriggers<C>, Error>;
+
+    /// Return the pointer to the parent of the block `block`. Reverting a
+    /// block moves a subgraph to that block
+    async fn parent_ptr(&self, logger: &Logger, block: &BlockPtr) -> Result<BlockPtr, Error>;
+}
+
+/// A chain that subgraphs can index. There is one instance of this for each
+/// network that graph-node is configured for
+pub trait Blockchain: Sized + Send + Sync + 'static {
+    const KIND: BlockchainKind;
+
+    type Block: Block + Clone;
+    /// The data sources of subgraphs on this chain; trigger filters are
+    /// built from them
+    type DataSource: DataSource;
+    type DataSourceTemplate: DataSourceTemplate;
+    type NodeCapabilities: NodeCapabilities<Self>;
+    type TriggerData: TriggerData + Clone + Send + Sync;
+    type TriggerFilter: TriggerFilter<Self>;
+    type TriggersAdapter: TriggersAdapter<Self>;
+    type BlockStream: BlockStream<Self>;
+    /// Processes the triggers of this chain for the runtime hosts of a
+    /// subgraph; see `TriggerProcessor`
+    type TriggerProcessor: Send + Sync + 'static;
+
+    /// The name of the network, as used in the `network` of data sources
+    fn network_name(&self) -> &str;
+
+    /// Create a triggers adapter for `deployment` that has the
+    /// `capabilities` the deployment requires. Metrics that are specific
+    /// to the chain are registered for the deployment here, once for each
+    /// time the subgraph is started
+    fn triggers_adapter(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        capabilities: &Self::NodeCapabilities,
+    ) -> Result<Arc<Self::TriggersAdapter>, Error>;
+
+    fn trigger_processor(&self) -> Arc<Self::TriggerProcessor>;
+
+    /// Create a block stream for `deployment` that starts at the block
+    /// the deployment is currently at, or at one of `start_blocks` for a
+    /// new deployment, and contains the triggers that match `filter`. The
+    /// `triggers_adapter` is the one that was created for the deployment
+    fn new_block_stream(
+        &self,
+        logger: &Logger,
+        deployment: SubgraphDeploymentId,
+        start_blocks: Vec<BlockNumber>,
+        filter: Self::TriggerFilter,
+        triggers_adapter: Arc<Self::TriggersAdapter>,
+        metrics: Arc<BlockStreamMetrics>,
+    ) -> Result<Self::BlockStream, Error>;
+}
+
+/// Metrics for the block stream of a subgraph deployment that all chains
+/// report
+#[derive(Clone)]
+pub struct BlockStreamMetrics {
+    pub blocks_behind: Box<Gauge>,
+    pub reverted_blocks: Box<Gauge>,
+    pub buffered_blocks: Box<Gauge>,
+    pub stopwatch: StopwatchMetrics,
+}
+
+impl BlockStreamMetrics {
+    pub fn new(
+        registry: Arc<dyn MetricsRegistry>,
+        deployment_id: &SubgraphDeploymentId,
+        stopwatch: StopwatchMetrics,
+    ) -> Self {
+        let blocks_behind = registry
+            .new_deployment_gauge(
+                "deployment_blocks_behind",
+                "Track the number of blocks a subgraph deployment is behind the HEAD block",
+                deployment_id.as_str(),
+            )
+            .expect("failed to create `deployment_blocks_behind` gauge");
+        let reverted_blocks = registry
+            .new_deployment_gauge(
+                "deployment_reverted_blocks",
+                "Track the last reverted block for a subgraph deployment",
+                deployment_id.as_str(),
+            )
+            .expect("Failed to create `deployment_reverted_blocks` gauge");
+        let buffered_blocks = registry
+            .new_deployment_gauge(
+                "deployment_buffered_blocks",
+                "Track the number of blocks the block stream has fetched that wait to be processed",
+                deployment_id.as_str(),
+            )
+            .expect("failed to create `deployment_buffered_blocks` gauge");
+        Self {
+            blocks_behind,
+            reverted_blocks,
+            buffered_blocks,
+            stopwatch,
+        }
+    }
+}
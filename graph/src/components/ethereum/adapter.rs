@@ -1,12 +1,12 @@
 use anyhow::{anyhow, Error};
-use ethabi::{Bytes, Error as ABIError, Function, ParamType, Token};
+use ethabi::{Bytes, Error as ABIError, Event, Function, ParamType, RawLog, Token};
 use futures::Future;
 use futures03::future::TryFutureExt;
 use mockall::predicate::*;
 use mockall::*;
 use petgraph::graphmap::GraphMap;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::marker::Unpin;
 use thiserror::Error;
@@ -15,7 +15,9 @@ use web3::types::*;
 
 use super::types::*;
 use crate::components::metrics::{CounterVec, GaugeVec, HistogramVec};
+use crate::data::subgraph::DeclaredCall;
 use crate::prelude::*;
+use crate::util::ethereum::contract_event_with_signature;
 
 pub type EventSignature = H256;
 
@@ -273,7 +275,7 @@ impl EthereumLogFilter {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct EthereumCallFilter {
     // Each call filter has a map of filters keyed by address, each containing a tuple with
     // start_block and the set of function signatures
@@ -485,10 +487,116 @@ impl EthereumBlockFilter {
     }
 }
 
+/// The contract calls that event handlers declare in the manifest. The
+/// block stream makes them for the logs in the blocks it prefetches so
+/// that their results are in the call cache before the handlers run
+#[derive(Clone, Debug, Default)]
+pub struct EthereumDeclaredCalls {
+    handlers: Vec<DeclaredCallsHandler>,
+}
+
+#[derive(Clone, Debug)]
+struct DeclaredCallsHandler {
+    /// The address of the data source, `None` if it handles logs from all
+    /// contracts
+    address: Option<Address>,
+    topic0: H256,
+    event: Event,
+    abis: Vec<MappingABI>,
+    calls: BTreeMap<String, DeclaredCall>,
+}
+
+impl EthereumDeclaredCalls {
+    pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
+        let mut this = EthereumDeclaredCalls::default();
+        for ds in iter {
+            let contract = match ds.mapping.abis.iter().find(|abi| abi.name == ds.source.abi) {
+                Some(abi) => &abi.contract,
+                None => continue,
+            };
+            for handler in &ds.mapping.event_handlers {
+                if handler.calls.is_empty() {
+                    continue;
+                }
+                // Validating the manifest makes sure that the event exists
+                if let Some(event) = contract_event_with_signature(contract, &handler.event) {
+                    this.handlers.push(DeclaredCallsHandler {
+                        address: ds.source.address,
+                        topic0: handler.topic0(),
+                        event: event.clone(),
+                        abis: ds.mapping.abis.clone(),
+                        calls: handler.calls.clone(),
+                    });
+                }
+            }
+        }
+        this
+    }
+
+    /// Extends these declared calls with another set of declared calls.
+    pub fn extend(&mut self, other: EthereumDeclaredCalls) {
+        self.handlers.extend(other.handlers);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// The calls that handlers declare for `log` in block `block_ptr`.
+    /// Calls that can not be made for the log are skipped since the handler
+    /// reports the problem when it runs
+    pub fn calls_for_log(
+        &self,
+        logger: &Logger,
+        block_ptr: EthereumBlockPointer,
+        log: &Log,
+    ) -> Vec<EthereumContractCall> {
+        let topic0 = match log.topics.first() {
+            Some(topic0) => topic0,
+            None => return vec![],
+        };
+
+        self.handlers
+            .iter()
+            .filter(|handler| {
+                handler
+                    .address
+                    .map_or(true, |address| address == log.address)
+                    && handler.topic0 == *topic0
+            })
+            .filter_map(|handler| {
+                handler
+                    .event
+                    .parse_log(RawLog {
+                        topics: log.topics.clone(),
+                        data: log.data.0.clone(),
+                    })
+                    .ok()
+                    .map(|decoded| (handler, decoded.params))
+            })
+            .flat_map(|(handler, params)| {
+                handler.calls.iter().filter_map(move |(label, call)| {
+                    call.contract_call(&handler.abis, block_ptr, log, &params)
+                        .map_err(|e| {
+                            debug!(logger, "Cannot make declared call";
+                                   "call" => label,
+                                   "error" => format!("{:#}", e));
+                        })
+                        .ok()
+                })
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 pub struct ProviderEthRpcMetrics {
     request_duration: Box<HistogramVec>,
     errors: Box<CounterVec>,
+    sent_requests: Box<CounterVec>,
+    coalesced_requests: Box<CounterVec>,
+    batched_requests: Box<CounterVec>,
+    batch_size: Box<HistogramVec>,
 }
 
 impl ProviderEthRpcMetrics {
@@ -508,9 +616,42 @@ impl ProviderEthRpcMetrics {
                 vec![String::from("method")],
             )
             .unwrap();
+        let sent_requests = registry
+            .new_counter_vec(
+                "eth_rpc_sent_requests",
+                "Counts eth rpc requests that are sent to the Ethereum node",
+                vec![String::from("method")],
+            )
+            .unwrap();
+        let coalesced_requests = registry
+            .new_counter_vec(
+                "eth_rpc_coalesced_requests",
+                "Counts eth rpc requests that are answered by an identical request in flight",
+                vec![String::from("method")],
+            )
+            .unwrap();
+        let batched_requests = registry
+            .new_counter_vec(
+                "eth_rpc_batched_requests",
+                "Counts eth rpc requests that are sent as part of a batch",
+                vec![String::from("method")],
+            )
+            .unwrap();
+        let batch_size = registry
+            .new_histogram_vec(
+                "eth_rpc_batch_size",
+                "Measures the number of requests in eth rpc batches",
+                vec![],
+                vec![2.0, 5.0, 10.0, 20.0, 50.0, 100.0],
+            )
+            .unwrap();
         Self {
             request_duration,
             errors,
+            sent_requests,
+            coalesced_requests,
+            batched_requests,
+            batch_size,
         }
     }
 
@@ -523,6 +664,28 @@ impl ProviderEthRpcMetrics {
     pub fn add_error(&self, method: &str) {
         self.errors.with_label_values(vec![method].as_slice()).inc();
     }
+
+    pub fn add_sent_request(&self, method: &str) {
+        self.sent_requests
+            .with_label_values(vec![method].as_slice())
+            .inc();
+    }
+
+    pub fn add_coalesced_request(&self, method: &str) {
+        self.coalesced_requests
+            .with_label_values(vec![method].as_slice())
+            .inc();
+    }
+
+    pub fn add_batched_request(&self, method: &str) {
+        self.batched_requests
+            .with_label_values(vec![method].as_slice())
+            .inc();
+    }
+
+    pub fn observe_batch_size(&self, size: usize) {
+        self.batch_size.with_label_values(&[]).observe(size as f64);
+    }
 }
 
 #[derive(Clone)]
@@ -532,7 +695,7 @@ pub struct SubgraphEthRpcMetrics {
 }
 
 impl SubgraphEthRpcMetrics {
-    pub fn new(registry: Arc<impl MetricsRegistry>, subgraph_hash: &str) -> Self {
+    pub fn new(registry: Arc<dyn MetricsRegistry>, subgraph_hash: &str) -> Self {
         let request_duration = registry
             .new_deployment_gauge_vec(
                 "deployment_eth_rpc_request_duration",
@@ -566,44 +729,6 @@ impl SubgraphEthRpcMetrics {
     }
 }
 
-#[derive(Clone)]
-pub struct BlockStreamMetrics {
-    pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
-    pub blocks_behind: Box<Gauge>,
-    pub reverted_blocks: Box<Gauge>,
-    pub stopwatch: StopwatchMetrics,
-}
-
-impl BlockStreamMetrics {
-    pub fn new(
-        registry: Arc<impl MetricsRegistry>,
-        ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
-        deployment_id: &SubgraphDeploymentId,
-        stopwatch: StopwatchMetrics,
-    ) -> Self {
-        let blocks_behind = registry
-            .new_deployment_gauge(
-                "deployment_blocks_behind",
-                "Track the number of blocks a subgraph deployment is behind the HEAD block",
-                deployment_id.as_str(),
-            )
-            .expect("failed to create `deployment_blocks_behind` gauge");
-        let reverted_blocks = registry
-            .new_deployment_gauge(
-                "deployment_reverted_blocks",
-                "Track the last reverted block for a subgraph deployment",
-                deployment_id.as_str(),
-            )
-            .expect("Failed to create `deployment_reverted_blocks` gauge");
-        Self {
-            ethrpc_metrics,
-            blocks_behind,
-            reverted_blocks,
-            stopwatch,
-        }
-    }
-}
-
 /// Common trait for components that watch and manage access to Ethereum.
 ///
 /// Implementations may be implemented against an in-process Ethereum node
@@ -668,9 +793,12 @@ pub trait EthereumAdapter: Send + Sync + 'static {
     ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send>;
 
     /// Load full information for the specified `block` (in particular, transaction receipts).
+    /// Receipts are taken from `chain_store` if it has them, and the full block is stored
+    /// there otherwise.
     fn load_full_block(
         &self,
         logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
         block: LightEthereumBlock,
     ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send>;
 
@@ -729,10 +857,13 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_ptr: EthereumBlockPointer,
     ) -> Box<dyn Future<Item = bool, Error = Error> + Send>;
 
+    /// Load the calls in the block with `block_hash`. The traces of the block are cached in
+    /// `chain_store`.
     fn calls_in_block(
         &self,
         logger: &Logger,
         subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
         block_number: u64,
         block_hash: H256,
     ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send>;
@@ -746,10 +877,14 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         log_filter: EthereumLogFilter,
     ) -> DynTryFuture<'static, Vec<Log>, Error>;
 
+    /// Find the calls in the block range `[from, to]` that match
+    /// `call_filter`. The traces of the blocks are served from `chain_store`
+    /// if it has them for the whole range, and are cached there otherwise.
     fn calls_in_block_range(
         &self,
         logger: &Logger,
         subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
         from: u64,
         to: u64,
         call_filter: EthereumCallFilter,
@@ -904,9 +1039,16 @@ pub async fn blocks_with_triggers(
 
     if !call_filter.is_empty() {
         trigger_futs.push(Box::new(
-            eth.calls_in_block_range(&logger, subgraph_metrics.clone(), from, to, call_filter)
-                .map(EthereumTrigger::Call)
-                .collect(),
+            eth.calls_in_block_range(
+                &logger,
+                subgraph_metrics.clone(),
+                chain_store.clone(),
+                from,
+                to,
+                call_filter,
+            )
+            .map(EthereumTrigger::Call)
+            .collect(),
         ));
     }
 
@@ -926,14 +1068,21 @@ pub async fn blocks_with_triggers(
         // a `call_filter` and run `blocks_with_calls`
         let call_filter = EthereumCallFilter::from(block_filter);
         trigger_futs.push(Box::new(
-            eth.calls_in_block_range(&logger, subgraph_metrics.clone(), from, to, call_filter)
-                .map(|call| {
-                    EthereumTrigger::Block(
-                        EthereumBlockPointer::from(&call),
-                        EthereumBlockTriggerType::WithCallTo(call.to),
-                    )
-                })
-                .collect(),
+            eth.calls_in_block_range(
+                &logger,
+                subgraph_metrics.clone(),
+                chain_store.clone(),
+                from,
+                to,
+                call_filter,
+            )
+            .map(|call| {
+                EthereumTrigger::Block(
+                    EthereumBlockPointer::from(&call),
+                    EthereumBlockTriggerType::WithCallTo(call.to),
+                )
+            })
+            .collect(),
         ));
     }
 
@@ -1,19 +1,19 @@
 mod adapter;
 mod listener;
 mod network;
-mod stream;
 mod types;
 
 pub use self::adapter::{
-    blocks_with_triggers, triggers_in_block, BlockStreamMetrics, EthGetLogsFilter, EthereumAdapter,
+    blocks_with_triggers, triggers_in_block, EthGetLogsFilter, EthereumAdapter,
     EthereumAdapterError, EthereumBlockFilter, EthereumCallFilter, EthereumContractCall,
     EthereumContractCallError, EthereumContractState, EthereumContractStateError,
-    EthereumContractStateRequest, EthereumLogFilter, EthereumNetworkIdentifier,
-    MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+    EthereumContractStateRequest, EthereumDeclaredCalls, EthereumLogFilter,
+    EthereumNetworkIdentifier, MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
 };
 pub use self::listener::{ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream};
-pub use self::network::{EthereumNetworkAdapters, EthereumNetworks, NodeCapabilities};
-pub use self::stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent};
+pub use self::network::{
+    EthereumNetworkAdapters, EthereumNetworks, NodeCapabilities, ProviderHealth, ProviderLimits,
+};
 pub use self::types::{
     BlockFinality, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
     EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall,
@@ -1,15 +1,39 @@
 use anyhow::anyhow;
-use rand::seq::IteratorRandom;
+use ethabi::Token;
+use futures::{Future, Stream};
+use lazy_static::lazy_static;
+use rand::seq::SliceRandom;
 use std::cmp::{Ord, Ordering, PartialOrd};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
+use std::time::Duration;
+use web3::types::{Log, H256};
 
-use crate::components::ethereum::EthereumAdapter;
+use crate::components::ethereum::{
+    EthereumAdapter, EthereumAdapterError, EthereumBlock, EthereumBlockPointer, EthereumCall,
+    EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumLogFilter,
+    EthereumNetworkIdentifier, LightEthereumBlock, SubgraphEthRpcMetrics,
+};
 pub use crate::impl_slog_value;
-use crate::prelude::Error;
+use crate::prelude::{
+    info, o, tokio, warn, ChainStore, CheapClone, DynTryFuture, Error, EthereumCallCache,
+    Future01CompatExt, Logger,
+};
+use futures03::FutureExt;
 use std::str::FromStr;
 
+lazy_static! {
+    static ref PROBE_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ETHEREUM_PROVIDER_PROBE_INTERVAL")
+            .map(|s| s
+                .parse()
+                .expect("invalid GRAPH_ETHEREUM_PROVIDER_PROBE_INTERVAL"))
+            .unwrap_or(30)
+    );
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct NodeCapabilities {
     pub archive: bool,
@@ -81,9 +105,87 @@ impl fmt::Display for NodeCapabilities {
 
 impl_slog_value!(NodeCapabilities, "{}");
 
+/// When an Ethereum provider is considered unhealthy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProviderLimits {
+    /// The number of requests in a row that may fail before the provider
+    /// is ejected
+    pub max_errors: usize,
+    /// How many blocks the provider's chain head may be behind the chain
+    /// head of the best provider for the same network
+    pub max_blocks_behind: u64,
+}
+
+impl Default for ProviderLimits {
+    fn default() -> Self {
+        ProviderLimits {
+            max_errors: 5,
+            max_blocks_behind: 50,
+        }
+    }
+}
+
+/// Tracks whether an Ethereum provider can be used. Providers that are
+/// ejected because requests to them fail are only selected when there is
+/// no healthy provider with the required capabilities; they are admitted
+/// again once they pass a health probe (see
+/// `EthereumNetworks::spawn_health_probes`)
+#[derive(Debug)]
+pub struct ProviderHealth {
+    label: String,
+    limits: ProviderLimits,
+    healthy: AtomicBool,
+    errors: AtomicUsize,
+}
+
+impl ProviderHealth {
+    pub fn new(label: String, limits: ProviderLimits) -> Self {
+        ProviderHealth {
+            label,
+            limits,
+            healthy: AtomicBool::new(true),
+            errors: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Record that the provider answered a request
+    pub fn record_success(&self) {
+        self.errors.store(0, AtomicOrdering::SeqCst);
+    }
+
+    /// Record that a request to the provider failed; after `max_errors`
+    /// failures in a row, the provider is ejected
+    pub fn record_error(&self) {
+        let errors = self.errors.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+        if errors >= self.limits.max_errors {
+            self.healthy.store(false, AtomicOrdering::SeqCst);
+        }
+    }
+
+    fn eject(&self) {
+        self.healthy.store(false, AtomicOrdering::SeqCst);
+    }
+
+    fn admit(&self) {
+        self.errors.store(0, AtomicOrdering::SeqCst);
+        self.healthy.store(true, AtomicOrdering::SeqCst);
+    }
+}
+
 #[derive(Clone)]
 pub struct EthereumNetworkAdapter {
     pub capabilities: NodeCapabilities,
+    /// Adapters with a higher weight are selected more often
+    pub weight: usize,
+    pub health: Arc<ProviderHealth>,
     adapter: Arc<dyn EthereumAdapter>,
 }
 
@@ -93,34 +195,288 @@ pub struct EthereumNetworkAdapters {
 }
 
 impl EthereumNetworkAdapters {
+    /// Return an adapter that sends each request to one of the adapters
+    /// with at least `required_capabilities`, so that requests move off
+    /// providers as soon as they are ejected
     pub fn cheapest_with(
         &self,
         required_capabilities: &NodeCapabilities,
-    ) -> Result<&Arc<dyn EthereumAdapter>, Error> {
-        let sufficient_adapters: Vec<&EthereumNetworkAdapter> = self
+    ) -> Result<Arc<dyn EthereumAdapter>, Error> {
+        let sufficient_adapters: Vec<EthereumNetworkAdapter> = self
             .adapters
             .iter()
             .filter(|adapter| &adapter.capabilities >= required_capabilities)
+            .cloned()
             .collect();
-        if sufficient_adapters.is_empty() {
-            return Err(anyhow!(
+        match sufficient_adapters.len() {
+            0 => Err(anyhow!(
                 "A matching Ethereum network with {:?} was not found.",
                 required_capabilities
-            ));
+            )),
+            // There is nothing to choose from
+            1 => Ok(sufficient_adapters[0].adapter.cheap_clone()),
+            _ => Ok(Arc::new(FailoverEthereumAdapter {
+                adapters: sufficient_adapters,
+            })),
         }
+    }
 
-        // Select from the matching adapters randomly
-        let mut rng = rand::thread_rng();
-        Ok(&sufficient_adapters.iter().choose(&mut rng).unwrap().adapter)
+    /// Return an adapter for requests that do not need any capabilities,
+    /// like the ones that the block ingestor makes
+    pub fn cheapest(&self) -> Option<Arc<dyn EthereumAdapter>> {
+        self.cheapest_with(&NodeCapabilities {
+            archive: false,
+            traces: false,
+        })
+        .ok()
     }
 
-    pub fn cheapest(&self) -> Option<&Arc<dyn EthereumAdapter>> {
-        // EthereumAdapters are sorted by their NodeCapabilities when the EthereumNetworks
-        // struct is instantiated so they do not need to be sorted here
-        self.adapters
-            .iter()
-            .next()
-            .map(|ethereum_network_adapter| &ethereum_network_adapter.adapter)
+    /// Eject adapters that fail to report their chain head or whose chain
+    /// head is too far behind the best chain head of all adapters, and
+    /// admit all other adapters
+    async fn probe(&self, logger: &Logger, network_name: &str) {
+        let heads = futures03::future::join_all(self.adapters.iter().map(|adapter| {
+            adapter
+                .adapter
+                .latest_block_header(logger)
+                .compat()
+                .map(|result| result.ok().and_then(|block| block.number))
+        }))
+        .await;
+        let best = heads.iter().filter_map(|head| *head).max();
+
+        for (adapter, head) in self.adapters.iter().zip(heads) {
+            let health = &adapter.health;
+            let healthy = match (head, best) {
+                (Some(head), Some(best)) => {
+                    best.as_u64().saturating_sub(head.as_u64()) <= health.limits.max_blocks_behind
+                }
+                _ => false,
+            };
+            if healthy && !health.is_healthy() {
+                info!(logger, "Admitting Ethereum provider again";
+                      "network" => network_name,
+                      "provider" => health.label());
+                health.admit();
+            } else if !healthy && health.is_healthy() {
+                warn!(logger, "Ejecting Ethereum provider since it is failing or falling behind";
+                      "network" => network_name,
+                      "provider" => health.label(),
+                      "head" => head.map(|head| head.as_u64()),
+                      "best_head" => best.map(|best| best.as_u64()));
+                health.eject();
+            }
+        }
+    }
+}
+
+/// Pick one of `adapters` at random in proportion to their weight.
+/// Healthy adapters are preferred, but we rather use an unhealthy adapter
+/// than none at all. `adapters` must not be empty
+fn select(adapters: &[EthereumNetworkAdapter]) -> &Arc<dyn EthereumAdapter> {
+    let any_healthy = adapters.iter().any(|adapter| adapter.health.is_healthy());
+    let mut rng = rand::thread_rng();
+    let selected = adapters
+        .choose_weighted(&mut rng, |adapter| {
+            if any_healthy && !adapter.health.is_healthy() {
+                0
+            } else {
+                adapter.weight
+            }
+        })
+        .unwrap_or(&adapters[0]);
+    &selected.adapter
+}
+
+/// An adapter that sends every request to one of several providers that
+/// is chosen with `select`. Since the choice is made for each request,
+/// ejecting a provider moves all subgraphs off it right away, and
+/// admitting it again lets them use it again, without the need to restart
+/// anything
+struct FailoverEthereumAdapter {
+    adapters: Vec<EthereumNetworkAdapter>,
+}
+
+impl FailoverEthereumAdapter {
+    fn adapter(&self) -> &Arc<dyn EthereumAdapter> {
+        select(&self.adapters)
+    }
+}
+
+impl EthereumAdapter for FailoverEthereumAdapter {
+    fn url_hostname(&self) -> &str {
+        self.adapter().url_hostname()
+    }
+
+    fn net_identifiers(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        self.adapter().net_identifiers(logger)
+    }
+
+    fn latest_block(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        self.adapter().latest_block(logger)
+    }
+
+    fn latest_block_header(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = web3::types::Block<H256>, Error = EthereumAdapterError> + Send> {
+        self.adapter().latest_block_header(logger)
+    }
+
+    fn load_block(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        self.adapter().load_block(logger, block_hash)
+    }
+
+    fn load_blocks(
+        &self,
+        logger: Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        self.adapter()
+            .load_blocks(logger, chain_store, block_hashes)
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        logger: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        self.adapter().block_range_to_ptrs(logger, from, to)
+    }
+
+    fn block_by_hash(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        self.adapter().block_by_hash(logger, block_hash)
+    }
+
+    fn block_by_number(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        self.adapter().block_by_number(logger, block_number)
+    }
+
+    fn load_full_block(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        self.adapter().load_full_block(logger, chain_store, block)
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        self.adapter()
+            .block_pointer_from_number(logger, chain_store, block_number)
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+        block_is_final: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        self.adapter()
+            .block_hash_by_block_number(logger, chain_store, block_number, block_is_final)
+    }
+
+    fn uncles(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<web3::types::Block<H256>>>, Error = Error> + Send> {
+        self.adapter().uncles(logger, block)
+    }
+
+    fn is_on_main_chain(
+        &self,
+        logger: &Logger,
+        metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        self.adapter()
+            .is_on_main_chain(logger, metrics, chain_store, block_ptr)
+    }
+
+    fn calls_in_block(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        self.adapter().calls_in_block(
+            logger,
+            subgraph_metrics,
+            chain_store,
+            block_number,
+            block_hash,
+        )
+    }
+
+    fn logs_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        self.adapter()
+            .logs_in_block_range(logger, subgraph_metrics, from, to, log_filter)
+    }
+
+    fn calls_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        from: u64,
+        to: u64,
+        call_filter: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        self.adapter().calls_in_block_range(
+            logger,
+            subgraph_metrics,
+            chain_store,
+            from,
+            to,
+            call_filter,
+        )
+    }
+
+    fn contract_call(
+        &self,
+        logger: &Logger,
+        call: EthereumContractCall,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        self.adapter().contract_call(logger, call, cache)
     }
 }
 
@@ -141,6 +497,23 @@ impl EthereumNetworks {
         name: String,
         capabilities: NodeCapabilities,
         adapter: Arc<dyn EthereumAdapter>,
+    ) {
+        let health = Arc::new(ProviderHealth::new(
+            adapter.url_hostname().to_string(),
+            ProviderLimits::default(),
+        ));
+        self.insert_weighted(name, capabilities, 1, health, adapter)
+    }
+
+    /// Add an adapter that is selected in proportion to `weight` and
+    /// whose health is tracked in `health`
+    pub fn insert_weighted(
+        &mut self,
+        name: String,
+        capabilities: NodeCapabilities,
+        weight: usize,
+        health: Arc<ProviderHealth>,
+        adapter: Arc<dyn EthereumAdapter>,
     ) {
         let network_adapters = self
             .networks
@@ -148,6 +521,8 @@ impl EthereumNetworks {
             .or_insert(EthereumNetworkAdapters { adapters: vec![] });
         network_adapters.adapters.push(EthereumNetworkAdapter {
             capabilities,
+            weight,
+            health,
             adapter: adapter.clone(),
         });
     }
@@ -182,6 +557,27 @@ impl EthereumNetworks {
         }
     }
 
+    /// Probe the health of all adapters every `GRAPH_ETHEREUM_PROVIDER_PROBE_INTERVAL`
+    /// seconds in the background
+    pub fn spawn_health_probes(&self, logger: &Logger) {
+        for (network_name, adapters) in &self.networks {
+            // A single adapter is used whether it is healthy or not
+            if adapters.adapters.len() < 2 {
+                continue;
+            }
+
+            let logger = logger.new(o!("component" => "EthereumProviderProbe"));
+            let network_name = network_name.clone();
+            let adapters = adapters.clone();
+            crate::spawn(async move {
+                loop {
+                    tokio::time::delay_for(*PROBE_INTERVAL).await;
+                    adapters.probe(&logger, &network_name).await;
+                }
+            });
+        }
+    }
+
     pub fn adapter_with_capabilities(
         &self,
         network_name: String,
@@ -196,7 +592,217 @@ impl EthereumNetworks {
 
 #[cfg(test)]
 mod tests {
-    use super::NodeCapabilities;
+    use super::{
+        select, EthereumNetworkAdapter, EthereumNetworks, NodeCapabilities, ProviderHealth,
+        ProviderLimits,
+    };
+    use crate::components::ethereum::{EthereumAdapter, EthereumAdapterError, MockEthereumAdapter};
+    use crate::prelude::{anyhow, future, o, slog, Future, Logger};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use web3::types::{Block, H256};
+
+    const FULL: NodeCapabilities = NodeCapabilities {
+        archive: false,
+        traces: false,
+    };
+
+    const LIMITS: ProviderLimits = ProviderLimits {
+        max_errors: 2,
+        max_blocks_behind: 10,
+    };
+
+    /// An adapter whose chain head is whatever `head` is set to; a head of
+    /// 0 makes requests for the chain head fail
+    fn adapter_at(head: Arc<AtomicU64>) -> Arc<dyn EthereumAdapter> {
+        let mut adapter = MockEthereumAdapter::new();
+        adapter
+            .expect_latest_block_header()
+            .returning(move |_: &Logger| {
+                let head = head.load(Ordering::SeqCst);
+                let result: Result<Block<H256>, EthereumAdapterError> = if head == 0 {
+                    Err(anyhow!("provider is down").into())
+                } else {
+                    let mut block: Block<H256> = Block::default();
+                    block.number = Some(head.into());
+                    Ok(block)
+                };
+                Box::new(future::result(result))
+            });
+        Arc::new(adapter)
+    }
+
+    fn network_adapter(
+        label: &str,
+        weight: usize,
+        adapter: Arc<dyn EthereumAdapter>,
+    ) -> EthereumNetworkAdapter {
+        EthereumNetworkAdapter {
+            capabilities: FULL,
+            weight,
+            health: Arc::new(ProviderHealth::new(label.to_string(), LIMITS)),
+            adapter,
+        }
+    }
+
+    #[test]
+    fn providers_are_ejected_after_errors_in_a_row() {
+        let health = ProviderHealth::new("one".to_string(), LIMITS);
+
+        // A single error does not eject a provider, and a success resets
+        // the error count
+        health.record_error();
+        health.record_success();
+        health.record_error();
+        assert!(health.is_healthy());
+
+        health.record_error();
+        assert!(!health.is_healthy());
+
+        // Successful requests do not admit a provider again; only a probe
+        // does that
+        health.record_success();
+        assert!(!health.is_healthy());
+        health.admit();
+        assert!(health.is_healthy());
+    }
+
+    #[test]
+    fn unhealthy_adapters_are_avoided() {
+        let adapter1: Arc<dyn EthereumAdapter> = Arc::new(MockEthereumAdapter::new());
+        let adapter2: Arc<dyn EthereumAdapter> = Arc::new(MockEthereumAdapter::new());
+        let adapters = vec![
+            network_adapter("one", 1, adapter1.clone()),
+            network_adapter("two", 1, adapter2.clone()),
+        ];
+
+        adapters[0].health.eject();
+        for _ in 0..20 {
+            assert!(Arc::ptr_eq(select(&adapters), &adapter2));
+        }
+
+        // Without a healthy provider, unhealthy ones are used
+        adapters[1].health.eject();
+        let selected = select(&adapters);
+        assert!(Arc::ptr_eq(selected, &adapter1) || Arc::ptr_eq(selected, &adapter2));
+
+        adapters[0].health.admit();
+        for _ in 0..20 {
+            assert!(Arc::ptr_eq(select(&adapters), &adapter1));
+        }
+    }
+
+    #[test]
+    fn adapters_are_selected_by_weight() {
+        let adapter1: Arc<dyn EthereumAdapter> = Arc::new(MockEthereumAdapter::new());
+        let adapter2: Arc<dyn EthereumAdapter> = Arc::new(MockEthereumAdapter::new());
+        let adapters = vec![
+            network_adapter("one", 3, adapter1.clone()),
+            network_adapter("two", 1, adapter2.clone()),
+        ];
+
+        // We expect 3000 requests to go to the first adapter; allow for
+        // plenty of randomness so that the test does not fail spuriously
+        let count = (0..4000)
+            .filter(|_| Arc::ptr_eq(select(&adapters), &adapter1))
+            .count();
+        assert!(count > 2700 && count < 3300, "count = {}", count);
+    }
+
+    #[test]
+    fn probes_eject_and_admit_providers() {
+        let logger = Logger::root(slog::Discard, o!());
+        let heads: Vec<_> = vec![100, 95, 80, 0]
+            .into_iter()
+            .map(|head| Arc::new(AtomicU64::new(head)))
+            .collect();
+
+        let mut networks = EthereumNetworks::new();
+        for (i, head) in heads.iter().enumerate() {
+            networks.insert_weighted(
+                "mainnet".to_string(),
+                FULL,
+                1,
+                Arc::new(ProviderHealth::new(i.to_string(), LIMITS)),
+                adapter_at(head.clone()),
+            );
+        }
+        let adapters = &networks.networks["mainnet"];
+        let healthy = || -> Vec<bool> {
+            adapters
+                .adapters
+                .iter()
+                .map(|adapter| adapter.health.is_healthy())
+                .collect()
+        };
+
+        // Providers that fall too far behind or fail are ejected
+        futures03::executor::block_on(adapters.probe(&logger, "mainnet"));
+        assert_eq!(vec![true, true, false, false], healthy());
+
+        // Once they catch up, they are admitted again; a provider that
+        // stops answering is ejected
+        heads[0].store(0, Ordering::SeqCst);
+        heads[2].store(99, Ordering::SeqCst);
+        heads[3].store(101, Ordering::SeqCst);
+        futures03::executor::block_on(adapters.probe(&logger, "mainnet"));
+        assert_eq!(vec![false, true, true, true], healthy());
+    }
+
+    #[test]
+    fn requests_follow_provider_health() {
+        let head1 = Arc::new(AtomicU64::new(1));
+        let head2 = Arc::new(AtomicU64::new(2));
+        let health1 = Arc::new(ProviderHealth::new("one".to_string(), LIMITS));
+
+        let mut networks = EthereumNetworks::new();
+        networks.insert_weighted(
+            "mainnet".to_string(),
+            FULL,
+            1,
+            health1.clone(),
+            adapter_at(head1),
+        );
+
+        // With only one provider, it is used directly
+        let adapter = networks
+            .adapter_with_capabilities("mainnet".to_string(), &FULL)
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            &adapter,
+            &networks.networks["mainnet"].adapters[0].adapter
+        ));
+
+        networks.insert_weighted(
+            "mainnet".to_string(),
+            FULL,
+            1,
+            Arc::new(ProviderHealth::new("two".to_string(), LIMITS)),
+            adapter_at(head2),
+        );
+
+        // The adapter that a subgraph holds on to moves off a provider as
+        // soon as it is ejected, and back to it when it is admitted again
+        let adapter = networks
+            .adapter_with_capabilities("mainnet".to_string(), &FULL)
+            .unwrap();
+        let head = |adapter: &Arc<dyn EthereumAdapter>| {
+            adapter
+                .latest_block_header(&Logger::root(slog::Discard, o!()))
+                .wait()
+                .unwrap()
+                .number
+                .unwrap()
+                .as_u64()
+        };
+
+        health1.eject();
+        for _ in 0..20 {
+            assert_eq!(2, head(&adapter));
+        }
+        health1.admit();
+        assert!((0..100).any(|_| head(&adapter) == 1));
+    }
 
     #[test]
     fn ethereum_capabilities_comparison() {
@@ -7,6 +7,7 @@ use std::{cmp::Ordering, convert::TryFrom};
 use std::{fmt, str::FromStr};
 use web3::types::*;
 
+use crate::blockchain::{Block as BlockchainBlock, BlockPtr, TriggerData};
 use crate::prelude::{EntityKey, SubgraphDeploymentId, ToEntityKey};
 
 pub type LightEthereumBlock = Block<Transaction>;
@@ -134,7 +135,7 @@ pub struct EthereumCall {
     pub block_number: u64,
     pub block_hash: H256,
     pub transaction_hash: Option<H256>,
-    transaction_index: u64,
+    pub transaction_index: u64,
 }
 
 impl EthereumCall {
@@ -202,6 +203,25 @@ impl PartialEq for EthereumTrigger {
 
 impl Eq for EthereumTrigger {}
 
+impl TriggerData for EthereumTrigger {
+    fn kind(&self) -> &'static str {
+        match self {
+            EthereumTrigger::Log(_) => "event",
+            EthereumTrigger::Call(_) => "call",
+            EthereumTrigger::Block(..) => "block",
+        }
+    }
+
+    fn error_context(&self) -> Option<String> {
+        let transaction_hash = match self {
+            EthereumTrigger::Log(log) => log.transaction_hash,
+            EthereumTrigger::Call(call) => call.transaction_hash,
+            EthereumTrigger::Block(..) => None,
+        };
+        transaction_hash.map(|hash| format!("transaction {:x}", hash))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum EthereumBlockTriggerType {
     Every,
@@ -546,6 +566,44 @@ impl<'a> From<&'a BlockFinality> for EthereumBlockPointer {
     }
 }
 
+impl From<EthereumBlockPointer> for BlockPtr {
+    fn from(ptr: EthereumBlockPointer) -> Self {
+        BlockPtr::new(ptr.hash.as_bytes().to_vec().into(), ptr.block_number())
+    }
+}
+
+impl<'a> TryFrom<&'a BlockPtr> for EthereumBlockPointer {
+    type Error = anyhow::Error;
+
+    fn try_from(ptr: &'a BlockPtr) -> Result<Self, Self::Error> {
+        let hash = ptr.hash.as_slice();
+        if hash.len() != 32 {
+            return Err(anyhow!(
+                "block hash `{}` is not a valid Ethereum block hash",
+                ptr.hash
+            ));
+        }
+        Ok(EthereumBlockPointer {
+            hash: H256::from_slice(hash),
+            number: ptr.number as u64,
+        })
+    }
+}
+
+impl BlockchainBlock for BlockFinality {
+    fn ptr(&self) -> BlockPtr {
+        EthereumBlockPointer::from(self).into()
+    }
+
+    fn parent_ptr(&self) -> Option<BlockPtr> {
+        let parent = match self {
+            BlockFinality::Final(block) => block.parent_ptr(),
+            BlockFinality::NonFinal(block) => block.ethereum_block.block.parent_ptr(),
+        };
+        parent.map(BlockPtr::from)
+    }
+}
+
 impl From<EthereumBlockPointer> for H256 {
     fn from(ptr: EthereumBlockPointer) -> Self {
         ptr.hash
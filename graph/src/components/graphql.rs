@@ -55,5 +55,11 @@ pub trait GraphQlRunner: Send + Sync + 'static {
 pub trait QueryLoadManager: Send + Sync {
     async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit;
 
-    fn record_work(&self, shape_hash: u64, duration: Duration, cache_status: CacheStatus);
+    fn record_work(
+        &self,
+        shape_hash: u64,
+        api_key: Option<&str>,
+        duration: Duration,
+        cache_status: CacheStatus,
+    );
 }
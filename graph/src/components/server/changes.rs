@@ -0,0 +1,13 @@
+use futures::prelude::*;
+
+/// Common trait for entity change streaming server implementations.
+pub trait EntityChangesServer {
+    type ServeError;
+
+    /// Creates a new Tokio task that, when spawned, brings up the entity
+    /// change streaming server.
+    fn serve(
+        &mut self,
+        port: u16,
+    ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError>;
+}
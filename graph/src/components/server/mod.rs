@@ -12,3 +12,6 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Component for streaming entity changes.
+pub mod changes;
@@ -13,10 +13,13 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 use thiserror::Error;
-use web3::types::{Address, H256};
+use web3::types::{Address, Trace, TransactionReceipt, H256};
 
 use crate::data::subgraph::status;
-use crate::data::{query::QueryTarget, subgraph::schema::*};
+use crate::data::{
+    query::{QueryTarget, SqlTrace},
+    subgraph::schema::*,
+};
 use crate::data::{store::*, subgraph::Source};
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
@@ -205,6 +208,19 @@ fn key_stable_hash() {
     );
 }
 
+/// A filter on the entities of type `entity_type` that an entity refers to
+/// through `attr`. It matches if at least one of the referenced entities
+/// matches `filter`. If `derived` is `true`, `attr` is the attribute of the
+/// child entities that holds the id of the parent; otherwise, it is the
+/// attribute of the parent entity that holds the ids of the children
+#[derive(Clone, Debug, PartialEq)]
+pub struct Child {
+    pub attr: Attribute,
+    pub entity_type: String,
+    pub filter: Box<EntityFilter>,
+    pub derived: bool,
+}
+
 /// Supported types of store filters.
 #[derive(Clone, Debug, PartialEq)]
 pub enum EntityFilter {
@@ -224,6 +240,15 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    ContainsNoCase(Attribute, Value),
+    NotContainsNoCase(Attribute, Value),
+    StartsWithNoCase(Attribute, Value),
+    NotStartsWithNoCase(Attribute, Value),
+    EndsWithNoCase(Attribute, Value),
+    NotEndsWithNoCase(Attribute, Value),
+    /// Case-insensitive match against a POSIX regular expression
+    Matches(Attribute, Value),
+    Child(Child),
 }
 
 // Define some convenience methods
@@ -302,6 +327,38 @@ impl EntityRange {
     }
 }
 
+/// A position in an ordered list of entities, as identified by the value
+/// of the attribute the list is ordered by and the id of the entity at
+/// that position. When entities are ordered by their id, `value` is `None`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CursorPosition {
+    pub value: Option<Value>,
+    pub id: String,
+}
+
+/// Where to start returning entities in an ordered list of entities. With
+/// `After`, the query returns entities that come strictly after the
+/// position in the query's order; with `Before`, the query returns the
+/// entities that come strictly before it, closest to the position first
+/// in the database but in the query's order in the result
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityCursor {
+    After(CursorPosition),
+    Before(CursorPosition),
+}
+
+impl EntityCursor {
+    pub fn position(&self) -> &CursorPosition {
+        match self {
+            EntityCursor::After(position) | EntityCursor::Before(position) => position,
+        }
+    }
+
+    pub fn is_before(&self) -> bool {
+        matches!(self, EntityCursor::Before(_))
+    }
+}
+
 /// The attribute we want to window by in an `EntityWindow`. We have to
 /// distinguish between scalar and list attributes since we need to use
 /// different queries for them, and the JSONB storage scheme can not
@@ -426,11 +483,19 @@ pub struct EntityQuery {
     /// A range to limit the size of the result.
     pub range: EntityRange,
 
+    /// Only return entities that come after or before a position in the
+    /// order given by `order`. Cursors can not be used with windowed
+    /// collections
+    pub cursor: Option<EntityCursor>,
+
     /// Optional logger for anything related to this query
     pub logger: Option<Logger>,
 
     pub query_id: Option<String>,
 
+    /// Whether the store should return a `SqlTrace` of the query it ran
+    pub trace: bool,
+
     _force_use_of_new: (),
 }
 
@@ -447,8 +512,10 @@ impl EntityQuery {
             filter: None,
             order: EntityOrder::Default,
             range: EntityRange::first(100),
+            cursor: None,
             logger: None,
             query_id: None,
+            trace: false,
             _force_use_of_new: (),
         }
     }
@@ -478,6 +545,11 @@ impl EntityQuery {
         self
     }
 
+    pub fn cursor(mut self, cursor: EntityCursor) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
     pub fn simplify(mut self) -> Self {
         // If there is one window, with one id, in a direct relation to the
         // entities, we can simplify the query by changing the filter and
@@ -506,6 +578,97 @@ impl EntityQuery {
     }
 }
 
+/// An aggregate that can be computed over the entities of one type. Apart
+/// from `Count`, aggregates can only be computed over numeric attributes
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntityAggregate {
+    Count,
+    Sum(Attribute),
+    Min(Attribute),
+    Max(Attribute),
+    Avg(Attribute),
+}
+
+impl EntityAggregate {
+    /// Parse the name of an aggregate as it appears in the GraphQL API,
+    /// i.e., either `count` or `<attribute>_<function>`, like `amount_sum`
+    pub fn from_name(name: &str) -> Option<Self> {
+        use EntityAggregate::*;
+
+        if name == "count" {
+            return Some(Count);
+        }
+        let mut parts = name.rsplitn(2, '_');
+        let function = parts.next()?;
+        let attr = parts.next().filter(|attr| !attr.is_empty())?.to_owned();
+        match function {
+            "sum" => Some(Sum(attr)),
+            "min" => Some(Min(attr)),
+            "max" => Some(Max(attr)),
+            "avg" => Some(Avg(attr)),
+            _ => None,
+        }
+    }
+
+    /// The name under which the value of this aggregate is returned; this
+    /// is the inverse of `from_name`
+    pub fn name(&self) -> String {
+        use EntityAggregate::*;
+
+        match self {
+            Count => "count".to_owned(),
+            Sum(attr) => format!("{}_sum", attr),
+            Min(attr) => format!("{}_min", attr),
+            Max(attr) => format!("{}_max", attr),
+            Avg(attr) => format!("{}_avg", attr),
+        }
+    }
+
+    /// The attribute this aggregate is computed over
+    pub fn attribute(&self) -> Option<&Attribute> {
+        use EntityAggregate::*;
+
+        match self {
+            Count => None,
+            Sum(attr) | Min(attr) | Max(attr) | Avg(attr) => Some(attr),
+        }
+    }
+}
+
+/// A query that computes aggregates over the entities of one type,
+/// optionally grouped by some of their attributes. Each result contains
+/// the value of each aggregate under its `name()`, and the value of each
+/// attribute in `group_by`
+#[derive(Clone, Debug)]
+pub struct EntityAggregation {
+    /// ID of the subgraph.
+    pub subgraph_id: SubgraphDeploymentId,
+
+    /// The block height at which to execute the query.
+    pub block: BlockNumber,
+
+    /// The entity type whose entities are aggregated
+    pub entity_type: String,
+
+    /// Filter for the entities that are aggregated
+    pub filter: Option<EntityFilter>,
+
+    /// The aggregates to compute
+    pub aggregates: Vec<EntityAggregate>,
+
+    /// The attributes to group entities by. Without any, the result
+    /// consists of exactly one row
+    pub group_by: Vec<Attribute>,
+
+    /// A range to limit the number of groups in the result
+    pub range: EntityRange,
+
+    /// Optional logger for anything related to this query
+    pub logger: Option<Logger>,
+
+    pub query_id: Option<String>,
+}
+
 /// Operation types that lead to entity changes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -1003,6 +1166,16 @@ pub trait Store: Send + Sync + 'static {
         block_ptr_to: EthereumBlockPointer,
     ) -> Result<(), StoreError>;
 
+    /// Return at most `limit` entries from the entity change log of the
+    /// deployment whose cursor is bigger than `after`, ordered by their
+    /// cursor. Fails if entries after `after` have been pruned from the log
+    fn entity_change_log(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError>;
+
     /// Find the deployment for the current version of subgraph `name` and
     /// return details about it needed for executing queries
     fn deployment_state_from_name(&self, name: SubgraphName)
@@ -1082,6 +1255,14 @@ pub trait Store: Send + Sync + 'static {
 
     fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError>;
 
+    /// Return the most recent reorgs of `network`, or of all networks if
+    /// `network` is `None`, newest first and at most `first` of them
+    fn chain_reorgs(
+        &self,
+        network: Option<String>,
+        first: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError>;
+
     /// Load the dynamic data sources for the given deployment
     async fn load_dynamic_data_sources(
         &self,
@@ -1141,6 +1322,12 @@ pub trait QueryStoreManager: Send + Sync + 'static {
         target: QueryTarget,
         for_subscription: bool,
     ) -> Result<Arc<dyn QueryStore + Send + Sync>, QueryExecutionError>;
+
+    /// Check whether a query with the given `shape_hash` may run against
+    /// the deployment `id`. Deployments that are in allow-list mode only
+    /// run queries whose shape is in their allow-list.
+    fn query_allowed(&self, id: &SubgraphDeploymentId, shape_hash: u64)
+        -> Result<bool, StoreError>;
 }
 
 mock! {
@@ -1226,6 +1413,15 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn entity_change_log(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _after: i64,
+        _limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        unimplemented!()
+    }
+
     fn deployment_state_from_name(&self, _: SubgraphName) -> Result<DeploymentState, StoreError> {
         unimplemented!()
     }
@@ -1289,6 +1485,14 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn chain_reorgs(
+        &self,
+        _: Option<String>,
+        _: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError> {
+        unimplemented!()
+    }
+
     async fn load_dynamic_data_sources(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -1353,6 +1557,33 @@ pub trait ChainStore: Send + Sync + 'static {
 
     fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), Error>;
 
+    /// Insert a single block together with its transaction receipts (or
+    /// update it if it is already present).
+    fn upsert_block(&self, block: EthereumBlock) -> Result<(), Error>;
+
+    /// Return the transaction receipts of the block with `block_hash` if
+    /// the full block is in the store. Returns `None` if the store only has
+    /// the light block, or no block at all.
+    fn transaction_receipts(
+        &self,
+        block_hash: &H256,
+    ) -> Result<Option<Vec<TransactionReceipt>>, Error>;
+
+    /// Return all traces of the block with `block_hash` if they are cached.
+    fn block_traces(&self, block_hash: &H256) -> Result<Option<Vec<Trace>>, Error>;
+
+    /// Cache all traces of the block `block_ptr`.
+    fn set_block_traces(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        traces: &[Trace],
+    ) -> Result<(), Error>;
+
+    /// Return all traces of the blocks with numbers in `[from, to]`, ordered
+    /// by block number. Returns `None` unless the traces of exactly one block
+    /// are cached for every number in the range.
+    fn block_traces_in_range(&self, from: u64, to: u64) -> Result<Option<Vec<Trace>>, Error>;
+
     /// Try to update the head block pointer to the block with the highest block number.
     ///
     /// Only updates pointer if there is a block with a higher block number than the current head
@@ -1371,6 +1602,19 @@ pub trait ChainStore: Send + Sync + 'static {
     /// `Ok(missing_blocks)`, where `missing_blocks` is a nonexhaustive list of missing blocks.
     fn attempt_chain_head_update(&self, ancestor_count: u64) -> Result<Vec<H256>, Error>;
 
+    /// Check whether the chain head moving from `old_head` to `new_head` was
+    /// a reorg, i.e., whether `old_head` is not an ancestor of `new_head`,
+    /// and if so, add it to the reorg log and return it. Only reorgs that
+    /// are at most `max_depth` blocks deep are detected; returns `None` if
+    /// the move was not a reorg or if the blocks needed to find the common
+    /// ancestor of the two heads are not in the store.
+    fn record_reorg(
+        &self,
+        old_head: EthereumBlockPointer,
+        new_head: EthereumBlockPointer,
+        max_depth: u64,
+    ) -> Result<Option<status::ChainReorg>, Error>;
+
     /// Subscribe to chain head updates.
     fn chain_head_updates(&self) -> ChainHeadUpdateStream;
 
@@ -1435,11 +1679,22 @@ pub trait EthereumCallCache: Send + Sync + 'static {
 /// Store operations used when serving queries for a specific deployment
 #[async_trait]
 pub trait QueryStore: Send + Sync {
+    /// Run `query`; if `query.trace` is set, also return a trace of the
+    /// SQL that was run
     fn find_query_values(
         &self,
         query: EntityQuery,
+    ) -> Result<(Vec<BTreeMap<String, q::Value>>, Option<SqlTrace>), QueryExecutionError>;
+
+    fn aggregate(
+        &self,
+        query: EntityAggregation,
     ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError>;
 
+    /// Return the database's estimate of the cost of running `query`
+    /// without running it
+    fn estimate_cost(&self, query: EntityQuery) -> Result<f64, QueryExecutionError>;
+
     fn is_deployment_synced(&self, id: &SubgraphDeploymentId) -> Result<bool, Error>;
 
     fn block_ptr(
@@ -1449,6 +1704,9 @@ pub trait QueryStore: Send + Sync {
 
     fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError>;
 
+    /// The head of the chain that the deployment indexes
+    fn chain_head_ptr(&self) -> Result<Option<EthereumBlockPointer>, QueryExecutionError>;
+
     fn wait_stats(&self) -> &PoolWaitStats;
 
     /// If `block` is `None`, assumes the latest block.
@@ -1496,6 +1754,33 @@ impl EntityModification {
     }
 }
 
+/// The entity modifications that processing one block made to a
+/// deployment
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockModifications {
+    pub block: BlockNumber,
+    pub mods: Vec<EntityModification>,
+}
+
+/// An entry in the entity change log of a deployment. Entries are written
+/// when a block is processed or reverted, and the entries of a deployment
+/// are numbered consecutively by their `cursor`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeLogEntry {
+    pub cursor: i64,
+    pub block: EthereumBlockPointer,
+    pub operation: ChangeLogOperation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeLogOperation {
+    /// Processing `block` made these modifications
+    Changes(Vec<EntityModification>),
+    /// All blocks after `block` were reverted, and the changes that were
+    /// logged for them must be discarded
+    Revert,
+}
+
 /// A representation of entity operations that can be accumulated.
 #[derive(Debug, Clone)]
 enum EntityOp {
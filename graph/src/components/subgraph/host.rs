@@ -1,4 +1,5 @@
 use std::cmp::PartialEq;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::sync::Arc;
 
@@ -8,6 +9,7 @@ use futures::sync::mpsc;
 
 use crate::components::metrics::HistogramVec;
 use crate::components::subgraph::SharedProofOfIndexing;
+use crate::data::subgraph::SubgraphFeature;
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
@@ -35,8 +37,19 @@ impl MappingError {
 }
 
 /// Common trait for runtime host implementations.
-#[async_trait]
 pub trait RuntimeHost: Send + Sync + Debug + 'static {
+    /// Block number in which this host was created.
+    /// Returns `None` for static data sources.
+    fn creation_block_number(&self) -> Option<u64>;
+
+    /// The entity types listed in the `entities` of the data source's mapping
+    fn entity_types(&self) -> &[String];
+}
+
+/// A runtime host with handlers for Ethereum triggers. The Ethereum chain
+/// uses this to match its triggers against hosts and to process them.
+#[async_trait]
+pub trait EthereumRuntimeHost: RuntimeHost {
     /// Returns true if the RuntimeHost has a handler for an Ethereum event.
     fn matches_log(&self, log: &Log) -> bool;
 
@@ -77,10 +90,6 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError>;
-
-    /// Block number in which this host was created.
-    /// Returns `None` for static data sources.
-    fn creation_block_number(&self) -> Option<u64>;
 }
 
 pub struct HostMetrics {
@@ -149,6 +158,7 @@ pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
         &self,
         network_name: String,
         subgraph_id: SubgraphDeploymentId,
+        features: &BTreeSet<SubgraphFeature>,
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
         mapping_request_sender: mpsc::Sender<Self::Req>,
@@ -1,12 +1,6 @@
-use async_trait::async_trait;
-use web3::types::Log;
-
+use crate::data::subgraph::schema::SubgraphError;
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
-use crate::{
-    components::subgraph::{MappingError, SharedProofOfIndexing},
-    data::subgraph::schema::SubgraphError,
-};
 
 #[derive(Clone, Debug)]
 pub struct DataSourceTemplateInfo {
@@ -74,6 +68,13 @@ impl BlockState {
         std::mem::replace(&mut self.created_data_sources, Vec::new())
     }
 
+    /// Add data sources that were created by handlers that ran with a
+    /// different `BlockState`
+    pub fn extend_created_data_sources(&mut self, data_sources: Vec<DataSourceTemplateInfo>) {
+        assert!(!self.in_handler);
+        self.created_data_sources.extend(data_sources);
+    }
+
     pub fn enter_handler(&mut self) {
         assert!(!self.in_handler);
         self.in_handler = true;
@@ -103,31 +104,7 @@ impl BlockState {
 }
 
 /// Represents a loaded instance of a subgraph.
-#[async_trait]
 pub trait SubgraphInstance<H: RuntimeHost> {
-    /// Returns true if the subgraph has a handler for an Ethereum event.
-    fn matches_log(&self, log: &Log) -> bool;
-
-    /// Process and Ethereum trigger and return the resulting entity operations as a future.
-    async fn process_trigger(
-        &self,
-        logger: &Logger,
-        block: &Arc<LightEthereumBlock>,
-        trigger: EthereumTrigger,
-        state: BlockState,
-        proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, MappingError>;
-
-    /// Like `process_trigger` but processes an Ethereum event in a given list of hosts.
-    async fn process_trigger_in_runtime_hosts(
-        logger: &Logger,
-        hosts: &[Arc<H>],
-        block: &Arc<LightEthereumBlock>,
-        trigger: EthereumTrigger,
-        state: BlockState,
-        proof_of_indexing: SharedProofOfIndexing,
-    ) -> Result<BlockState, MappingError>;
-
     /// Adds dynamic data sources to the subgraph.
     fn add_dynamic_data_source(
         &mut self,
@@ -264,4 +264,60 @@ mod tests {
             }
         }
     }
+
+    /// Writing events to deferred PoIs and appending those must give the
+    /// same digest as writing the events directly
+    #[test]
+    fn deferred_matches_direct() {
+        let logger = Logger::root(Discard, o!());
+        let data = hashmap! {
+            "val".to_owned() => Value::Int(1)
+        };
+        let events = vec![
+            (
+                "eth",
+                ProofOfIndexingEvent::RemoveEntity {
+                    entity_type: "t",
+                    id: "1",
+                },
+            ),
+            (
+                "eth",
+                ProofOfIndexingEvent::SetEntity {
+                    entity_type: "t",
+                    id: "2",
+                    data: &data,
+                },
+            ),
+            (
+                "ipfs",
+                ProofOfIndexingEvent::RemoveEntity {
+                    entity_type: "u",
+                    id: "3",
+                },
+            ),
+        ];
+
+        let mut direct = ProofOfIndexing::new(7);
+        for (region, event) in &events {
+            direct.write(&logger, region, event);
+        }
+
+        let mut appended = ProofOfIndexing::new(7);
+        for chunk in events.chunks(2) {
+            let mut deferred = ProofOfIndexing::deferred();
+            for (region, event) in chunk {
+                deferred.write(&logger, region, event);
+            }
+            appended.append(&logger, deferred);
+        }
+
+        let digests = |poi: ProofOfIndexing| {
+            poi.take()
+                .into_iter()
+                .map(|(region, stream)| (region, stream.pause(None)))
+                .collect::<HashMap<_, _>>()
+        };
+        assert_eq!(digests(direct), digests(appended));
+    }
 }
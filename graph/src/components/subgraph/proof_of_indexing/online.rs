@@ -3,7 +3,7 @@
 //! to the reference implementation, but this is updated incrementally
 
 use super::ProofOfIndexingEvent;
-use crate::prelude::{debug, EthereumBlockPointer, Logger, SubgraphDeploymentId};
+use crate::prelude::{debug, EthereumBlockPointer, Logger, SubgraphDeploymentId, Value};
 use lazy_static::lazy_static;
 use stable_hash::crypto::{Blake3SeqNo, SetHasher};
 use stable_hash::prelude::*;
@@ -99,6 +99,56 @@ impl BlockEventStream {
     }
 }
 
+/// An event that was written to a deferred `ProofOfIndexing`
+enum DeferredEvent {
+    RemoveEntity {
+        entity_type: String,
+        id: String,
+    },
+    SetEntity {
+        entity_type: String,
+        id: String,
+        data: HashMap<String, Value>,
+    },
+}
+
+impl DeferredEvent {
+    fn new(event: &ProofOfIndexingEvent<'_>) -> Self {
+        match event {
+            ProofOfIndexingEvent::RemoveEntity { entity_type, id } => DeferredEvent::RemoveEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+            },
+            ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => DeferredEvent::SetEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                data: (*data).clone(),
+            },
+        }
+    }
+
+    fn as_event(&self) -> ProofOfIndexingEvent<'_> {
+        match self {
+            DeferredEvent::RemoveEntity { entity_type, id } => {
+                ProofOfIndexingEvent::RemoveEntity { entity_type, id }
+            }
+            DeferredEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            },
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ProofOfIndexing {
     block_number: u64,
@@ -107,6 +157,9 @@ pub struct ProofOfIndexing {
     /// state with other data sources. This may also give us some freedom to change
     /// the order of triggers in the future.
     per_causality_region: HashMap<String, BlockEventStream>,
+    /// Set for a deferred `ProofOfIndexing`; events are kept here in the
+    /// order in which they were written instead of being hashed
+    deferred: Option<Vec<(String, DeferredEvent)>>,
 }
 
 impl fmt::Debug for ProofOfIndexing {
@@ -120,8 +173,22 @@ impl ProofOfIndexing {
         Self {
             block_number,
             per_causality_region: HashMap::new(),
+            deferred: None,
         }
     }
+
+    /// A `ProofOfIndexing` that only collects the events written to it.
+    /// They are hashed when the deferred `ProofOfIndexing` is passed to
+    /// `append`. This makes it possible to run handlers concurrently and
+    /// still hash their events in the order a sequential run would have
+    pub fn deferred() -> Self {
+        Self {
+            block_number: 0,
+            per_causality_region: HashMap::new(),
+            deferred: Some(Vec::new()),
+        }
+    }
+
     /// Adds an event to the digest of the ProofOfIndexingStream local to the causality region
     pub fn write(
         &mut self,
@@ -129,6 +196,11 @@ impl ProofOfIndexing {
         causality_region: &str,
         event: &ProofOfIndexingEvent<'_>,
     ) {
+        if let Some(deferred) = &mut self.deferred {
+            deferred.push((causality_region.to_owned(), DeferredEvent::new(event)));
+            return;
+        }
+
         if *LOG_EVENTS {
             debug!(
                 logger,
@@ -148,7 +220,20 @@ impl ProofOfIndexing {
                 .insert(causality_region.to_owned(), entry);
         }
     }
+
+    /// Write all events of the deferred `other` in the order in which they
+    /// were written to it
+    pub fn append(&mut self, logger: &Logger, other: ProofOfIndexing) {
+        let events = other
+            .deferred
+            .expect("only a deferred ProofOfIndexing can be appended");
+        for (causality_region, event) in events {
+            self.write(logger, &causality_region, &event.as_event());
+        }
+    }
+
     pub fn take(self) -> HashMap<String, BlockEventStream> {
+        assert!(self.deferred.is_none());
         self.per_causality_region
     }
 }
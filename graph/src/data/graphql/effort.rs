@@ -11,8 +11,9 @@ use std::time::{Duration, Instant};
 
 use crate::components::metrics::{Counter, Gauge, MetricsRegistry};
 use crate::components::store::PoolWaitStats;
+use crate::data::graphql::quota::{ApiKeys, QuotaError, Quotas};
 use crate::data::graphql::shape_hash::shape_hash;
-use crate::data::query::{CacheStatus, QueryExecutionError};
+use crate::data::query::{CacheStatus, QueryExecutionError, QueryTarget};
 use crate::prelude::q;
 use crate::prelude::{async_trait, debug, info, o, warn, CheapClone, Logger, QueryLoadManager};
 use crate::util::stats::{MovingStats, BIN_SIZE, WINDOW_SIZE};
@@ -256,12 +257,16 @@ pub struct LoadManager {
     query_semaphore: Arc<tokio::sync::Semaphore>,
     semaphore_wait_stats: RwLock<MovingStats>,
     semaphore_wait_gauge: Box<Gauge>,
+
+    /// The quotas for the clients that send an API key with their queries
+    quotas: Quotas,
 }
 
 impl LoadManager {
     pub fn new(
         logger: &Logger,
         blocked_queries: Vec<Arc<q::Document>>,
+        api_keys: ApiKeys,
         registry: Arc<dyn MetricsRegistry>,
         store_conn_pool_size: usize,
     ) -> Self {
@@ -279,6 +284,15 @@ impl LoadManager {
             "enabled"
         };
         info!(logger, "Creating LoadManager in {} mode", mode,);
+        if !api_keys.keys.is_empty() {
+            info!(
+                logger,
+                "Enforcing quotas for {} API keys",
+                api_keys.keys.len();
+                "api_key_required" => api_keys.required
+            );
+        }
+        let quotas = Quotas::new(api_keys, registry.as_ref());
 
         let effort_gauge = registry
             .new_gauge(
@@ -325,21 +339,44 @@ impl LoadManager {
             query_semaphore,
             semaphore_wait_stats: RwLock::new(MovingStats::default()),
             semaphore_wait_gauge,
+            quotas,
         }
     }
 
     /// Record that we spent `duration` amount of work for the query
     /// `shape_hash`, where `cache_status` indicates whether the query
-    /// was cached or had to actually run
-    pub fn record_work(&self, shape_hash: u64, duration: Duration, cache_status: CacheStatus) {
+    /// was cached or had to actually run. If the query was sent with an
+    /// API key, `api_key` is the label of the key as returned by
+    /// `check_quota`, and the work counts against the key's quota
+    pub fn record_work(
+        &self,
+        shape_hash: u64,
+        api_key: Option<&str>,
+        duration: Duration,
+        cache_status: CacheStatus,
+    ) {
         self.query_counters
             .get(&cache_status)
             .map(|counter| counter.inc());
+        if let Some(api_key) = api_key {
+            self.quotas.add_effort(api_key, duration);
+        }
         if !*LOAD_MANAGEMENT_DISABLED {
             self.effort.add(shape_hash, duration, &self.effort_gauge);
         }
     }
 
+    /// Check whether a request with `api_key` for `target` is within the
+    /// quota for the key. On success, return the label of the key, or
+    /// `None` if the request is not subject to a quota
+    pub fn check_quota(
+        &self,
+        api_key: Option<&str>,
+        target: &QueryTarget,
+    ) -> Result<Option<String>, QuotaError> {
+        self.quotas.check(api_key, target)
+    }
+
     /// Decide whether we should decline to run the query with this
     /// `ShapeHash`. This is the heart of reacting to overload situations.
     ///
@@ -565,12 +602,13 @@ impl QueryLoadManager for LoadManager {
         permit
     }
 
-    fn record_work(&self, shape_hash: u64, duration: Duration, cache_status: CacheStatus) {
-        self.query_counters
-            .get(&cache_status)
-            .map(|counter| counter.inc());
-        if !*LOAD_MANAGEMENT_DISABLED {
-            self.effort.add(shape_hash, duration, &self.effort_gauge);
-        }
+    fn record_work(
+        &self,
+        shape_hash: u64,
+        api_key: Option<&str>,
+        duration: Duration,
+        cache_status: CacheStatus,
+    ) {
+        LoadManager::record_work(self, shape_hash, api_key, duration, cache_status)
     }
 }
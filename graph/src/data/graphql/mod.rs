@@ -25,6 +25,8 @@ pub mod shape_hash;
 
 pub mod effort;
 
+pub mod quota;
+
 pub mod object_or_interface;
 pub use object_or_interface::ObjectOrInterface;
 
@@ -0,0 +1,407 @@
+//! Per-client quotas for queries. Clients identify themselves with an API
+//! key that they send in the `API_KEY_HEADER` of their requests
+
+use http::StatusCode;
+use std::collections::{HashMap, HashSet};
+use std::iter::FromIterator;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::components::metrics::{Counter, MetricsRegistry};
+use crate::data::query::QueryTarget;
+use crate::util::stats::MovingStats;
+
+/// The HTTP header in which clients send their API key
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// The window over which we add up the effort of the queries for an API key
+const EFFORT_WINDOW: Duration = Duration::from_secs(60);
+const EFFORT_BIN: Duration = Duration::from_secs(1);
+
+/// The quota for the queries that are sent with one API key
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiKeyQuota {
+    /// A name for the key that is used in logs and metrics so that the
+    /// key itself never shows up there
+    pub label: String,
+    pub key: String,
+    /// How many queries per second may be sent with this key. Clients may
+    /// use up a second's worth of queries in a burst
+    pub queries_per_second: Option<u32>,
+    /// How many seconds of query time the queries sent with this key may
+    /// take in a minute
+    pub effort_seconds_per_minute: Option<u64>,
+    /// The subgraph names and deployment ids that may be queried with this
+    /// key. If it is empty, all subgraphs may be queried
+    pub subgraphs: HashSet<String>,
+}
+
+/// The API keys that clients may use, and their quotas
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ApiKeys {
+    /// Reject requests that do not have an API key. If it is `false`, such
+    /// requests are not subject to any quota
+    pub required: bool,
+    pub keys: Vec<ApiKeyQuota>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum QuotaError {
+    #[error("a valid API key is required in the `{}` header", API_KEY_HEADER)]
+    InvalidApiKey,
+    #[error("the API key `{0}` can not be used to query `{1}`")]
+    SubgraphNotAllowed(String, String),
+    #[error("the API key `{0}` can only be used for {1} queries per second")]
+    TooManyQueries(String, u32),
+    #[error("the queries for the API key `{0}` can only take {1}s per minute")]
+    TooMuchEffort(String, u64),
+}
+
+impl QuotaError {
+    /// The status code for HTTP responses to requests that exceed their
+    /// quota
+    pub fn status_code(&self) -> StatusCode {
+        use QuotaError::*;
+        match self {
+            InvalidApiKey => StatusCode::UNAUTHORIZED,
+            SubgraphNotAllowed(_, _) => StatusCode::FORBIDDEN,
+            TooManyQueries(_, _) | TooMuchEffort(_, _) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// How many seconds clients should wait before they retry a request,
+    /// if retrying can succeed at all
+    pub fn retry_after(&self) -> Option<u64> {
+        use QuotaError::*;
+        match self {
+            InvalidApiKey | SubgraphNotAllowed(_, _) => None,
+            TooManyQueries(_, _) => Some(1),
+            TooMuchEffort(_, _) => Some(EFFORT_WINDOW.as_secs()),
+        }
+    }
+
+    fn outcome(&self) -> &'static str {
+        use QuotaError::*;
+        match self {
+            InvalidApiKey => "invalid_key",
+            SubgraphNotAllowed(_, _) => "subgraph_not_allowed",
+            TooManyQueries(_, _) => "too_many_queries",
+            TooMuchEffort(_, _) => "too_much_effort",
+        }
+    }
+}
+
+/// A token bucket that holds at most one second's worth of queries
+struct RateLimit {
+    queries_per_second: u32,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    fn new(queries_per_second: u32) -> Self {
+        RateLimit {
+            queries_per_second,
+            tokens: queries_per_second as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn take_at(&mut self, now: Instant) -> bool {
+        let rate = self.queries_per_second as f64;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct KeyState {
+    quota: ApiKeyQuota,
+    rate_limit: Option<Mutex<RateLimit>>,
+    effort: RwLock<MovingStats>,
+    request_counters: HashMap<&'static str, Counter>,
+    effort_counter: Counter,
+}
+
+impl KeyState {
+    fn new(quota: ApiKeyQuota, registry: &dyn MetricsRegistry) -> Self {
+        let labels = |outcome: &str| {
+            let mut labels = vec![("api_key".to_owned(), quota.label.clone())];
+            if !outcome.is_empty() {
+                labels.push(("outcome".to_owned(), outcome.to_owned()));
+            }
+            HashMap::from_iter(labels)
+        };
+        let request_counters = vec![
+            "accepted",
+            "subgraph_not_allowed",
+            "too_many_queries",
+            "too_much_effort",
+        ]
+        .into_iter()
+        .map(|outcome| {
+            let counter = registry
+                .global_counter(
+                    "query_api_key_requests",
+                    "Count requests per API key and whether they were within their quota",
+                    labels(outcome),
+                )
+                .expect("failed to create `query_api_key_requests` counter");
+            (outcome, counter)
+        })
+        .collect();
+        let effort_counter = registry
+            .global_counter(
+                "query_api_key_effort_ms",
+                "Total time spent running queries per API key",
+                labels(""),
+            )
+            .expect("failed to create `query_api_key_effort_ms` counter");
+
+        KeyState {
+            rate_limit: quota
+                .queries_per_second
+                .map(|qps| Mutex::new(RateLimit::new(qps))),
+            quota,
+            effort: RwLock::new(MovingStats::new(EFFORT_WINDOW, EFFORT_BIN)),
+            request_counters,
+            effort_counter,
+        }
+    }
+
+    fn check(&self, target: &QueryTarget, now: Instant) -> Result<(), QuotaError> {
+        let label = &self.quota.label;
+        if !self.quota.subgraphs.is_empty() {
+            let target = match target {
+                QueryTarget::Name(name) => name.to_string(),
+                QueryTarget::Deployment(id) => id.to_string(),
+            };
+            if !self.quota.subgraphs.contains(&target) {
+                return Err(QuotaError::SubgraphNotAllowed(label.clone(), target));
+            }
+        }
+        if let Some(max_effort) = self.quota.effort_seconds_per_minute {
+            let effort = self.effort.write().unwrap().duration_at(now);
+            if effort >= Duration::from_secs(max_effort) {
+                return Err(QuotaError::TooMuchEffort(label.clone(), max_effort));
+            }
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            let mut rate_limit = rate_limit.lock().unwrap();
+            if !rate_limit.take_at(now) {
+                return Err(QuotaError::TooManyQueries(
+                    label.clone(),
+                    rate_limit.queries_per_second,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn count(&self, outcome: &str) {
+        if let Some(counter) = self.request_counters.get(outcome) {
+            counter.inc();
+        }
+    }
+}
+
+/// Keeps track of how much of their quota the clients with an API key
+/// have used
+pub struct Quotas {
+    required: bool,
+    /// The state for each API key, indexed by the key
+    by_key: HashMap<String, KeyState>,
+    /// Map the label of a key to the key
+    keys: HashMap<String, String>,
+}
+
+impl Quotas {
+    pub fn new(api_keys: ApiKeys, registry: &dyn MetricsRegistry) -> Self {
+        let keys = api_keys
+            .keys
+            .iter()
+            .map(|quota| (quota.label.clone(), quota.key.clone()))
+            .collect();
+        let by_key = api_keys
+            .keys
+            .into_iter()
+            .map(|quota| (quota.key.clone(), KeyState::new(quota, registry)))
+            .collect();
+        Quotas {
+            required: api_keys.required,
+            by_key,
+            keys,
+        }
+    }
+
+    /// Check whether a request with `api_key` for `target` is within the
+    /// quota for the key, and count it against the quota if it is. Return
+    /// the label of the key, which needs to be passed to `add_effort` once
+    /// the query has run
+    pub fn check(
+        &self,
+        api_key: Option<&str>,
+        target: &QueryTarget,
+    ) -> Result<Option<String>, QuotaError> {
+        self.check_at(api_key, target, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        api_key: Option<&str>,
+        target: &QueryTarget,
+        now: Instant,
+    ) -> Result<Option<String>, QuotaError> {
+        if self.by_key.is_empty() {
+            return Ok(None);
+        }
+        let state = match api_key {
+            None if !self.required => return Ok(None),
+            None => return Err(QuotaError::InvalidApiKey),
+            Some(key) => self.by_key.get(key).ok_or(QuotaError::InvalidApiKey)?,
+        };
+        match state.check(target, now) {
+            Ok(()) => {
+                state.count("accepted");
+                Ok(Some(state.quota.label.clone()))
+            }
+            Err(e) => {
+                state.count(e.outcome());
+                Err(e)
+            }
+        }
+    }
+
+    /// Count the `duration` of a query against the quota for the key
+    /// with `label`
+    pub fn add_effort(&self, label: &str, duration: Duration) {
+        if let Some(state) = self.keys.get(label).and_then(|key| self.by_key.get(key)) {
+            state.effort.write().unwrap().add(duration);
+            state.effort_counter.inc_by(duration.as_millis() as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::metrics::{Collector, Gauge, Opts, PrometheusError};
+    use crate::prelude::{SubgraphDeploymentId, SubgraphName};
+
+    struct TestRegistry;
+
+    impl MetricsRegistry for TestRegistry {
+        fn register(&self, _name: &str, _c: Box<dyn Collector>) {}
+
+        fn unregister(&self, _metric: Box<dyn Collector>) {}
+
+        fn global_counter(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Counter, PrometheusError> {
+            Counter::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn global_gauge(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Gauge, PrometheusError> {
+            Gauge::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+    }
+
+    fn make_quotas(required: bool) -> Quotas {
+        let quota = ApiKeyQuota {
+            label: "partner".to_owned(),
+            key: "secret".to_owned(),
+            queries_per_second: Some(2),
+            effort_seconds_per_minute: Some(10),
+            subgraphs: HashSet::from_iter(vec!["partner/subgraph".to_owned()]),
+        };
+        let api_keys = ApiKeys {
+            required,
+            keys: vec![quota],
+        };
+        Quotas::new(api_keys, &TestRegistry)
+    }
+
+    fn target() -> QueryTarget {
+        SubgraphName::new("partner/subgraph").unwrap().into()
+    }
+
+    #[test]
+    fn checks_keys() {
+        let quotas = make_quotas(false);
+        assert_eq!(Ok(None), quotas.check(None, &target()));
+        assert_eq!(
+            Ok(Some("partner".to_owned())),
+            quotas.check(Some("secret"), &target())
+        );
+        assert_eq!(
+            Err(QuotaError::InvalidApiKey),
+            quotas.check(Some("guess"), &target())
+        );
+
+        let quotas = make_quotas(true);
+        assert_eq!(
+            Err(QuotaError::InvalidApiKey),
+            quotas.check(None, &target())
+        );
+    }
+
+    #[test]
+    fn checks_subgraphs() {
+        let quotas = make_quotas(false);
+        let other = QueryTarget::Deployment(SubgraphDeploymentId::new("Qmother").unwrap());
+        assert_eq!(
+            Err(QuotaError::SubgraphNotAllowed(
+                "partner".to_owned(),
+                "Qmother".to_owned()
+            )),
+            quotas.check(Some("secret"), &other)
+        );
+    }
+
+    #[test]
+    fn limits_queries_per_second() {
+        let quotas = make_quotas(false);
+        let start = Instant::now();
+        let check = |millis| quotas.check_at(Some("secret"), &target(), start + ms(millis));
+
+        assert!(check(0).is_ok());
+        assert!(check(10).is_ok());
+        assert_eq!(
+            Err(QuotaError::TooManyQueries("partner".to_owned(), 2)),
+            check(20)
+        );
+        // Half a second later, there is room for one more query
+        assert!(check(520).is_ok());
+        assert!(check(530).is_err());
+    }
+
+    #[test]
+    fn limits_effort() {
+        let quotas = make_quotas(false);
+        assert!(quotas.check(Some("secret"), &target()).is_ok());
+        quotas.add_effort("partner", Duration::from_secs(11));
+        assert_eq!(
+            Err(QuotaError::TooMuchEffort("partner".to_owned(), 10)),
+            quotas.check(Some("secret"), &target())
+        );
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+}
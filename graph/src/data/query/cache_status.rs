@@ -2,7 +2,7 @@ use std::fmt;
 use std::slice::Iter;
 
 /// Used for checking if a response hit the cache.
-#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum CacheStatus {
     /// Hit is a hit in the generational cache.
     Hit,
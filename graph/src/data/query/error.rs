@@ -10,7 +10,7 @@ use std::sync::Arc;
 
 use crate::data::graphql::SerializableValue;
 use crate::data::subgraph::*;
-use crate::prelude::q;
+use crate::prelude::{q, BlockNumber};
 use crate::{components::store::StoreError, prelude::CacheWeight};
 
 #[derive(Debug)]
@@ -78,6 +78,12 @@ pub enum QueryExecutionError {
     EventStreamError,
     FulltextQueryRequiresFilter,
     DeploymentReverted,
+    BlockNotRetained(BlockNumber, BlockNumber), // (block, earliest_block)
+    InvalidCursor(String),
+    InvalidRegex(String, String), // (pattern, reason)
+    QueryNotAllowed(u64),         // shape_hash
+    PersistedQueryNotFound,
+    EstimatedCostTooHigh(f64, f64), // (estimated_cost, max_cost)
 }
 
 impl Error for QueryExecutionError {
@@ -218,6 +224,17 @@ impl fmt::Display for QueryExecutionError {
             TooExpensive => write!(f, "query is too expensive"),
             Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes"),
             DeploymentReverted => write!(f, "the chain was reorganized while executing the query"),
+            BlockNotRetained(block, earliest_block) => write!(f, "the history for block {} has been pruned; \
+                           this deployment can only be queried for blocks from block {} on", block, earliest_block),
+            InvalidCursor(msg) => write!(f, "invalid cursor: {}", msg),
+            InvalidRegex(pattern, reason) => write!(f, "invalid regular expression `{}`: {}", pattern, reason),
+            QueryNotAllowed(shape_hash) => write!(f, "this subgraph only runs queries from its allow-list, \
+                           and the query with shape hash {} is not on it", shape_hash),
+            // Clients that implement Automatic Persisted Queries look for exactly this message
+            PersistedQueryNotFound => write!(f, "PersistedQueryNotFound"),
+            EstimatedCostTooHigh(cost, max_cost) => write!(f, "the estimated cost of this query is {:.0}, \
+                           which exceeds the maximum of {:.0}; try selecting fewer entities or \
+                           narrowing the query with filters", cost, max_cost),
         }
     }
 }
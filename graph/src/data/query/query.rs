@@ -131,6 +131,14 @@ pub struct Query {
     pub shape_hash: u64,
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
+    /// The label of the API key the client sent the query with, if the
+    /// query is subject to a quota
+    pub api_key: Option<String>,
+    /// Whether to include a trace of executing the query in the response
+    pub trace: bool,
+    /// Whether the response will be sent with HTTP cache headers if it
+    /// can be cached
+    pub cache_headers: bool,
     _force_use_of_new: (),
 }
 
@@ -155,7 +163,31 @@ impl Query {
             shape_hash,
             query_text: Arc::new(query_text),
             variables_text: Arc::new(variables_text),
+            api_key: None,
+            trace: false,
+            cache_headers: false,
             _force_use_of_new: (),
         }
     }
+
+    /// Count the work for this query against the quota of the API key
+    /// with label `api_key`
+    pub fn with_api_key(self, api_key: Option<String>) -> Self {
+        Query { api_key, ..self }
+    }
+
+    /// Include a trace of executing the query in the response if `trace`
+    /// is `true`
+    pub fn with_trace(self, trace: bool) -> Self {
+        Query { trace, ..self }
+    }
+
+    /// Determine whether HTTP caches may store the response if
+    /// `cache_headers` is `true`
+    pub fn with_cache_headers(self, cache_headers: bool) -> Self {
+        Query {
+            cache_headers,
+            ..self
+        }
+    }
 }
@@ -1,4 +1,5 @@
 use super::error::{QueryError, QueryExecutionError};
+use super::trace::Trace;
 use crate::{
     data::graphql::SerializableValue,
     prelude::{q, CacheWeight, SubgraphDeploymentId},
@@ -40,21 +41,84 @@ where
 
 pub type Data = BTreeMap<String, q::Value>;
 
+/// How HTTP caches may store the response to a query
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpCachePolicy {
+    /// Identifies the data the response was computed from. Responses to
+    /// the same request with the same tag are identical
+    pub etag: String,
+    /// How many seconds caches may serve the response without checking
+    /// with us whether it is still current
+    pub max_age: u64,
+    /// Whether the response to the same request will never change
+    pub immutable: bool,
+}
+
 /// A collection of query results that is serialized as a single result.
 pub struct QueryResults {
     results: Vec<Arc<QueryResult>>,
+    cache_policy: Option<HttpCachePolicy>,
 }
 
 impl QueryResults {
     pub fn empty() -> Self {
         QueryResults {
             results: Vec::new(),
+            cache_policy: None,
         }
     }
 
     pub fn first(&self) -> Option<&Arc<QueryResult>> {
         self.results.first()
     }
+
+    /// The sum of the estimated costs of all results, or `None` if the
+    /// cost of none of them was estimated
+    pub fn estimated_cost(&self) -> Option<f64> {
+        self.results
+            .iter()
+            .filter_map(|r| r.estimated_cost)
+            .fold(None, |sum, cost| Some(sum.unwrap_or(0.0) + cost))
+    }
+
+    fn extensions(&self) -> Option<Data> {
+        let mut extensions = Data::new();
+        if let Some(cost) = self.estimated_cost() {
+            extensions.insert("estimatedCost".to_string(), q::Value::Float(cost));
+        }
+        if extensions.is_empty() && !self.has_traces() {
+            None
+        } else {
+            Some(extensions)
+        }
+    }
+
+    /// Allow HTTP caches to store the response according to `policy`
+    pub fn set_cache_policy(&mut self, policy: Option<HttpCachePolicy>) {
+        self.cache_policy = policy;
+    }
+
+    /// The values for the `ETag` and `Cache-Control` headers of the
+    /// response, if caches may store it. Responses with errors or traces
+    /// are never cached
+    pub fn cache_headers(&self) -> Option<(String, String)> {
+        if self.results.iter().any(|r| r.has_errors()) || self.has_traces() {
+            return None;
+        }
+        self.cache_policy.as_ref().map(|policy| {
+            let etag = format!("\"{}\"", policy.etag);
+            let cache_control = if policy.immutable {
+                format!("public, max-age={}, immutable", policy.max_age)
+            } else {
+                format!("public, max-age={}", policy.max_age)
+            };
+            (etag, cache_control)
+        })
+    }
+
+    fn has_traces(&self) -> bool {
+        self.results.iter().any(|r| r.trace.is_some())
+    }
 }
 
 impl Serialize for QueryResults {
@@ -68,6 +132,10 @@ impl Serialize for QueryResults {
         if has_errors {
             len += 1;
         }
+        let extensions = self.extensions();
+        if extensions.is_some() {
+            len += 1;
+        }
 
         let mut state = serializer.serialize_struct("QueryResults", len)?;
 
@@ -104,6 +172,33 @@ impl Serialize for QueryResults {
             state.serialize_field("errors", &SerError(self))?;
         }
 
+        // Serialize extensions. Traces are a list with one entry for each
+        // block at which parts of the query were executed
+        if let Some(extensions) = &extensions {
+            struct SerExtensions<'a>(&'a Data, &'a QueryResults);
+
+            impl Serialize for SerExtensions<'_> {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let mut ser = serializer.serialize_map(None)?;
+                    for (k, v) in self.0 {
+                        ser.serialize_entry(k, &SerializableValue(v))?;
+                    }
+                    if self.1.has_traces() {
+                        let traces: Vec<_> = self
+                            .1
+                            .results
+                            .iter()
+                            .filter_map(|r| r.trace.as_ref())
+                            .collect();
+                        ser.serialize_entry("tracing", &traces)?;
+                    }
+                    ser.end()
+                }
+            }
+
+            state.serialize_field("extensions", &SerExtensions(extensions, self))?;
+        }
+
         state.end()
     }
 }
@@ -112,6 +207,7 @@ impl From<Data> for QueryResults {
     fn from(x: Data) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            cache_policy: None,
         }
     }
 }
@@ -120,13 +216,17 @@ impl From<QueryResult> for QueryResults {
     fn from(x: QueryResult) -> Self {
         QueryResults {
             results: vec![Arc::new(x)],
+            cache_policy: None,
         }
     }
 }
 
 impl From<Arc<QueryResult>> for QueryResults {
     fn from(x: Arc<QueryResult>) -> Self {
-        QueryResults { results: vec![x] }
+        QueryResults {
+            results: vec![x],
+            cache_policy: None,
+        }
     }
 }
 
@@ -134,6 +234,7 @@ impl From<QueryExecutionError> for QueryResults {
     fn from(x: QueryExecutionError) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            cache_policy: None,
         }
     }
 }
@@ -142,6 +243,7 @@ impl From<Vec<QueryExecutionError>> for QueryResults {
     fn from(x: Vec<QueryExecutionError>) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            cache_policy: None,
         }
     }
 }
@@ -159,10 +261,19 @@ impl QueryResults {
         let status_code = http::StatusCode::OK;
         let json =
             serde_json::to_string(self).expect("Failed to serialize GraphQL response to JSON");
-        http::Response::builder()
+        let mut response = http::Response::builder();
+        if let Some((etag, cache_control)) = self.cache_headers() {
+            response = response
+                .header(http::header::ETAG, etag)
+                .header(http::header::CACHE_CONTROL, cache_control);
+        }
+        response
             .status(status_code)
             .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+            .header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, User-Agent, X-Api-Key, X-Graph-Trace",
+            )
             .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
             .header("Content-Type", "application/json")
             .body(T::from(json))
@@ -171,7 +282,7 @@ impl QueryResults {
 }
 
 /// The result of running a query, if successful.
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct QueryResult {
     #[serde(
         skip_serializing_if = "Option::is_none",
@@ -182,6 +293,13 @@ pub struct QueryResult {
     errors: Vec<QueryError>,
     #[serde(skip_serializing)]
     pub deployment: Option<SubgraphDeploymentId>,
+    /// The database's estimate of the cost of the queries needed to
+    /// compute this result, if it was estimated
+    #[serde(skip_serializing)]
+    pub estimated_cost: Option<f64>,
+    /// A trace of computing this result, if the client asked for one
+    #[serde(skip_serializing)]
+    pub trace: Option<Trace>,
 }
 
 impl QueryResult {
@@ -190,6 +308,8 @@ impl QueryResult {
             data: Some(data),
             errors: Vec::new(),
             deployment: None,
+            estimated_cost: None,
+            trace: None,
         }
     }
 
@@ -228,6 +348,8 @@ impl From<QueryExecutionError> for QueryResult {
             data: None,
             errors: vec![e.into()],
             deployment: None,
+            estimated_cost: None,
+            trace: None,
         }
     }
 }
@@ -238,6 +360,8 @@ impl From<QueryError> for QueryResult {
             data: None,
             errors: vec![e],
             deployment: None,
+            estimated_cost: None,
+            trace: None,
         }
     }
 }
@@ -248,6 +372,8 @@ impl From<Vec<QueryExecutionError>> for QueryResult {
             data: None,
             errors: e.into_iter().map(QueryError::from).collect(),
             deployment: None,
+            estimated_cost: None,
+            trace: None,
         }
     }
 }
@@ -308,3 +434,66 @@ fn multiple_data_items() {
     let actual = serde_json::to_string(&res).unwrap();
     assert_eq!(expected, actual)
 }
+
+#[test]
+fn estimated_cost_in_extensions() {
+    use serde_json::json;
+
+    fn make_obj(key: &str, cost: Option<f64>) -> Arc<QueryResult> {
+        let mut map = BTreeMap::new();
+        map.insert(key.to_owned(), q::Value::Int(q::Number::from(1)));
+        let mut result = QueryResult::from(map);
+        result.estimated_cost = cost;
+        Arc::new(result)
+    }
+
+    let mut res = QueryResults::empty();
+    res.append(make_obj("key1", None));
+    let expected = serde_json::to_string(&json!({"data":{"key1": 1}})).unwrap();
+    assert_eq!(expected, serde_json::to_string(&res).unwrap());
+
+    res.append(make_obj("key2", Some(12.5)));
+    res.append(make_obj("key3", Some(30.0)));
+    let expected = serde_json::to_string(
+        &json!({"data":{"key1": 1, "key2": 1, "key3": 1}, "extensions": {"estimatedCost": 42.5}}),
+    )
+    .unwrap();
+    assert_eq!(expected, serde_json::to_string(&res).unwrap());
+}
+
+#[test]
+fn cache_headers() {
+    let policy = HttpCachePolicy {
+        etag: "Qmdeployment@17:0abc".to_owned(),
+        max_age: 30,
+        immutable: false,
+    };
+
+    let mut map = BTreeMap::new();
+    map.insert("key".to_owned(), q::Value::String("value".to_owned()));
+    let mut res = QueryResults::from(map);
+    assert_eq!(None, res.cache_headers());
+
+    res.set_cache_policy(Some(policy.clone()));
+    assert_eq!(
+        Some((
+            "\"Qmdeployment@17:0abc\"".to_owned(),
+            "public, max-age=30".to_owned()
+        )),
+        res.cache_headers()
+    );
+
+    res.set_cache_policy(Some(HttpCachePolicy {
+        immutable: true,
+        ..policy.clone()
+    }));
+    assert_eq!(
+        Some("public, max-age=30, immutable".to_owned()),
+        res.cache_headers().map(|(_, cache_control)| cache_control)
+    );
+
+    // Responses with errors are never cached
+    res.append(Arc::new(QueryExecutionError::Timeout.into()));
+    res.set_cache_policy(Some(policy));
+    assert_eq!(None, res.cache_headers());
+}
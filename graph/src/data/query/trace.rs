@@ -0,0 +1,180 @@
+//! Tracing information about how a query was executed that clients can ask
+//! to have included in the `extensions` of the response. Traces include the
+//! SQL that was run, and are therefore only available to clients that know
+//! the token set in `GRAPH_GRAPHQL_TRACE_TOKEN`
+use lazy_static::lazy_static;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::prelude::BlockNumber;
+
+use super::CacheStatus;
+
+/// The header with which clients ask for a trace; its value must be the
+/// trace token
+pub const TRACE_HEADER: &str = "X-Graph-Trace";
+
+lazy_static! {
+    static ref TRACE_TOKEN: Option<String> = env::var("GRAPH_GRAPHQL_TRACE_TOKEN").ok();
+}
+
+/// Return `true` if a request with the given value for the `TRACE_HEADER`
+/// should be traced
+pub fn trace_requested(header: Option<&str>) -> bool {
+    match (TRACE_TOKEN.as_deref(), header) {
+        (Some(token), Some(header)) => constant_time_eq(token.as_bytes(), header.as_bytes()),
+        _ => false,
+    }
+}
+
+/// Compare `a` and `b` in a time that only depends on their length so that
+/// how long it takes to reject a header does not reveal how much of it
+/// matches the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn serialize_nanos<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_nanos() as u64)
+}
+
+/// The SQL query that the store ran, how long that took, and how many
+/// rows it returned
+#[derive(Clone, Debug, Serialize)]
+pub struct SqlTrace {
+    pub sql: String,
+    #[serde(rename = "sqlDuration", serialize_with = "serialize_nanos")]
+    pub elapsed: Duration,
+    pub rows: usize,
+}
+
+/// How long it took to resolve a field. The prefetch resolves a field for
+/// all parents at once, and `path` therefore consists only of the response
+/// keys leading to the field without list indices
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldTrace {
+    pub path: Vec<String>,
+    pub parent_type: String,
+    pub field_name: String,
+    #[serde(serialize_with = "serialize_nanos")]
+    pub start_offset: Duration,
+    #[serde(serialize_with = "serialize_nanos")]
+    pub duration: Duration,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub sql: Option<SqlTrace>,
+}
+
+/// A trace of executing a query at one block, modeled after Apollo's
+/// tracing format. Durations are in nanoseconds
+#[derive(Clone, Debug)]
+pub struct Trace {
+    start: Instant,
+    pub block: Option<BlockNumber>,
+    pub cache_status: CacheStatus,
+    pub duration: Duration,
+    pub resolvers: Vec<FieldTrace>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Trace {
+            start: Instant::now(),
+            block: None,
+            cache_status: CacheStatus::default(),
+            duration: Duration::from_secs(0),
+            resolvers: Vec::new(),
+        }
+    }
+
+    /// Record that resolving the field at `path` started at `start` and
+    /// has just finished
+    pub fn add_field(
+        &mut self,
+        path: Vec<String>,
+        parent_type: &str,
+        field_name: &str,
+        start: Instant,
+        sql: Option<SqlTrace>,
+    ) {
+        self.resolvers.push(FieldTrace {
+            path,
+            parent_type: parent_type.to_owned(),
+            field_name: field_name.to_owned(),
+            start_offset: start.saturating_duration_since(self.start),
+            duration: start.elapsed(),
+            sql,
+        })
+    }
+
+    /// Mark the query as finished
+    pub fn finish(&mut self, block: Option<BlockNumber>, cache_status: CacheStatus) {
+        self.block = block;
+        self.cache_status = cache_status;
+        self.duration = self.start.elapsed();
+    }
+}
+
+impl Serialize for Trace {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Execution<'a> {
+            resolvers: &'a Vec<FieldTrace>,
+        }
+
+        let mut state = serializer.serialize_struct("Trace", 5)?;
+        state.serialize_field("version", &1)?;
+        state.serialize_field("block", &self.block)?;
+        state.serialize_field("cacheStatus", &self.cache_status.to_string())?;
+        state.serialize_field("duration", &(self.duration.as_nanos() as u64))?;
+        state.serialize_field(
+            "execution",
+            &Execution {
+                resolvers: &self.resolvers,
+            },
+        )?;
+        state.end()
+    }
+}
+
+#[test]
+fn serialize_trace() {
+    use serde_json::json;
+
+    let mut trace = Trace::new();
+    trace.add_field(
+        vec!["musicians".to_owned()],
+        "Query",
+        "musicians",
+        Instant::now(),
+        Some(SqlTrace {
+            sql: "select 1".to_owned(),
+            elapsed: Duration::from_nanos(7),
+            rows: 3,
+        }),
+    );
+    trace.finish(Some(17), CacheStatus::Insert);
+
+    let value = serde_json::to_value(&trace).unwrap();
+    assert_eq!(json!(1), value["version"]);
+    assert_eq!(json!(17), value["block"]);
+    assert_eq!(json!("insert"), value["cacheStatus"]);
+    let resolver = &value["execution"]["resolvers"][0];
+    assert_eq!(json!(["musicians"]), resolver["path"]);
+    assert_eq!(json!("Query"), resolver["parentType"]);
+    assert_eq!(json!("musicians"), resolver["fieldName"]);
+    assert_eq!(json!("select 1"), resolver["sql"]);
+    assert_eq!(json!(7), resolver["sqlDuration"]);
+    assert_eq!(json!(3), resolver["rows"]);
+}
+
+#[test]
+fn compare_tokens() {
+    assert!(constant_time_eq(b"token", b"token"));
+    assert!(constant_time_eq(b"", b""));
+    assert!(!constant_time_eq(b"token", b"tokeN"));
+    assert!(!constant_time_eq(b"token", b"tok"));
+    assert!(!constant_time_eq(b"token", b"token2"));
+}
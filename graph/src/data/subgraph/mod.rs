@@ -1,5 +1,5 @@
 use anyhow::{anyhow, Context as _, Error};
-use ethabi::Contract;
+use ethabi::{Contract, Event, Function, LogParam, ParamType, Token};
 use futures03::{
     future::{try_join, try_join3},
     stream::FuturesOrdered,
@@ -12,11 +12,12 @@ use serde::ser;
 use serde_yaml;
 use slog::{debug, info, Logger};
 use stable_hash::prelude::*;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use thiserror::Error;
 use wasmparser;
-use web3::types::{Address, H256};
+use web3::types::{Address, Log, H256};
 
+use crate::blockchain;
 use crate::components::store::{Store, StoreError};
 use crate::components::subgraph::DataSourceTemplateInfo;
 use crate::data::graphql::TryFromValue;
@@ -32,9 +33,9 @@ use crate::data::subgraph::schema::{
 use crate::{components::link_resolver::LinkResolver, prelude::CheapClone};
 
 use crate::prelude::{impl_slog_value, q, BlockNumber, Deserialize, Serialize};
-use crate::util::ethereum::string_to_h256;
+use crate::util::ethereum::{contract_event_with_signature, string_to_h256};
 
-use crate::components::ethereum::NodeCapabilities;
+use crate::components::ethereum::{EthereumBlockPointer, EthereumContractCall, NodeCapabilities};
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
@@ -405,6 +406,8 @@ pub enum SubgraphManifestValidationError {
     SchemaValidationError(Vec<SchemaValidationError>),
     #[error("the graft base is invalid: {0}")]
     GraftBaseInvalid(String),
+    #[error("call `{1}` declared for handler `{0}` is invalid: {2}")]
+    DeclaredCallInvalid(String, String, String),
 }
 
 #[derive(Error, Debug)]
@@ -578,6 +581,10 @@ pub struct MappingEventHandler {
     pub event: String,
     pub topic0: Option<H256>,
     pub handler: String,
+    /// Contract calls that are made before the handler runs, keyed by a
+    /// label that is only used in log messages
+    #[serde(default)]
+    pub calls: BTreeMap<String, DeclaredCall>,
 }
 
 impl MappingEventHandler {
@@ -593,10 +600,313 @@ impl From<EthereumContractEventHandlerEntity> for MappingEventHandler {
             event: entity.event,
             topic0: entity.topic0,
             handler: entity.handler,
+            calls: BTreeMap::new(),
+        }
+    }
+}
+
+/// A contract call that an event handler declares in the manifest, for
+/// example `ERC20[event.address].balanceOf(event.params.to)`. Declared
+/// calls are made in parallel before the handler runs, and their results
+/// end up in the call cache, from where `ethereum.call` in the mapping
+/// picks them up.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct DeclaredCall {
+    /// The name of the ABI of the contract
+    pub contract: String,
+    pub address: DeclaredCallArg,
+    pub function: String,
+    pub args: Vec<DeclaredCallArg>,
+}
+
+/// An argument of a declared call, taken from the event that triggers the
+/// handler
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum DeclaredCallArg {
+    /// `event.address`
+    Address,
+    /// `event.params.<name>`
+    Param(String),
+}
+
+impl DeclaredCallArg {
+    fn value(&self, log: &Log, params: &[LogParam]) -> Result<Token, Error> {
+        match self {
+            DeclaredCallArg::Address => Ok(Token::Address(log.address)),
+            DeclaredCallArg::Param(name) => params
+                .iter()
+                .find(|param| &param.name == name)
+                .map(|param| param.value.clone())
+                .ok_or_else(|| anyhow!("event has no parameter `{}`", name)),
+        }
+    }
+}
+
+impl FromStr for DeclaredCallArg {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        if s == "event.address" {
+            return Ok(DeclaredCallArg::Address);
+        }
+        match s.strip_prefix("event.params.") {
+            Some(name) if !name.is_empty() => Ok(DeclaredCallArg::Param(name.to_owned())),
+            _ => Err(anyhow!(
+                "invalid argument `{}`, expected `event.address` or `event.params.<name>`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DeclaredCallArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeclaredCallArg::Address => write!(f, "event.address"),
+            DeclaredCallArg::Param(name) => write!(f, "event.params.{}", name),
+        }
+    }
+}
+
+impl DeclaredCall {
+    /// Find the function that is called in `contract`. Overloaded functions
+    /// are told apart by the number of arguments
+    pub fn function<'a>(&self, contract: &'a Contract) -> Result<&'a Function, Error> {
+        let functions = contract
+            .functions_by_name(&self.function)
+            .map_err(|_| anyhow!("contract has no function `{}`", self.function))?
+            .iter()
+            .filter(|function| function.inputs.len() == self.args.len())
+            .collect::<Vec<_>>();
+        match functions.as_slice() {
+            [function] => Ok(function),
+            [] => Err(anyhow!(
+                "function `{}` does not take {} arguments",
+                self.function,
+                self.args.len()
+            )),
+            _ => Err(anyhow!(
+                "function `{}` is overloaded with {} arguments",
+                self.function,
+                self.args.len()
+            )),
+        }
+    }
+
+    /// The address of the contract and the arguments for a call that is
+    /// triggered by `log` with the decoded `params`
+    pub fn address_and_args(
+        &self,
+        log: &Log,
+        params: &[LogParam],
+    ) -> Result<(Address, Vec<Token>), Error> {
+        let address = match self.address.value(log, params)? {
+            Token::Address(address) => address,
+            token => {
+                return Err(anyhow!(
+                    "`{}` is not an address but {}",
+                    self.address,
+                    token
+                ))
+            }
+        };
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.value(log, params))
+            .collect::<Result<_, _>>()?;
+        Ok((address, args))
+    }
+
+    /// The contract call that is made in block `block_ptr` for `log` with
+    /// the decoded `params`; the contract is looked up in `abis`
+    pub fn contract_call(
+        &self,
+        abis: &[MappingABI],
+        block_ptr: EthereumBlockPointer,
+        log: &Log,
+        params: &[LogParam],
+    ) -> Result<EthereumContractCall, Error> {
+        let contract = &abis
+            .iter()
+            .find(|abi| abi.name == self.contract)
+            .with_context(|| format!("Could not find ABI for contract \"{}\"", self.contract))?
+            .contract;
+        let function = self.function(contract)?;
+        let (address, args) = self.address_and_args(log, params)?;
+
+        Ok(EthereumContractCall {
+            address,
+            block_ptr,
+            function: function.clone(),
+            args,
+        })
+    }
+
+    /// Check that the call refers to a contract and function in the ABIs of
+    /// `mapping`, and only uses parameters that `event` has
+    fn validate(&self, mapping: &Mapping, event: &Event) -> Result<(), Error> {
+        let abi = mapping
+            .abis
+            .iter()
+            .find(|abi| abi.name == self.contract)
+            .ok_or_else(|| anyhow!("there is no ABI named `{}`", self.contract))?;
+        let function = self.function(&abi.contract)?;
+
+        let param = |arg: &DeclaredCallArg| -> Result<ParamType, Error> {
+            match arg {
+                DeclaredCallArg::Address => Ok(ParamType::Address),
+                DeclaredCallArg::Param(name) => event
+                    .inputs
+                    .iter()
+                    .find(|input| &input.name == name)
+                    .map(|input| input.kind.clone())
+                    .ok_or_else(|| anyhow!("event `{}` has no parameter `{}`", event.name, name)),
+            }
+        };
+
+        if param(&self.address)? != ParamType::Address {
+            return Err(anyhow!("`{}` is not an address", self.address));
+        }
+        for (arg, input) in self.args.iter().zip(function.inputs.iter()) {
+            let kind = param(arg)?;
+            if kind != input.kind {
+                return Err(anyhow!(
+                    "`{}` has type `{}` but argument `{}` of `{}` has type `{}`",
+                    arg,
+                    kind,
+                    input.name,
+                    self.function,
+                    input.kind
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DeclaredCall {
+    type Err = Error;
+
+    /// Parse a call of the form `Contract[address].function(arg, ...)`
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let err = || {
+            anyhow!(
+                "invalid call `{}`, expected `Contract[address].function(arg, ...)`",
+                s
+            )
+        };
+
+        let s = s.trim();
+        let (contract, rest) = s.split_at(s.find('[').ok_or_else(err)?);
+        let (address, rest) = rest[1..].split_at(rest.find("].").ok_or_else(err)? - 1);
+        let rest = &rest[2..];
+        let (function, rest) = rest.split_at(rest.find('(').ok_or_else(err)?);
+        let args = rest[1..].strip_suffix(')').ok_or_else(err)?;
+
+        let contract = contract.trim();
+        let function = function.trim();
+        if contract.is_empty() || function.is_empty() {
+            return Err(err());
         }
+
+        let args = if args.trim().is_empty() {
+            vec![]
+        } else {
+            args.split(',')
+                .map(DeclaredCallArg::from_str)
+                .collect::<Result<_, _>>()?
+        };
+
+        Ok(DeclaredCall {
+            contract: contract.to_owned(),
+            address: address.parse()?,
+            function: function.to_owned(),
+            args,
+        })
+    }
+}
+
+impl fmt::Display for DeclaredCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{}[{}].{}({})",
+            self.contract,
+            self.address,
+            self.function,
+            args.join(", ")
+        )
+    }
+}
+
+impl<'de> de::Deserialize<'de> for DeclaredCall {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s: String = de::Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
     }
 }
 
+#[test]
+fn test_declared_call_parsing() {
+    let call: DeclaredCall = "ERC20[event.address].balanceOf(event.params.to)"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        DeclaredCall {
+            contract: "ERC20".to_owned(),
+            address: DeclaredCallArg::Address,
+            function: "balanceOf".to_owned(),
+            args: vec![DeclaredCallArg::Param("to".to_owned())],
+        },
+        call
+    );
+    assert_eq!(
+        "ERC20[event.address].balanceOf(event.params.to)",
+        call.to_string()
+    );
+
+    let call: DeclaredCall = "Pool[event.params.pool].getReserves()".parse().unwrap();
+    assert_eq!(DeclaredCallArg::Param("pool".to_owned()), call.address);
+    assert!(call.args.is_empty());
+
+    let call: DeclaredCall = "Pair[event.params.pair].get(event.params.a, event.address)"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        vec![
+            DeclaredCallArg::Param("a".to_owned()),
+            DeclaredCallArg::Address
+        ],
+        call.args
+    );
+
+    assert!("ERC20.balanceOf(event.params.to)"
+        .parse::<DeclaredCall>()
+        .is_err());
+    assert!("ERC20[event.address].balanceOf"
+        .parse::<DeclaredCall>()
+        .is_err());
+    assert!("ERC20[0x00].balanceOf(event.params.to)"
+        .parse::<DeclaredCall>()
+        .is_err());
+    assert!("ERC20[event.address].balanceOf(to)"
+        .parse::<DeclaredCall>()
+        .is_err());
+    assert!("[event.address].balanceOf()"
+        .parse::<DeclaredCall>()
+        .is_err());
+}
+
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UnresolvedMapping {
@@ -664,12 +974,49 @@ impl Mapping {
             })
     }
 
+    fn has_declared_calls(&self) -> bool {
+        self.event_handlers
+            .iter()
+            .any(|handler| !handler.calls.is_empty())
+    }
+
     pub fn required_capabilities(&self) -> NodeCapabilities {
         NodeCapabilities {
             traces: self.has_block_handler_with_call_filter() || self.has_call_handler(),
-            archive: self.calls_host_fn("ethereum.call"),
+            archive: self.calls_host_fn("ethereum.call") || self.has_declared_calls(),
         }
     }
+
+    /// Check the declared calls of all event handlers; `abi` is the name of
+    /// the ABI of the contract that emits the events
+    fn validate_declared_calls(&self, abi: &str) -> Vec<SubgraphManifestValidationError> {
+        let contract = self
+            .abis
+            .iter()
+            .find(|mapping_abi| mapping_abi.name == abi)
+            .map(|mapping_abi| &mapping_abi.contract);
+
+        self.event_handlers
+            .iter()
+            .flat_map(|handler| {
+                let event = contract
+                    .and_then(|contract| contract_event_with_signature(contract, &handler.event));
+                handler.calls.iter().filter_map(move |(label, call)| {
+                    let result = match event {
+                        Some(event) => call.validate(self, event),
+                        None => Err(anyhow!("event `{}` is not in ABI `{}`", handler.event, abi)),
+                    };
+                    result.err().map(|e| {
+                        SubgraphManifestValidationError::DeclaredCallInvalid(
+                            handler.handler.clone(),
+                            label.clone(),
+                            e.to_string(),
+                        )
+                    })
+                })
+            })
+            .collect()
+    }
 }
 
 impl UnresolvedMapping {
@@ -751,6 +1098,13 @@ pub struct BaseDataSource<M> {
 pub type UnresolvedDataSource = BaseDataSource<UnresolvedMapping>;
 pub type DataSource = BaseDataSource<Mapping>;
 
+/// Ethereum data sources are the data sources of the manifest
+impl blockchain::DataSource for DataSource {
+    fn from_manifest(data_source: &DataSource) -> Result<Self, Error> {
+        Ok(data_source.clone())
+    }
+}
+
 impl UnresolvedDataSource {
     pub async fn resolve(
         self,
@@ -853,6 +1207,12 @@ impl From<EthereumContractDataSourceTemplateEntity> for UnresolvedDataSourceTemp
 pub type UnresolvedDataSourceTemplate = BaseDataSourceTemplate<UnresolvedMapping>;
 pub type DataSourceTemplate = BaseDataSourceTemplate<Mapping>;
 
+impl blockchain::DataSourceTemplate for DataSourceTemplate {
+    fn from_manifest(template: &DataSourceTemplate) -> Result<Self, Error> {
+        Ok(template.clone())
+    }
+}
+
 impl UnresolvedDataSourceTemplate {
     pub async fn resolve(
         self,
@@ -1018,6 +1378,23 @@ impl UnvalidatedSubgraphManifest {
             errors.push(SubgraphManifestValidationError::DataSourceBlockHandlerLimitExceeded)
         }
 
+        // Validate that declared calls refer to contracts, functions and
+        // event parameters that exist
+        for data_source in &self.0.data_sources {
+            errors.extend(
+                data_source
+                    .mapping
+                    .validate_declared_calls(&data_source.source.abi),
+            );
+        }
+        for template in &self.0.templates {
+            errors.extend(
+                template
+                    .mapping
+                    .validate_declared_calls(&template.source.abi),
+            );
+        }
+
         let mut networks = self
             .0
             .data_sources
@@ -1079,24 +1456,34 @@ impl SubgraphManifest {
 
         let file = String::from_utf8(file_bytes.to_vec())
             .map_err(|_| SubgraphManifestResolveError::NonUtf8)?;
-        let mut raw: serde_yaml::Value = serde_yaml::from_str(&file)?;
+        let raw: serde_yaml::Value = serde_yaml::from_str(&file)?;
+
+        // Use the IPFS hash as the ID of the subgraph
+        let id = link.link.trim_start_matches("/ipfs/").to_owned();
+        Self::resolve_from_raw(id, link.link, raw, resolver, logger).await
+    }
 
+    /// Resolve a manifest that has already been parsed into `raw`. The
+    /// `id` and `location` of the subgraph are not part of the manifest
+    /// file and are added to it here.
+    pub async fn resolve_from_raw(
+        id: String,
+        location: String,
+        mut raw: serde_yaml::Value,
+        resolver: &impl LinkResolver,
+        logger: &Logger,
+    ) -> Result<Self, SubgraphManifestResolveError> {
         let raw_mapping = raw
             .as_mapping_mut()
             .ok_or(SubgraphManifestResolveError::InvalidFormat)?;
 
-        // Inject the IPFS hash as the ID of the subgraph
-        // into the definition.
-        raw_mapping.insert(
-            serde_yaml::Value::from("id"),
-            serde_yaml::Value::from(link.link.trim_start_matches("/ipfs/")),
-        );
+        // Inject the ID of the subgraph into the definition.
+        raw_mapping.insert(serde_yaml::Value::from("id"), serde_yaml::Value::from(id));
 
-        // Inject the IPFS link as the location of the data
-        // source into the definition
+        // Inject the location of the data source into the definition
         raw_mapping.insert(
             serde_yaml::Value::from("location"),
-            serde_yaml::Value::from(link.link),
+            serde_yaml::Value::from(location),
         );
 
         // Parse the YAML data into an UnresolvedSubgraphManifest
@@ -1114,9 +1501,7 @@ impl SubgraphManifest {
         // Assume the manifest has been validated, ensuring network names are homogenous
         self.data_sources
             .iter()
-            .cloned()
-            .filter(|d| &d.kind == "ethereum/contract")
-            .filter_map(|d| d.network)
+            .filter_map(|d| d.network.clone())
             .next()
             .expect("Validated manifest does not have a network defined on any datasource")
     }
@@ -1153,15 +1538,21 @@ impl SubgraphManifest {
     }
 
     pub fn required_ethereum_capabilities(&self) -> NodeCapabilities {
-        let mappings = self.mappings();
-        NodeCapabilities {
-            archive: mappings
-                .iter()
-                .any(|mapping| mapping.calls_host_fn("ethereum.call")),
-            traces: mappings.iter().any(|mapping| {
-                mapping.has_call_handler() || mapping.has_block_handler_with_call_filter()
-            }),
-        }
+        required_ethereum_capabilities(self.mappings().iter())
+    }
+}
+
+/// The capabilities that Ethereum nodes need to have to run `mappings`
+pub fn required_ethereum_capabilities<'a>(
+    mut mappings: impl Iterator<Item = &'a Mapping> + Clone,
+) -> NodeCapabilities {
+    NodeCapabilities {
+        archive: mappings
+            .clone()
+            .any(|mapping| mapping.calls_host_fn("ethereum.call")),
+        traces: mappings.any(|mapping| {
+            mapping.has_call_handler() || mapping.has_block_handler_with_call_filter()
+        }),
     }
 }
 
@@ -1252,12 +1643,14 @@ pub struct DeploymentState {
 #[allow(non_camel_case_types)]
 pub enum SubgraphFeature {
     nonFatalErrors,
+    parallelDataSources,
 }
 
 impl std::fmt::Display for SubgraphFeature {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SubgraphFeature::nonFatalErrors => write!(f, "nonFatalErrors"),
+            SubgraphFeature::parallelDataSources => write!(f, "parallelDataSources"),
         }
     }
 }
@@ -1268,6 +1661,7 @@ impl FromStr for SubgraphFeature {
     fn from_str(s: &str) -> anyhow::Result<Self> {
         match s {
             "nonFatalErrors" => Ok(SubgraphFeature::nonFatalErrors),
+            "parallelDataSources" => Ok(SubgraphFeature::parallelDataSources),
             _ => Err(anyhow::anyhow!("invalid subgraph feature {}", s)),
         }
     }
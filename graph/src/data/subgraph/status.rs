@@ -158,3 +158,37 @@ impl IntoValue for Info {
         }
     }
 }
+
+/// A reorg that a block ingestor observed: the chain head moved from
+/// `old_head` to `new_head`, and `old_head` is not an ancestor of `new_head`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainReorg {
+    /// The network name (e.g. `mainnet`, `ropsten`, `rinkeby`, `kovan` or `goerli`).
+    pub network: String,
+    pub old_head: EthereumBlockPointer,
+    pub new_head: EthereumBlockPointer,
+    /// The number of blocks of the old chain that were replaced.
+    pub depth: u64,
+    /// When the reorg was recorded, in seconds since the epoch.
+    pub timestamp: u64,
+}
+
+impl IntoValue for ChainReorg {
+    fn into_value(self) -> q::Value {
+        let ChainReorg {
+            network,
+            old_head,
+            new_head,
+            depth,
+            timestamp,
+        } = self;
+        object! {
+            __typename: "ChainReorg",
+            network: network,
+            oldHead: EthereumBlock::from(old_head),
+            newHead: EthereumBlock::from(new_head),
+            depth: depth,
+            timestamp: timestamp,
+        }
+    }
+}
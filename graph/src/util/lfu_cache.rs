@@ -163,6 +163,28 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
         self.queue.len()
     }
 
+    /// Move the entries whose key satisfies `pred` into a new cache and
+    /// return that. The entries keep their priorities, and the two caches
+    /// can be put back together with `extend`.
+    pub fn split_off(&mut self, pred: impl Fn(&K) -> bool) -> Self {
+        let mut other = LfuCache {
+            queue: PriorityQueue::new(),
+            total_weight: 0,
+            stale_counter: self.stale_counter,
+        };
+        let queue = std::mem::replace(&mut self.queue, PriorityQueue::new());
+        for (entry, priority) in queue {
+            if pred(&entry.key) {
+                self.total_weight -= entry.weight;
+                other.total_weight += entry.weight;
+                other.queue.push(entry, priority);
+            } else {
+                self.queue.push(entry, priority);
+            }
+        }
+        other
+    }
+
     /// Same as `evict_with_period(max_weight, STALE_PERIOD)`
     pub fn evict(&mut self, max_weight: usize) -> Option<(usize, usize, usize)> {
         self.evict_with_period(max_weight, STALE_PERIOD)
@@ -225,7 +247,14 @@ impl<K: Ord + Eq + Hash + 'static, V: 'static> IntoIterator for LfuCache<K, V> {
 
 impl<K: Ord + Eq + Hash, V> Extend<(CacheEntry<K, V>, Priority)> for LfuCache<K, V> {
     fn extend<T: IntoIterator<Item = (CacheEntry<K, V>, Priority)>>(&mut self, iter: T) {
-        self.queue.extend(iter);
+        for (entry, priority) in iter {
+            let weight = entry.weight;
+            // An entry that is already in the cache is not replaced, only
+            // its priority is updated
+            if self.queue.push(entry, priority).is_none() {
+                self.total_weight += weight;
+            }
+        }
     }
 }
 
@@ -294,3 +323,28 @@ fn entity_lru_cache() {
     assert!(cache.get(&"alligator").is_none());
     assert_eq!(cache.get(&"lion"), Some(&Weight(lion_inner_weight)));
 }
+
+#[test]
+fn split_off_and_extend() {
+    let mut cache: LfuCache<&'static str, usize> = LfuCache::new();
+    cache.insert("panda", 1);
+    cache.insert("cow", 2);
+    cache.insert("lion", 3);
+    let total_weight = cache.total_weight;
+
+    let mut cats = cache.split_off(|key| *key == "lion");
+    assert_eq!(2, cache.len());
+    assert_eq!(1, cats.len());
+    assert_eq!(Some(&3), cats.get(&"lion"));
+    assert!(cache.get(&"lion").is_none());
+    assert_eq!(total_weight, cache.total_weight + cats.total_weight);
+
+    cats.insert("tiger", 4);
+    let tiger_weight = cats.weight("tiger");
+    // Duplicate entries are only counted once
+    cats.insert("cow", 2);
+    cache.extend(cats);
+    assert_eq!(4, cache.len());
+    assert_eq!(total_weight + tiger_weight, cache.total_weight);
+    assert_eq!(Some(&4), cache.get(&"tiger"));
+}
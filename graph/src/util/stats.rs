@@ -179,6 +179,13 @@ impl MovingStats {
     pub fn duration(&self) -> Duration {
         self.total.duration
     }
+
+    /// Return the total duration of the measurements that are within
+    /// `window_size` of `now`, and forget about older measurements
+    pub fn duration_at(&mut self, now: Instant) -> Duration {
+        self.expire_bins(now);
+        self.total.duration
+    }
 }
 
 #[cfg(test)]
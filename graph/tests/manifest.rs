@@ -223,3 +223,49 @@ specVersion: 0.0.2
     assert_eq!("Qmmanifest", manifest.id.as_str());
     assert_eq!(true, requires_traces);
 }
+
+#[tokio::test]
+async fn parse_declared_calls() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Factory
+    network: mainnet
+    source:
+      abi: Factory
+      startBlock: 9562480
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: Created(address,uint256)
+          handler: handleCreated
+          calls:
+            owner: Factory[event.address].get(event.params.index)
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let handler = &manifest.data_sources[0].mapping.event_handlers[0];
+    let call = handler.calls.get("owner").expect("the call is declared");
+
+    assert_eq!("Factory", call.contract);
+    assert_eq!("get", call.function);
+    assert_eq!(
+        "Factory[event.address].get(event.params.index)",
+        call.to_string()
+    );
+    assert!(manifest.required_ethereum_capabilities().archive);
+}
@@ -12,10 +12,11 @@ use stable_hash::prelude::*;
 use stable_hash::utils::stable_hash;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use graph::data::graphql::*;
-use graph::data::query::CacheStatus;
+use graph::data::query::{CacheStatus, Trace};
 use graph::prelude::*;
 use graph::util::lfu_cache::LfuCache;
 
@@ -217,9 +218,20 @@ where
     /// Max value for `skip`
     pub max_skip: u32,
 
+    /// Max estimated cost of the database queries for this query; if
+    /// this is `None`, the cost is not estimated.
+    pub max_cost: Option<f64>,
+
     /// Records whether this was a cache hit, used for logging.
     pub(crate) cache_status: AtomicCell<CacheStatus>,
 
+    /// The estimated cost of the database queries, reported back to the
+    /// client in the result.
+    pub(crate) estimated_cost: AtomicCell<Option<f64>>,
+
+    /// The trace of executing the query if the client asked for one
+    pub(crate) trace: Option<Mutex<Trace>>,
+
     pub load_manager: Arc<dyn QueryLoadManager>,
 
     /// Set if this query is being executed in another resolver and therefore reentering functions
@@ -274,9 +286,13 @@ where
             deadline: self.deadline,
             max_first: std::u32::MAX,
             max_skip: std::u32::MAX,
+            max_cost: None,
 
-            // `cache_status` and `load_manager` are dead values for the introspection context.
+            // `cache_status`, `estimated_cost` and `load_manager` are dead values for the
+            // introspection context.
             cache_status: AtomicCell::new(CacheStatus::Miss),
+            estimated_cost: AtomicCell::new(None),
+            trace: None,
             load_manager: self.load_manager.cheap_clone(),
             nested_resolver: self.nested_resolver,
         }
@@ -415,6 +431,7 @@ pub async fn execute_root_selection_set<R: Resolver>(
             // Unwrap: In practice should never fail, but if it does we will catch the panic.
             execute_ctx.resolver.post_process(&mut query_res).unwrap();
             query_res.deployment = Some(execute_ctx.query.schema.id().clone());
+            query_res.estimated_cost = execute_ctx.estimated_cost.load();
             Arc::new(query_res)
         })
         .await
@@ -99,6 +99,12 @@ pub struct Query {
     pub variables_text: Arc<String>,
     pub query_id: String,
     pub(crate) complexity: u64,
+
+    /// The label of the API key that the query was sent with
+    pub api_key: Option<String>,
+
+    /// Whether to trace the execution of the query
+    pub trace: bool,
 }
 
 impl Query {
@@ -172,6 +178,8 @@ impl Query {
             variables_text: query.variables_text.cheap_clone(),
             query_id,
             complexity: 0,
+            api_key: query.api_key,
+            trace: query.trace,
         };
 
         query.validate_fields()?;
@@ -268,6 +276,8 @@ impl Query {
             variables_text: self.variables_text.clone(),
             query_id: self.query_id.clone(),
             complexity: self.complexity,
+            api_key: self.api_key.clone(),
+            trace: false,
         })
     }
 
@@ -1,5 +1,6 @@
+use graph::data::query::Trace;
 use graph::prelude::{q, CheapClone, EthereumBlockPointer, QueryExecutionError, QueryResult};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use graph::data::graphql::effort::LoadManager;
@@ -26,6 +27,10 @@ pub struct QueryExecutionOptions<R> {
     /// Maximum value for the `skip` argument
     pub max_skip: u32,
 
+    /// Maximum estimated cost of the database queries needed for the
+    /// query. Queries are only estimated if this is set
+    pub max_cost: Option<f64>,
+
     pub load_manager: Arc<LoadManager>,
 }
 
@@ -49,7 +54,14 @@ where
         deadline: options.deadline,
         max_first: options.max_first,
         max_skip: options.max_skip,
+        max_cost: options.max_cost,
         cache_status: Default::default(),
+        estimated_cost: Default::default(),
+        trace: if query.trace {
+            Some(Mutex::new(Trace::new()))
+        } else {
+            None
+        },
         load_manager: options.load_manager.cheap_clone(),
         nested_resolver,
     });
@@ -75,14 +87,29 @@ where
     .await;
     let elapsed = start.elapsed();
     let cache_status = ctx.cache_status.load();
-    options
-        .load_manager
-        .record_work(query.shape_hash, elapsed, cache_status);
+    options.load_manager.record_work(
+        query.shape_hash,
+        query.api_key.as_deref(),
+        elapsed,
+        cache_status,
+    );
     query.log_cache_status(
         &selection_set,
         block_ptr.map(|b| b.number).unwrap_or(0),
         start,
         cache_status.to_string(),
     );
-    result
+
+    match &ctx.trace {
+        Some(trace) => {
+            let mut trace = trace.lock().unwrap().clone();
+            trace.finish(block_ptr.map(|b| b.block_number()), cache_status);
+            // The result might be shared with the query cache, and we must
+            // not put the trace there
+            let mut result = Arc::try_unwrap(result).unwrap_or_else(|result| (*result).clone());
+            result.trace = Some(trace);
+            Arc::new(result)
+        }
+        None => result,
+    }
 }
@@ -3,24 +3,30 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::prelude::{QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions};
+use crate::prelude::{
+    BlockConstraint, QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions,
+};
 use crate::query::execute_query;
 use crate::subscription::execute_prepared_subscription;
 use graph::{
     components::store::SubscriptionManager,
     prelude::{
-        async_trait, o, CheapClone, DeploymentState, GraphQlRunner as GraphQlRunnerTrait, Logger,
-        Query, QueryExecutionError, Subscription, SubscriptionError, SubscriptionResult,
+        async_trait, o, CheapClone, DeploymentState, EthereumBlockPointer,
+        GraphQlRunner as GraphQlRunnerTrait, Logger, Query, QueryExecutionError,
+        SubgraphDeploymentId, Subscription, SubscriptionError, SubscriptionResult,
     },
 };
 use graph::{data::graphql::effort::LoadManager, prelude::QueryStoreManager};
 use graph::{
-    data::query::{QueryResults, QueryTarget},
+    data::query::{HttpCachePolicy, QueryResults, QueryTarget},
     prelude::QueryStore,
 };
 
 use lazy_static::lazy_static;
 
+/// How long HTTP caches may keep responses that will never change
+const IMMUTABLE_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
 /// GraphQL runner implementation for The Graph.
 pub struct GraphQlRunner<S, SM> {
     logger: Logger,
@@ -55,6 +61,22 @@ lazy_static! {
         .map(|s| u32::from_str(&s)
             .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_SKIP")))
         .unwrap_or(std::u32::MAX);
+    static ref GRAPHQL_MAX_COST: Option<f64> = env::var("GRAPH_GRAPHQL_MAX_COST")
+        .ok()
+        .map(|s| f64::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_COST")));
+    // Default to an Ethereum reorg threshold of 50 blocks, like the
+    // block ingestor does
+    static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
+        .ok()
+        .map(|s| u64::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
+        .unwrap_or(50);
+    static ref GRAPHQL_HTTP_CACHE_MAX_AGE: u64 = env::var("GRAPH_GRAPHQL_HTTP_CACHE_MAX_AGE")
+        .ok()
+        .map(|s| u64::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_HTTP_CACHE_MAX_AGE")))
+        .unwrap_or(30);
     // Allow skipping the check whether a deployment has changed while
     // we were running a query. Once we are sure that the check mechanism
     // is reliable, this variable should be removed
@@ -157,6 +179,7 @@ where
             .unwrap_or(state);
 
         let max_depth = max_depth.unwrap_or(*GRAPHQL_MAX_DEPTH);
+        let cache_headers = query.cache_headers;
         let query = crate::execution::Query::new(
             &self.logger,
             schema,
@@ -165,6 +188,13 @@ where
             max_complexity,
             max_depth,
         )?;
+        if !self
+            .store
+            .query_allowed(query.schema.id(), query.shape_hash)
+            .map_err(QueryExecutionError::from)?
+        {
+            return Err(QueryExecutionError::QueryNotAllowed(query.shape_hash).into());
+        }
         self.load_manager
             .decide(
                 store.wait_stats(),
@@ -176,8 +206,34 @@ where
         let mut max_block = 0;
         let mut result: QueryResults = QueryResults::empty();
 
+        // HTTP caches may only store the response if none of the blocks it
+        // was computed at can be reorged anymore. We only need to know
+        // where the final part of the chain ends if the response might
+        // get cache headers
+        let final_block = if cache_headers {
+            store
+                .chain_head_ptr()?
+                .map(|head| head.number.saturating_sub(*REORG_THRESHOLD))
+        } else {
+            None
+        };
+        let mut cache_policy = final_block.map(|_| HttpCachePolicy {
+            etag: String::new(),
+            max_age: IMMUTABLE_MAX_AGE,
+            immutable: true,
+        });
+
         // Note: This will always iterate at least once.
         for (bc, (selection_set, error_policy)) in by_block_constraint {
+            // The result for a block hash, or a final block number, never
+            // changes
+            let pinned = match &bc {
+                BlockConstraint::Hash(_) => true,
+                BlockConstraint::Number(number) => final_block
+                    .map(|final_block| *number as u64 <= final_block)
+                    .unwrap_or(false),
+                BlockConstraint::Latest => false,
+            };
             let resolver = StoreResolver::at_block(
                 &self.logger,
                 store.cheap_clone(),
@@ -188,6 +244,15 @@ where
             )
             .await?;
             max_block = max_block.max(resolver.block_number());
+            cache_policy = cache_policy.and_then(|policy| {
+                add_to_cache_policy(
+                    policy,
+                    query.schema.id(),
+                    resolver.block_ptr.as_ref()?,
+                    pinned,
+                    final_block?,
+                )
+            });
             let query_res = execute_query(
                 query.clone(),
                 Some(selection_set),
@@ -197,6 +262,7 @@ where
                     deadline: GRAPHQL_QUERY_TIMEOUT.map(|t| Instant::now() + t),
                     max_first: max_first.unwrap_or(*GRAPHQL_MAX_FIRST),
                     max_skip: max_skip.unwrap_or(*GRAPHQL_MAX_SKIP),
+                    max_cost: *GRAPHQL_MAX_COST,
                     load_manager: self.load_manager.clone(),
                 },
                 nested_resolver,
@@ -208,8 +274,41 @@ where
         query.log_execution(max_block);
         self.deployment_changed(store.as_ref(), state, max_block as u64)
             .map_err(QueryResults::from)
-            .map(|()| result)
+            .map(|()| {
+                result.set_cache_policy(cache_policy);
+                result
+            })
+    }
+}
+
+/// Add the part of a query that was executed at `block_ptr` to `policy`.
+/// Return `None` if the result of that part might still change
+fn add_to_cache_policy(
+    mut policy: HttpCachePolicy,
+    deployment: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+    pinned: bool,
+    final_block: u64,
+) -> Option<HttpCachePolicy> {
+    if !pinned && block_ptr.number > final_block {
+        return None;
+    }
+    if !policy.etag.is_empty() {
+        policy.etag.push(',');
     }
+    // Queries for a block number get a block pointer with an all zeroes
+    // hash, and the tag therefore also includes the block number
+    policy.etag.push_str(&format!(
+        "{}@{}:{}",
+        deployment,
+        block_ptr.number,
+        block_ptr.hash_hex()
+    ));
+    if !pinned {
+        policy.immutable = false;
+        policy.max_age = *GRAPHQL_HTTP_CACHE_MAX_AGE;
+    }
+    Some(policy)
 }
 
 #[async_trait]
@@ -277,6 +376,16 @@ where
             *GRAPHQL_MAX_DEPTH,
         )?;
 
+        if !self
+            .store
+            .query_allowed(query.schema.id(), query.shape_hash)
+            .map_err(QueryExecutionError::from)?
+        {
+            return Err(SubscriptionError::GraphQLError(vec![
+                QueryExecutionError::QueryNotAllowed(query.shape_hash),
+            ]));
+        }
+
         if let Err(err) = self
             .load_manager
             .decide(
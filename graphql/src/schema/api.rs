@@ -7,7 +7,7 @@ use lazy_static::lazy_static;
 use crate::schema::ast;
 
 use graph::data::{
-    graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
+    graphql::ext::{DirectiveExt, DocumentExt, TypeExt, ValueExt},
     schema::{META_FIELD_NAME, META_FIELD_TYPE},
     subgraph::SubgraphFeature,
 };
@@ -29,6 +29,19 @@ const BLOCK_HEIGHT: &str = "Block_height";
 
 const ERROR_POLICY_TYPE: &str = "_SubgraphErrorPolicy_";
 
+/// The directive that marks the `Query` fields that compute aggregates
+/// over an entity type. Its `entity` argument is the name of that type
+pub(crate) const AGGREGATE_DIRECTIVE: &str = "aggregate";
+
+/// The field on entity types that holds an opaque cursor for the position
+/// of the entity in the collection it was retrieved from. It can be passed
+/// as `after` or `before` to the same collection query to continue from
+/// that position
+pub(crate) const CURSOR_FIELD: &str = "_cursor";
+
+/// The numeric types over which we can compute aggregates
+const AGGREGATE_SCALARS: &[&str] = &["Int", "BigInt", "BigDecimal"];
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum ErrorPolicy {
     Allow,
@@ -82,7 +95,9 @@ pub fn api_schema(
     add_meta_field_type(&mut schema);
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
+    add_child_filter_fields(&mut schema, &object_types, &interface_types);
     add_field_arguments(&mut schema, &input_schema)?;
+    add_cursor_fields(&mut schema, &object_types, &interface_types);
     add_query_type(&mut schema, &object_types, &interface_types, features)?;
     add_subscription_type(&mut schema, &object_types, &interface_types, features)?;
     Ok(schema)
@@ -237,10 +252,48 @@ fn add_types_for_object_types(
     for object_type in object_types {
         add_order_by_type(schema, &object_type.name, &object_type.fields)?;
         add_filter_type(schema, &object_type.name, &object_type.fields)?;
+        add_aggregate_types(schema, &object_type.name, &object_type.fields)?;
     }
     Ok(())
 }
 
+/// Adds a `_cursor: String` field to the given object and interface types
+/// unless the type already has a field with that name
+fn add_cursor_fields(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+    interface_types: &[&InterfaceType],
+) {
+    let cursor_field = Field {
+        position: Pos::default(),
+        description: Some(
+            "An opaque cursor for the position of this entity in a top-level \
+             collection query. Pass it as `after` or `before` to the same query \
+             to page through the collection"
+                .to_owned(),
+        ),
+        name: CURSOR_FIELD.to_string(),
+        arguments: vec![],
+        field_type: Type::NamedType("String".to_string()),
+        directives: vec![],
+    };
+
+    for object_type in object_types {
+        let object_type = ast::get_object_type_mut(schema, &object_type.name)
+            .expect("object type from input schema is missing in API schema");
+        if object_type.fields.iter().all(|f| f.name != CURSOR_FIELD) {
+            object_type.fields.push(cursor_field.clone());
+        }
+    }
+    for interface_type in interface_types {
+        let interface_type = ast::get_interface_type_mut(schema, &interface_type.name)
+            .expect("interface type from input schema is missing in API schema");
+        if interface_type.fields.iter().all(|f| f.name != CURSOR_FIELD) {
+            interface_type.fields.push(cursor_field.clone());
+        }
+    }
+}
+
 /// Adds `*_orderBy` and `*_filter` enum types for the given interfaces to the schema.
 fn add_types_for_interface_types(
     schema: &mut Document,
@@ -321,6 +374,195 @@ fn add_filter_type(
     Ok(())
 }
 
+/// Adds a field `<field>_: <Child>_filter` to the filter types of the given
+/// object and interface types for each field that refers to entities of an
+/// object type `Child`, including fields that are derived. The nested filter
+/// matches if at least one of the entities the field refers to matches it.
+///
+/// This has to happen after the filter types for all entity types have been
+/// added, since the filter type for `Child` must already exist
+fn add_child_filter_fields(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+    interface_types: &[&InterfaceType],
+) {
+    let types_and_fields = object_types
+        .iter()
+        .map(|t| (&t.name, &t.fields))
+        .chain(interface_types.iter().map(|t| (&t.name, &t.fields)));
+    for (type_name, fields) in types_and_fields {
+        let child_filters: Vec<_> = fields
+            .iter()
+            .filter_map(|field| {
+                let child_type = field.field_type.get_base_type();
+                match ast::get_named_type(schema, child_type) {
+                    Some(TypeDefinition::Object(_)) => (),
+                    _ => return None,
+                }
+                let child_filter = format!("{}_filter", child_type);
+                ast::get_named_type(schema, &child_filter)?;
+                Some(input_value(
+                    &format!("{}_", field.name),
+                    "",
+                    Type::NamedType(child_filter),
+                ))
+            })
+            .collect();
+
+        let filter_type_name = format!("{}_filter", type_name);
+        if let Some(TypeDefinition::InputObject(filter_type)) =
+            ast::get_named_type_definition_mut(schema, &filter_type_name)
+        {
+            for child_filter in child_filters {
+                if filter_type
+                    .fields
+                    .iter()
+                    .all(|field| field.name != child_filter.name)
+                {
+                    filter_type.fields.push(child_filter);
+                }
+            }
+        }
+    }
+}
+
+/// Adds a `<type_name>_aggregate` object type, and a `<type_name>_groupBy`
+/// enum type if there are fields that entities can be grouped by, for the
+/// given fields to the schema.
+///
+/// The aggregate type has a `count` field, fields `<field>_sum`,
+/// `<field>_min`, `<field>_max`, and `<field>_avg` for each numeric field,
+/// and a field for each field that entities can be grouped by.
+fn add_aggregate_types(
+    schema: &mut Document,
+    type_name: &String,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    let aggregate_type_name = format!("{}_aggregate", type_name);
+    let group_by_type_name = format!("{}_groupBy", type_name);
+    for name in &[&aggregate_type_name, &group_by_type_name] {
+        if ast::get_named_type(schema, name).is_some() {
+            return Err(APISchemaError::TypeExists(name.to_string()));
+        }
+    }
+
+    let mut aggregate_fields = vec![output_field(
+        "count".to_owned(),
+        Type::NonNullType(Box::new(Type::NamedType("Int".to_owned()))),
+    )];
+    for field in fields {
+        if let Some(name) = aggregate_scalar(field) {
+            let sum_type = if name == "Int" { "BigInt" } else { name };
+            for (function, value_type) in &[
+                ("sum", sum_type),
+                ("min", name),
+                ("max", name),
+                ("avg", "BigDecimal"),
+            ] {
+                aggregate_fields.push(output_field(
+                    format!("{}_{}", field.name, function),
+                    Type::NamedType(value_type.to_string()),
+                ));
+            }
+        }
+    }
+
+    // Fields we can group by appear with their own name in the aggregate
+    // type; fields whose name would clash with an aggregate are skipped
+    let group_by_fields: Vec<_> = fields
+        .iter()
+        .filter(|field| {
+            aggregate_fields
+                .iter()
+                .all(|aggregate| aggregate.name != field.name)
+        })
+        .filter_map(|field| group_by_type(schema, field).map(|value_type| (field, value_type)))
+        .collect();
+    aggregate_fields.extend(
+        group_by_fields
+            .iter()
+            .map(|(field, value_type)| output_field(field.name.clone(), value_type.clone())),
+    );
+
+    if !group_by_fields.is_empty() {
+        let typedef = TypeDefinition::Enum(EnumType {
+            position: Pos::default(),
+            description: None,
+            name: group_by_type_name,
+            directives: vec![],
+            values: group_by_fields
+                .iter()
+                .map(|(field, _)| EnumValue {
+                    position: Pos::default(),
+                    description: None,
+                    name: field.name.to_owned(),
+                    directives: vec![],
+                })
+                .collect(),
+        });
+        schema.definitions.push(Definition::TypeDefinition(typedef));
+    }
+
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: aggregate_type_name,
+        implements_interfaces: vec![],
+        directives: vec![],
+        fields: aggregate_fields,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// Returns the name of the scalar type of `field` if we can compute
+/// aggregates over it
+fn aggregate_scalar(field: &Field) -> Option<&'static str> {
+    let name = match &field.field_type {
+        Type::NamedType(name) => name,
+        Type::NonNullType(inner) => match inner.as_ref() {
+            Type::NamedType(name) => name,
+            _ => return None,
+        },
+        Type::ListType(_) => return None,
+    };
+    AGGREGATE_SCALARS
+        .iter()
+        .find(|scalar| **scalar == name.as_str())
+        .copied()
+}
+
+/// Returns the type with which `field` appears in the aggregate type if
+/// entities can be grouped by it. That is the case for fields that are
+/// neither lists nor derived; references to other entities are grouped by
+/// the id of the referenced entity
+fn group_by_type(schema: &Document, field: &Field) -> Option<Type> {
+    if ast::is_list_or_non_null_list_field(field)
+        || ast::get_derived_from_directive(field).is_some()
+    {
+        return None;
+    }
+    match ast::get_type_definition_from_field(schema, field)? {
+        TypeDefinition::Scalar(t) => Some(Type::NamedType(t.name.to_owned())),
+        TypeDefinition::Enum(t) => Some(Type::NamedType(t.name.to_owned())),
+        TypeDefinition::Object(_) | TypeDefinition::Interface(_) => {
+            Some(Type::NamedType("String".to_owned()))
+        }
+        TypeDefinition::InputObject(_) | TypeDefinition::Union(_) => None,
+    }
+}
+
+fn output_field(name: String, field_type: Type) -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name,
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    }
+}
+
 /// Generates `*_filter` input values for the given set of fields.
 fn field_input_values(
     schema: &Document,
@@ -405,6 +647,13 @@ fn field_scalar_filter_input_values(
             "not_starts_with",
             "ends_with",
             "not_ends_with",
+            "contains_nocase",
+            "not_contains_nocase",
+            "starts_with_nocase",
+            "not_starts_with_nocase",
+            "ends_with_nocase",
+            "not_ends_with_nocase",
+            "matches",
         ],
         _ => vec!["", "not"],
     }
@@ -519,6 +768,14 @@ fn add_query_type(
         .chain(interface_types.iter().map(|t| &t.name))
         .flat_map(|name| query_fields_for_type(schema, name, features))
         .collect::<Vec<Field>>();
+    // Aggregate fields are only added if their name does not clash with
+    // one of the other fields
+    let aggregate_fields: Vec<_> = object_types
+        .iter()
+        .map(|t| query_field_for_aggregate(schema, &t.name, features))
+        .filter(|aggregate| fields.iter().all(|field| field.name != aggregate.name))
+        .collect();
+    fields.extend(aggregate_fields);
     let mut fulltext_fields = schema
         .get_fulltext_directives()
         .map_err(|_| APISchemaError::FulltextSearchNonDeterministic)?
@@ -606,6 +863,62 @@ fn query_field_for_fulltext(
     })
 }
 
+/// Generates the `Query` field that computes aggregates over the entities of
+/// the given object type (e.g. `userAggregate`)
+fn query_field_for_aggregate(
+    schema: &Document,
+    type_name: &String,
+    features: &BTreeSet<SubgraphFeature>,
+) -> Field {
+    let mut skip = input_value(&"skip".to_string(), "", Type::NamedType("Int".to_string()));
+    skip.default_value = Some(Value::Int(0.into()));
+
+    let mut first = input_value(&"first".to_string(), "", Type::NamedType("Int".to_string()));
+    first.default_value = Some(Value::Int(100.into()));
+
+    let mut arguments = vec![skip, first];
+
+    // Not all types have filter or group by types
+    let filter_name = format!("{}_filter", type_name);
+    if ast::get_named_type(schema, &filter_name).is_some() {
+        arguments.push(input_value(
+            &"where".to_string(),
+            "",
+            Type::NamedType(filter_name),
+        ));
+    }
+    let group_by_name = format!("{}_groupBy", type_name);
+    if ast::get_named_type(schema, &group_by_name).is_some() {
+        arguments.push(input_value(
+            &"groupBy".to_string(),
+            "",
+            Type::ListType(Box::new(Type::NonNullType(Box::new(Type::NamedType(
+                group_by_name,
+            ))))),
+        ));
+    }
+    arguments.push(block_argument());
+
+    if features.contains(&SubgraphFeature::nonFatalErrors) {
+        arguments.push(subgraph_error_argument());
+    }
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Aggregate", type_name.as_str().to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!("{}_aggregate", type_name))),
+        ))))),
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: AGGREGATE_DIRECTIVE.to_owned(),
+            arguments: vec![("entity".to_owned(), Value::String(type_name.to_owned()))],
+        }],
+    }
+}
+
 /// Adds a root `Subscription` object type to the schema.
 fn add_subscription_type(
     schema: &mut Document,
@@ -657,6 +970,22 @@ fn block_argument() -> InputValue {
     }
 }
 
+fn cursor_arguments() -> Vec<InputValue> {
+    let cursor_argument = |name: &str| InputValue {
+        position: Pos::default(),
+        description: Some(format!(
+            "Only return entities that come {} the entity with this `_cursor` \
+             in the requested order.",
+            name
+        )),
+        name: name.to_string(),
+        value_type: Type::NamedType("String".to_string()),
+        default_value: None,
+        directives: vec![],
+    };
+    vec![cursor_argument("after"), cursor_argument("before")]
+}
+
 fn subgraph_error_argument() -> InputValue {
     InputValue {
         position: Pos::default(),
@@ -679,6 +1008,7 @@ fn query_fields_for_type(
 ) -> Vec<Field> {
     let input_objects = ast::get_input_object_definitions(schema);
     let mut collection_arguments = collection_arguments_for_named_type(&input_objects, type_name);
+    collection_arguments.extend(cursor_arguments());
     collection_arguments.push(block_argument());
 
     let mut by_id_arguments = vec![
@@ -1010,6 +1340,13 @@ mod tests {
                 "name_not_starts_with",
                 "name_ends_with",
                 "name_not_ends_with",
+                "name_contains_nocase",
+                "name_not_contains_nocase",
+                "name_starts_with_nocase",
+                "name_not_starts_with_nocase",
+                "name_ends_with_nocase",
+                "name_not_ends_with_nocase",
+                "name_matches",
                 "favoritePetNames",
                 "favoritePetNames_not",
                 "favoritePetNames_contains",
@@ -1032,11 +1369,32 @@ mod tests {
                 "favoritePet_not_starts_with",
                 "favoritePet_ends_with",
                 "favoritePet_not_ends_with",
+                "favoritePet_contains_nocase",
+                "favoritePet_not_contains_nocase",
+                "favoritePet_starts_with_nocase",
+                "favoritePet_not_starts_with_nocase",
+                "favoritePet_ends_with_nocase",
+                "favoritePet_not_ends_with_nocase",
+                "favoritePet_matches",
+                "pets_",
+                "favoritePet_",
+                "leastFavoritePet_",
+                "mostFavoritePets_",
             ]
             .iter()
             .map(|name| name.to_string())
             .collect::<Vec<String>>()
         );
+
+        let pets_filter = filter_type
+            .fields
+            .iter()
+            .find(|field| field.name == "pets_")
+            .expect("User_filter has a nested filter for pets");
+        assert_eq!(
+            Type::NamedType("Pet_filter".to_string()),
+            pets_filter.value_type
+        );
     }
 
     #[test]
@@ -1096,6 +1454,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
+                "after",
+                "before",
                 "block"
             ]
             .iter()
@@ -1195,6 +1555,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
+                "after",
+                "before",
                 "block",
                 "subgraphError"
             ]
@@ -1241,4 +1603,117 @@ type Gravatar @entity {
         }
         .expect("\"metadata\" field is missing on Query type");
     }
+
+    #[test]
+    fn api_schema_contains_cursor_fields() {
+        let input_schema = parse_schema(
+            "interface Node { id: ID!, name: String! } \
+             type User implements Node @entity { id: ID!, name: String! } \
+             type Page @entity { id: ID!, _cursor: Int! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let cursor_type = |type_name: &str| -> Vec<String> {
+            let fields = match ast::get_named_type(&schema, &type_name.to_string()) {
+                Some(TypeDefinition::Object(t)) => &t.fields,
+                Some(TypeDefinition::Interface(t)) => &t.fields,
+                _ => panic!("type {} is missing in derived API schema", type_name),
+            };
+            fields
+                .iter()
+                .filter(|field| field.name == CURSOR_FIELD)
+                .map(|field| field.field_type.to_string())
+                .collect()
+        };
+        assert_eq!(vec!["String".to_string()], cursor_type("Node"));
+        assert_eq!(vec!["String".to_string()], cursor_type("User"));
+        // An existing `_cursor` field is left alone
+        assert_eq!(vec!["Int!".to_string()], cursor_type("Page"));
+    }
+
+    #[test]
+    fn api_schema_contains_aggregate_types_and_query_field() {
+        let input_schema = parse_schema(
+            "type Token @entity { id: ID!, owner: User!, amount: BigInt!, decimals: Int, \
+             tags: [String!]! } \
+             type User @entity { id: ID!, tokens: [Token!]! @derivedFrom(field: \"owner\") }",
+        )
+        .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let aggregate_type = match ast::get_named_type(&schema, &"Token_aggregate".to_string()) {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Token_aggregate type is missing in derived API schema"),
+        };
+        assert_eq!(
+            aggregate_type
+                .fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.field_type.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("count", "Int!".to_string()),
+                ("amount_sum", "BigInt".to_string()),
+                ("amount_min", "BigInt".to_string()),
+                ("amount_max", "BigInt".to_string()),
+                ("amount_avg", "BigDecimal".to_string()),
+                ("decimals_sum", "BigInt".to_string()),
+                ("decimals_min", "Int".to_string()),
+                ("decimals_max", "Int".to_string()),
+                ("decimals_avg", "BigDecimal".to_string()),
+                ("id", "ID".to_string()),
+                ("owner", "String".to_string()),
+                ("amount", "BigInt".to_string()),
+                ("decimals", "Int".to_string()),
+            ]
+        );
+
+        let group_by_type = match ast::get_named_type(&schema, &"Token_groupBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t,
+            _ => panic!("Token_groupBy type is missing in derived API schema"),
+        };
+        assert_eq!(
+            group_by_type
+                .values
+                .iter()
+                .map(|value| value.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["id", "owner", "amount", "decimals"]
+        );
+
+        let query_type = ast::get_named_type(&schema, &"Query".to_string())
+            .expect("Query type is missing in derived API schema");
+        let aggregate_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"tokenAggregate".to_string()),
+            _ => None,
+        }
+        .expect("\"tokenAggregate\" field is missing on Query type");
+
+        assert_eq!(
+            aggregate_field.field_type.to_string(),
+            "[Token_aggregate!]!".to_string()
+        );
+        assert_eq!(
+            aggregate_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["skip", "first", "where", "groupBy", "block"]
+        );
+
+        // Aggregates are only generated for object types, and do not appear
+        // on the subscription type
+        let subscription_type = ast::get_named_type(&schema, &"Subscription".to_string())
+            .expect("Subscription type is missing in derived API schema");
+        match subscription_type {
+            TypeDefinition::Object(t) => {
+                assert!(ast::get_field(t, &"tokenAggregate".to_string()).is_none())
+            }
+            _ => unreachable!("Subscription is an object type"),
+        }
+    }
 }
@@ -24,6 +24,13 @@ pub(crate) enum FilterOp {
     NotStartsWith,
     EndsWith,
     NotEndsWith,
+    ContainsNoCase,
+    NotContainsNoCase,
+    StartsWithNoCase,
+    NotStartsWithNoCase,
+    EndsWithNoCase,
+    NotEndsWithNoCase,
+    Matches,
     Equal,
 }
 
@@ -37,6 +44,21 @@ pub(crate) fn parse_field_as_filter(key: &String) -> (String, FilterOp) {
         k if k.ends_with("_lte") => ("_lte", FilterOp::LessOrEqual),
         k if k.ends_with("_not_in") => ("_not_in", FilterOp::NotIn),
         k if k.ends_with("_in") => ("_in", FilterOp::In),
+        k if k.ends_with("_not_contains_nocase") => {
+            ("_not_contains_nocase", FilterOp::NotContainsNoCase)
+        }
+        k if k.ends_with("_contains_nocase") => ("_contains_nocase", FilterOp::ContainsNoCase),
+        k if k.ends_with("_not_starts_with_nocase") => {
+            ("_not_starts_with_nocase", FilterOp::NotStartsWithNoCase)
+        }
+        k if k.ends_with("_not_ends_with_nocase") => {
+            ("_not_ends_with_nocase", FilterOp::NotEndsWithNoCase)
+        }
+        k if k.ends_with("_starts_with_nocase") => {
+            ("_starts_with_nocase", FilterOp::StartsWithNoCase)
+        }
+        k if k.ends_with("_ends_with_nocase") => ("_ends_with_nocase", FilterOp::EndsWithNoCase),
+        k if k.ends_with("_matches") => ("_matches", FilterOp::Matches),
         k if k.ends_with("_not_contains") => ("_not_contains", FilterOp::NotContains),
         k if k.ends_with("_contains") => ("_contains", FilterOp::Contains),
         k if k.ends_with("_not_starts_with") => ("_not_starts_with", FilterOp::NotStartsWith),
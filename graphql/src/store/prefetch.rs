@@ -4,27 +4,81 @@
 use anyhow::{anyhow, Error};
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::iter::once;
 use std::rc::Rc;
-use std::time::Instant;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use graph::data::graphql::*;
+use graph::data::query::SqlTrace;
 use graph::prelude::{
-    q, s, ApiSchema, BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink,
-    EntityOrder, EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore,
-    Value as StoreValue, WindowAttribute,
+    q, s, ApiSchema, BlockNumber, CacheWeight, ChildMultiplicity, EntityCollection, EntityFilter,
+    EntityLink, EntityOrder, EntityQuery, EntityWindow, Logger, ParentLink, QueryExecutionError,
+    QueryStore, SubgraphDeploymentId, Value as StoreValue, WindowAttribute,
 };
+use graph::util::lfu_cache::LfuCache;
 
 use crate::execution::{ExecutionContext, Resolver};
 use crate::query::ast as qast;
+use crate::schema::api::{AGGREGATE_DIRECTIVE, CURSOR_FIELD};
 use crate::schema::ast as sast;
-use crate::store::{build_query, StoreResolver};
+use crate::store::query::encode_cursor;
+use crate::store::{build_aggregation, build_query, StoreResolver};
 
 lazy_static! {
     static ref ARG_FIRST: String = String::from("first");
     static ref ARG_SKIP: String = String::from("skip");
     static ref ARG_ID: String = String::from("id");
+    static ref ARG_TEXT: String = String::from("text");
+
+    /// Cost estimates for the top-level fields of queries
+    static ref COST_ESTIMATES: Mutex<LfuCache<CostKey, CostEstimate>> =
+        Mutex::new(LfuCache::new());
+}
+
+/// How long we reuse a cost estimate before asking the database again.
+/// Estimates are made for a query shape, and different variables or
+/// changes in the data can change them
+const COST_ESTIMATE_TTL: Duration = Duration::from_secs(300);
+
+/// The maximum weight of the cost estimates we keep, in bytes; when we
+/// have more than that, the least frequently used ones are evicted
+const COST_ESTIMATES_MAX_WEIGHT: usize = 1_000_000;
+
+/// Cost estimates are keyed by the deployment and a hash of the query
+/// shape and the response keys of the top-level fields
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct CostKey(SubgraphDeploymentId, u64);
+
+impl CacheWeight for CostKey {
+    fn indirect_weight(&self) -> usize {
+        self.0.as_str().len()
+    }
+}
+
+/// A cost estimate and the time at which it was made
+struct CostEstimate {
+    cost: f64,
+    made_at: Instant,
+}
+
+// Only needed to look entries up in the `LfuCache`
+impl Default for CostEstimate {
+    fn default() -> Self {
+        CostEstimate {
+            cost: 0.0,
+            made_at: Instant::now(),
+        }
+    }
+}
+
+impl CacheWeight for CostEstimate {
+    fn indirect_weight(&self) -> usize {
+        0
+    }
 }
 
 /// An `ObjectType` with `Hash` and `Eq` derived from the name.
@@ -424,6 +478,8 @@ pub fn run(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
 ) -> Result<q::Value, Vec<QueryExecutionError>> {
+    check_cost(resolver, ctx, selection_set)?;
+
     execute_root_selection_set(resolver, ctx, selection_set).map(|nodes| {
         let map = BTreeMap::default();
         q::Value::Object(nodes.into_iter().fold(map, |mut map, node| {
@@ -436,6 +492,112 @@ pub fn run(
     })
 }
 
+/// Estimate the cost of the queries for the top-level fields in
+/// `selection_set` and reject the query if it exceeds `ctx.max_cost`.
+/// Estimates only consider the queries for the top-level collections and
+/// not the queries for nested fields, since we can not construct those
+/// without knowing the parents they will be joined with. Aggregates are
+/// not estimated either
+fn check_cost(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+) -> Result<(), Vec<QueryExecutionError>> {
+    let max_cost = match ctx.max_cost {
+        Some(max_cost) => max_cost,
+        None => return Ok(()),
+    };
+
+    let query_type = ctx.query.schema.query_type.as_ref().into();
+    let grouped_field_set = collect_fields(ctx, query_type, once(selection_set));
+
+    let key = {
+        let mut hasher = DefaultHasher::new();
+        ctx.query.shape_hash.hash(&mut hasher);
+        for response_key in grouped_field_set.keys() {
+            response_key.hash(&mut hasher);
+        }
+        CostKey(ctx.query.schema.id().clone(), hasher.finish())
+    };
+
+    let cached = COST_ESTIMATES
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|estimate| estimate.made_at.elapsed() < COST_ESTIMATE_TTL)
+        .map(|estimate| estimate.cost);
+    let cost = match cached {
+        Some(cost) => cost,
+        None => {
+            let cost = estimate_cost(resolver, ctx, grouped_field_set)?;
+            let mut estimates = COST_ESTIMATES.lock().unwrap();
+            estimates.insert(
+                key,
+                CostEstimate {
+                    cost,
+                    made_at: Instant::now(),
+                },
+            );
+            estimates.evict(COST_ESTIMATES_MAX_WEIGHT);
+            cost
+        }
+    };
+
+    ctx.estimated_cost.store(Some(cost));
+    if cost > max_cost {
+        return Err(vec![QueryExecutionError::EstimatedCostTooHigh(
+            cost, max_cost,
+        )]);
+    }
+    Ok(())
+}
+
+/// Ask the store for the cost of the queries for the top-level fields in
+/// `grouped_field_set` and add them up
+fn estimate_cost<'a>(
+    resolver: &StoreResolver,
+    ctx: &'a ExecutionContext<impl Resolver>,
+    grouped_field_set: IndexMap<&'a String, CollectedResponseKey<'a>>,
+) -> Result<f64, Vec<QueryExecutionError>> {
+    let schema = &ctx.query.schema;
+    let mut cost = 0.0;
+
+    for (_, collected_fields) in grouped_field_set {
+        for (type_cond, fields) in collected_fields {
+            // Unwrap: The query was validated to contain only valid fields,
+            // and `collect_fields` will skip introspection fields.
+            let field = type_cond.field(&fields[0].name).unwrap();
+            if field
+                .find_directive(AGGREGATE_DIRECTIVE.to_string())
+                .is_some()
+            {
+                continue;
+            }
+
+            let child_type = schema
+                .document()
+                .object_or_interface(field.field_type.get_base_type())
+                .expect("we only collect fields that are objects or interfaces");
+            let arguments =
+                crate::execution::coerce_argument_values(&ctx.query, type_cond, fields[0])?;
+            let query = entity_query(
+                child_type,
+                &arguments,
+                multiplicity(field),
+                schema.types_for_interface(),
+                schema.document(),
+                resolver.block_number(),
+                ctx.max_first,
+                ctx.max_skip,
+                ctx.query.query_id.clone(),
+            )
+            .map_err(|e| vec![e])?;
+            cost += resolver.store.estimate_cost(query).map_err(|e| vec![e])?;
+        }
+    }
+    Ok(cost)
+}
+
 /// Executes the root selection set of a query.
 fn execute_root_selection_set(
     resolver: &StoreResolver,
@@ -448,7 +610,7 @@ fn execute_root_selection_set(
     let grouped_field_set = collect_fields(ctx, query_type, once(selection_set));
 
     // Execute the root selection set against the root query type
-    execute_selection_set(resolver, ctx, make_root_node(), grouped_field_set)
+    execute_selection_set(resolver, ctx, make_root_node(), grouped_field_set, &[])
 }
 
 fn execute_selection_set<'a>(
@@ -456,6 +618,7 @@ fn execute_selection_set<'a>(
     ctx: &'a ExecutionContext<impl Resolver>,
     mut parents: Vec<Node>,
     grouped_field_set: IndexMap<&'a String, CollectedResponseKey<'a>>,
+    path: &[&'a String],
 ) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
     let schema = &ctx.query.schema;
     let mut errors: Vec<QueryExecutionError> = Vec::new();
@@ -487,6 +650,21 @@ fn execute_selection_set<'a>(
             // Unwrap: The query was validated to contain only valid fields,
             // and `collect_fields` will skip introspection fields.
             let field = type_cond.field(&fields[0].name).unwrap();
+            let start = Instant::now();
+
+            // Aggregates are computed in the database and their results
+            // have no nested entities that we would need to join
+            if let Some(aggregate) = field.find_directive(AGGREGATE_DIRECTIVE.to_string()) {
+                match execute_aggregate(resolver, ctx, type_cond, aggregate, &fields, field) {
+                    Ok(children) => {
+                        trace_field(ctx, path, response_key, type_cond, field, start, None);
+                        Join::perform(parents, children, response_key)
+                    }
+                    Err(mut e) => errors.append(&mut e),
+                }
+                continue;
+            }
+
             let child_type = schema
                 .document()
                 .object_or_interface(field.field_type.get_base_type())
@@ -506,8 +684,10 @@ fn execute_selection_set<'a>(
             match execute_field(
                 resolver, &ctx, type_cond, &parents, &join, &fields[0], field,
             ) {
-                Ok(children) => {
-                    match execute_selection_set(resolver, ctx, children, grouped_field_set) {
+                Ok((children, sql)) => {
+                    trace_field(ctx, path, response_key, type_cond, field, start, sql);
+                    let path: Vec<_> = path.iter().cloned().chain(once(response_key)).collect();
+                    match execute_selection_set(resolver, ctx, children, grouped_field_set, &path) {
                         Ok(children) => Join::perform(parents, children, response_key),
                         Err(mut e) => errors.append(&mut e),
                     }
@@ -526,6 +706,31 @@ fn execute_selection_set<'a>(
     }
 }
 
+/// Add the time it took to resolve `field` to the trace of the query, if
+/// the client asked for one
+fn trace_field(
+    ctx: &ExecutionContext<impl Resolver>,
+    path: &[&String],
+    response_key: &String,
+    parent_type: ObjectOrInterface<'_>,
+    field: &s::Field,
+    start: Instant,
+    sql: Option<SqlTrace>,
+) {
+    if let Some(trace) = &ctx.trace {
+        let path = path
+            .iter()
+            .cloned()
+            .chain(once(response_key))
+            .cloned()
+            .collect();
+        trace
+            .lock()
+            .unwrap()
+            .add_field(path, parent_type.name(), &field.name, start, sql);
+    }
+}
+
 /// If the top-level selection is on an object, there will be a single entry in `obj_types` with all
 /// the collected fields.
 ///
@@ -758,33 +963,101 @@ fn execute_field(
     join: &Join<'_>,
     field: &q::Field,
     field_definition: &s::Field,
-) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
+) -> Result<(Vec<Node>, Option<SqlTrace>), Vec<QueryExecutionError>> {
     let argument_values = crate::execution::coerce_argument_values(&ctx.query, object_type, field)?;
 
-    let multiplicity = if sast::is_list_or_non_null_list_field(field_definition) {
-        ChildMultiplicity::Many
-    } else {
-        ChildMultiplicity::Single
-    };
     fetch(
         ctx.logger.clone(),
         resolver.store.as_ref(),
         parents,
         &join,
         argument_values,
-        multiplicity,
+        multiplicity(field_definition),
         ctx.query.schema.types_for_interface(),
+        ctx.query.schema.document(),
         resolver.block_number(),
         ctx.max_first,
         ctx.max_skip,
         ctx.query.query_id.clone(),
+        ctx.trace.is_some(),
     )
     .map_err(|e| vec![e])
 }
 
+fn multiplicity(field_definition: &s::Field) -> ChildMultiplicity {
+    if sast::is_list_or_non_null_list_field(field_definition) {
+        ChildMultiplicity::Many
+    } else {
+        ChildMultiplicity::Single
+    }
+}
+
+/// Computes the aggregates for a field marked with the `@aggregate`
+/// directive. Since aggregate fields only exist on `Query`, they always
+/// belong to the root node
+fn execute_aggregate(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    object_type: ObjectOrInterface<'_>,
+    directive: &s::Directive,
+    fields: &[&q::Field],
+    field_definition: &s::Field,
+) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
+    let schema = ctx.query.schema.document();
+    let argument_values =
+        crate::execution::coerce_argument_values(&ctx.query, object_type, fields[0])?;
+
+    // Unwrap: `api_schema` generates the `@aggregate` directive and the
+    // aggregate type together with the entity type they refer to
+    let entity = match directive.argument("entity") {
+        Some(s::Value::String(name)) => schema.get_object_type_definition(name).unwrap(),
+        _ => unreachable!("the `@aggregate` directive always has an `entity` argument"),
+    };
+    let aggregate_type = schema
+        .get_object_type_definition(field_definition.field_type.get_base_type())
+        .unwrap();
+
+    let selected_fields = crate::execution::collect_fields(
+        ctx,
+        aggregate_type,
+        fields.iter().map(|field| &field.selection_set),
+    );
+
+    let mut aggregation = build_aggregation(
+        entity,
+        resolver.block_number(),
+        &argument_values,
+        selected_fields.values().map(|fields| &fields[0].name),
+        schema,
+        ctx.max_first,
+        ctx.max_skip,
+    )
+    .map_err(|e| vec![e])?;
+    aggregation.logger = Some(ctx.logger.clone());
+    aggregation.query_id = Some(ctx.query.query_id.clone());
+
+    resolver
+        .store
+        .aggregate(aggregation)
+        .map(|values| {
+            values
+                .into_iter()
+                .map(|mut value| {
+                    value.insert(
+                        "__typename".to_owned(),
+                        q::Value::String(aggregate_type.name.clone()),
+                    );
+                    value.into()
+                })
+                .collect()
+        })
+        .map_err(|e| vec![e])
+}
+
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
-/// `is_single` is `true`, there is at most one child per parent.
+/// `is_single` is `true`, there is at most one child per parent. If `trace`
+/// is `true`, also return a trace of the SQL query that was run
 fn fetch(
     logger: Logger,
     store: &(impl QueryStore + ?Sized),
@@ -793,16 +1066,86 @@ fn fetch(
     arguments: HashMap<&String, q::Value>,
     multiplicity: ChildMultiplicity,
     types_for_interface: &BTreeMap<String, Vec<s::ObjectType>>,
+    schema: &s::Document,
     block: BlockNumber,
     max_first: u32,
     max_skip: u32,
     query_id: String,
-) -> Result<Vec<Node>, QueryExecutionError> {
-    let mut query = build_query(
+    trace: bool,
+) -> Result<(Vec<Node>, Option<SqlTrace>), QueryExecutionError> {
+    let mut query = entity_query(
         join.child_type,
-        block,
         &arguments,
+        multiplicity,
+        types_for_interface,
+        schema,
+        block,
+        max_first,
+        max_skip,
+        query_id,
+    )?;
+    query.logger = Some(logger);
+    query.trace = trace;
+
+    // Entities in top-level collections get a `_cursor` that clients can
+    // use to continue paging through the collection
+    let mut cursor_order = None;
+    if !is_root_node(parents.iter().map(|p| &**p)) {
+        // For anything but the root node, restrict the children we select
+        // by the parent list
+        let windows = join.windows(parents, multiplicity);
+        if windows.len() == 0 {
+            return Ok((vec![], None));
+        }
+        query.collection = EntityCollection::Window(windows);
+    } else if !arguments.contains_key(&*ARG_TEXT) {
+        cursor_order = match &query.order {
+            EntityOrder::Ascending(attr, _) | EntityOrder::Descending(attr, _) => {
+                Some(Some(attr.to_owned()))
+            }
+            EntityOrder::Default => Some(None),
+            EntityOrder::Unordered => None,
+        };
+    }
+
+    store.find_query_values(query).map(|(entities, sql)| {
+        let nodes = entities
+            .into_iter()
+            .map(|mut entity| {
+                if let Some(order_attr) = &cursor_order {
+                    if !entity.contains_key(CURSOR_FIELD) {
+                        if let Some(cursor) = encode_cursor(order_attr.as_deref(), &entity) {
+                            entity.insert(CURSOR_FIELD.to_owned(), q::Value::String(cursor));
+                        }
+                    }
+                }
+                entity.into()
+            })
+            .collect();
+        (nodes, sql)
+    })
+}
+
+/// Build the query for the children of a field with the given
+/// `arguments`. The query selects from all entities of `child_type`; it is
+/// up to the caller to restrict that to the children of specific parents
+fn entity_query<'a>(
+    child_type: ObjectOrInterface<'a>,
+    arguments: &HashMap<&String, q::Value>,
+    multiplicity: ChildMultiplicity,
+    types_for_interface: &BTreeMap<String, Vec<s::ObjectType>>,
+    schema: &s::Document,
+    block: BlockNumber,
+    max_first: u32,
+    max_skip: u32,
+    query_id: String,
+) -> Result<EntityQuery, QueryExecutionError> {
+    let mut query = build_query(
+        child_type,
+        block,
+        arguments,
         types_for_interface,
+        schema,
         max_first,
         max_skip,
     )?;
@@ -814,25 +1157,11 @@ fn fetch(
         query.order = EntityOrder::Unordered;
     }
 
-    query.logger = Some(logger);
     if let Some(q::Value::String(id)) = arguments.get(&*ARG_ID) {
         query.filter = Some(
             EntityFilter::Equal(ARG_ID.to_owned(), StoreValue::from(id.to_owned()))
                 .and_maybe(query.filter),
         );
     }
-
-    if !is_root_node(parents.iter().map(|p| &**p)) {
-        // For anything but the root node, restrict the children we select
-        // by the parent list
-        let windows = join.windows(parents, multiplicity);
-        if windows.len() == 0 {
-            return Ok(vec![]);
-        }
-        query.collection = EntityCollection::Window(windows);
-    }
-
-    store
-        .find_query_values(query)
-        .map(|entities| entities.into_iter().map(|entity| entity.into()).collect())
+    Ok(query)
 }
@@ -2,10 +2,16 @@ use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::mem::discriminant;
 
 use graph::prelude::*;
-use graph::{components::store::EntityType, data::graphql::ObjectOrInterface};
+use graph::{
+    components::store::EntityType,
+    data::graphql::{DocumentExt, ObjectOrInterface, TypeExt},
+};
 
 use crate::schema::ast as sast;
 
+/// The maximum length of the pattern for a `_matches` filter
+const MAX_REGEX_LENGTH: usize = 256;
+
 #[derive(Debug)]
 enum OrderDirection {
     Ascending,
@@ -20,6 +26,7 @@ pub fn build_query<'a>(
     block: BlockNumber,
     arguments: &HashMap<&String, q::Value>,
     types_for_interface: &BTreeMap<String, Vec<s::ObjectType>>,
+    schema: &s::Document,
     max_first: u32,
     max_skip: u32,
 ) -> Result<EntityQuery, QueryExecutionError> {
@@ -33,7 +40,7 @@ pub fn build_query<'a>(
     });
     let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
         .range(build_range(arguments, max_first, max_skip)?);
-    if let Some(filter) = build_filter(entity, arguments)? {
+    if let Some(filter) = build_filter(entity, arguments, schema)? {
         query = query.filter(filter);
     }
     let order = match (
@@ -48,10 +55,77 @@ pub fn build_query<'a>(
         }
         (None, _) => EntityOrder::Default,
     };
+    if let Some(cursor) = build_cursor(entity, arguments, &order)? {
+        query = query.cursor(cursor);
+    }
     query = query.order(order);
     Ok(query)
 }
 
+/// Builds an `EntityAggregation` for the aggregate field of `entity` from
+/// GraphQL arguments. `selected_fields` are the names of the fields that
+/// the query selects from the aggregate type; we only compute the
+/// aggregates that are actually needed
+pub fn build_aggregation<'a>(
+    entity: &s::ObjectType,
+    block: BlockNumber,
+    arguments: &HashMap<&String, q::Value>,
+    selected_fields: impl Iterator<Item = &'a String>,
+    schema: &s::Document,
+    max_first: u32,
+    max_skip: u32,
+) -> Result<EntityAggregation, QueryExecutionError> {
+    let group_by: Vec<Attribute> = match arguments.get(&"groupBy".to_string()) {
+        Some(q::Value::List(values)) => values
+            .iter()
+            .map(|value| match value {
+                q::Value::Enum(attr) => attr.to_owned(),
+                _ => unreachable!("groupBy is a list of enum values"),
+            })
+            .collect(),
+        Some(q::Value::Null) | None => vec![],
+        _ => unreachable!("groupBy is a list of enum values"),
+    };
+
+    // Fields of the aggregate type that are neither aggregates over a
+    // numeric field nor mentioned in `groupBy` resolve to `null`
+    let mut aggregates = vec![];
+    for name in selected_fields {
+        if group_by.contains(name) {
+            continue;
+        }
+        let aggregate = match EntityAggregate::from_name(name) {
+            Some(aggregate) => aggregate,
+            None => continue,
+        };
+        let is_numeric = match aggregate.attribute() {
+            None => true,
+            Some(attr) => sast::get_field(entity, attr)
+                .filter(|field| !sast::is_list_or_non_null_list_field(field))
+                .map(|field| {
+                    let base_type = field.field_type.get_base_type();
+                    base_type == "Int" || base_type == "BigInt" || base_type == "BigDecimal"
+                })
+                .unwrap_or(false),
+        };
+        if is_numeric && !aggregates.contains(&aggregate) {
+            aggregates.push(aggregate);
+        }
+    }
+
+    Ok(EntityAggregation {
+        subgraph_id: parse_subgraph_id(entity)?,
+        block,
+        entity_type: entity.name.clone(),
+        filter: build_filter(entity.into(), arguments, schema)?,
+        aggregates,
+        group_by,
+        range: build_range(arguments, max_first, max_skip)?,
+        logger: None,
+        query_id: None,
+    })
+}
+
 /// Parses GraphQL arguments into a EntityRange, if present.
 fn build_range(
     arguments: &HashMap<&String, q::Value>,
@@ -98,9 +172,10 @@ fn build_range(
 fn build_filter(
     entity: ObjectOrInterface,
     arguments: &HashMap<&String, q::Value>,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     match arguments.get(&"where".to_string()) {
-        Some(q::Value::Object(object)) => build_filter_from_object(entity, object),
+        Some(q::Value::Object(object)) => build_filter_from_object(entity, object, schema),
         Some(q::Value::Null) => Ok(None),
         None => match arguments.get(&"text".to_string()) {
             Some(q::Value::Object(filter)) => build_fulltext_filter_from_object(filter),
@@ -133,6 +208,7 @@ fn build_fulltext_filter_from_object(
 fn build_filter_from_object(
     entity: ObjectOrInterface,
     object: &BTreeMap<String, q::Value>,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     Ok(Some(EntityFilter::And({
         object
@@ -140,6 +216,15 @@ fn build_filter_from_object(
             .map(|(key, value)| {
                 use self::sast::FilterOp::*;
 
+                // A nested filter `pair_: { .. }` for a reference field
+                match value {
+                    q::Value::Object(object) if key.ends_with('_') => {
+                        let field_name = &key[..key.len() - 1];
+                        return build_child_filter(entity, field_name, object, schema);
+                    }
+                    _ => (),
+                }
+
                 let (field_name, op) = sast::parse_field_as_filter(key);
 
                 let field = sast::get_field(entity, &field_name).ok_or_else(|| {
@@ -166,6 +251,20 @@ fn build_filter_from_object(
                     NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
                     EndsWith => EntityFilter::EndsWith(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
+                    ContainsNoCase => EntityFilter::ContainsNoCase(field_name, store_value),
+                    NotContainsNoCase => EntityFilter::NotContainsNoCase(field_name, store_value),
+                    StartsWithNoCase => EntityFilter::StartsWithNoCase(field_name, store_value),
+                    NotStartsWithNoCase => {
+                        EntityFilter::NotStartsWithNoCase(field_name, store_value)
+                    }
+                    EndsWithNoCase => EntityFilter::EndsWithNoCase(field_name, store_value),
+                    NotEndsWithNoCase => EntityFilter::NotEndsWithNoCase(field_name, store_value),
+                    Matches => {
+                        if let Value::String(pattern) = &store_value {
+                            check_regex(pattern)?;
+                        }
+                        EntityFilter::Matches(field_name, store_value)
+                    }
                     Equal => EntityFilter::Equal(field_name, store_value),
                 })
             })
@@ -173,6 +272,122 @@ fn build_filter_from_object(
     })))
 }
 
+/// Reject regular expressions for `_matches` filters that are likely to
+/// make Postgres spend an inordinate amount of time matching: patterns
+/// that are very long, that use backreferences, or that nest quantifiers
+/// like `(a+)*`
+fn check_regex(pattern: &str) -> Result<(), QueryExecutionError> {
+    let invalid = |reason: &str| {
+        Err(QueryExecutionError::InvalidRegex(
+            pattern.to_owned(),
+            reason.to_owned(),
+        ))
+    };
+
+    if pattern.len() > MAX_REGEX_LENGTH {
+        return invalid(&format!(
+            "patterns can be at most {} characters long",
+            MAX_REGEX_LENGTH
+        ));
+    }
+
+    // For each open group, whether it contains a quantifier
+    let mut groups: Vec<bool> = vec![];
+    // Whether the last complete atom contains a quantifier
+    let mut last_quantified = false;
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() && d != '0' => {
+                    return invalid("backreferences are not supported")
+                }
+                _ => last_quantified = false,
+            },
+            '[' => {
+                // Skip over the bracket expression; a `]` right after the
+                // opening `[` or `[^` is a literal
+                if chars.peek() == Some(&'^') {
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                }
+                for d in chars.by_ref() {
+                    if d == ']' {
+                        break;
+                    }
+                }
+                last_quantified = false;
+            }
+            '(' => {
+                groups.push(false);
+                last_quantified = false;
+            }
+            ')' => {
+                last_quantified = groups.pop().unwrap_or(false);
+                if let Some(group) = groups.last_mut() {
+                    *group |= last_quantified;
+                }
+            }
+            '*' | '+' | '?' | '{' => {
+                if c == '{' {
+                    for d in chars.by_ref() {
+                        if d == '}' {
+                            break;
+                        }
+                    }
+                }
+                if last_quantified {
+                    return invalid("nested quantifiers are not supported");
+                }
+                if let Some(group) = groups.last_mut() {
+                    *group = true;
+                }
+                last_quantified = false;
+            }
+            _ => last_quantified = false,
+        }
+    }
+    Ok(())
+}
+
+/// Parses the nested filter `object` for the entities that the field
+/// `field_name` of `entity` refers to into an `EntityFilter::Child`
+fn build_child_filter(
+    entity: ObjectOrInterface,
+    field_name: &str,
+    object: &BTreeMap<String, q::Value>,
+    schema: &s::Document,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let field = sast::get_field(entity, field_name).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), field_name.to_owned())
+    })?;
+    // Nested filters are only generated for fields whose type is an
+    // object type
+    let child_type = schema
+        .get_object_type_definition(field.field_type.get_base_type())
+        .ok_or_else(|| {
+            QueryExecutionError::NotSupported(format!(
+                "nested filter on field `{}` of `{}`",
+                field_name,
+                entity.name()
+            ))
+        })?;
+    let (attr, derived) = match sast::get_derived_from_field(child_type, field) {
+        Some(derived_from) => (derived_from.name.clone(), true),
+        None => (field.name.clone(), false),
+    };
+    let filter = build_filter_from_object(child_type.into(), object, schema)?
+        .unwrap_or_else(|| EntityFilter::And(vec![]));
+    Ok(EntityFilter::Child(Child {
+        attr,
+        entity_type: child_type.name.clone(),
+        filter: Box::new(filter),
+        derived,
+    }))
+}
+
 /// Parses a list of GraphQL values into a vector of entity field values.
 fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecutionError> {
     match value {
@@ -257,6 +472,111 @@ fn build_order_direction(
         .unwrap_or(OrderDirection::Ascending))
 }
 
+/// Parses the `after` and `before` arguments into an `EntityCursor`, if
+/// present. Cursors are produced by `encode_cursor` and only valid for the
+/// order that was used for the query that produced them
+fn build_cursor(
+    entity: ObjectOrInterface,
+    arguments: &HashMap<&String, q::Value>,
+    order: &EntityOrder,
+) -> Result<Option<EntityCursor>, QueryExecutionError> {
+    let string_arg = |name: &str| match arguments.get(&name.to_string()) {
+        Some(q::Value::String(s)) => Some(s),
+        _ => None,
+    };
+    let (cursor, before) = match (string_arg("after"), string_arg("before")) {
+        (None, None) => return Ok(None),
+        (Some(cursor), None) => (cursor, false),
+        (None, Some(cursor)) => (cursor, true),
+        (Some(_), Some(_)) => {
+            return Err(QueryExecutionError::InvalidCursor(
+                "only one of `after` and `before` can be used".to_string(),
+            ))
+        }
+    };
+    if arguments.contains_key(&"text".to_string()) {
+        return Err(QueryExecutionError::InvalidCursor(
+            "cursors can not be used with fulltext search".to_string(),
+        ));
+    }
+
+    let invalid = || QueryExecutionError::InvalidCursor(format!("`{}` is not a cursor", cursor));
+    let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+    let json: serde_json::Value = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+    let attr = match json.get("attr") {
+        Some(serde_json::Value::String(attr)) => Some(attr.as_str()),
+        Some(serde_json::Value::Null) | None => None,
+        Some(_) => return Err(invalid()),
+    };
+    let id = match json.get("id") {
+        Some(serde_json::Value::String(id)) => id.to_owned(),
+        _ => return Err(invalid()),
+    };
+
+    let order_attr = match order {
+        EntityOrder::Ascending(attr, _) | EntityOrder::Descending(attr, _) => Some(attr.as_str()),
+        EntityOrder::Default | EntityOrder::Unordered => None,
+    };
+    if attr != order_attr {
+        return Err(QueryExecutionError::InvalidCursor(format!(
+            "the cursor was created for a query ordered by `{}`",
+            attr.unwrap_or("id")
+        )));
+    }
+
+    let value = match attr {
+        Some(attr) if attr != "id" => {
+            let field = sast::get_field(entity, attr).ok_or_else(|| {
+                QueryExecutionError::EntityFieldError(entity.name().to_owned(), attr.to_owned())
+            })?;
+            let value = match json.get("value") {
+                Some(serde_json::Value::Null) | None => q::Value::Null,
+                Some(serde_json::Value::Bool(b)) => q::Value::Boolean(*b),
+                Some(serde_json::Value::String(s)) => q::Value::String(s.to_owned()),
+                Some(serde_json::Value::Number(n)) => match n.as_i64() {
+                    Some(n) if n >= std::i32::MIN as i64 && n <= std::i32::MAX as i64 => {
+                        q::Value::Int((n as i32).into())
+                    }
+                    _ => return Err(invalid()),
+                },
+                Some(_) => return Err(invalid()),
+            };
+            Some(Value::from_query_value(&value, &field.field_type).map_err(|_| invalid())?)
+        }
+        _ => None,
+    };
+
+    let position = CursorPosition { value, id };
+    Ok(Some(if before {
+        EntityCursor::Before(position)
+    } else {
+        EntityCursor::After(position)
+    }))
+}
+
+/// Encode the position of `entity` in a collection ordered by `order_attr`
+/// (or by `id` if `order_attr` is `None`) as an opaque cursor that
+/// `build_cursor` understands. Returns `None` if the entity has no id or
+/// its value for `order_attr` can not be used in a cursor
+pub(crate) fn encode_cursor(
+    order_attr: Option<&str>,
+    entity: &BTreeMap<String, q::Value>,
+) -> Option<String> {
+    let id = match entity.get("id") {
+        Some(q::Value::String(id)) => id,
+        _ => return None,
+    };
+    let value = match order_attr.map(|attr| entity.get(attr).unwrap_or(&q::Value::Null)) {
+        None | Some(q::Value::Null) => serde_json::Value::Null,
+        Some(q::Value::Boolean(b)) => serde_json::Value::from(*b),
+        Some(q::Value::Int(n)) => serde_json::Value::from(n.as_i64()?),
+        Some(q::Value::String(s)) | Some(q::Value::Enum(s)) => serde_json::Value::from(s.as_str()),
+        Some(_) => return None,
+    };
+    let cursor = serde_json::json!({ "attr": order_attr, "value": value, "id": id });
+    Some(hex::encode(cursor.to_string()))
+}
+
 /// Parses the subgraph ID from the ObjectType directives.
 pub fn parse_subgraph_id<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
@@ -406,6 +726,12 @@ mod tests {
         }
     }
 
+    fn empty_schema() -> s::Document {
+        s::Document {
+            definitions: vec![],
+        }
+    }
+
     fn default_arguments<'a>() -> HashMap<&'a String, q::Value> {
         let mut map = HashMap::new();
         let first: &String = Box::leak(Box::new("first".to_owned()));
@@ -423,6 +749,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -436,6 +763,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -453,6 +781,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -473,6 +802,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -489,6 +819,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -509,6 +840,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -525,6 +857,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -547,6 +880,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -564,6 +898,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -584,6 +919,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -604,6 +940,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -626,6 +963,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -643,6 +981,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -660,6 +999,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -680,6 +1020,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX
             )
@@ -712,6 +1053,7 @@ mod tests {
                 BLOCK_NUMBER_MAX,
                 &args,
                 &BTreeMap::new(),
+                &empty_schema(),
                 std::u32::MAX,
                 std::u32::MAX,
             )
@@ -723,4 +1065,191 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_yields_nocase_and_regex_filters() {
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![
+                (
+                    "name_not_starts_with_nocase".to_string(),
+                    q::Value::String("he".to_string()),
+                ),
+                (
+                    "name_matches".to_string(),
+                    q::Value::String("^h.*o$".to_string()),
+                ),
+            ])),
+        );
+        let object = ObjectType {
+            fields: vec![field("name", Type::NamedType("string".to_owned()))],
+            ..default_object()
+        };
+        assert_eq!(
+            build_query(
+                &object,
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                &empty_schema(),
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![
+                EntityFilter::Matches("name".to_string(), Value::String("^h.*o$".to_string())),
+                EntityFilter::NotStartsWithNoCase(
+                    "name".to_string(),
+                    Value::String("he".to_string())
+                ),
+            ]))
+        );
+
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "name_matches".to_string(),
+                q::Value::String("(a+)+$".to_string()),
+            )])),
+        );
+        assert!(build_query(
+            &object,
+            BLOCK_NUMBER_MAX,
+            &args,
+            &BTreeMap::new(),
+            &empty_schema(),
+            std::u32::MAX,
+            std::u32::MAX,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn check_regex_rejects_expensive_patterns() {
+        assert!(check_regex("^[a-z]+(foo|bar)?$").is_ok());
+        assert!(check_regex("(ab)*c+").is_ok());
+        assert!(check_regex("[)+]*").is_ok());
+        assert!(check_regex("(a+)+").is_err());
+        assert!(check_regex("((a*)b)*").is_err());
+        assert!(check_regex("(x{2,5}){3}").is_err());
+        assert!(check_regex("(a)\\1").is_err());
+        assert!(check_regex(&"a".repeat(MAX_REGEX_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn build_query_yields_child_filters() {
+        let pair = ObjectType {
+            fields: vec![
+                field("id", Type::NamedType("ID".to_owned())),
+                field("name", Type::NamedType("String".to_owned())),
+                Field {
+                    directives: vec![Directive {
+                        name: "derivedFrom".to_string(),
+                        position: Pos::default(),
+                        arguments: vec![(
+                            "field".to_string(),
+                            SchemaValue::String("pair".to_string()),
+                        )],
+                    }],
+                    ..field(
+                        "swaps",
+                        Type::ListType(Box::new(Type::NamedType("Swap".to_owned()))),
+                    )
+                },
+            ],
+            ..object("Pair")
+        };
+        let swap = ObjectType {
+            fields: vec![
+                field("id", Type::NamedType("ID".to_owned())),
+                field("amount", Type::NamedType("Int".to_owned())),
+                field("pair", Type::NamedType("Pair".to_owned())),
+            ],
+            ..object("Swap")
+        };
+        let schema = s::Document {
+            definitions: vec![
+                s::Definition::TypeDefinition(s::TypeDefinition::Object(pair.clone())),
+                s::Definition::TypeDefinition(s::TypeDefinition::Object(swap.clone())),
+            ],
+        };
+
+        let nested = |key: &str, filter: BTreeMap<String, q::Value>| {
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                key.to_string(),
+                q::Value::Object(filter),
+            )]))
+        };
+
+        // swaps(where: { pair_: { name: "ETH" } })
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            nested(
+                "pair_",
+                BTreeMap::from_iter(vec![(
+                    "name".to_string(),
+                    q::Value::String("ETH".to_string()),
+                )]),
+            ),
+        );
+        assert_eq!(
+            build_query(
+                &swap,
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                &schema,
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::Child(Child {
+                attr: "pair".to_string(),
+                entity_type: "Pair".to_string(),
+                filter: Box::new(EntityFilter::And(vec![EntityFilter::Equal(
+                    "name".to_string(),
+                    Value::String("ETH".to_string()),
+                )])),
+                derived: false,
+            })]))
+        );
+
+        // pairs(where: { swaps_: { amount_gt: 10 } })
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            nested(
+                "swaps_",
+                BTreeMap::from_iter(vec![("amount_gt".to_string(), q::Value::Int(10.into()))]),
+            ),
+        );
+        assert_eq!(
+            build_query(
+                &pair,
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                &schema,
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::Child(Child {
+                attr: "pair".to_string(),
+                entity_type: "Swap".to_string(),
+                filter: Box::new(EntityFilter::And(vec![EntityFilter::GreaterThan(
+                    "amount".to_string(),
+                    Value::Int(10),
+                )])),
+                derived: true,
+            })]))
+        );
+    }
 }
@@ -93,7 +93,10 @@ fn create_source_event_stream(
         deadline: None,
         max_first: options.max_first,
         max_skip: options.max_skip,
+        max_cost: None,
         cache_status: Default::default(),
+        estimated_cost: Default::default(),
+        trace: None,
         load_manager: options.load_manager.cheap_clone(),
         nested_resolver: false,
     };
@@ -225,7 +228,10 @@ async fn execute_subscription_event(
         deadline: timeout.map(|t| Instant::now() + t),
         max_first,
         max_skip,
+        max_cost: None,
         cache_status: Default::default(),
+        estimated_cost: Default::default(),
+        trace: None,
         load_manager,
         nested_resolver: false,
     });
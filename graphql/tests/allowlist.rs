@@ -0,0 +1,112 @@
+//! Tests for deployments in allow-list mode. They use their own deployment
+//! since removing a deployment does not remove its allow-list
+
+use std::sync::Arc;
+
+use graph::{
+    components::store::EntityType,
+    data::query::QueryTarget,
+    prelude::{
+        q, shape_hash, Entity, EntityKey, EntityOperation, GraphQlRunner as _, Query, QueryError,
+        QueryExecutionError, QueryResult, SubgraphDeploymentId, Value,
+    },
+};
+use graph_graphql::prelude::*;
+use test_store::{
+    create_test_subgraph, remove_subgraphs, run_test_sequentially, transact_entity_operations,
+    GENESIS_PTR, LOAD_MANAGER, LOGGER, STORE, SUBSCRIPTION_MANAGER,
+};
+
+const SCHEMA: &str = "type Musician @entity { id: ID!, name: String! }";
+
+const ALLOWED: &str = "query { musicians(first: 100) { id name } }";
+
+/// Has the same shape as `ALLOWED` since only argument values differ
+const SAME_SHAPE: &str = "query { musicians(first: 1) { id name } }";
+
+const NOT_ALLOWED: &str = "query { musicians(first: 100) { id } }";
+
+fn parse(query: &str) -> q::Document {
+    graphql_parser::parse_query(query).unwrap().into_static()
+}
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("graphqlTestsAllowlist").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SCHEMA);
+
+    // Clear out what an earlier, failed run might have left behind
+    let store = STORE.store();
+    store.set_query_allowlist_enabled(&id, false).unwrap();
+    store
+        .remove_from_query_allowlist(&id, shape_hash(&parse(ALLOWED)))
+        .unwrap();
+
+    let ops = ["john", "lisa"]
+        .iter()
+        .map(|name| EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: id.clone(),
+                entity_type: EntityType::data("Musician".to_owned()),
+                entity_id: name.to_string(),
+            },
+            data: Entity::from(vec![
+                ("id", Value::from(*name)),
+                ("name", Value::from(*name)),
+            ]),
+        })
+        .collect();
+    transact_entity_operations(&STORE, id.clone(), GENESIS_PTR.clone(), ops).unwrap();
+    id
+}
+
+async fn run_query(id: &SubgraphDeploymentId, query: &str) -> QueryResult {
+    let runner = Arc::new(GraphQlRunner::new(
+        &*LOGGER,
+        STORE.clone(),
+        SUBSCRIPTION_MANAGER.clone(),
+        LOAD_MANAGER.clone(),
+    ));
+    let query = Query::new(parse(query), None);
+    let target = QueryTarget::Deployment(id.clone());
+
+    runner
+        .run_query_with_complexity(query, target, None, None, None, None, false)
+        .await
+        .unwrap_first()
+}
+
+fn assert_not_allowed(result: QueryResult) {
+    match result.to_result().unwrap_err().as_slice() {
+        [QueryError::ExecutionError(QueryExecutionError::QueryNotAllowed(shape))] => {
+            assert_eq!(shape_hash(&parse(NOT_ALLOWED)), *shape)
+        }
+        errors => panic!("expected QueryNotAllowed but got {:?}", errors),
+    }
+}
+
+#[test]
+fn allowlist_only_runs_allowed_shapes() {
+    run_test_sequentially(setup, |_, id| async move {
+        let store = STORE.store();
+        let added = store.add_to_query_allowlist(&id, &parse(ALLOWED)).unwrap();
+        assert_eq!(Some(shape_hash(&parse(ALLOWED))), added);
+
+        // Without allow-list mode, any query runs
+        assert!(!run_query(&id, NOT_ALLOWED).await.has_errors());
+
+        store.set_query_allowlist_enabled(&id, true).unwrap();
+        assert!(!run_query(&id, ALLOWED).await.has_errors());
+        assert!(!run_query(&id, SAME_SHAPE).await.has_errors());
+        assert_not_allowed(run_query(&id, NOT_ALLOWED).await);
+
+        // Removing the query from the allow-list rejects it, too
+        assert!(store
+            .remove_from_query_allowlist(&id, shape_hash(&parse(ALLOWED)))
+            .unwrap());
+        assert!(run_query(&id, ALLOWED).await.has_errors());
+
+        store.set_query_allowlist_enabled(&id, false).unwrap();
+        assert!(!run_query(&id, NOT_ALLOWED).await.has_errors());
+    })
+}
@@ -562,6 +562,7 @@ async fn introspection_query(schema: Schema, query: &str) -> QueryResult {
         deadline: None,
         max_first: std::u32::MAX,
         max_skip: std::u32::MAX,
+        max_cost: None,
         load_manager: LOAD_MANAGER.clone(),
     };
 
@@ -0,0 +1,89 @@
+//! Tests for rejecting queries whose estimated cost is too high. They live
+//! in their own file since `GRAPH_GRAPHQL_MAX_COST` is only read once per
+//! process, and would otherwise affect other tests
+
+use std::sync::Arc;
+
+use graph::{
+    components::store::EntityType,
+    data::query::QueryTarget,
+    prelude::{
+        Entity, EntityKey, EntityOperation, GraphQlRunner as _, Query, QueryError,
+        QueryExecutionError, QueryResult, SubgraphDeploymentId, Value,
+    },
+};
+use graph_graphql::prelude::*;
+use test_store::{
+    create_test_subgraph, remove_subgraphs, run_test_sequentially, transact_entity_operations,
+    GENESIS_PTR, LOAD_MANAGER, LOGGER, STORE, SUBSCRIPTION_MANAGER,
+};
+
+const SCHEMA: &str = "type Musician @entity { id: ID!, name: String! }";
+
+/// Any query that reads from a table costs more than this
+const MAX_COST: f64 = 0.001;
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("graphqlTestsMaxCost").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SCHEMA);
+
+    let ops = ["john", "lisa"]
+        .iter()
+        .map(|name| EntityOperation::Set {
+            key: EntityKey {
+                subgraph_id: id.clone(),
+                entity_type: EntityType::data("Musician".to_owned()),
+                entity_id: name.to_string(),
+            },
+            data: Entity::from(vec![
+                ("id", Value::from(*name)),
+                ("name", Value::from(*name)),
+            ]),
+        })
+        .collect();
+    transact_entity_operations(&STORE, id.clone(), GENESIS_PTR.clone(), ops).unwrap();
+    id
+}
+
+async fn run_query(id: &SubgraphDeploymentId, query: &str) -> QueryResult {
+    let runner = Arc::new(GraphQlRunner::new(
+        &*LOGGER,
+        STORE.clone(),
+        SUBSCRIPTION_MANAGER.clone(),
+        LOAD_MANAGER.clone(),
+    ));
+    let query = Query::new(
+        graphql_parser::parse_query(query).unwrap().into_static(),
+        None,
+    );
+    let target = QueryTarget::Deployment(id.clone());
+
+    runner
+        .run_query_with_complexity(query, target, None, None, None, None, false)
+        .await
+        .unwrap_first()
+}
+
+#[test]
+fn max_cost_rejects_expensive_queries() {
+    std::env::set_var("GRAPH_GRAPHQL_MAX_COST", MAX_COST.to_string());
+
+    run_test_sequentially(setup, |_, id| async move {
+        let result = run_query(&id, "query { musicians(first: 100) { id name } }").await;
+        match result.to_result().unwrap_err().as_slice() {
+            [QueryError::ExecutionError(QueryExecutionError::EstimatedCostTooHigh(
+                cost,
+                max_cost,
+            ))] => {
+                assert!(*cost > MAX_COST);
+                assert_eq!(MAX_COST, *max_cost);
+            }
+            errors => panic!("expected EstimatedCostTooHigh but got {:?}", errors),
+        }
+
+        // Queries that do not read entities are not estimated
+        let result = run_query(&id, "query { __typename }").await;
+        assert!(!result.has_errors());
+    })
+}
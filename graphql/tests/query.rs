@@ -264,7 +264,14 @@ impl QueryLoadManager for MockQueryLoadManager {
         self.0.clone().acquire_owned().await
     }
 
-    fn record_work(&self, _shape_hash: u64, _duration: Duration, _cache_status: CacheStatus) {}
+    fn record_work(
+        &self,
+        _shape_hash: u64,
+        _api_key: Option<&str>,
+        _duration: Duration,
+        _cache_status: CacheStatus,
+    ) {
+    }
 }
 
 fn mock_query_load_manager() -> Arc<MockQueryLoadManager> {
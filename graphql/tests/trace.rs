@@ -0,0 +1,176 @@
+//! Tests for tracing the execution of queries. They live in their own file
+//! since `GRAPH_GRAPHQL_TRACE_TOKEN` and `GRAPH_CACHED_SUBGRAPH_IDS` are only
+//! read once per process, and would otherwise affect other tests
+
+use std::sync::Arc;
+
+use graph::{
+    components::store::EntityType,
+    data::query::{trace_requested, QueryResults, QueryTarget},
+    prelude::{
+        serde_json, Entity, EntityKey, EntityOperation, GraphQlRunner as _, Query,
+        SubgraphDeploymentId, Value,
+    },
+};
+use graph_graphql::prelude::*;
+use test_store::{
+    create_test_subgraph, remove_subgraphs, run_test_sequentially, transact_entity_operations,
+    GENESIS_PTR, LOAD_MANAGER, LOGGER, STORE, SUBSCRIPTION_MANAGER,
+};
+
+const SCHEMA: &str = "
+    type Musician @entity { id: ID!, name: String!, bands: [Band!]! }
+    type Band @entity { id: ID!, name: String! }
+";
+
+const TOKEN: &str = "trace-token";
+
+const QUERY: &str = "query { musicians(orderBy: id) { id name bands(orderBy: id) { id } } }";
+
+fn set_env() {
+    std::env::set_var("GRAPH_GRAPHQL_TRACE_TOKEN", TOKEN);
+    std::env::set_var("GRAPH_CACHED_SUBGRAPH_IDS", "*");
+}
+
+fn entity(entity_type: &str, data: Vec<(&str, Value)>) -> EntityOperation {
+    let data = Entity::from(data);
+    EntityOperation::Set {
+        key: EntityKey {
+            subgraph_id: SubgraphDeploymentId::new("graphqlTestsTrace").unwrap(),
+            entity_type: EntityType::data(entity_type.to_owned()),
+            entity_id: data.id().unwrap(),
+        },
+        data,
+    }
+}
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("graphqlTestsTrace").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SCHEMA);
+
+    let bands = |ids: &[&str]| Value::List(ids.iter().map(|id| Value::from(*id)).collect());
+    let ops = vec![
+        entity(
+            "Musician",
+            vec![
+                ("id", Value::from("m1")),
+                ("name", Value::from("John")),
+                ("bands", bands(&["b1"])),
+            ],
+        ),
+        entity(
+            "Musician",
+            vec![
+                ("id", Value::from("m2")),
+                ("name", Value::from("Lisa")),
+                ("bands", bands(&["b1", "b2"])),
+            ],
+        ),
+        entity(
+            "Band",
+            vec![
+                ("id", Value::from("b1")),
+                ("name", Value::from("The Musicians")),
+            ],
+        ),
+        entity(
+            "Band",
+            vec![
+                ("id", Value::from("b2")),
+                ("name", Value::from("The Amateurs")),
+            ],
+        ),
+    ];
+    transact_entity_operations(&STORE, id.clone(), GENESIS_PTR.clone(), ops).unwrap();
+    id
+}
+
+/// Run `QUERY` like the HTTP server does for a request whose trace header
+/// is `header`, and return the serialized response
+async fn run_query(id: &SubgraphDeploymentId, header: Option<&str>) -> serde_json::Value {
+    let runner = Arc::new(GraphQlRunner::new(
+        &*LOGGER,
+        STORE.clone(),
+        SUBSCRIPTION_MANAGER.clone(),
+        LOAD_MANAGER.clone(),
+    ));
+    let query = Query::new(
+        graphql_parser::parse_query(QUERY).unwrap().into_static(),
+        None,
+    )
+    .with_trace(trace_requested(header));
+    let target = QueryTarget::Deployment(id.clone());
+
+    let results: QueryResults = runner
+        .run_query_with_complexity(query, target, None, None, None, None, false)
+        .await;
+    serde_json::to_value(&results).unwrap()
+}
+
+/// The trace of the field `field_name`, which must be in `trace` exactly once
+fn resolver<'a>(trace: &'a serde_json::Value, field_name: &str) -> &'a serde_json::Value {
+    let resolvers: Vec<_> = trace["execution"]["resolvers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|resolver| resolver["fieldName"] == field_name)
+        .collect();
+    assert_eq!(1, resolvers.len(), "one trace for {}", field_name);
+    resolvers[0]
+}
+
+#[test]
+fn trace_requires_token() {
+    set_env();
+
+    assert!(trace_requested(Some(TOKEN)));
+    assert!(!trace_requested(Some("trace-tokeN")));
+    assert!(!trace_requested(Some("trace")));
+    assert!(!trace_requested(Some("")));
+    assert!(!trace_requested(None));
+}
+
+#[test]
+fn traces_are_returned_and_not_cached() {
+    set_env();
+
+    run_test_sequentially(setup, |_, id| async move {
+        // The first query runs against the store and puts its result into
+        // the query cache
+        let response = run_query(&id, Some(TOKEN)).await;
+        assert!(response["errors"].is_null());
+        let traces = response["extensions"]["tracing"].as_array().unwrap();
+        assert_eq!(1, traces.len());
+        let trace = &traces[0];
+        assert_eq!(1, trace["version"]);
+        assert_eq!(0, trace["block"]);
+        assert_eq!("insert", trace["cacheStatus"]);
+
+        let musicians = resolver(trace, "musicians");
+        assert_eq!(serde_json::json!(["musicians"]), musicians["path"]);
+        assert_eq!("Query", musicians["parentType"]);
+        assert!(musicians["sql"].as_str().unwrap().contains("select"));
+        assert_eq!(2, musicians["rows"]);
+
+        let bands = resolver(trace, "bands");
+        assert_eq!(serde_json::json!(["musicians", "bands"]), bands["path"]);
+        assert_eq!("Musician", bands["parentType"]);
+        assert!(bands["sql"].as_str().unwrap().contains("select"));
+        assert_eq!(3, bands["rows"]);
+
+        // The cached result does not contain the trace
+        let response = run_query(&id, Some("wrong")).await;
+        assert!(response["errors"].is_null());
+        assert!(response["extensions"]["tracing"].is_null());
+        assert_eq!("John", response["data"]["musicians"][0]["name"]);
+        assert_eq!("Lisa", response["data"]["musicians"][1]["name"]);
+
+        // A traced query that is answered from the cache gets a trace of its
+        // own that shows that no fields were resolved
+        let response = run_query(&id, Some(TOKEN)).await;
+        let trace = &response["extensions"]["tracing"][0];
+        assert_eq!("hit", trace["cacheStatus"]);
+        assert_eq!(serde_json::json!([]), trace["execution"]["resolvers"]);
+    })
+}
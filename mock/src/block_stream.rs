@@ -1,64 +1,46 @@
 use futures::sync::mpsc::{channel, Receiver, Sender};
+use std::marker::PhantomData;
 
+use graph::blockchain::{BlockStream, BlockStreamEvent, Blockchain};
 use graph::prelude::*;
 
-pub struct MockBlockStream {
+pub struct MockBlockStream<C> {
     chain_head_update_sink: Sender<ChainHeadUpdate>,
     _chain_head_update_stream: Receiver<ChainHeadUpdate>,
+    _chain: PhantomData<C>,
 }
 
-impl MockBlockStream {
-    fn new() -> Self {
+impl<C: Blockchain> MockBlockStream<C> {
+    pub fn new() -> Self {
         let (chain_head_update_sink, chain_head_update_stream) = channel(100);
 
         Self {
             chain_head_update_sink,
             _chain_head_update_stream: chain_head_update_stream,
+            _chain: PhantomData,
         }
     }
 }
 
-impl Stream for MockBlockStream {
-    type Item = BlockStreamEvent;
+impl<C: Blockchain> Default for MockBlockStream<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Blockchain> Stream for MockBlockStream<C> {
+    type Item = BlockStreamEvent<C>;
     type Error = Error;
 
-    fn poll(&mut self) -> Result<Async<Option<BlockStreamEvent>>, Error> {
+    fn poll(&mut self) -> Result<Async<Option<BlockStreamEvent<C>>>, Error> {
         Ok(Async::Ready(None))
     }
 }
 
-impl EventConsumer<ChainHeadUpdate> for MockBlockStream {
+impl<C: Blockchain> EventConsumer<ChainHeadUpdate> for MockBlockStream<C> {
     fn event_sink(&self) -> Box<dyn Sink<SinkItem = ChainHeadUpdate, SinkError = ()> + Send> {
         Box::new(self.chain_head_update_sink.clone().sink_map_err(|_| ()))
     }
 }
 
-impl BlockStream for MockBlockStream {}
-
-#[derive(Clone)]
-pub struct MockBlockStreamBuilder;
-
-impl MockBlockStreamBuilder {
-    pub fn new() -> Self {
-        Self {}
-    }
-}
-
-impl BlockStreamBuilder for MockBlockStreamBuilder {
-    type Stream = MockBlockStream;
-
-    fn build(
-        &self,
-        _logger: Logger,
-        _deployment_id: SubgraphDeploymentId,
-        _network_name: String,
-        _start_blocks: Vec<u64>,
-        _: EthereumLogFilter,
-        _: EthereumCallFilter,
-        _: EthereumBlockFilter,
-        _: bool,
-        _: Arc<BlockStreamMetrics>,
-    ) -> Self::Stream {
-        MockBlockStream::new()
-    }
-}
+impl<C: Blockchain> BlockStream<C> for MockBlockStream<C> {}
@@ -9,6 +9,6 @@ mod block_stream;
 mod metrics_registry;
 mod store;
 
-pub use self::block_stream::{MockBlockStream, MockBlockStreamBuilder};
+pub use self::block_stream::MockBlockStream;
 pub use self::metrics_registry::MockMetricsRegistry;
 pub use self::store::MockStore;
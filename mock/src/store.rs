@@ -6,7 +6,7 @@ use graph::components::{server::index_node::VersionInfo, store::StoredDynamicDat
 use graph::data::subgraph::schema::SubgraphError;
 use graph::prelude::*;
 use graph::{components::store::EntityType, data::subgraph::status};
-use web3::types::{Address, H256};
+use web3::types::{Address, Trace, TransactionReceipt, H256};
 
 mock! {
     pub Store {
@@ -30,8 +30,25 @@ mock! {
 
         fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), Error>;
 
+        fn upsert_block(&self, block: EthereumBlock) -> Result<(), Error>;
+
+        fn transaction_receipts(&self, block_hash: &H256) -> Result<Option<Vec<TransactionReceipt>>, Error>;
+
+        fn block_traces(&self, block_hash: &H256) -> Result<Option<Vec<Trace>>, Error>;
+
+        fn set_block_traces(&self, block_ptr: EthereumBlockPointer, traces: &[Trace]) -> Result<(), Error>;
+
+        fn block_traces_in_range(&self, from: u64, to: u64) -> Result<Option<Vec<Trace>>, Error>;
+
         fn attempt_chain_head_update(&self, ancestor_count: u64) -> Result<Vec<H256>, Error>;
 
+        fn record_reorg(
+            &self,
+            old_head: EthereumBlockPointer,
+            new_head: EthereumBlockPointer,
+            max_depth: u64,
+        ) -> Result<Option<status::ChainReorg>, Error>;
+
         fn chain_head_updates(&self) -> ChainHeadUpdateStream;
 
         fn chain_head_ptr(&self) -> Result<Option<EthereumBlockPointer>, Error>;
@@ -122,6 +139,15 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn entity_change_log(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _after: i64,
+        _limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        unimplemented!()
+    }
+
     fn deployment_state_from_name(&self, _: SubgraphName) -> Result<DeploymentState, StoreError> {
         unimplemented!()
     }
@@ -190,6 +216,14 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn chain_reorgs(
+        &self,
+        _: Option<String>,
+        _: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError> {
+        unimplemented!()
+    }
+
     async fn load_dynamic_data_sources(
         &self,
         _: SubgraphDeploymentId,
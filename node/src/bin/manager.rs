@@ -1,4 +1,4 @@
-use std::{env, sync::Arc};
+use std::{env, path::PathBuf, sync::Arc};
 
 use git_testament::{git_testament, render_testament};
 use graph_core::MetricsRegistry;
@@ -12,7 +12,9 @@ use graph::{
 };
 use graph_node::config;
 use graph_node::store_builder::StoreBuilder;
-use graph_store_postgres::{connection_pool::ConnectionPool, ShardedStore, PRIMARY_SHARD};
+use graph_store_postgres::{
+    command_support::DumpFormat, connection_pool::ConnectionPool, ShardedStore, PRIMARY_SHARD,
+};
 
 use crate::config::Config;
 use graph_node::manager::commands;
@@ -72,11 +74,125 @@ pub enum Command {
     },
     /// Print how a specific subgraph would be placed
     Place { name: String, network: String },
+    /// Move a deployment to a different shard
+    ///
+    /// The deployment is copied while it keeps indexing. Once the copy has
+    /// caught up, indexing is paused briefly to copy the remaining changes
+    /// and to switch to the new copy. The old copy is left in place
+    Move {
+        /// The deployment id
+        id: String,
+        /// The shard to which to move the deployment
+        shard: String,
+    },
+    /// Prune the entity history of a deployment
+    ///
+    /// Remove all entity versions that are not needed to answer queries
+    /// for the most recent blocks. Queries for blocks before that are
+    /// rejected once the deployment has been pruned
+    Prune {
+        /// The deployment id
+        id: String,
+        /// How many blocks of history to retain. The value is stored as the
+        /// deployment's setting; if it is not given, the stored setting is used
+        #[structopt(long, short)]
+        history: Option<i32>,
+    },
+    /// Export the entities of a deployment
+    ///
+    /// Write the entities of a deployment as they were at a given block
+    /// into a directory, one file per entity type, together with a
+    /// `manifest.json` that describes the block and the schema of the dump
+    Dump {
+        /// The deployment id
+        id: String,
+        /// The block at which to dump the entities (default: the current
+        /// head of the deployment). The block cache must have the hash of
+        /// an earlier block
+        #[structopt(long, short)]
+        block: Option<i32>,
+        /// The format of the files: `parquet`, `csv`, or `jsonl`
+        #[structopt(long, short, default_value = "parquet")]
+        format: DumpFormat,
+        /// The directory into which to write the dump
+        #[structopt(long, short)]
+        out: PathBuf,
+    },
+    /// Create a deployment from a dump made with `dump`
+    ///
+    /// The deployment is created in the given shard, becomes the current
+    /// version of the subgraph `name`, and resumes indexing from the block
+    /// at which the dump was taken
+    Restore {
+        /// The directory that contains the dump
+        dir: PathBuf,
+        /// The shard in which to create the deployment
+        #[structopt(long, short, default_value = "primary")]
+        shard: String,
+        /// The name of the subgraph for the deployment
+        #[structopt(long)]
+        name: String,
+        /// The node to which the deployment should be assigned
+        #[structopt(long)]
+        node: String,
+    },
+    /// Run a handler again from a recording
+    ///
+    /// Recordings are made by graph-node when `GRAPH_MAPPING_RECORD_DIR`
+    /// is set. The handler runs against the trigger and the results of
+    /// host calls from the recording and does not need an Ethereum node.
+    /// Messages that the handler logs and the error it fails with are
+    /// printed. The deployment of the recording must exist since its
+    /// schema is needed
+    Replay {
+        /// The recording file
+        recording: PathBuf,
+    },
     /// Manage unused deployments
     ///
     /// Record which deployments are unused with `record`, then remove them
     /// with `remove`
     Unused(UnusedCommand),
+    /// Manage the query allow-lists of deployments
+    ///
+    /// A deployment in allow-list mode only runs queries whose shape is in
+    /// its allow-list; queries have the same shape if they only differ in
+    /// the values of their arguments. Changes take effect on query nodes
+    /// within 30 seconds
+    Allowlist(AllowlistCommand),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum AllowlistCommand {
+    /// List the queries in the allow-list of a deployment
+    List {
+        /// The deployment id
+        id: String,
+    },
+    /// Add the query in a file to the allow-list of a deployment
+    Add {
+        /// The deployment id
+        id: String,
+        /// A file with the query
+        file: PathBuf,
+    },
+    /// Remove a query from the allow-list of a deployment
+    Remove {
+        /// The deployment id
+        id: String,
+        /// The shape hash of the query as shown by `list`
+        shape_hash: u64,
+    },
+    /// Only run queries from its allow-list for a deployment
+    Enable {
+        /// The deployment id
+        id: String,
+    },
+    /// Run all queries for a deployment again
+    Disable {
+        /// The deployment id
+        id: String,
+    },
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -175,6 +291,38 @@ async fn main() {
             commands::info::run(pool, name, current, pending, used)
         }
         Place { name, network } => commands::place::run(&config.deployment, &name, &network),
+        Move { id, shard } => {
+            let store = make_store(&logger, &config);
+            commands::move_deployment::run(&logger, store, id, shard)
+        }
+        Prune { id, history } => {
+            let store = make_store(&logger, &config);
+            commands::prune::run(&logger, store, id, history)
+        }
+        Dump {
+            id,
+            block,
+            format,
+            out,
+        } => {
+            let store = make_store(&logger, &config);
+            commands::dump::run(store, id, block, format, out)
+        }
+        Restore {
+            dir,
+            shard,
+            name,
+            node,
+        } => {
+            let store = make_store(&logger, &config);
+            commands::restore::run(store, dir, shard, name, node)
+        }
+        Replay { recording } => {
+            // Always show what the handler logs
+            let logger = graph::log::logger(true);
+            let store = make_store(&logger, &config);
+            commands::replay::run(&logger, store, make_registry(&logger), recording)
+        }
         Unused(cmd) => {
             let store = make_store(&logger, &config);
             use UnusedCommand::*;
@@ -188,6 +336,18 @@ async fn main() {
                 }
             }
         }
+        Allowlist(cmd) => {
+            let store = make_store(&logger, &config);
+            use AllowlistCommand::*;
+
+            match cmd {
+                List { id } => commands::allowlist::list(store, id),
+                Add { id, file } => commands::allowlist::add(store, id, file),
+                Remove { id, shape_hash } => commands::allowlist::remove(store, id, shape_hash),
+                Enable { id } => commands::allowlist::enable(store, id, true),
+                Disable { id } => commands::allowlist::enable(store, id, false),
+            }
+        }
     };
     if let Err(e) = result {
         die!("error: {}", e)
@@ -1,13 +1,15 @@
+use graph::components::ethereum::{NodeCapabilities, ProviderLimits};
+use graph::data::graphql::quota::{self, ApiKeyQuota};
 use graph::prelude::{
     anyhow::{anyhow, Result},
-    info, serde_json, Logger, NodeId,
+    info, serde_json, Logger, NodeId, SubgraphDeploymentId, SubgraphName,
 };
 use graph_chain_ethereum::CLEANUP_BLOCKS;
 use graph_store_postgres::{DeploymentPlacer, Shard as ShardName, PRIMARY_SHARD};
 
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::read_to_string;
 use url::Url;
 
@@ -43,6 +45,10 @@ pub struct Config {
     pub stores: BTreeMap<String, Shard>,
     pub deployment: Deployment,
     ingestor: Ingestor,
+    #[serde(default)]
+    pub chains: BTreeMap<String, Chain>,
+    #[serde(default)]
+    pub api_keys: ApiKeys,
 }
 
 fn validate_replica_name(s: &str) -> Result<()> {
@@ -87,6 +93,10 @@ impl Config {
             shard.validate(opt)?;
         }
         self.deployment.validate()?;
+        for (name, chain) in self.chains.iter_mut() {
+            chain.validate(name)?;
+        }
+        self.api_keys.validate()?;
 
         // Check that deployment rules only reference existing stores
         for (i, rule) in self.deployment.rules.iter().enumerate() {
@@ -128,6 +138,8 @@ impl Config {
             stores,
             deployment,
             ingestor,
+            chains: BTreeMap::new(),
+            api_keys: ApiKeys::default(),
         })
     }
 
@@ -146,6 +158,26 @@ impl Config {
             .get(PRIMARY_SHARD.as_str())
             .expect("a validated config has a primary store")
     }
+
+    /// The API keys and quotas that the query servers enforce
+    pub fn api_keys(&self) -> quota::ApiKeys {
+        let keys = self
+            .api_keys
+            .keys
+            .iter()
+            .map(|(label, key)| ApiKeyQuota {
+                label: label.clone(),
+                key: key.key.clone(),
+                queries_per_second: key.queries_per_second,
+                effort_seconds_per_minute: key.effort_seconds_per_minute,
+                subgraphs: key.subgraphs.iter().cloned().collect(),
+            })
+            .collect();
+        quota::ApiKeys {
+            required: self.api_keys.required,
+            keys,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -231,6 +263,188 @@ impl Replica {
     }
 }
 
+/// The Ethereum providers for one network. Providers from the config file
+/// are used in addition to the ones passed on the command line
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Chain {
+    #[serde(rename = "provider")]
+    pub providers: Vec<Provider>,
+}
+
+impl Chain {
+    fn validate(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow!("chain names must not be empty"));
+        }
+        let mut labels = BTreeSet::new();
+        for provider in self.providers.iter_mut() {
+            provider.validate()?;
+            if !labels.insert(provider.label.clone()) {
+                return Err(anyhow!(
+                    "duplicate provider label `{}` for chain {}",
+                    provider.label,
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Rpc,
+    Ws,
+    Ipc,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::Rpc
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Provider {
+    /// A name for the provider that is used in logs
+    pub label: String,
+    pub url: String,
+    #[serde(default)]
+    pub transport: Transport,
+    /// Any of `archive` and `traces`
+    #[serde(default)]
+    pub features: BTreeSet<String>,
+    /// Providers with a higher weight get a larger share of requests
+    #[serde(default = "one")]
+    pub weight: usize,
+    /// Eject the provider after this many failed requests in a row
+    #[serde(default = "max_errors")]
+    pub max_errors: usize,
+    /// Eject the provider when its chain head is more than this many
+    /// blocks behind the chain head of the best provider
+    #[serde(default = "max_blocks_behind")]
+    pub max_blocks_behind: u64,
+}
+
+impl Provider {
+    fn validate(&mut self) -> Result<()> {
+        validate_replica_name(&self.label)?;
+        self.url = shellexpand::env(&self.url)?.into_owned();
+        if self.url.is_empty() {
+            return Err(anyhow!("provider {} has an empty url", self.label));
+        }
+        for feature in &self.features {
+            if feature != "archive" && feature != "traces" {
+                return Err(anyhow!(
+                    "invalid feature `{}` for provider {}; the supported features are `archive` and `traces`",
+                    feature,
+                    self.label
+                ));
+            }
+        }
+        if self.weight == 0 {
+            return Err(anyhow!(
+                "provider {} must have a positive weight",
+                self.label
+            ));
+        }
+        if self.max_errors == 0 {
+            return Err(anyhow!(
+                "provider {} must allow at least one error",
+                self.label
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn capabilities(&self) -> NodeCapabilities {
+        NodeCapabilities {
+            archive: self.features.contains("archive"),
+            traces: self.features.contains("traces"),
+        }
+    }
+
+    pub fn limits(&self) -> ProviderLimits {
+        ProviderLimits {
+            max_errors: self.max_errors,
+            max_blocks_behind: self.max_blocks_behind,
+        }
+    }
+}
+
+/// The API keys that clients can send with their queries. Queries with a key
+/// are subject to the quota for the key
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ApiKeys {
+    /// Reject queries that do not have an API key
+    #[serde(default)]
+    pub required: bool,
+    /// The API keys, indexed by a label that is used in logs and metrics
+    #[serde(rename = "key", default)]
+    pub keys: BTreeMap<String, ApiKey>,
+}
+
+impl ApiKeys {
+    fn validate(&mut self) -> Result<()> {
+        if self.required && self.keys.is_empty() {
+            return Err(anyhow!(
+                "api_keys.required is set, but there are no API keys"
+            ));
+        }
+        let mut keys = BTreeSet::new();
+        for (label, key) in self.keys.iter_mut() {
+            validate_replica_name(label)?;
+            key.validate(label)?;
+            if !keys.insert(key.key.clone()) {
+                return Err(anyhow!("API key {} is the same as another key", label));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApiKey {
+    /// The key itself; it is never included when the config is printed
+    #[serde(skip_serializing)]
+    pub key: String,
+    pub queries_per_second: Option<u32>,
+    /// How many seconds queries sent with this key may take per minute
+    pub effort_seconds_per_minute: Option<u64>,
+    /// The subgraph names and deployment ids that may be queried with this
+    /// key. All subgraphs may be queried if this is empty
+    #[serde(default)]
+    pub subgraphs: BTreeSet<String>,
+}
+
+impl ApiKey {
+    fn validate(&mut self, label: &str) -> Result<()> {
+        self.key = shellexpand::env(&self.key)?.into_owned();
+        if self.key.is_empty() {
+            return Err(anyhow!("API key {} is empty", label));
+        }
+        if self.queries_per_second == Some(0) {
+            return Err(anyhow!(
+                "API key {} must allow at least one query per second",
+                label
+            ));
+        }
+        for subgraph in &self.subgraphs {
+            if SubgraphName::new(subgraph.as_str()).is_err()
+                && SubgraphDeploymentId::new(subgraph.as_str()).is_err()
+            {
+                return Err(anyhow!(
+                    "API key {} lists `{}`, which is neither a subgraph name nor a deployment id",
+                    label,
+                    subgraph
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Deployment {
     #[serde(rename = "rule")]
@@ -410,3 +624,11 @@ fn one() -> usize {
 fn zero() -> u32 {
     0
 }
+
+fn max_errors() -> usize {
+    ProviderLimits::default().max_errors
+}
+
+fn max_blocks_behind() -> u64 {
+    ProviderLimits::default().max_blocks_behind
+}
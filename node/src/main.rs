@@ -11,21 +11,26 @@ use std::time::Duration;
 use structopt::StructOpt;
 use tokio::sync::mpsc;
 
-use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+use graph::components::ethereum::{
+    EthereumNetworks, NodeCapabilities, ProviderHealth, ProviderLimits,
+};
 use graph::components::forward;
 use graph::data::graphql::effort::LoadManager;
 use graph::log::logger;
-use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
+use graph::prelude::{EntityChangesServer as _, IndexNodeServer as _, JsonRpcServer as _, *};
 use graph::util::security::SafeDisplay;
 use graph_chain_arweave::adapter::ArweaveAdapter;
-use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
+use graph_chain_ethereum::{
+    self as ethereum, network_indexer, BlockIngestor, FlatFileBlockSource, ReorgMetrics, Transport,
+};
 use graph_core::{
-    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
+    three_box::ThreeBoxAdapter, ChainRunner, LinkResolver, MetricsRegistry,
     SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
-    SubgraphRegistrar as IpfsSubgraphRegistrar,
+    SubgraphRegistrar as IpfsSubgraphRegistrar, SubgraphRunner,
 };
 use graph_graphql::prelude::GraphQlRunner;
 use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
+use graph_server_changes::EntityChangesServer;
 use graph_server_http::GraphQLServer as GraphQLQueryServer;
 use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
@@ -152,6 +157,9 @@ async fn main() {
     // Obtain metrics server port
     let metrics_port = opt.metrics_port;
 
+    // Obtain entity changes server port
+    let changes_port = opt.changes_port;
+
     // Obtain STORE_CONNECTION_POOL_SIZE setting
     let store_conn_pool_size: u32 = opt.store_connection_pool_size;
 
@@ -193,6 +201,7 @@ async fn main() {
     let eth_networks = create_ethereum_networks(
         &logger,
         metrics_registry.clone(),
+        &config,
         &opt.ethereum_rpc,
         &opt.ethereum_ipc,
         &opt.ethereum_ws,
@@ -207,6 +216,7 @@ async fn main() {
     let contention_logger = logger.clone();
 
     let expensive_queries = read_expensive_queries().unwrap();
+    let api_keys = config.api_keys();
 
     let store_builder = Arc::new(StoreBuilder::new(
         &logger,
@@ -252,6 +262,7 @@ async fn main() {
             let load_manager = Arc::new(LoadManager::new(
                 &logger,
                 expensive_queries,
+                api_keys,
                 metrics_registry.clone(),
                 store_conn_pool_size as usize,
             ));
@@ -280,6 +291,12 @@ async fn main() {
                 store_builder.store(),
             );
 
+            let mut changes_server = EntityChangesServer::new(
+                &logger_factory,
+                store_builder.store(),
+                store_builder.subscription_manager(),
+            );
+
             // Spawn Ethereum network indexers for all networks that are to be indexed
             opt.network_subgraphs
                 .into_iter()
@@ -296,8 +313,7 @@ async fn main() {
                                     traces: false,
                                 },
                             )
-                            .expect(&*format!("adapter for network, {}", network_name))
-                            .clone(),
+                            .expect(&*format!("adapter for network, {}", network_name)),
                         network_stores
                             .get(&network_name)
                             .expect("store for network")
@@ -329,17 +345,10 @@ async fn main() {
                     &eth_networks,
                     &network_stores,
                     &logger_factory,
+                    metrics_registry.clone(),
                 );
             }
 
-            let block_stream_builder = BlockStreamBuilder::new(
-                store_builder.store(),
-                network_stores.clone(),
-                eth_networks.clone(),
-                node_id.clone(),
-                *REORG_THRESHOLD,
-                metrics_registry.clone(),
-            );
             let runtime_host_builder = WASMRuntimeHostBuilder::new(
                 eth_networks.clone(),
                 link_resolver.clone(),
@@ -348,14 +357,43 @@ async fn main() {
                 three_box_adapter,
             );
 
-            let subgraph_instance_manager = SubgraphInstanceManager::new(
-                &logger_factory,
-                network_stores.clone(),
-                eth_networks.clone(),
-                runtime_host_builder,
-                block_stream_builder,
-                metrics_registry.clone(),
-            );
+            let chains: HashMap<_, _> = network_stores
+                .iter()
+                .map(|(name, chain_store)| {
+                    let flat_files = opt.ethereum_flat_files.as_ref().and_then(|dir| {
+                        let dir = Path::new(dir).join(name);
+                        if !dir.is_dir() {
+                            return None;
+                        }
+                        let source = FlatFileBlockSource::new(
+                            &logger.new(o!("network_name" => name.clone())),
+                            &dir,
+                        )
+                        .expect("failed to load flat files");
+                        Some(Arc::new(source))
+                    });
+                    let chain = ethereum::Chain::new(
+                        name.clone(),
+                        node_id.clone(),
+                        store_builder.store(),
+                        chain_store.cheap_clone(),
+                        eth_networks.clone(),
+                        flat_files,
+                        *REORG_THRESHOLD,
+                        metrics_registry.clone(),
+                    );
+                    let runner: Arc<dyn SubgraphRunner> = Arc::new(ChainRunner::new(
+                        Arc::new(chain),
+                        chain_store.cheap_clone(),
+                        runtime_host_builder.clone(),
+                        metrics_registry.clone(),
+                    ));
+                    (name.clone(), runner)
+                })
+                .collect();
+
+            let subgraph_instance_manager =
+                SubgraphInstanceManager::new(&logger_factory, chains, metrics_registry.clone());
 
             // Create IPFS-based subgraph provider
             let mut subgraph_provider = IpfsSubgraphAssignmentProvider::new(
@@ -456,6 +494,23 @@ async fn main() {
                     .compat(),
             );
 
+            // Run the entity changes server if it was asked for
+            if let Some(changes_port) = changes_port {
+                if std::env::var("GRAPH_ENTITY_CHANGE_LOG").is_err() {
+                    warn!(
+                        logger,
+                        "The entity changes server streams the entity change log, \
+                         but GRAPH_ENTITY_CHANGE_LOG is not set and the log is not written"
+                    );
+                }
+                graph::spawn(
+                    changes_server
+                        .serve(changes_port)
+                        .expect("Failed to start entity changes server")
+                        .compat(),
+                );
+            }
+
             graph::spawn(
                 metrics_server
                     .serve(metrics_port)
@@ -507,9 +562,8 @@ async fn parse_ethereum_networks(
     logger: Logger,
     networks: Vec<String>,
     connection_type: ConnectionType,
-    registry: Arc<MetricsRegistry>,
+    eth_rpc_metrics: Arc<ProviderEthRpcMetrics>,
 ) -> Result<EthereumNetworks, anyhow::Error> {
-    let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
     let mut parsed_networks = EthereumNetworks::new();
     for network_arg in networks {
         if network_arg.starts_with("wss://")
@@ -573,19 +627,31 @@ async fn parse_ethereum_networks(
                 "capabilities" => capabilities
             );
 
+            let health = Arc::new(ProviderHealth::new(
+                url.to_string(),
+                ProviderLimits::default(),
+            ));
             let (transport_event_loop, transport) = match connection_type {
-                ConnectionType::RPC => Transport::new_rpc(url),
-                ConnectionType::IPC => Transport::new_ipc(url),
-                ConnectionType::WS => Transport::new_ws(url),
+                ConnectionType::RPC => {
+                    Transport::new_rpc(url, eth_rpc_metrics.clone(), health.clone())
+                }
+                ConnectionType::IPC => {
+                    Transport::new_ipc(url, eth_rpc_metrics.clone(), health.clone())
+                }
+                ConnectionType::WS => {
+                    Transport::new_ws(url, eth_rpc_metrics.clone(), health.clone())
+                }
             };
 
             // If we drop the event loop the transport will stop working.
             // For now it's fine to just leak it.
             std::mem::forget(transport_event_loop);
 
-            parsed_networks.insert(
+            parsed_networks.insert_weighted(
                 name.to_string(),
                 capabilities,
+                1,
+                health,
                 Arc::new(
                     graph_chain_ethereum::EthereumAdapter::new(
                         url,
@@ -670,10 +736,14 @@ fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<Ipf
 async fn create_ethereum_networks(
     logger: &Logger,
     metrics_registry: Arc<MetricsRegistry>,
+    config: &Config,
     rpc: &Vec<String>,
     ipc: &Vec<String>,
     ws: &Vec<String>,
 ) -> EthereumNetworks {
+    // The metrics can only be registered once, and are shared by all
+    // providers
+    let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(metrics_registry));
     let mut eth_networks = EthereumNetworks::new();
 
     for (connection_type, values) in [
@@ -689,14 +759,65 @@ async fn create_ethereum_networks(
             logger.clone(),
             values.clone(),
             connection_type,
-            metrics_registry.clone(),
+            eth_rpc_metrics.clone(),
         )
         .await
         .expect("Failed to parse Ethereum networks");
 
         eth_networks.extend(networks);
     }
+
+    for (name, chain) in &config.chains {
+        for provider in &chain.providers {
+            info!(
+                logger,
+                "Creating transport";
+                "network" => name,
+                "provider" => &provider.label,
+                "capabilities" => provider.capabilities(),
+                "weight" => provider.weight
+            );
+
+            let health = Arc::new(ProviderHealth::new(
+                provider.label.clone(),
+                provider.limits(),
+            ));
+            let url = provider.url.as_str();
+            let (transport_event_loop, transport) = match provider.transport {
+                config::Transport::Rpc => {
+                    Transport::new_rpc(url, eth_rpc_metrics.clone(), health.clone())
+                }
+                config::Transport::Ipc => {
+                    Transport::new_ipc(url, eth_rpc_metrics.clone(), health.clone())
+                }
+                config::Transport::Ws => {
+                    Transport::new_ws(url, eth_rpc_metrics.clone(), health.clone())
+                }
+            };
+
+            // If we drop the event loop the transport will stop working.
+            // For now it's fine to just leak it.
+            std::mem::forget(transport_event_loop);
+
+            eth_networks.insert_weighted(
+                name.clone(),
+                provider.capabilities(),
+                provider.weight,
+                health,
+                Arc::new(
+                    graph_chain_ethereum::EthereumAdapter::new(
+                        url,
+                        transport,
+                        eth_rpc_metrics.clone(),
+                    )
+                    .await,
+                ) as Arc<dyn EthereumAdapter>,
+            );
+        }
+    }
+
     eth_networks.sort();
+    eth_networks.spawn_health_probes(logger);
     eth_networks
 }
 
@@ -706,6 +827,7 @@ fn start_block_ingestor(
     eth_networks: &EthereumNetworks,
     network_stores: &HashMap<String, Arc<DieselNetworkStore>>,
     logger_factory: &LoggerFactory,
+    metrics_registry: Arc<MetricsRegistry>,
 ) {
     // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
     // otherwise BlockStream will not work properly.
@@ -714,6 +836,7 @@ fn start_block_ingestor(
     assert!(*ANCESTOR_COUNT >= *REORG_THRESHOLD);
 
     info!(logger, "Starting block ingestors");
+    let reorg_metrics = Arc::new(ReorgMetrics::new(metrics_registry));
 
     // Create Ethereum block ingestors and spawn a thread to run each
     eth_networks
@@ -731,11 +854,12 @@ fn start_block_ingestor(
                     .get(network_name)
                     .expect("network with name")
                     .clone(),
-                eth_adapter.clone(),
+                eth_adapter,
                 *ANCESTOR_COUNT,
                 network_name.to_string(),
                 logger_factory,
                 block_polling_interval,
+                reorg_metrics.clone(),
             )
             .expect("failed to create Ethereum block ingestor");
 
@@ -748,7 +872,7 @@ fn start_block_ingestor(
 mod test {
     use super::parse_ethereum_networks;
     use crate::ConnectionType;
-    use graph::components::ethereum::NodeCapabilities;
+    use graph::components::ethereum::{NodeCapabilities, ProviderEthRpcMetrics};
     use graph::log::logger;
     use graph::prelude::tokio;
     use graph_core::MetricsRegistry;
@@ -769,9 +893,10 @@ mod test {
             logger.clone(),
             prometheus_registry.clone(),
         ));
+        let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(metrics_registry));
 
         let ethereum_networks =
-            parse_ethereum_networks(logger, network_args, ConnectionType::RPC, metrics_registry)
+            parse_ethereum_networks(logger, network_args, ConnectionType::RPC, eth_rpc_metrics)
                 .await
                 .expect("Correctly parse Ethereum network args");
         let mut network_names = ethereum_networks.networks.keys().collect::<Vec<&String>>();
@@ -0,0 +1,68 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use graph::prelude::{anyhow::anyhow, anyhow::Error, SubgraphDeploymentId};
+use graph_store_postgres::ShardedStore;
+
+use crate::manager::display::List;
+
+fn deployment_id(id: String) -> Result<SubgraphDeploymentId, Error> {
+    SubgraphDeploymentId::new(id).map_err(|s| anyhow!("illegal deployment id: {}", s))
+}
+
+pub fn list(store: Arc<ShardedStore>, id: String) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    let (enabled, queries) = store.query_allowlist(&id)?;
+
+    let mut list = List::new(vec!["shape hash", "query"]);
+    for query in queries {
+        list.append(vec![query.shape_hash().to_string(), query.query]);
+    }
+
+    if enabled {
+        println!("{} only runs queries from its allow-list", id);
+    } else {
+        println!("{} runs all queries; its allow-list is not used", id);
+    }
+    if list.is_empty() {
+        println!("the allow-list is empty");
+    } else {
+        list.render();
+    }
+    Ok(())
+}
+
+pub fn add(store: Arc<ShardedStore>, id: String, file: PathBuf) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    let text = fs::read_to_string(&file)
+        .map_err(|e| anyhow!("can not read query from {}: {}", file.display(), e))?;
+    let query = graphql_parser::parse_query(&text)
+        .map_err(|e| anyhow!("invalid GraphQL query in {}: {}", file.display(), e))?
+        .into_static();
+
+    match store.add_to_query_allowlist(&id, &query)? {
+        Some(shape_hash) => println!("added query with shape hash {}", shape_hash),
+        None => println!("the allow-list already has a query with the same shape"),
+    }
+    Ok(())
+}
+
+pub fn remove(store: Arc<ShardedStore>, id: String, shape_hash: u64) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    if store.remove_from_query_allowlist(&id, shape_hash)? {
+        println!("removed query with shape hash {}", shape_hash);
+    } else {
+        println!("the allow-list has no query with shape hash {}", shape_hash);
+    }
+    Ok(())
+}
+
+pub fn enable(store: Arc<ShardedStore>, id: String, enabled: bool) -> Result<(), Error> {
+    let id = deployment_id(id)?;
+    store.set_query_allowlist_enabled(&id, enabled)?;
+    if enabled {
+        println!("{} now only runs queries from its allow-list", id);
+    } else {
+        println!("{} now runs all queries", id);
+    }
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use graph::prelude::{anyhow::anyhow, anyhow::Error, BlockNumber, SubgraphDeploymentId};
+use graph_store_postgres::{command_support::DumpFormat, ShardedStore};
+
+pub fn run(
+    store: Arc<ShardedStore>,
+    id: String,
+    block: Option<BlockNumber>,
+    format: DumpFormat,
+    out: PathBuf,
+) -> Result<(), Error> {
+    let id = SubgraphDeploymentId::new(id).map_err(|s| anyhow!("illegal deployment id: {}", s))?;
+
+    println!(
+        "Dumping {} to {}. This might take a while.",
+        id,
+        out.display()
+    );
+    let start = Instant::now();
+    let manifest = store.dump(&id, block, format, &out)?;
+    for table in &manifest.tables {
+        println!("{:>12} {:<30} {}", table.rows, table.entity, table.file);
+    }
+    let metadata: usize = manifest.metadata.iter().map(|table| table.rows).sum();
+    println!("{:>12} metadata entries", metadata);
+    println!(
+        "Dumped {} at block {} in {:.1}s",
+        id,
+        manifest.block.number,
+        start.elapsed().as_millis() as f64 / 1000.0
+    );
+    if manifest.block.hash.is_none() {
+        println!(
+            "Warning: the hash of block {} is not known; the dump can not be restored",
+            manifest.block.number
+        );
+    }
+    Ok(())
+}
@@ -1,4 +1,10 @@
+pub mod allowlist;
+pub mod dump;
 pub mod info;
+pub mod move_deployment;
 pub mod place;
+pub mod prune;
+pub mod replay;
+pub mod restore;
 pub mod txn_speed;
 pub mod unused_deployments;
@@ -0,0 +1,30 @@
+use std::{sync::Arc, time::Instant};
+
+use graph::prelude::{anyhow::anyhow, anyhow::Error, Logger, SubgraphDeploymentId};
+use graph_store_postgres::{Shard, ShardedStore};
+
+pub fn run(
+    logger: &Logger,
+    store: Arc<ShardedStore>,
+    id: String,
+    shard: String,
+) -> Result<(), Error> {
+    let id = SubgraphDeploymentId::new(id).map_err(|s| anyhow!("illegal deployment id: {}", s))?;
+    let shard = Shard::new(shard)?;
+
+    println!("Moving {} to shard {}. This might take a while.", id, shard);
+    let start = Instant::now();
+    let old = store.move_deployment(logger, &id, shard.clone())?;
+    println!(
+        "Moved {} from shard {} to shard {} in {:.1}s",
+        id,
+        old.shard,
+        shard,
+        start.elapsed().as_millis() as f64 / 1000.0
+    );
+    println!(
+        "The entities of the old copy in namespace {} of shard {} are not used anymore",
+        old.namespace, old.shard
+    );
+    Ok(())
+}
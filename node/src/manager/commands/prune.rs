@@ -0,0 +1,25 @@
+use std::{sync::Arc, time::Instant};
+
+use graph::prelude::{anyhow::anyhow, anyhow::Error, BlockNumber, Logger, SubgraphDeploymentId};
+use graph_store_postgres::ShardedStore;
+
+pub fn run(
+    logger: &Logger,
+    store: Arc<ShardedStore>,
+    id: String,
+    history: Option<BlockNumber>,
+) -> Result<(), Error> {
+    let id = SubgraphDeploymentId::new(id).map_err(|s| anyhow!("illegal deployment id: {}", s))?;
+
+    println!("Pruning {}. This might take a while.", id);
+    let start = Instant::now();
+    let (earliest_block, count) = store.prune(logger, &id, history)?;
+    println!(
+        "Removed {} entity versions in {:.1}s; the history of {} now starts at block {}",
+        count,
+        start.elapsed().as_millis() as f64 / 1000.0,
+        id,
+        earliest_block
+    );
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::{path::PathBuf, sync::Arc};
+
+use graph::prelude::{anyhow::anyhow, anyhow::Error, Logger};
+use graph_core::MetricsRegistry;
+use graph_runtime_wasm::replay::{self, HandlerRecording};
+use graph_store_postgres::ShardedStore;
+
+pub fn run(
+    logger: &Logger,
+    store: Arc<ShardedStore>,
+    registry: Arc<MetricsRegistry>,
+    path: PathBuf,
+) -> Result<(), Error> {
+    let recording = HandlerRecording::load(&path)?;
+    let module = recording.load_module(&path)?;
+
+    println!(
+        "Replaying handler {} of data source {} in block {} of {}",
+        recording.handler,
+        recording.data_source,
+        recording
+            .block
+            .number
+            .map(|number| number.to_string())
+            .unwrap_or_else(|| "unknown".to_owned()),
+        recording.subgraph_id
+    );
+    let recorded_error = recording.error.clone();
+
+    // Print the full chain of errors since it contains the WASM backtrace
+    let result = replay::replay(logger, registry, store, recording, &module)
+        .map_err(|e| anyhow!("the handler failed: {:#}", e))?;
+    match result {
+        None => println!("The handler succeeded"),
+        Some(error) => println!("The handler failed: {}", error.message),
+    }
+    if let Some(error) = recorded_error {
+        println!("The recorded invocation failed with: {}", error);
+    }
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use graph::prelude::{anyhow::anyhow, anyhow::Error, NodeId, SubgraphName};
+use graph_store_postgres::{Shard, ShardedStore};
+
+pub fn run(
+    store: Arc<ShardedStore>,
+    dir: PathBuf,
+    shard: String,
+    name: String,
+    node: String,
+) -> Result<(), Error> {
+    let shard = Shard::new(shard)?;
+    let name = SubgraphName::new(name.clone())
+        .map_err(|()| anyhow!("illegal subgraph name `{}`", name))?;
+    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("illegal node id `{}`", node))?;
+
+    println!(
+        "Restoring {} into shard {}. This might take a while.",
+        dir.display(),
+        shard
+    );
+    let start = Instant::now();
+    let manifest = store.restore(&dir, shard, name.clone(), node.clone())?;
+    let rows: usize = manifest.tables.iter().map(|table| table.rows).sum();
+    println!(
+        "Restored {} with {} entities at block {} in {:.1}s",
+        manifest.deployment,
+        rows,
+        manifest.block.number,
+        start.elapsed().as_millis() as f64 / 1000.0
+    );
+    println!(
+        "The deployment is now the current version of {} and assigned to {}",
+        name, node
+    );
+    Ok(())
+}
@@ -134,6 +134,15 @@ pub struct Opt {
         help = "Port for the Prometheus metrics server"
     )]
     pub metrics_port: u16,
+    #[structopt(
+        long,
+        value_name = "PORT",
+        env = "GRAPH_CHANGES_PORT",
+        help = "Port for the entity changes streaming server. The server is only \
+                started if this is set, and streams the entity change log which \
+                is only written if GRAPH_ENTITY_CHANGE_LOG is set"
+    )]
+    pub changes_port: Option<u16>,
     #[structopt(
         long,
         default_value = "default",
@@ -175,6 +184,15 @@ pub struct Opt {
         help = "How often to poll the Ethereum node for new blocks"
     )]
     pub ethereum_polling_interval: u64,
+    #[structopt(
+        long,
+        value_name = "DIR",
+        env = "ETHEREUM_FLAT_FILES",
+        help = "Directory with blocks exported to flat files that are used instead of \
+                the Ethereum node to sync historical blocks; it must contain one \
+                subdirectory per network"
+    )]
+    pub ethereum_flat_files: Option<String>,
     #[structopt(
         long,
         value_name = "DISABLE_BLOCK_INGESTOR",
@@ -33,6 +33,7 @@ impl StoreBuilder {
 
         let (store, primary_pool) =
             Self::make_sharded_store_and_primary_pool(logger, config, registry.cheap_clone());
+        store.listen_for_site_changes(logger, primary.connection.to_owned());
 
         let chain_head_update_listener = Arc::new(PostgresChainHeadUpdateListener::new(
             &logger,
@@ -1,5 +1,5 @@
 use std::cmp::PartialEq;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 
@@ -18,7 +18,7 @@ use graph::components::ethereum::*;
 use graph::components::store::Store;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
-use graph::data::subgraph::{Mapping, Source};
+use graph::data::subgraph::{Mapping, Source, SubgraphFeature};
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
@@ -27,6 +27,7 @@ use web3::types::{Log, Transaction};
 
 use crate::host_exports::HostExports;
 use crate::mapping::{MappingContext, MappingRequest, MappingTrigger};
+use crate::replay::{HostCalls, RecordConfig};
 
 lazy_static! {
     static ref TIMEOUT: Option<Duration> = std::env::var("GRAPH_MAPPING_HANDLER_TIMEOUT")
@@ -35,6 +36,11 @@ lazy_static! {
         .map(Duration::from_secs);
     static ref ALLOW_NON_DETERMINISTIC_IPFS: bool =
         std::env::var("GRAPH_ALLOW_NON_DETERMINISTIC_IPFS").is_ok();
+    static ref RECORD: Option<RecordConfig> =
+        std::env::var_os("GRAPH_MAPPING_RECORD_DIR").map(|dir| RecordConfig {
+            dir: dir.into(),
+            all: std::env::var("GRAPH_MAPPING_RECORD_ALL").is_ok(),
+        });
 }
 
 struct RuntimeHostConfig {
@@ -46,6 +52,8 @@ struct RuntimeHostConfig {
     data_source_creation_block: Option<u64>,
     contract: Source,
     templates: Arc<Vec<DataSourceTemplate>>,
+    /// The entity types that handlers may access, if they are restricted
+    allowed_entity_types: Option<BTreeSet<String>>,
 }
 
 pub struct RuntimeHostBuilder<S> {
@@ -113,6 +121,7 @@ where
             tokio::runtime::Handle::current(),
             *TIMEOUT,
             *ALLOW_NON_DETERMINISTIC_IPFS,
+            RECORD.clone(),
         )
     }
 
@@ -120,6 +129,7 @@ where
         &self,
         network_name: String,
         subgraph_id: SubgraphDeploymentId,
+        features: &BTreeSet<SubgraphFeature>,
         data_source: DataSource,
         templates: Arc<Vec<DataSourceTemplate>>,
         mapping_request_sender: Sender<MappingRequest>,
@@ -134,12 +144,22 @@ where
 
         let required_capabilities = data_source.mapping.required_capabilities();
 
+        // Handlers of data sources that run in parallel must stick to the
+        // entity types they declare, otherwise they could see the changes
+        // of other data sources in a different order than when they run
+        // sequentially
+        let allowed_entity_types = if features.contains(&SubgraphFeature::parallelDataSources) {
+            Some(data_source.mapping.entities.iter().cloned().collect())
+        } else {
+            None
+        };
+
         let ethereum_adapter = self
             .ethereum_networks
             .adapter_with_capabilities(network_name.clone(), &required_capabilities)?;
 
         RuntimeHost::new(
-            ethereum_adapter.clone(),
+            ethereum_adapter,
             self.link_resolver.clone(),
             store.clone(),
             store.clone(),
@@ -152,6 +172,7 @@ where
                 data_source_creation_block: data_source.creation_block,
                 contract: data_source.source,
                 templates,
+                allowed_entity_types,
             },
             mapping_request_sender,
             metrics,
@@ -170,6 +191,7 @@ pub struct RuntimeHost {
     data_source_call_handlers: Vec<MappingCallHandler>,
     data_source_block_handlers: Vec<MappingBlockHandler>,
     data_source_creation_block: Option<u64>,
+    data_source_entities: Vec<String>,
     mapping_request_sender: Sender<MappingRequest>,
     host_exports: Arc<HostExports>,
     metrics: Arc<HostMetrics>,
@@ -222,6 +244,7 @@ impl RuntimeHost {
             config.data_source_network,
             config.data_source_context,
             config.templates,
+            config.allowed_entity_types,
             config.mapping.abis,
             ethereum_adapter,
             link_resolver,
@@ -239,6 +262,7 @@ impl RuntimeHost {
             data_source_call_handlers: config.mapping.call_handlers,
             data_source_block_handlers: config.mapping.block_handlers,
             data_source_creation_block: config.data_source_creation_block,
+            data_source_entities: config.mapping.entities,
             mapping_request_sender,
             host_exports,
             metrics,
@@ -411,6 +435,7 @@ impl RuntimeHost {
                     host_exports: self.host_exports.cheap_clone(),
                     block: block.cheap_clone(),
                     proof_of_indexing,
+                    host_calls: HostCalls::Live,
                 },
                 trigger,
                 result_sender,
@@ -449,7 +474,7 @@ impl RuntimeHost {
 }
 
 #[async_trait]
-impl RuntimeHostTrait for RuntimeHost {
+impl EthereumRuntimeHost for RuntimeHost {
     fn matches_log(&self, log: &Log) -> bool {
         self.matches_log_address(log)
             && self.matches_log_signature(log)
@@ -699,6 +724,10 @@ impl RuntimeHostTrait for RuntimeHost {
             )
         );
 
+        self.host_exports
+            .make_declared_calls(logger, block, log, &params, &event_handler)
+            .await;
+
         self.send_mapping_request(
             logger,
             o! {
@@ -719,10 +748,16 @@ impl RuntimeHostTrait for RuntimeHost {
         .err_into()
         .await
     }
+}
 
+impl RuntimeHostTrait for RuntimeHost {
     fn creation_block_number(&self) -> Option<u64> {
         self.data_source_creation_block
     }
+
+    fn entity_types(&self) -> &[String] {
+        &self.data_source_entities
+    }
 }
 
 impl PartialEq for RuntimeHost {
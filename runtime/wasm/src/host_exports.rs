@@ -1,6 +1,6 @@
 use crate::UnresolvedContractCall;
 use bytes::Bytes;
-use ethabi::{Address, Token};
+use ethabi::{Address, LogParam, Token};
 use graph::components::ethereum::*;
 use graph::components::store::EntityKey;
 use graph::components::subgraph::{ProofOfIndexingEvent, SharedProofOfIndexing};
@@ -10,11 +10,11 @@ use graph::data::store;
 use graph::prelude::serde_json;
 use graph::prelude::{slog::b, slog::record_static, *};
 use semver::Version;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
-use web3::types::H160;
+use web3::types::{Log, H160};
 
 use graph::ensure;
 use graph_graphql::prelude::validate_entity;
@@ -51,16 +51,19 @@ impl From<anyhow::Error> for HostExportError {
 pub(crate) struct HostExports {
     pub(crate) subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
-    data_source_name: String,
-    data_source_address: Option<Address>,
+    pub(crate) data_source_name: String,
+    pub(crate) data_source_address: Option<Address>,
     data_source_network: String,
-    data_source_context: Option<DataSourceContext>,
+    pub(crate) data_source_context: Option<DataSourceContext>,
     /// Some data sources have indeterminism or different notions of time. These
     /// need to be each be stored separately to separate causality between them,
     /// and merge the results later. Right now, this is just the ethereum
     /// networks but will be expanded for ipfs and the availability chain.
     causality_region: String,
     templates: Arc<Vec<DataSourceTemplate>>,
+    /// The entity types that handlers are allowed to access. This is only
+    /// restricted for subgraphs that use the `parallelDataSources` feature
+    allowed_entity_types: Option<BTreeSet<String>>,
     abis: Vec<MappingABI>,
     ethereum_adapter: Arc<dyn EthereumAdapter>,
     pub(crate) link_resolver: Arc<dyn LinkResolver>,
@@ -86,6 +89,7 @@ impl HostExports {
         data_source_network: String,
         data_source_context: Option<DataSourceContext>,
         templates: Arc<Vec<DataSourceTemplate>>,
+        allowed_entity_types: Option<BTreeSet<String>>,
         abis: Vec<MappingABI>,
         ethereum_adapter: Arc<dyn EthereumAdapter>,
         link_resolver: Arc<dyn LinkResolver>,
@@ -105,6 +109,7 @@ impl HostExports {
             data_source_context,
             causality_region,
             templates,
+            allowed_entity_types,
             abis,
             ethereum_adapter,
             link_resolver,
@@ -144,6 +149,23 @@ impl HostExports {
         )))
     }
 
+    /// Fail deterministically if handlers of this data source are not
+    /// allowed to access `entity_type`
+    pub(crate) fn check_entity_type(&self, entity_type: &str) -> Result<(), HostExportError> {
+        match &self.allowed_entity_types {
+            Some(allowed) if !allowed.contains(entity_type) => {
+                Err(HostExportError::Deterministic(anyhow!(
+                    "Data source `{}` accessed entity type `{}` which is not listed in the \
+                     `entities` of its mapping. Subgraphs with the `parallelDataSources` \
+                     feature can only access the entity types that a data source declares",
+                    self.data_source_name,
+                    entity_type
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub(crate) fn store_set(
         &self,
         logger: &Logger,
@@ -353,6 +375,55 @@ impl HostExports {
         result.map_err(Into::into)
     }
 
+    /// Make the calls that `handler` declares in the manifest for `log` in
+    /// parallel so that their results are in the call cache by the time the
+    /// handler makes them with `ethereum.call`. Calls that fail are only
+    /// logged since the handler gets the error when it makes the call itself
+    pub(crate) async fn make_declared_calls(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        log: &Log,
+        params: &[LogParam],
+        handler: &MappingEventHandler,
+    ) {
+        if handler.calls.is_empty() {
+            return;
+        }
+
+        let start_time = Instant::now();
+        let calls = handler.calls.iter().filter_map(|(label, declared_call)| {
+            let call = match declared_call.contract_call(&self.abis, block.into(), log, params) {
+                Ok(call) => call,
+                Err(e) => {
+                    warn!(logger, "Cannot make declared call";
+                          "call" => label,
+                          "error" => format!("{:#}", e));
+                    return None;
+                }
+            };
+            let logger = logger.cheap_clone();
+            Some(
+                self.ethereum_adapter
+                    .contract_call(&logger, call, self.call_cache.cheap_clone())
+                    .compat()
+                    .map(move |result| {
+                        if let Err(e) = result {
+                            debug!(logger, "Declared call failed";
+                                   "call" => label,
+                                   "error" => e.to_string());
+                        }
+                    }),
+            )
+        });
+        futures03::future::join_all(calls).await;
+
+        debug!(logger, "Declared calls finished";
+               "handler" => &handler.handler,
+               "calls" => handler.calls.len(),
+               "time" => format!("{}ms", start_time.elapsed().as_millis()));
+    }
+
     /// Prints the module of `n` in hex.
     /// Integers are encoded using the least amount of digits (no leading zero digits).
     /// Their encoding may be of uneven length. The number zero encodes as "0x0".
@@ -14,6 +14,9 @@ mod module;
 /// Runtime-agnostic implementation of exports to WASM.
 mod host_exports;
 
+/// Recording of handler invocations and running them again from a recording.
+pub mod replay;
+
 use graph::prelude::web3::types::Address;
 use graph::prelude::Store;
 
@@ -1,4 +1,5 @@
 use crate::module::WasmInstance;
+use crate::replay::{HostCalls, RecordConfig, Recorder};
 use ethabi::LogParam;
 use futures::sync::mpsc;
 use futures03::channel::oneshot::Sender;
@@ -21,8 +22,16 @@ pub fn spawn_module(
     runtime: tokio::runtime::Handle,
     timeout: Option<Duration>,
     allow_non_deterministic_ipfs: bool,
+    record: Option<RecordConfig>,
 ) -> Result<mpsc::Sender<MappingRequest>, anyhow::Error> {
     let valid_module = Arc::new(ValidModule::new(&raw_module)?);
+    let recorder = match record {
+        Some(config) => Some(
+            Recorder::new(config, &subgraph_id, &raw_module)
+                .context("Failed to set up recording of handlers")?,
+        ),
+        None => None,
+    };
 
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
@@ -43,11 +52,18 @@ pub fn spawn_module(
                 .map_err(|()| unreachable!())
                 .for_each(move |request| {
                     let MappingRequest {
-                        ctx,
+                        mut ctx,
                         trigger,
                         result_sender,
                     } = request;
 
+                    let logger = ctx.logger.cheap_clone();
+                    let errors_before = ctx.state.deterministic_errors.len();
+                    let recording = recorder
+                        .as_ref()
+                        .map(|recorder| recorder.start(&mut ctx, trigger.handler(), &trigger));
+                    let host_calls = ctx.host_calls.clone();
+
                     // Start the WASM module runtime.
                     let section = host_metrics.stopwatch.start_section("module_init");
                     let module = WasmInstance::from_valid_module_with_ctx(
@@ -91,6 +107,19 @@ pub fn spawn_module(
                     };
                     section.end();
 
+                    if let (Some(recorder), Some(recording)) = (&recorder, recording) {
+                        let error = match &result {
+                            Ok(state) => state
+                                .deterministic_errors
+                                .get(errors_before)
+                                .map(|error| error.message.clone()),
+                            Err(MappingError::PossibleReorg(e)) | Err(MappingError::Unknown(e)) => {
+                                Some(format!("{:#}", e))
+                            }
+                        };
+                        recorder.finish(&logger, recording, &host_calls, error);
+                    }
+
                     result_sender
                         .send((result, future::ok(Instant::now())))
                         .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
@@ -129,6 +158,17 @@ pub(crate) enum MappingTrigger {
     },
 }
 
+impl MappingTrigger {
+    /// The name of the handler for this trigger
+    pub(crate) fn handler(&self) -> &str {
+        match self {
+            MappingTrigger::Log { handler, .. } => &handler.handler,
+            MappingTrigger::Call { handler, .. } => &handler.handler,
+            MappingTrigger::Block { handler } => &handler.handler,
+        }
+    }
+}
+
 type MappingResponse = (
     Result<BlockState, MappingError>,
     futures::Finished<Instant, Error>,
@@ -148,6 +188,7 @@ pub(crate) struct MappingContext {
     pub(crate) block: Arc<LightEthereumBlock>,
     pub(crate) state: BlockState,
     pub(crate) proof_of_indexing: SharedProofOfIndexing,
+    pub(crate) host_calls: HostCalls,
 }
 
 impl MappingContext {
@@ -158,6 +199,7 @@ impl MappingContext {
             block: self.block.clone(),
             state: BlockState::new(self.state.entity_cache.store.clone(), Default::default()),
             proof_of_indexing: self.proof_of_indexing.cheap_clone(),
+            host_calls: HostCalls::Live,
         }
     }
 }
@@ -24,6 +24,7 @@ use crate::asc_abi::class::*;
 use crate::asc_abi::*;
 use crate::host_exports::{EthereumCallError, HostExports};
 use crate::mapping::ValidModule;
+use crate::replay::{self, HostCall, HostCalls};
 use crate::UnresolvedContractCall;
 
 mod into_wasm_ret;
@@ -729,9 +730,10 @@ impl WasmInstanceContext {
         id_ptr: AscPtr<AscString>,
         data_ptr: AscPtr<AscEntity>,
     ) -> Result<(), Trap> {
-        let entity = self.asc_get(entity_ptr);
+        let entity: String = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
         let data = self.try_asc_get(data_ptr)?;
+        try_host_export!(self, self.ctx.host_exports.check_entity_type(&entity));
         self.ctx.host_exports.store_set(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -744,9 +746,14 @@ impl WasmInstanceContext {
     }
 
     /// function store.remove(entity: string, id: string): void
-    fn store_remove(&mut self, entity_ptr: AscPtr<AscString>, id_ptr: AscPtr<AscString>) {
-        let entity = self.asc_get(entity_ptr);
+    fn store_remove(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<(), Trap> {
+        let entity: String = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
+        try_host_export!(self, self.ctx.host_exports.check_entity_type(&entity));
         self.ctx.host_exports.store_remove(
             &self.ctx.logger,
             &mut self.ctx.state,
@@ -754,6 +761,7 @@ impl WasmInstanceContext {
             entity,
             id,
         );
+        Ok(())
     }
 
     /// function store.get(entity: string, id: string): Entity | null
@@ -763,12 +771,31 @@ impl WasmInstanceContext {
         id_ptr: AscPtr<AscString>,
     ) -> Result<AscPtr<AscEntity>, Trap> {
         let start = Instant::now();
-        let entity_ptr = self.asc_get(entity_ptr);
-        let id_ptr = self.asc_get(id_ptr);
-        let entity_option =
-            self.ctx
-                .host_exports
-                .store_get(&mut self.ctx.state, entity_ptr, id_ptr)?;
+        let entity_type: String = self.asc_get(entity_ptr);
+        let id: String = self.asc_get(id_ptr);
+        try_host_export!(self, self.ctx.host_exports.check_entity_type(&entity_type));
+        let entity_option = if self.ctx.host_calls.is_replay() {
+            self.ctx.host_calls.replay("store.get", |call| match call {
+                HostCall::StoreGet {
+                    entity_type: recorded_type,
+                    id: recorded_id,
+                    result,
+                } if recorded_type == entity_type && recorded_id == id => Ok(result),
+                call => Err(call),
+            })?
+        } else {
+            let entity_option = self.ctx.host_exports.store_get(
+                &mut self.ctx.state,
+                entity_type.clone(),
+                id.clone(),
+            )?;
+            self.ctx.host_calls.record(|| HostCall::StoreGet {
+                entity_type,
+                id,
+                result: entity_option.clone(),
+            });
+            entity_option
+        };
 
         let ret = Ok(match entity_option {
             Some(entity) => {
@@ -792,10 +819,25 @@ impl WasmInstanceContext {
         &mut self,
         call: UnresolvedContractCall,
     ) -> Result<AscEnumArray<EthereumValueKind>, Trap> {
+        if self.ctx.host_calls.is_replay() {
+            let result = replay::replay_ethereum_call(&self.ctx.host_calls, &call)?;
+            return Ok(match result {
+                Some(tokens) => self.asc_new(tokens.as_slice()),
+                None => AscPtr::null(),
+            });
+        }
+
+        let recorded_call = match self.ctx.host_calls {
+            HostCalls::Record(_) => Some(call.clone()),
+            HostCalls::Live | HostCalls::Replay(_) => None,
+        };
         let result = self
             .ctx
             .host_exports
             .ethereum_call(&self.ctx.logger, &self.ctx.block, call);
+        if let (Some(call), Ok(result)) = (recorded_call, &result) {
+            replay::record_ethereum_call(&self.ctx.host_calls, &call, result);
+        }
         match result {
             Ok(Some(tokens)) => Ok(self.asc_new(tokens.as_slice())),
             Ok(None) => Ok(AscPtr::null()),
@@ -890,8 +932,29 @@ impl WasmInstanceContext {
             .into());
         }
 
-        let link = self.asc_get(link_ptr);
-        let ipfs_res = self.ctx.host_exports.ipfs_cat(&self.ctx.logger, link);
+        let link: String = self.asc_get(link_ptr);
+        if self.ctx.host_calls.is_replay() {
+            let result = self.ctx.host_calls.replay("ipfs.cat", |call| match call {
+                HostCall::IpfsCat {
+                    link: recorded_link,
+                    result,
+                } if recorded_link == link => Ok(result),
+                call => Err(call),
+            })?;
+            return Ok(match result {
+                Some(bytes) => self.asc_new(&*bytes.0),
+                None => AscPtr::null(),
+            });
+        }
+
+        let ipfs_res = self
+            .ctx
+            .host_exports
+            .ipfs_cat(&self.ctx.logger, link.clone());
+        self.ctx.host_calls.record(|| HostCall::IpfsCat {
+            link,
+            result: ipfs_res.as_ref().ok().cloned().map(Into::into),
+        });
         match ipfs_res {
             Ok(bytes) => {
                 let bytes_obj: AscPtr<Uint8Array> = self.asc_new(&*bytes);
@@ -927,6 +990,14 @@ impl WasmInstanceContext {
         let callback: String = self.asc_get(callback);
         let user_data: store::Value = self.try_asc_get(user_data)?;
 
+        if self.ctx.host_calls.is_replay() {
+            return Err(anyhow::anyhow!("`ipfs.map` can not be replayed").into());
+        }
+        self.ctx.host_calls.record(|| HostCall::IpfsMap {
+            link: link.clone(),
+            callback: callback.clone(),
+        });
+
         let flags = self.asc_get(flags);
 
         // Pause the timeout while running ipfs_map, ensure it will be restarted by using a guard.
@@ -1224,15 +1295,7 @@ impl WasmInstanceContext {
     ) -> Result<(), Trap> {
         let name: String = self.asc_get(name_ptr);
         let params: Vec<String> = self.asc_get(params_ptr);
-        self.ctx.host_exports.data_source_create(
-            &self.ctx.logger,
-            &mut self.ctx.state,
-            name,
-            params,
-            None,
-            self.ctx.block.block_ptr().number,
-        )?;
-        Ok(())
+        self.create_data_source(name, params, None)
     }
 
     /// function createWithContext(name: string, params: Array<string>, context: DataSourceContext): void
@@ -1245,12 +1308,43 @@ impl WasmInstanceContext {
         let name: String = self.asc_get(name_ptr);
         let params: Vec<String> = self.asc_get(params_ptr);
         let context: HashMap<_, _> = self.try_asc_get(context_ptr)?;
+        self.create_data_source(name, params, Some(context.into()))
+    }
+
+    fn create_data_source(
+        &mut self,
+        name: String,
+        params: Vec<String>,
+        context: Option<DataSourceContext>,
+    ) -> Result<(), Trap> {
+        if self.ctx.host_calls.is_replay() {
+            info!(&self.ctx.logger, "Skipping data source creation during replay";
+                                    "name" => &name,
+                                    "params" => params.join(","));
+            return Ok(self
+                .ctx
+                .host_calls
+                .replay("dataSource.create", |call| match call {
+                    HostCall::DataSourceCreate {
+                        name: recorded_name,
+                        params: recorded_params,
+                        ..
+                    } if recorded_name == name && recorded_params == params => Ok(()),
+                    call => Err(call),
+                })?);
+        }
+
+        self.ctx.host_calls.record(|| HostCall::DataSourceCreate {
+            name: name.clone(),
+            params: params.clone(),
+            context: context.clone(),
+        });
         self.ctx.host_exports.data_source_create(
             &self.ctx.logger,
             &mut self.ctx.state,
             name,
             params,
-            Some(context.into()),
+            context,
             self.ctx.block.block_ptr().number,
         )?;
         Ok(())
@@ -1273,7 +1367,24 @@ impl WasmInstanceContext {
 
     fn ens_name_by_hash(&mut self, hash_ptr: AscPtr<AscString>) -> Result<AscPtr<AscString>, Trap> {
         let hash: String = self.asc_get(hash_ptr);
-        let name = self.ctx.host_exports.ens_name_by_hash(&*hash)?;
+        let name = if self.ctx.host_calls.is_replay() {
+            self.ctx
+                .host_calls
+                .replay("ens.nameByHash", |call| match call {
+                    HostCall::EnsNameByHash {
+                        hash: recorded_hash,
+                        result,
+                    } if recorded_hash == hash => Ok(result),
+                    call => Err(call),
+                })?
+        } else {
+            let name = self.ctx.host_exports.ens_name_by_hash(&*hash)?;
+            self.ctx.host_calls.record(|| HostCall::EnsNameByHash {
+                hash,
+                result: name.clone(),
+            });
+            name
+        };
         // map `None` to `null`, and `Some(s)` to a runtime string
         Ok(name
             .map(|name| self.asc_new(&*name))
@@ -1292,7 +1403,26 @@ impl WasmInstanceContext {
         tx_id: AscPtr<AscString>,
     ) -> Result<AscPtr<Uint8Array>, Trap> {
         let tx_id: String = self.asc_get(tx_id);
-        let data = self.ctx.host_exports.arweave_transaction_data(&tx_id);
+        let data: Option<bytes::Bytes> = if self.ctx.host_calls.is_replay() {
+            self.ctx
+                .host_calls
+                .replay("arweave.transactionData", |call| match call {
+                    HostCall::ArweaveTransactionData {
+                        tx_id: recorded_tx_id,
+                        result,
+                    } if recorded_tx_id == tx_id => Ok(result.map(|bytes| bytes.0.into())),
+                    call => Err(call),
+                })?
+        } else {
+            let data = self.ctx.host_exports.arweave_transaction_data(&tx_id);
+            self.ctx
+                .host_calls
+                .record(|| HostCall::ArweaveTransactionData {
+                    tx_id,
+                    result: data.as_ref().map(|data| data.to_vec().into()),
+                });
+            data
+        };
         Ok(data
             .map(|data| self.asc_new(&*data))
             .unwrap_or(AscPtr::null()))
@@ -1301,7 +1431,24 @@ impl WasmInstanceContext {
     /// function box.profile(address: string): JSONValue | null
     fn box_profile(&mut self, address: AscPtr<AscString>) -> Result<AscPtr<AscJson>, Trap> {
         let address: String = self.asc_get(address);
-        let profile = self.ctx.host_exports.box_profile(&address);
+        let profile = if self.ctx.host_calls.is_replay() {
+            self.ctx
+                .host_calls
+                .replay("box.profile", |call| match call {
+                    HostCall::BoxProfile {
+                        address: recorded_address,
+                        result,
+                    } if recorded_address == address => Ok(result),
+                    call => Err(call),
+                })?
+        } else {
+            let profile = self.ctx.host_exports.box_profile(&address);
+            self.ctx.host_calls.record(|| HostCall::BoxProfile {
+                address,
+                result: profile.clone(),
+            });
+            profile
+        };
         Ok(profile
             .map(|profile| self.asc_new(&profile))
             .unwrap_or(AscPtr::null()))
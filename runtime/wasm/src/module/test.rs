@@ -5,6 +5,8 @@ use std::io::Cursor;
 use std::str::FromStr;
 
 use crate::host_exports::HostExports;
+use crate::mapping::MappingTrigger;
+use crate::replay::{self, HandlerRecording, RecordConfig, Recorder};
 use graph::components::store::*;
 use graph::data::store::scalar;
 use graph::data::subgraph::*;
@@ -147,6 +149,7 @@ fn mock_host_exports(
         data_source.network.unwrap(),
         data_source.context,
         Arc::new(templates),
+        None,
         data_source.mapping.abis,
         mock_ethereum_adapter,
         Arc::new(graph_core::LinkResolver::from(
@@ -173,6 +176,7 @@ fn mock_context(
         host_exports: Arc::new(mock_host_exports(subgraph_id, data_source, store.clone())),
         state: BlockState::new(store, Default::default()),
         proof_of_indexing: None,
+        host_calls: HostCalls::Live,
     }
 }
 
@@ -726,6 +730,112 @@ async fn entity_store() {
     }
 }
 
+#[tokio::test]
+async fn record_and_replay_handler() {
+    const SUBGRAPH_ID: &str = "recordReplay";
+    const MODULE: &str = "wasm_test/store.wasm";
+
+    let (mut module, store) = test_valid_module_and_store(SUBGRAPH_ID, mock_data_source(MODULE));
+    let subgraph_id = SubgraphDeploymentId::new(SUBGRAPH_ID).unwrap();
+    let mut steve = Entity::new();
+    steve.set("id", "steve");
+    steve.set("name", "Steve");
+    test_store::insert_entities(
+        subgraph_id.clone(),
+        vec![(EntityType::data("User".to_string()), steve)],
+    )
+    .unwrap();
+
+    // Run the same handler code for recording and replaying, and return
+    // what the handler got from the host and the entity changes it made
+    let run = |module: &mut WasmInstance| {
+        let id = module.asc_new("steve");
+        let entity_ptr: AscPtr<AscEntity> = module.invoke_export("getUser", id);
+        let user = Entity::from(
+            module
+                .try_asc_get::<HashMap<String, Value>, _>(entity_ptr)
+                .unwrap(),
+        );
+        for (id, name) in &[("steve", "Steve-O"), ("herobrine", "Brine-O")] {
+            let id_ptr = module.asc_new(*id);
+            let name_ptr = module.asc_new(*name);
+            module
+                .invoke_export2_void("loadAndSetUserName", id_ptr, name_ptr)
+                .unwrap();
+        }
+        let mut mods = module
+            .take_ctx()
+            .ctx
+            .state
+            .entity_cache
+            .as_modifications(store.as_ref())
+            .unwrap()
+            .modifications;
+        mods.sort_by(|a, b| a.entity_key().entity_id.cmp(&b.entity_key().entity_id));
+        (user, mods)
+    };
+
+    // Record the handler into a file
+    let dir = std::env::temp_dir().join("graph-record-replay");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = RecordConfig {
+        dir: dir.clone(),
+        all: true,
+    };
+    let raw_module = std::fs::read(MODULE).unwrap();
+    let recorder = Recorder::new(config, &subgraph_id, &raw_module).unwrap();
+    let trigger = MappingTrigger::Block {
+        handler: MappingBlockHandler {
+            handler: "loadAndSetUserName".to_owned(),
+            filter: None,
+        },
+    };
+    let recording = recorder.start(
+        &mut module.instance_ctx_mut().ctx,
+        trigger.handler(),
+        &trigger,
+    );
+    let host_calls = module.instance_ctx().ctx.host_calls.clone();
+    let recorded = run(&mut module);
+    recorder.finish(&test_store::LOGGER, recording, &host_calls, None);
+
+    // Load the recording the way `graphman replay` does
+    let path = std::fs::read_dir(dir.join(SUBGRAPH_ID))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .expect("the handler invocation was recorded");
+    let recording = HandlerRecording::load(&path).unwrap();
+    let recorded_module = recording.load_module(&path).unwrap();
+    assert_eq!(raw_module, recorded_module);
+    assert_eq!(
+        vec!["store.get"; 3],
+        recording
+            .host_calls
+            .iter()
+            .map(|call| call.function())
+            .collect::<Vec<_>>()
+    );
+
+    // Replay the handler and check that it sees the same host call results
+    // and makes the same changes
+    let (mut module, host_calls) = replay::instantiate(
+        &test_store::LOGGER,
+        Arc::new(MockMetricsRegistry::new()),
+        store.clone(),
+        &recording,
+        &recorded_module,
+    )
+    .unwrap();
+    let replayed = run(&mut module);
+    assert!(host_calls.take().is_empty());
+    assert_eq!(recorded, replayed);
+
+    let (user, mods) = replayed;
+    assert_eq!(Some(&Value::from("Steve")), user.get("name"));
+    assert_eq!(2, mods.len());
+}
+
 #[tokio::test]
 async fn detect_contract_calls() {
     let data_source_without_calls = mock_data_source("wasm_test/abi_store_value.wasm");
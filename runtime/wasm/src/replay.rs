@@ -0,0 +1,690 @@
+//! Record the inputs of handler invocations so that a single handler can be
+//! run again later, without an Ethereum node, IPFS, or any other service
+//! that the original invocation used.
+//!
+//! A recording contains the trigger and the block that the handler was
+//! invoked with, and the results of all host functions that depend on the
+//! outside world, like `store.get` or `ethereum.call`. Host functions that
+//! are pure computations are simply executed again during a replay. The
+//! WASM module is written once per subgraph next to the recordings.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethabi::{LogParam, Token};
+use semver::Version;
+use web3::types::{Address, Bytes, Log, Transaction, U256};
+
+use graph::components::arweave::ArweaveAdapter;
+use graph::components::subgraph::MappingError;
+use graph::components::three_box::ThreeBoxAdapter;
+use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::Link;
+use graph::mock::MockEthereumAdapter;
+use graph::prelude::*;
+
+use crate::host_exports::HostExports;
+use crate::mapping::{MappingContext, MappingTrigger, ValidModule};
+use crate::module::WasmInstance;
+use crate::UnresolvedContractCall;
+
+/// Where to write recordings of handler invocations
+#[derive(Clone, Debug)]
+pub(crate) struct RecordConfig {
+    pub dir: PathBuf,
+    /// Write a recording for every handler invocation, not just for the
+    /// ones that failed
+    pub all: bool,
+}
+
+/// An Ethereum value in a form that can be written to a recording
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum RecordedToken {
+    Address(Address),
+    FixedBytes(Bytes),
+    Bytes(Bytes),
+    Int(U256),
+    Uint(U256),
+    Bool(bool),
+    String(String),
+    FixedArray(Vec<RecordedToken>),
+    Array(Vec<RecordedToken>),
+    Tuple(Vec<RecordedToken>),
+}
+
+impl From<&Token> for RecordedToken {
+    fn from(token: &Token) -> Self {
+        let tokens = |tokens: &Vec<Token>| tokens.iter().map(RecordedToken::from).collect();
+        match token {
+            Token::Address(address) => RecordedToken::Address(*address),
+            Token::FixedBytes(bytes) => RecordedToken::FixedBytes(Bytes(bytes.clone())),
+            Token::Bytes(bytes) => RecordedToken::Bytes(Bytes(bytes.clone())),
+            Token::Int(n) => RecordedToken::Int(*n),
+            Token::Uint(n) => RecordedToken::Uint(*n),
+            Token::Bool(b) => RecordedToken::Bool(*b),
+            Token::String(s) => RecordedToken::String(s.clone()),
+            Token::FixedArray(elements) => RecordedToken::FixedArray(tokens(elements)),
+            Token::Array(elements) => RecordedToken::Array(tokens(elements)),
+            Token::Tuple(elements) => RecordedToken::Tuple(tokens(elements)),
+        }
+    }
+}
+
+impl From<RecordedToken> for Token {
+    fn from(token: RecordedToken) -> Self {
+        let tokens = |tokens: Vec<RecordedToken>| tokens.into_iter().map(Token::from).collect();
+        match token {
+            RecordedToken::Address(address) => Token::Address(address),
+            RecordedToken::FixedBytes(bytes) => Token::FixedBytes(bytes.0),
+            RecordedToken::Bytes(bytes) => Token::Bytes(bytes.0),
+            RecordedToken::Int(n) => Token::Int(n),
+            RecordedToken::Uint(n) => Token::Uint(n),
+            RecordedToken::Bool(b) => Token::Bool(b),
+            RecordedToken::String(s) => Token::String(s),
+            RecordedToken::FixedArray(elements) => Token::FixedArray(tokens(elements)),
+            RecordedToken::Array(elements) => Token::Array(tokens(elements)),
+            RecordedToken::Tuple(elements) => Token::Tuple(tokens(elements)),
+        }
+    }
+}
+
+/// A decoded parameter of an event or a call
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedParam {
+    pub name: String,
+    pub value: RecordedToken,
+}
+
+fn record_params(params: &[LogParam]) -> Vec<RecordedParam> {
+    params
+        .iter()
+        .map(|param| RecordedParam {
+            name: param.name.clone(),
+            value: RecordedToken::from(&param.value),
+        })
+        .collect()
+}
+
+fn replay_params(params: Vec<RecordedParam>) -> Vec<LogParam> {
+    params
+        .into_iter()
+        .map(|param| LogParam {
+            name: param.name,
+            value: param.value.into(),
+        })
+        .collect()
+}
+
+fn tokens_of(tokens: &[Token]) -> Vec<RecordedToken> {
+    tokens.iter().map(RecordedToken::from).collect()
+}
+
+/// The trigger that a handler was invoked for
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RecordedTrigger {
+    Log {
+        transaction: Transaction,
+        log: Log,
+        params: Vec<RecordedParam>,
+    },
+    Call {
+        transaction: Transaction,
+        from: Address,
+        to: Address,
+        inputs: Vec<RecordedParam>,
+        outputs: Vec<RecordedParam>,
+    },
+    Block,
+}
+
+impl From<&MappingTrigger> for RecordedTrigger {
+    fn from(trigger: &MappingTrigger) -> Self {
+        match trigger {
+            MappingTrigger::Log {
+                transaction,
+                log,
+                params,
+                ..
+            } => RecordedTrigger::Log {
+                transaction: transaction.as_ref().clone(),
+                log: log.as_ref().clone(),
+                params: record_params(params),
+            },
+            MappingTrigger::Call {
+                transaction,
+                call,
+                inputs,
+                outputs,
+                ..
+            } => RecordedTrigger::Call {
+                transaction: transaction.as_ref().clone(),
+                from: call.from,
+                to: call.to,
+                inputs: record_params(inputs),
+                outputs: record_params(outputs),
+            },
+            MappingTrigger::Block { .. } => RecordedTrigger::Block,
+        }
+    }
+}
+
+/// A call of a host function that depends on the outside world, together
+/// with its result
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "function", rename_all = "camelCase")]
+pub enum HostCall {
+    #[serde(rename = "store.get")]
+    StoreGet {
+        entity_type: String,
+        id: String,
+        result: Option<Entity>,
+    },
+    #[serde(rename = "ethereum.call")]
+    EthereumCall {
+        contract: String,
+        address: Address,
+        function: String,
+        args: Vec<RecordedToken>,
+        /// `None` if the call reverted
+        result: Option<Vec<RecordedToken>>,
+    },
+    #[serde(rename = "ipfs.cat")]
+    IpfsCat {
+        link: String,
+        /// `None` if the file could not be read
+        result: Option<Bytes>,
+    },
+    /// `ipfs.map` is recorded so the recording shows that it was called,
+    /// but it can not be replayed
+    #[serde(rename = "ipfs.map")]
+    IpfsMap { link: String, callback: String },
+    #[serde(rename = "ens.nameByHash")]
+    EnsNameByHash {
+        hash: String,
+        result: Option<String>,
+    },
+    #[serde(rename = "arweave.transactionData")]
+    ArweaveTransactionData {
+        tx_id: String,
+        result: Option<Bytes>,
+    },
+    #[serde(rename = "box.profile")]
+    BoxProfile {
+        address: String,
+        result: Option<serde_json::Map<String, serde_json::Value>>,
+    },
+    /// Data sources that the handler creates are only recorded; they are
+    /// not instantiated during a replay
+    #[serde(rename = "dataSource.create")]
+    DataSourceCreate {
+        name: String,
+        params: Vec<String>,
+        context: Option<DataSourceContext>,
+    },
+}
+
+impl HostCall {
+    pub(crate) fn function(&self) -> &'static str {
+        match self {
+            HostCall::StoreGet { .. } => "store.get",
+            HostCall::EthereumCall { .. } => "ethereum.call",
+            HostCall::IpfsCat { .. } => "ipfs.cat",
+            HostCall::IpfsMap { .. } => "ipfs.map",
+            HostCall::EnsNameByHash { .. } => "ens.nameByHash",
+            HostCall::ArweaveTransactionData { .. } => "arweave.transactionData",
+            HostCall::BoxProfile { .. } => "box.profile",
+            HostCall::DataSourceCreate { .. } => "dataSource.create",
+        }
+    }
+}
+
+/// What to do with the calls of host functions that depend on the outside
+/// world while a handler runs
+#[derive(Clone, Debug)]
+pub(crate) enum HostCalls {
+    /// Call the host functions
+    Live,
+    /// Call the host functions and record their results
+    Record(Arc<Mutex<Vec<HostCall>>>),
+    /// Do not call the host functions, and take their results from a
+    /// recording instead
+    Replay(Arc<Mutex<VecDeque<HostCall>>>),
+}
+
+impl HostCalls {
+    pub(crate) fn is_replay(&self) -> bool {
+        match self {
+            HostCalls::Replay(_) => true,
+            HostCalls::Live | HostCalls::Record(_) => false,
+        }
+    }
+
+    pub(crate) fn record(&self, call: impl FnOnce() -> HostCall) {
+        if let HostCalls::Record(calls) = self {
+            calls.lock().unwrap().push(call());
+        }
+    }
+
+    /// Take the next call from the recording. `replay` returns the recorded
+    /// call as an error if it does not match the call of `function` that
+    /// the handler made, which means that the replay diverged from the
+    /// original invocation
+    pub(crate) fn replay<T>(
+        &self,
+        function: &str,
+        replay: impl FnOnce(HostCall) -> Result<T, HostCall>,
+    ) -> Result<T, anyhow::Error> {
+        let calls = match self {
+            HostCalls::Replay(calls) => calls,
+            HostCalls::Live | HostCalls::Record(_) => {
+                unreachable!("host calls can only be replayed from a recording")
+            }
+        };
+        let call = calls.lock().unwrap().pop_front().ok_or_else(|| {
+            anyhow!(
+                "the handler called `{}`, but the recording has no more host calls",
+                function
+            )
+        })?;
+        replay(call).map_err(|call| {
+            anyhow!(
+                "the handler called `{}`, but the recording has a different call to `{}`: {:?}",
+                function,
+                call.function(),
+                call
+            )
+        })
+    }
+
+    pub(crate) fn take(&self) -> Vec<HostCall> {
+        match self {
+            HostCalls::Live => vec![],
+            HostCalls::Record(calls) => std::mem::take(&mut *calls.lock().unwrap()),
+            HostCalls::Replay(calls) => calls.lock().unwrap().drain(..).collect(),
+        }
+    }
+}
+
+/// Everything that is needed to run a handler again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HandlerRecording {
+    pub subgraph_id: String,
+    pub data_source: String,
+    pub data_source_address: Option<Address>,
+    pub data_source_network: String,
+    pub data_source_context: Option<DataSourceContext>,
+    pub api_version: String,
+    /// The file that contains the WASM module, relative to the directory
+    /// of the recording
+    pub module: String,
+    pub handler: String,
+    pub block: LightEthereumBlock,
+    pub trigger: RecordedTrigger,
+    pub host_calls: Vec<HostCall>,
+    /// The error with which the handler failed
+    pub error: Option<String>,
+}
+
+impl HandlerRecording {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = fs::File::open(path)
+            .with_context(|| format!("failed to open recording {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to read recording {}", path.display()))
+    }
+
+    /// Read the WASM module of a recording that was loaded from `path`
+    pub fn load_module(&self, path: &Path) -> Result<Vec<u8>, anyhow::Error> {
+        let path = path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(&self.module);
+        fs::read(&path).with_context(|| format!("failed to read WASM module {}", path.display()))
+    }
+}
+
+/// Records the handler invocations of one WASM module
+pub(crate) struct Recorder {
+    config: RecordConfig,
+    dir: PathBuf,
+    module: String,
+}
+
+impl Recorder {
+    /// Set up recording for `raw_module` and write the module into the
+    /// directory for `subgraph_id`
+    pub(crate) fn new(
+        config: RecordConfig,
+        subgraph_id: &SubgraphDeploymentId,
+        raw_module: &[u8],
+    ) -> Result<Self, anyhow::Error> {
+        let dir = config.dir.join(subgraph_id.as_str());
+        fs::create_dir_all(&dir)?;
+
+        let module = format!(
+            "{}.wasm",
+            hex::encode(&tiny_keccak::keccak256(raw_module)[..8])
+        );
+        let module_path = dir.join(&module);
+        if !module_path.exists() {
+            fs::write(&module_path, raw_module)?;
+        }
+
+        Ok(Recorder {
+            config,
+            dir,
+            module,
+        })
+    }
+
+    /// Prepare `ctx` so that the host calls the handler makes get recorded
+    pub(crate) fn start(
+        &self,
+        ctx: &mut MappingContext,
+        handler: &str,
+        trigger: &MappingTrigger,
+    ) -> HandlerRecording {
+        ctx.host_calls = HostCalls::Record(Arc::new(Mutex::new(Vec::new())));
+
+        let host_exports = &ctx.host_exports;
+        HandlerRecording {
+            subgraph_id: host_exports.subgraph_id.to_string(),
+            data_source: host_exports.data_source_name.clone(),
+            data_source_address: host_exports.data_source_address,
+            data_source_network: host_exports.data_source_network(),
+            data_source_context: host_exports.data_source_context.clone(),
+            api_version: host_exports.api_version.to_string(),
+            module: self.module.clone(),
+            handler: handler.to_owned(),
+            block: ctx.block.as_ref().clone(),
+            trigger: RecordedTrigger::from(trigger),
+            host_calls: vec![],
+            error: None,
+        }
+    }
+
+    /// Write the recording if the handler failed, or if all invocations
+    /// should be recorded
+    pub(crate) fn finish(
+        &self,
+        logger: &Logger,
+        mut recording: HandlerRecording,
+        host_calls: &HostCalls,
+        error: Option<String>,
+    ) {
+        recording.host_calls = host_calls.take();
+        if error.is_none() && !self.config.all {
+            return;
+        }
+        recording.error = error;
+
+        let path = self.dir.join(format!(
+            "{}-{}-{}.json",
+            recording.block.number.unwrap_or_default(),
+            recording.handler,
+            uuid::Uuid::new_v4().to_simple()
+        ));
+        let result = serde_json::to_vec_pretty(&recording)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| fs::write(&path, contents).map_err(anyhow::Error::from));
+        match result {
+            Ok(()) => info!(logger, "Recorded handler invocation";
+                            "handler" => &recording.handler,
+                            "path" => path.display().to_string()),
+            Err(e) => warn!(logger, "Failed to record handler invocation";
+                            "handler" => &recording.handler,
+                            "error" => e.to_string()),
+        }
+    }
+}
+
+/// Stands in for the services that a replay must not use; all host calls
+/// that would use them are answered from the recording
+struct Unavailable;
+
+const UNAVAILABLE: &str = "external services are not available during a replay";
+
+#[async_trait]
+impl LinkResolver for Unavailable {
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, _logger: &Logger, _link: &Link) -> Result<Vec<u8>, anyhow::Error> {
+        Err(anyhow!(UNAVAILABLE))
+    }
+
+    async fn json_stream(
+        &self,
+        _logger: &Logger,
+        _link: &Link,
+    ) -> Result<JsonValueStream, anyhow::Error> {
+        Err(anyhow!(UNAVAILABLE))
+    }
+}
+
+impl EthereumCallCache for Unavailable {
+    fn get_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        Err(anyhow!(UNAVAILABLE))
+    }
+
+    fn set_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+        _return_value: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        Err(anyhow!(UNAVAILABLE))
+    }
+}
+
+#[async_trait]
+impl ArweaveAdapter for Unavailable {
+    async fn tx_data(&self, _tx_id: &str) -> Result<bytes::Bytes, anyhow::Error> {
+        Err(anyhow!(UNAVAILABLE))
+    }
+}
+
+#[async_trait]
+impl ThreeBoxAdapter for Unavailable {
+    async fn profile(
+        &self,
+        _address: &str,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, anyhow::Error> {
+        Err(anyhow!(UNAVAILABLE))
+    }
+}
+
+/// Run the handler of `recording` again. The `store` is only used to look
+/// up the schema of the subgraph; all entities that the handler loads come
+/// from the recording. Returns the error of the handler if it failed
+/// deterministically, and an `Err` if it failed in any other way. Messages
+/// that the handler logs go to `logger`
+pub fn replay<S: Store>(
+    logger: &Logger,
+    registry: Arc<impl MetricsRegistry>,
+    store: Arc<S>,
+    recording: HandlerRecording,
+    raw_module: &[u8],
+) -> Result<Option<SubgraphError>, anyhow::Error> {
+    let logger = logger.new(o!(
+        "data_source" => recording.data_source.clone(),
+        "handler" => recording.handler.clone()
+    ));
+    let (module, host_calls) = instantiate(&logger, registry, store, &recording, raw_module)?;
+
+    let handler = recording.handler.as_str();
+    let result = match recording.trigger {
+        RecordedTrigger::Log {
+            transaction,
+            log,
+            params,
+        } => module.handle_ethereum_log(
+            handler,
+            Arc::new(transaction),
+            Arc::new(log),
+            replay_params(params),
+        ),
+        RecordedTrigger::Call {
+            transaction,
+            from,
+            to,
+            inputs,
+            outputs,
+        } => {
+            let call = EthereumCall {
+                from,
+                to,
+                ..Default::default()
+            };
+            module.handle_ethereum_call(
+                handler,
+                Arc::new(transaction),
+                Arc::new(call),
+                replay_params(inputs),
+                replay_params(outputs),
+            )
+        }
+        RecordedTrigger::Block => module.handle_ethereum_block(handler),
+    };
+
+    let unused = host_calls.take();
+    if !unused.is_empty() {
+        warn!(logger, "The handler did not make all recorded host calls";
+                      "unused" => unused.len(),
+                      "next" => unused[0].function());
+    }
+
+    match result {
+        Ok(state) => Ok(state.deterministic_errors.into_iter().next()),
+        Err(MappingError::PossibleReorg(e)) | Err(MappingError::Unknown(e)) => Err(e),
+    }
+}
+
+/// Instantiate the WASM module of `recording` so that the host functions
+/// that depend on the outside world are answered from the recording
+pub(crate) fn instantiate<S: Store>(
+    logger: &Logger,
+    registry: Arc<impl MetricsRegistry>,
+    store: Arc<S>,
+    recording: &HandlerRecording,
+    raw_module: &[u8],
+) -> Result<(WasmInstance, HostCalls), anyhow::Error> {
+    let subgraph_id = SubgraphDeploymentId::new(recording.subgraph_id.clone())
+        .map_err(|id| anyhow!("invalid subgraph id `{}` in recording", id))?;
+
+    let stopwatch = StopwatchMetrics::new(logger.clone(), subgraph_id.clone(), registry.clone());
+    let host_metrics = Arc::new(HostMetrics::new(registry, subgraph_id.as_str(), stopwatch));
+
+    // The Ethereum adapter is never called since the results of all
+    // `ethereum.call`s come from the recording
+    let host_exports = HostExports::new(
+        subgraph_id,
+        Version::parse(&recording.api_version)?,
+        recording.data_source.clone(),
+        recording.data_source_address,
+        recording.data_source_network.clone(),
+        recording.data_source_context.clone(),
+        Arc::new(vec![]),
+        None,
+        vec![],
+        Arc::new(MockEthereumAdapter::new()),
+        Arc::new(Unavailable),
+        store.clone(),
+        Arc::new(Unavailable),
+        Arc::new(Unavailable),
+        Arc::new(Unavailable),
+    );
+    let host_calls = HostCalls::Replay(Arc::new(Mutex::new(
+        recording.host_calls.iter().cloned().collect(),
+    )));
+    let ctx = MappingContext {
+        logger: logger.clone(),
+        host_exports: Arc::new(host_exports),
+        block: Arc::new(recording.block.clone()),
+        state: BlockState::new(store, Default::default()),
+        proof_of_indexing: None,
+        host_calls: host_calls.clone(),
+    };
+
+    let valid_module = Arc::new(ValidModule::new(raw_module)?);
+    let module =
+        WasmInstance::from_valid_module_with_ctx(valid_module, ctx, host_metrics, None, true)?;
+    Ok((module, host_calls))
+}
+
+/// Convert the recorded result of an `ethereum.call` back into tokens
+pub(crate) fn replay_ethereum_call(
+    host_calls: &HostCalls,
+    call: &UnresolvedContractCall,
+) -> Result<Option<Vec<Token>>, anyhow::Error> {
+    let args = tokens_of(&call.function_args);
+    host_calls.replay("ethereum.call", |recorded| match recorded {
+        HostCall::EthereumCall {
+            contract,
+            address,
+            function,
+            args: recorded_args,
+            result,
+        } if contract == call.contract_name
+            && address == call.contract_address
+            && function == call.function_name
+            && recorded_args == args =>
+        {
+            Ok(result.map(|tokens| tokens.into_iter().map(Token::from).collect()))
+        }
+        recorded => Err(recorded),
+    })
+}
+
+/// Record the result of an `ethereum.call`
+pub(crate) fn record_ethereum_call(
+    host_calls: &HostCalls,
+    call: &UnresolvedContractCall,
+    result: &Option<Vec<Token>>,
+) {
+    host_calls.record(|| HostCall::EthereumCall {
+        contract: call.contract_name.clone(),
+        address: call.contract_address,
+        function: call.function_name.clone(),
+        args: tokens_of(&call.function_args),
+        result: result.as_ref().map(|tokens| tokens_of(tokens)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_roundtrip() {
+        let token = Token::Tuple(vec![
+            Token::Address(Address::from_low_u64_be(7)),
+            Token::FixedBytes(vec![1, 2, 3]),
+            Token::Int(U256::from(42)),
+            Token::Array(vec![Token::Bool(true), Token::Bool(false)]),
+            Token::String("graph".to_owned()),
+        ]);
+
+        let json = serde_json::to_string(&RecordedToken::from(&token)).unwrap();
+        let recorded: RecordedToken = serde_json::from_str(&json).unwrap();
+        assert_eq!(token, Token::from(recorded));
+    }
+}
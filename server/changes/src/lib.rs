@@ -0,0 +1,5 @@
+mod server;
+mod service;
+
+pub use self::server::EntityChangesServer;
+pub use self::service::EntityChangesService;
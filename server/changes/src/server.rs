@@ -0,0 +1,89 @@
+use hyper::service::make_service_fn;
+use hyper::Server;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use graph::prelude::{EntityChangesServer as EntityChangesServerTrait, *};
+
+use crate::service::EntityChangesService;
+use thiserror::Error;
+
+/// Errors that may occur when starting the server.
+#[derive(Debug, Error)]
+pub enum EntityChangesServeError {
+    #[error("Bind error: {0}")]
+    BindError(hyper::Error),
+}
+
+impl From<hyper::Error> for EntityChangesServeError {
+    fn from(err: hyper::Error) -> Self {
+        EntityChangesServeError::BindError(err)
+    }
+}
+
+/// A server that streams the entity changes of deployments as
+/// newline-delimited JSON.
+pub struct EntityChangesServer<S> {
+    logger: Logger,
+    store: Arc<S>,
+    subscription_manager: Arc<dyn SubscriptionManager>,
+}
+
+impl<S> EntityChangesServer<S> {
+    /// Creates a new entity change streaming server.
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        store: Arc<S>,
+        subscription_manager: Arc<dyn SubscriptionManager>,
+    ) -> Self {
+        let logger = logger_factory.component_logger(
+            "EntityChangesServer",
+            Some(ComponentLoggerConfig {
+                elastic: Some(ElasticComponentLoggerConfig {
+                    index: String::from("entity-changes-server-logs"),
+                }),
+            }),
+        );
+
+        EntityChangesServer {
+            logger,
+            store,
+            subscription_manager,
+        }
+    }
+}
+
+impl<S> EntityChangesServerTrait for EntityChangesServer<S>
+where
+    S: Store,
+{
+    type ServeError = EntityChangesServeError;
+
+    fn serve(
+        &mut self,
+        port: u16,
+    ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
+        let logger = self.logger.clone();
+
+        info!(
+            logger,
+            "Starting entity changes server at: http://localhost:{}", port
+        );
+
+        let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+
+        let service = EntityChangesService::new(
+            self.logger.clone(),
+            self.store.clone(),
+            self.subscription_manager.clone(),
+        );
+        let new_service =
+            make_service_fn(move |_| futures03::future::ok::<_, Error>(service.clone()));
+
+        // Create a task to run the server and handle HTTP requests
+        let task = Server::try_bind(&addr.into())?
+            .serve(new_service)
+            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+
+        Ok(Box::new(task.compat()))
+    }
+}
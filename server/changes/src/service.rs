@@ -0,0 +1,333 @@
+use hyper::body::{Bytes, Sender};
+use hyper::service::Service;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use graph::components::server::query::GraphQLServerError;
+use graph::data::subgraph::schema::MetadataType;
+use graph::prelude::*;
+
+/// The maximum number of entries we read from the change log at once
+const MAX_ENTRIES: usize = 100;
+
+/// How long to wait for the deployment to advance before checking the
+/// change log for new entries even if we did not receive a notification
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// An asynchronous response to a request for entity changes.
+pub type EntityChangesServiceResponse = DynTryFuture<'static, Response<Body>, GraphQLServerError>;
+
+/// A Hyper Service that streams the entity change log of a deployment over
+/// a `GET /changes/<deployment>?cursor=<cursor>` endpoint.
+///
+/// The response is newline-delimited JSON. Each line is either
+/// `{"type":"changes","cursor":..,"block":{..},"changes":[..]}` with all
+/// entity changes that processing a block made, or
+/// `{"type":"revert","cursor":..,"block":{..}}` which means that all
+/// changes for blocks after the given block must be discarded. Blocks that
+/// did not change any entities are skipped.
+///
+/// The stream starts with the entry after `cursor`, or with the first
+/// entry if no `cursor` is given. A client that has processed the entry
+/// with cursor `n` resumes the stream with `cursor=n`; since reverts are
+/// part of the log, it will also see reverts that happened while it was
+/// not connected. The log is only written if graph-node runs with
+/// `GRAPH_ENTITY_CHANGE_LOG` set.
+pub struct EntityChangesService<S> {
+    logger: Logger,
+    store: Arc<S>,
+    subscription_manager: Arc<dyn SubscriptionManager>,
+}
+
+impl<S> Clone for EntityChangesService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            logger: self.logger.clone(),
+            store: self.store.clone(),
+            subscription_manager: self.subscription_manager.clone(),
+        }
+    }
+}
+
+impl<S> CheapClone for EntityChangesService<S> {}
+
+impl<S> EntityChangesService<S>
+where
+    S: Store,
+{
+    /// Creates a new entity changes service.
+    pub fn new(
+        logger: Logger,
+        store: Arc<S>,
+        subscription_manager: Arc<dyn SubscriptionManager>,
+    ) -> Self {
+        EntityChangesService {
+            logger,
+            store,
+            subscription_manager,
+        }
+    }
+
+    fn index() -> Response<Body> {
+        Response::builder()
+            .status(200)
+            .body(Body::from("OK"))
+            .unwrap()
+    }
+
+    /// Handles 404s.
+    fn handle_not_found() -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body(Body::from("Not found\n"))
+            .unwrap()
+    }
+
+    /// Extract the cursor after which to stream from the `cursor` parameter
+    /// of the query string; without it, we stream the entire log
+    fn cursor(query: Option<&str>) -> Result<i64, GraphQLServerError> {
+        let cursor = query
+            .unwrap_or("")
+            .split('&')
+            .filter_map(|pair| {
+                let mut kv = pair.splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some("cursor"), Some(value)) => Some(value),
+                    _ => None,
+                }
+            })
+            .last();
+        match cursor {
+            None => Ok(0),
+            Some(cursor) => cursor
+                .parse::<i64>()
+                .ok()
+                .filter(|cursor| *cursor >= 0)
+                .ok_or_else(|| {
+                    GraphQLServerError::ClientError(format!(
+                        "`cursor` must be a non-negative number but is `{}`",
+                        cursor
+                    ))
+                }),
+        }
+    }
+
+    async fn read_entries(
+        store: Arc<S>,
+        id: SubgraphDeploymentId,
+        after: i64,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        graph::spawn_blocking_allow_panic(move || store.entity_change_log(&id, after, MAX_ENTRIES))
+            .await
+            .map_err(|e| {
+                StoreError::Unknown(anyhow!("reading the entity change log failed: {}", e))
+            })?
+    }
+
+    async fn handle_changes(
+        &self,
+        id: &str,
+        query: Option<&str>,
+    ) -> Result<Response<Body>, GraphQLServerError> {
+        let id = SubgraphDeploymentId::new(id).map_err(|id| {
+            GraphQLServerError::ClientError(format!("invalid deployment id `{}`", id))
+        })?;
+        let after = Self::cursor(query)?;
+
+        // Read the first batch of entries before we start the response so
+        // that we can report problems like an unknown deployment or a
+        // pruned log with a proper status code
+        let entries = match Self::read_entries(self.store.clone(), id.clone(), after).await {
+            Ok(entries) => entries,
+            Err(StoreError::DeploymentNotFound(_)) => return Ok(Self::handle_not_found()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let (sender, body) = Body::channel();
+        let logger = self.logger.new(o!("deployment" => id.to_string()));
+        graph::spawn_allow_panic(
+            self.cheap_clone()
+                .stream(logger, id, after, entries, sender),
+        );
+
+        Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .unwrap())
+    }
+
+    /// Send entries to the client until the client disconnects or we
+    /// encounter an error. `after` is the cursor of the last entry the
+    /// client has processed, and `entries` the first batch of entries
+    /// after that
+    async fn stream(
+        self,
+        logger: Logger,
+        id: SubgraphDeploymentId,
+        mut after: i64,
+        mut entries: Vec<ChangeLogEntry>,
+        mut sender: Sender,
+    ) {
+        // Every block pointer change for the deployment causes a store
+        // event for its `SubgraphDeployment` metadata entity
+        let mut events = self
+            .subscription_manager
+            .subscribe(vec![SubscriptionFilter::Entities(
+                id.clone(),
+                MetadataType::SubgraphDeployment.into(),
+            )])
+            .compat();
+
+        loop {
+            let caught_up = entries.len() < MAX_ENTRIES;
+            for entry in entries {
+                after = entry.cursor;
+                if sender
+                    .send_data(Bytes::from(Self::line(entry)))
+                    .await
+                    .is_err()
+                {
+                    debug!(logger, "Client disconnected from entity change stream");
+                    return;
+                }
+            }
+
+            // When we have sent everything in the log, wait for the
+            // deployment to make progress
+            if caught_up {
+                match tokio::time::timeout(POLL_INTERVAL, events.next()).await {
+                    Ok(None) => tokio::time::delay_for(POLL_INTERVAL).await,
+                    Ok(Some(_)) | Err(_) => { /* check for new entries */ }
+                }
+            }
+
+            entries = match Self::read_entries(self.store.clone(), id.clone(), after).await {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!(logger, "Failed to read the entity change log"; "error" => e.to_string());
+                    let line = serde_json::json!({ "type": "error", "message": e.to_string() });
+                    sender
+                        .send_data(Bytes::from(format!("{}\n", line)))
+                        .await
+                        .ok();
+                    return;
+                }
+            };
+        }
+    }
+
+    fn line(entry: ChangeLogEntry) -> String {
+        use EntityModification::*;
+
+        fn change(op: &str, key: EntityKey, data: Option<Entity>) -> serde_json::Value {
+            let mut change = serde_json::json!({
+                "op": op,
+                "entity": key.entity_type.to_string(),
+                "id": key.entity_id,
+            });
+            if let Some(data) = data {
+                change["data"] = serde_json::json!(SerializableValue(&q::Value::from(data)));
+            }
+            change
+        }
+
+        let block = serde_json::json!({
+            "number": entry.block.number,
+            "hash": format!("{:x}", entry.block.hash),
+        });
+        let line = match entry.operation {
+            ChangeLogOperation::Changes(mods) => {
+                let changes: Vec<_> = mods
+                    .into_iter()
+                    .map(|modification| match modification {
+                        Insert { key, data } => change("insert", key, Some(data)),
+                        Overwrite { key, data } => change("overwrite", key, Some(data)),
+                        Remove { key } => change("remove", key, None),
+                    })
+                    .collect();
+                serde_json::json!({
+                    "type": "changes",
+                    "cursor": entry.cursor,
+                    "block": block,
+                    "changes": changes,
+                })
+            }
+            ChangeLogOperation::Revert => serde_json::json!({
+                "type": "revert",
+                "cursor": entry.cursor,
+                "block": block,
+            }),
+        };
+        format!("{}\n", line)
+    }
+
+    async fn handle_call(self, req: Request<Body>) -> Result<Response<Body>, GraphQLServerError> {
+        let method = req.method().clone();
+
+        let path = req.uri().path().to_owned();
+        let path_segments = {
+            let mut segments = path.split('/');
+
+            // Remove leading '/'
+            assert_eq!(segments.next(), Some(""));
+
+            segments.collect::<Vec<_>>()
+        };
+
+        match (method, path_segments.as_slice()) {
+            (Method::GET, [""]) => Ok(Self::index()),
+            (Method::GET, ["changes", id]) => self.handle_changes(id, req.uri().query()).await,
+            _ => Ok(Self::handle_not_found()),
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for EntityChangesService<S>
+where
+    S: Store,
+{
+    type Response = Response<Body>;
+    type Error = GraphQLServerError;
+    type Future = EntityChangesServiceResponse;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let logger = self.logger.clone();
+
+        // Returning Err here will prevent the client from receiving any response.
+        // Instead, we generate a Response with an error code and return Ok
+        Box::pin(
+            self.cheap_clone()
+                .handle_call(req)
+                .map(move |result| match result {
+                    Ok(response) => Ok(response),
+                    Err(err @ GraphQLServerError::ClientError(_))
+                    | Err(err @ GraphQLServerError::QueryError(_)) => {
+                        debug!(logger, "EntityChangesService call failed: {}", err);
+
+                        Ok(Response::builder()
+                            .status(400)
+                            .header("Content-Type", "text/plain")
+                            .body(Body::from(format!("Invalid request: {}", err)))
+                            .unwrap())
+                    }
+                    Err(err @ GraphQLServerError::InternalError(_)) => {
+                        error!(logger, "EntityChangesService call failed: {}", err);
+
+                        Ok(Response::builder()
+                            .status(500)
+                            .header("Content-Type", "text/plain")
+                            .body(Body::from(format!("Internal server error: {}", err)))
+                            .unwrap())
+                    }
+                }),
+        )
+    }
+}
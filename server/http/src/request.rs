@@ -1,9 +1,30 @@
+use std::sync::Mutex;
+
 use graph::prelude::serde_json;
 use graphql_parser;
 use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
 
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
+use graph::url::form_urlencoded;
+use graph::util::lfu_cache::LfuCache;
+
+lazy_static! {
+    /// The texts of the queries that clients registered with Automatic
+    /// Persisted Queries, keyed by the hex-encoded SHA-256 hash of the text
+    static ref PERSISTED_QUERIES: Mutex<LfuCache<String, String>> = Mutex::new(LfuCache::new());
+
+    static ref PERSISTED_QUERY_CACHE_MAX_MEM: usize = {
+        1_000_000 *
+        std::env::var("GRAPH_PERSISTED_QUERY_CACHE_MAX_MEM")
+        .unwrap_or("10".to_string())
+        .parse::<usize>()
+        .expect("Invalid value for GRAPH_PERSISTED_QUERY_CACHE_MAX_MEM environment variable")
+    };
+}
+
+type JsonObject = serde_json::Map<String, serde_json::Value>;
 
 /// Future for a query parsed from an HTTP request.
 pub struct GraphQLRequest {
@@ -15,6 +36,72 @@ impl GraphQLRequest {
     pub fn new(body: Bytes) -> Self {
         GraphQLRequest { body }
     }
+
+    /// Creates a new GraphQLRequest from the query string of a GET request.
+    /// The query string has the same fields as the body of a POST request,
+    /// with `variables` and `extensions` encoded as JSON
+    pub fn from_url_query(query: &str) -> Result<Self, GraphQLServerError> {
+        let mut obj = JsonObject::new();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            let value = match key.as_ref() {
+                "query" | "operationName" => serde_json::Value::String(value.into_owned()),
+                "variables" | "extensions" => serde_json::from_str(&value).map_err(|e| {
+                    GraphQLServerError::ClientError(format!(
+                        "The \"{}\" parameter is not valid JSON: {}",
+                        key, e
+                    ))
+                })?,
+                _ => continue,
+            };
+            obj.insert(key.into_owned(), value);
+        }
+        // Turn the parameters into the body of the equivalent POST request
+        // so that both kinds of request are processed the same way
+        let body = serde_json::Value::Object(obj).to_string();
+        Ok(GraphQLRequest::new(Bytes::from(body)))
+    }
+}
+
+/// Get the `query` field of the request
+fn query_text(obj: &JsonObject) -> Result<&str, GraphQLServerError> {
+    // Ensure the JSON data has a "query" field
+    let query_value = obj.get("query").ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from(
+            "The \"query\" field is missing in request data",
+        ))
+    })?;
+
+    // Ensure the "query" field is a string
+    query_value.as_str().ok_or_else(|| {
+        GraphQLServerError::ClientError(String::from("The \"query\" field is not a string"))
+    })
+}
+
+/// Get the hash from `extensions.persistedQuery` if the request uses
+/// Automatic Persisted Queries
+fn persisted_query_hash(obj: &JsonObject) -> Result<Option<String>, GraphQLServerError> {
+    let persisted_query = match obj
+        .get("extensions")
+        .and_then(|extensions| extensions.get("persistedQuery"))
+    {
+        None => return Ok(None),
+        Some(persisted_query) => persisted_query,
+    };
+
+    if persisted_query.get("version").and_then(|v| v.as_u64()) != Some(1) {
+        return Err(GraphQLServerError::ClientError(String::from(
+            "Unsupported persisted query version",
+        )));
+    }
+    persisted_query
+        .get("sha256Hash")
+        .and_then(|hash| hash.as_str())
+        .map(|hash| Some(hash.to_lowercase()))
+        .ok_or_else(|| {
+            GraphQLServerError::ClientError(String::from(
+                "The \"sha256Hash\" of the persisted query is missing or not a string",
+            ))
+        })
 }
 
 impl Future for GraphQLRequest {
@@ -31,23 +118,46 @@ impl Future for GraphQLRequest {
             GraphQLServerError::ClientError(String::from("Request data is not an object"))
         })?;
 
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field is missing in request data",
-            ))
-        })?;
-
-        // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The \"query\" field is not a string"))
-        })?;
+        // With Automatic Persisted Queries, clients send the hash of the
+        // query and leave out its text once they expect us to know it. A
+        // query that comes with its text and hash is registered under the
+        // hash once it parses
+        let mut register_as = None;
+        let query_string = match (persisted_query_hash(obj)?, obj.get("query")) {
+            (Some(hash), None) | (Some(hash), Some(serde_json::Value::Null)) => PERSISTED_QUERIES
+                .lock()
+                .unwrap()
+                .get(&hash)
+                .cloned()
+                .ok_or_else(|| {
+                    GraphQLServerError::from(QueryError::from(
+                        QueryExecutionError::PersistedQueryNotFound,
+                    ))
+                })?,
+            (Some(hash), Some(_)) => {
+                let query_string = query_text(obj)?;
+                if format!("{:x}", Sha256::digest(query_string.as_bytes())) != hash {
+                    return Err(GraphQLServerError::ClientError(String::from(
+                        "The \"sha256Hash\" of the persisted query does not match the query",
+                    )));
+                }
+                register_as = Some(hash);
+                query_string.to_owned()
+            }
+            (None, _) => query_text(obj)?.to_owned(),
+        };
 
         // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string)
+        let document = graphql_parser::parse_query(&query_string)
             .map_err(|e| GraphQLServerError::from(QueryError::ParseError(Arc::new(e.into()))))?
             .into_static();
 
+        if let Some(hash) = register_as {
+            let mut persisted_queries = PERSISTED_QUERIES.lock().unwrap();
+            persisted_queries.insert(hash, query_string);
+            persisted_queries.evict(*PERSISTED_QUERY_CACHE_MAX_MEM);
+        }
+
         // Parse the "variables" field of the JSON body, if present
         let variables = match obj.get("variables") {
             None | Some(serde_json::Value::Null) => Ok(None),
@@ -69,8 +179,10 @@ impl Future for GraphQLRequest {
 mod tests {
     use graphql_parser;
     use hyper;
+    use sha2::{Digest, Sha256};
     use std::collections::{BTreeMap, HashMap};
 
+    use graph::components::server::query::GraphQLServerError;
     use graph::{data::query::QueryTarget, prelude::*};
 
     use super::GraphQLRequest;
@@ -185,4 +297,76 @@ mod tests {
         assert_eq!(query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
+
+    #[test]
+    fn parses_url_queries() {
+        let request = GraphQLRequest::from_url_query(
+            "query=%7B%20user(id%3A%20%24id)%20%7B%20name%20%7D%20%7D\
+             &variables=%7B%22id%22%3A%22u1%22%7D&ignored=1",
+        )
+        .expect("Should accept a valid query string");
+        let query = request.wait().expect("Should accept valid queries");
+
+        let expected_query = graphql_parser::parse_query("{ user(id: $id) { name } }")
+            .unwrap()
+            .into_static();
+        let expected_variables = QueryVariables::new(HashMap::from_iter(
+            vec![(String::from("id"), q::Value::String(String::from("u1")))].into_iter(),
+        ));
+        assert_eq!(query.document, expected_query);
+        assert_eq!(query.variables, Some(expected_variables));
+
+        GraphQLRequest::from_url_query("query=%7B%20user%20%7B%20name%20%7D%20%7D&variables=5x")
+            .err()
+            .expect("Should reject variables that are not JSON");
+        GraphQLRequest::from_url_query("variables=%7B%7D")
+            .unwrap()
+            .wait()
+            .expect_err("Should reject a query string without a query");
+    }
+
+    /// The body of a request that uses Automatic Persisted Queries
+    fn persisted_query_body(query: Option<&str>, hash: &str) -> hyper::body::Bytes {
+        let mut body = serde_json::json!({
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+        });
+        if let Some(query) = query {
+            body["query"] = serde_json::Value::from(query);
+        }
+        hyper::body::Bytes::from(body.to_string())
+    }
+
+    #[test]
+    fn resolves_persisted_queries() {
+        let text = "{ persisted { name } }";
+        let hash = &format!("{:x}", Sha256::digest(text.as_bytes()));
+
+        // The query has not been registered yet
+        match GraphQLRequest::new(persisted_query_body(None, hash)).wait() {
+            Err(GraphQLServerError::QueryError(QueryError::ExecutionError(
+                QueryExecutionError::PersistedQueryNotFound,
+            ))) => (),
+            _ => panic!("Should not know the persisted query"),
+        }
+
+        // Register the query and then use it without its text
+        GraphQLRequest::new(persisted_query_body(Some(text), hash))
+            .wait()
+            .expect("Should accept a query with its hash");
+        let query = GraphQLRequest::new(persisted_query_body(None, hash))
+            .wait()
+            .expect("Should know the persisted query");
+        assert_eq!(
+            query.document,
+            graphql_parser::parse_query(text).unwrap().into_static()
+        );
+    }
+
+    #[test]
+    fn rejects_persisted_queries_with_wrong_hash() {
+        let hash = "0000000000000000000000000000000000000000000000000000000000000000";
+        GraphQLRequest::new(persisted_query_body(Some("{ user { name } }"), hash))
+            .wait()
+            .expect_err("Should reject a query that does not match its hash");
+    }
 }
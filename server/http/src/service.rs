@@ -5,8 +5,12 @@ use std::task::Context;
 use std::task::Poll;
 use std::time::Instant;
 
+use graph::data::graphql::quota::{QuotaError, API_KEY_HEADER};
 use graph::prelude::*;
-use graph::{components::server::query::GraphQLServerError, data::query::QueryTarget};
+use graph::{
+    components::server::query::GraphQLServerError,
+    data::query::{trace_requested, QueryTarget, TRACE_HEADER},
+};
 use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
@@ -161,7 +165,7 @@ where
             GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
         })?;
 
-        self.handle_graphql_query(subgraph_name.into(), request.into_body())
+        self.handle_graphql_query(subgraph_name.into(), request)
             .await
     }
 
@@ -174,28 +178,66 @@ where
             .map_err(|id| GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id)));
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(id) => self
-                .handle_graphql_query(id.into(), request.into_body())
-                .boxed(),
+            Ok(id) => self.handle_graphql_query(id.into(), request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         target: QueryTarget,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
         let service = self.clone();
         let service_metrics = self.metrics.clone();
 
+        let api_key = request
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let api_key = match self
+            .graphql_runner
+            .load_manager()
+            .check_quota(api_key, &target)
+        {
+            Ok(api_key) => api_key,
+            Err(e) => return Ok(Self::quota_exceeded(e)),
+        };
+        let trace = trace_requested(
+            request
+                .headers()
+                .get(TRACE_HEADER)
+                .and_then(|value| value.to_str().ok()),
+        );
+
+        // Only responses to GET requests can be stored by HTTP caches
+        let is_get = request.method() == Method::GET;
+        let if_none_match = request
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
         let start = Instant::now();
-        let body = hyper::body::to_bytes(request_body)
-            .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
-            .await?;
-        let query = GraphQLRequest::new(body).compat().await;
+        let request = if is_get {
+            GraphQLRequest::from_url_query(request.uri().query().unwrap_or(""))?
+        } else {
+            let body = hyper::body::to_bytes(request.into_body())
+                .map_err(|_| {
+                    GraphQLServerError::InternalError("Failed to read request body".into())
+                })
+                .await?;
+            GraphQLRequest::new(body)
+        };
+        let query = request.compat().await;
 
         let result = match query {
-            Ok(query) => service.graphql_runner.run_query(query, target, false).await,
+            Ok(query) => {
+                let query = query
+                    .with_api_key(api_key)
+                    .with_trace(trace)
+                    .with_cache_headers(is_get && !trace);
+                service.graphql_runner.run_query(query, target, false).await
+            }
             Err(GraphQLServerError::QueryError(e)) => QueryResult::from(e).into(),
             Err(e) => return Err(e),
         };
@@ -205,16 +247,48 @@ where
                 .observe_query_execution_time(start.elapsed().as_secs_f64(), id.to_string());
         }
 
+        // Let caches that revalidate a response they stored know that it
+        // has not changed without sending it to them again
+        if let (Some((etag, cache_control)), Some(if_none_match)) =
+            (result.cache_headers(), if_none_match)
+        {
+            if if_none_match == etag {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .header("Access-Control-Allow-Origin", "*")
+                    .header(header::ETAG, etag)
+                    .header(header::CACHE_CONTROL, cache_control)
+                    .body(Body::empty())
+                    .unwrap());
+            }
+        }
+
         Ok(result.as_http_response())
     }
 
+    /// Respond to a request that is not within the quota for its API key
+    fn quota_exceeded(error: QuotaError) -> Response<Body> {
+        let body = serde_json::json!({ "errors": [{ "message": error.to_string() }] });
+        let mut response = Response::builder()
+            .status(error.status_code())
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Content-Type", "application/json");
+        if let Some(seconds) = error.retry_after() {
+            response = response.header(header::RETRY_AFTER, seconds);
+        }
+        response.body(Body::from(body.to_string())).unwrap()
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         async {
             Ok(Response::builder()
                 .status(200)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+                .header(
+                    "Access-Control-Allow-Headers",
+                    "Content-Type, User-Agent, X-Api-Key, X-Graph-Trace",
+                )
                 .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap())
@@ -276,6 +350,31 @@ where
             | (Method::GET, &["subgraphs", "network", _, _, "graphql"])
             | (Method::GET, &["subgraphs", "graphql"]) => self.handle_graphiql(),
 
+            // GET requests with a query string are GraphQL queries; without
+            // one, they are meant for GraphiQL
+            (Method::GET, &["subgraphs", "id", subgraph_id]) if req.uri().query().is_some() => {
+                self.handle_graphql_query_by_id(subgraph_id.to_owned(), req)
+            }
+            (Method::GET, &["subgraphs", "name", subgraph_name]) if req.uri().query().is_some() => {
+                self.handle_graphql_query_by_name(subgraph_name.to_owned(), req)
+                    .boxed()
+            }
+            (Method::GET, ["subgraphs", "name", subgraph_name_part1, subgraph_name_part2])
+                if req.uri().query().is_some() =>
+            {
+                let subgraph_name = format!("{}/{}", subgraph_name_part1, subgraph_name_part2);
+                self.handle_graphql_query_by_name(subgraph_name, req)
+                    .boxed()
+            }
+            (Method::GET, ["subgraphs", "network", subgraph_name_part1, subgraph_name_part2])
+                if req.uri().query().is_some() =>
+            {
+                let subgraph_name =
+                    format!("network/{}/{}", subgraph_name_part1, subgraph_name_part2);
+                self.handle_graphql_query_by_name(subgraph_name, req)
+                    .boxed()
+            }
+
             (Method::GET, path @ ["subgraphs", "id", _])
             | (Method::GET, path @ ["subgraphs", "name", _])
             | (Method::GET, path @ ["subgraphs", "name", _, _])
@@ -365,14 +464,17 @@ where
 
 #[cfg(test)]
 mod tests {
-    use http::status::StatusCode;
+    use http::{header, status::StatusCode};
     use hyper::service::Service;
     use hyper::{Body, Method, Request};
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, HashSet};
 
     use graph::data::{
-        graphql::effort::LoadManager,
-        query::{QueryResults, QueryTarget},
+        graphql::{
+            effort::LoadManager,
+            quota::{ApiKeyQuota, ApiKeys, API_KEY_HEADER},
+        },
+        query::{HttpCachePolicy, QueryResults, QueryTarget},
     };
     use graph::prelude::*;
     use graph_mock::MockMetricsRegistry;
@@ -383,7 +485,24 @@ mod tests {
     use super::GraphQLServiceMetrics;
 
     /// A simple stupid query runner for testing.
-    pub struct TestGraphQlRunner;
+    pub struct TestGraphQlRunner {
+        load_manager: Arc<LoadManager>,
+    }
+
+    impl TestGraphQlRunner {
+        fn new(api_keys: ApiKeys) -> Self {
+            let load_manager = LoadManager::new(
+                &Logger::root(slog::Discard, o!()),
+                Vec::new(),
+                api_keys,
+                Arc::new(MockMetricsRegistry::new()),
+                10,
+            );
+            TestGraphQlRunner {
+                load_manager: Arc::new(load_manager),
+            }
+        }
+    }
 
     lazy_static! {
         static ref USERS: SubgraphDeploymentId = SubgraphDeploymentId::new("users").unwrap();
@@ -406,17 +525,25 @@ mod tests {
 
         async fn run_query(
             self: Arc<Self>,
-            _query: Query,
+            query: Query,
             _target: QueryTarget,
             _: bool,
         ) -> QueryResults {
-            QueryResults::from(BTreeMap::from_iter(
+            let mut results = QueryResults::from(BTreeMap::from_iter(
                 vec![(
                     String::from("name"),
                     q::Value::String(String::from("Jordi")),
                 )]
                 .into_iter(),
-            ))
+            ));
+            if query.cache_headers {
+                results.set_cache_policy(Some(HttpCachePolicy {
+                    etag: format!("{}@1:00", *USERS),
+                    max_age: 30,
+                    immutable: false,
+                }));
+            }
+            results
         }
 
         async fn run_subscription(
@@ -428,7 +555,7 @@ mod tests {
         }
 
         fn load_manager(&self) -> Arc<LoadManager> {
-            unimplemented!()
+            self.load_manager.clone()
         }
     }
 
@@ -438,7 +565,7 @@ mod tests {
         let metrics_registry = Arc::new(MockMetricsRegistry::new());
         let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
         let subgraph_id = USERS.clone();
-        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let graphql_runner = Arc::new(TestGraphQlRunner::new(ApiKeys::default()));
 
         let node_id = NodeId::new("test").unwrap();
         let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
@@ -470,7 +597,7 @@ mod tests {
         let metrics_registry = Arc::new(MockMetricsRegistry::new());
         let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
         let subgraph_id = USERS.clone();
-        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let graphql_runner = Arc::new(TestGraphQlRunner::new(ApiKeys::default()));
 
         let node_id = NodeId::new("test").unwrap();
         let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
@@ -499,4 +626,93 @@ mod tests {
             .expect("Query result field \"name\" is not a string");
         assert_eq!(name, "Jordi".to_string());
     }
+
+    #[test]
+    fn enforces_api_key_quotas() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let api_keys = ApiKeys {
+            required: true,
+            keys: vec![ApiKeyQuota {
+                label: "partner".to_owned(),
+                key: "secret".to_owned(),
+                queries_per_second: Some(1),
+                effort_seconds_per_minute: None,
+                subgraphs: HashSet::new(),
+            }],
+        };
+        let graphql_runner = Arc::new(TestGraphQlRunner::new(api_keys));
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+
+        let mut query = |api_key: Option<&str>| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(format!("http://localhost:8000/subgraphs/id/{}", *USERS));
+            if let Some(api_key) = api_key {
+                request = request.header(API_KEY_HEADER, api_key);
+            }
+            let request = request
+                .body(Body::from("{\"query\": \"{ name }\"}"))
+                .unwrap();
+            futures03::executor::block_on(service.call(request)).expect("Should return a response")
+        };
+
+        test_utils::assert_error_response(query(None), StatusCode::UNAUTHORIZED, true);
+        test_utils::assert_error_response(query(Some("guess")), StatusCode::UNAUTHORIZED, true);
+        test_utils::assert_successful_response(query(Some("secret")));
+
+        // The quota only allows one query per second
+        let response = query(Some("secret"));
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "1");
+        let errors =
+            test_utils::assert_error_response(response, StatusCode::TOO_MANY_REQUESTS, true);
+        assert_eq!(
+            errors[0]["message"],
+            "the API key `partner` can only be used for 1 queries per second"
+        );
+    }
+
+    #[test]
+    fn get_queries_yield_cacheable_responses() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let graphql_runner = Arc::new(TestGraphQlRunner::new(ApiKeys::default()));
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+
+        let mut query = |if_none_match: Option<&str>| {
+            let mut request = Request::builder().method(Method::GET).uri(format!(
+                "http://localhost:8000/subgraphs/id/{}?query=%7B%20name%20%7D",
+                *USERS
+            ));
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            let request = request.body(Body::empty()).unwrap();
+            futures03::executor::block_on(service.call(request)).expect("Should return a response")
+        };
+
+        let response = query(None);
+        let etag = format!("\"{}@1:00\"", *USERS);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+        assert_eq!(
+            response.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=30"
+        );
+        let data = test_utils::assert_successful_response(response);
+        assert_eq!(data["name"], "Jordi");
+
+        // A cache revalidating the response learns that it is still current
+        let response = query(Some(&etag));
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+
+        let response = query(Some("\"outdated\""));
+        test_utils::assert_successful_response(response);
+    }
 }
@@ -4,7 +4,7 @@ use std::collections::BTreeMap;
 use std::time::Duration;
 
 use graph::data::{
-    graphql::effort::LoadManager,
+    graphql::{effort::LoadManager, quota::ApiKeys},
     query::{QueryResults, QueryTarget},
 };
 use graph::prelude::*;
@@ -14,6 +14,16 @@ use graph_server_http::GraphQLServer as HyperGraphQLServer;
 
 use tokio::time::delay_for;
 
+lazy_static! {
+    static ref LOAD_MANAGER: Arc<LoadManager> = Arc::new(LoadManager::new(
+        &Logger::root(slog::Discard, o!()),
+        Vec::new(),
+        ApiKeys::default(),
+        Arc::new(graph_mock::MockMetricsRegistry::new()),
+        10,
+    ));
+}
+
 /// A simple stupid query runner for testing.
 pub struct TestGraphQlRunner;
 
@@ -76,7 +86,7 @@ impl GraphQlRunner for TestGraphQlRunner {
     }
 
     fn load_manager(&self) -> Arc<LoadManager> {
-        unimplemented!()
+        LOAD_MANAGER.clone()
     }
 }
 
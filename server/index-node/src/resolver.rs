@@ -124,6 +124,29 @@ where
         Ok(poi)
     }
 
+    fn resolve_chain_reorgs(
+        &self,
+        arguments: &HashMap<&String, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let network = arguments
+            .get_optional::<String>("network")
+            .expect("Invalid network");
+        let first = arguments
+            .get_optional::<u64>("first")
+            .expect("Invalid first")
+            .unwrap_or(100);
+        if first > 1000 {
+            return Err(QueryExecutionError::RangeArgumentsError(
+                "first",
+                1000,
+                first as i64,
+            ));
+        }
+
+        let reorgs = self.store.chain_reorgs(network, first as usize)?;
+        Ok(reorgs.into_value())
+    }
+
     fn resolve_indexing_status_for_version(
         &self,
         arguments: &HashMap<&String, q::Value>,
@@ -226,6 +249,9 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `chainReorgs` field
+            (None, "ChainReorg", "chainReorgs") => self.resolve_chain_reorgs(arguments),
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
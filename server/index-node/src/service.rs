@@ -110,6 +110,7 @@ where
                 deadline: None,
                 max_first: std::u32::MAX,
                 max_skip: std::u32::MAX,
+                max_cost: None,
                 load_manager,
             };
             let result = execute_query(query_clone.cheap_clone(), None, None, options, false).await;
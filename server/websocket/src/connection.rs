@@ -165,6 +165,16 @@ impl Drop for Operations {
     }
 }
 
+/// The API key a client sent when it opened a connection. Every operation
+/// on the connection counts against the quota for the key
+pub(crate) struct ConnectionQuota {
+    pub api_key: Option<String>,
+    /// The label of the key, if the connection is subject to a quota
+    pub api_key_label: Option<String>,
+    /// The subgraph name or deployment id from the URL of the connection
+    pub target: QueryTarget,
+}
+
 /// A WebSocket connection implementing the GraphQL over WebSocket protocol.
 pub struct GraphQlConnection<Q, S> {
     id: String,
@@ -172,6 +182,7 @@ pub struct GraphQlConnection<Q, S> {
     graphql_runner: Arc<Q>,
     stream: WebSocketStream<S>,
     schema: Arc<ApiSchema>,
+    quota: Arc<ConnectionQuota>,
 }
 
 impl<Q, S> GraphQlConnection<Q, S>
@@ -185,6 +196,7 @@ where
         schema: Arc<ApiSchema>,
         stream: WebSocketStream<S>,
         graphql_runner: Arc<Q>,
+        quota: ConnectionQuota,
     ) -> Self {
         GraphQlConnection {
             id: Uuid::new_v4().to_string(),
@@ -192,6 +204,7 @@ where
             graphql_runner,
             stream,
             schema,
+            quota: Arc::new(quota),
         }
     }
 
@@ -202,6 +215,7 @@ where
         connection_id: String,
         schema: Arc<ApiSchema>,
         graphql_runner: Arc<Q>,
+        quota: Arc<ConnectionQuota>,
     ) -> Result<(), WsError> {
         let mut operations = Operations::new(msg_sink.clone());
 
@@ -260,6 +274,17 @@ where
                         }
                     }
 
+                    // Respond with a GQL_ERROR if the client exceeded the quota
+                    // for its API key
+                    if quota.api_key_label.is_some() {
+                        if let Err(e) = graphql_runner
+                            .load_manager()
+                            .check_quota(quota.api_key.as_deref(), &quota.target)
+                        {
+                            return send_error_string(&msg_sink, id.clone(), e.to_string());
+                        }
+                    }
+
                     // Parse the GraphQL query document; respond with a GQL_ERROR if
                     // the query is invalid
                     let query = match parse_query(&payload.query) {
@@ -302,7 +327,8 @@ where
                     let subscription = Subscription {
                         // Subscriptions currently do not benefit from the generational cache
                         // anyways, so don't bother passing a network.
-                        query: Query::new(query, variables),
+                        query: Query::new(query, variables)
+                            .with_api_key(quota.api_key_label.clone()),
                     };
 
                     debug!(logger, "Start operation";
@@ -405,6 +431,7 @@ where
             self.id.clone(),
             self.schema.clone(),
             self.graphql_runner.clone(),
+            self.quota.clone(),
         );
 
         // Send outgoing messages asynchronously
@@ -1,3 +1,5 @@
+use graph::data::graphql::quota::API_KEY_HEADER;
+use graph::data::query::QueryTarget;
 use graph::prelude::{SubscriptionServer as SubscriptionServerTrait, *};
 use http::{HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
@@ -6,7 +8,7 @@ use tokio::net::TcpListener;
 use tokio_tungstenite::accept_hdr_async;
 use tokio_tungstenite::tungstenite::handshake::server::Request;
 
-use crate::connection::GraphQlConnection;
+use crate::connection::{ConnectionQuota, GraphQlConnection};
 
 /// A GraphQL subscription server based on Hyper / Websockets.
 pub struct SubscriptionServer<Q, S> {
@@ -28,41 +30,45 @@ where
         }
     }
 
-    fn subgraph_id_from_url_path(
-        store: Arc<S>,
-        path: &str,
-    ) -> Result<Option<SubgraphDeploymentId>, Error> {
-        fn id_from_name<S: Store>(store: Arc<S>, name: String) -> Option<SubgraphDeploymentId> {
-            SubgraphName::new(name)
-                .ok()
-                .map(|subgraph_name| store.deployment_state_from_name(subgraph_name))
-                .transpose()
-                .map(|state| state.map(|state| state.id))
-                .ok()
-                .flatten()
-        }
-
+    fn target_from_url_path(path: &str) -> Option<QueryTarget> {
         let path_segments = {
             let mut segments = path.split("/");
 
             // Remove leading '/'
             let first_segment = segments.next();
             if first_segment != Some("") {
-                return Ok(None);
+                return None;
             }
 
             segments.collect::<Vec<_>>()
         };
 
         match path_segments.as_slice() {
-            &["subgraphs", "id", subgraph_id] => Ok(SubgraphDeploymentId::new(subgraph_id).ok()),
+            &["subgraphs", "id", subgraph_id] => SubgraphDeploymentId::new(subgraph_id)
+                .ok()
+                .map(QueryTarget::Deployment),
             &["subgraphs", "name", _] | &["subgraphs", "name", _, _] => {
-                Ok(id_from_name(store, path_segments[2..].join("/")))
+                SubgraphName::new(path_segments[2..].join("/"))
+                    .ok()
+                    .map(QueryTarget::Name)
             }
-            &["subgraphs", "network", _, _] => {
-                Ok(id_from_name(store, path_segments[1..].join("/")))
-            }
-            _ => Ok(None),
+            &["subgraphs", "network", _, _] => SubgraphName::new(path_segments[1..].join("/"))
+                .ok()
+                .map(QueryTarget::Name),
+            _ => None,
+        }
+    }
+
+    fn subgraph_id_from_target(
+        store: Arc<S>,
+        target: QueryTarget,
+    ) -> Result<Option<SubgraphDeploymentId>, Error> {
+        match target {
+            QueryTarget::Deployment(id) => Ok(Some(id)),
+            QueryTarget::Name(name) => Ok(store
+                .deployment_state_from_name(name)
+                .map(|state| state.id)
+                .ok()),
         }
     }
 }
@@ -99,15 +105,43 @@ where
             let store = self.store.clone();
             let store2 = self.store.clone();
 
-            // Subgraph that the request is resolved to (if any)
+            // Subgraph that the request is resolved to (if any), and the
+            // API key for it
             let subgraph_id = Arc::new(Mutex::new(None));
             let accept_subgraph_id = subgraph_id.clone();
+            let quota = Arc::new(Mutex::new(None));
+            let accept_quota = quota.clone();
+            let accept_graphql_runner = graphql_runner.clone();
 
             accept_hdr_async(stream, move |request: &Request, mut response: Response<()>| {
                 // Try to obtain the subgraph ID or name from the URL path.
                 // Return a 404 if the URL path contains no name/ID segment.
                 let path = request.uri().path();
-                let subgraph_id = Self::subgraph_id_from_url_path(store.clone(), path.as_ref())
+                let target = Self::target_from_url_path(path.as_ref()).ok_or_else(|| {
+                    Response::builder().status(StatusCode::NOT_FOUND).body(None).unwrap()
+                })?;
+
+                // Check that the client is allowed to query the subgraph
+                let api_key = request
+                    .headers()
+                    .get(API_KEY_HEADER)
+                    .and_then(|value| value.to_str().ok());
+                let api_key_label = accept_graphql_runner
+                    .load_manager()
+                    .check_quota(api_key, &target)
+                    .map_err(|e| {
+                        Response::builder()
+                            .status(e.status_code())
+                            .body(Some(e.to_string()))
+                            .unwrap()
+                    })?;
+                *accept_quota.lock().unwrap() = Some(ConnectionQuota {
+                    api_key: api_key.map(str::to_owned),
+                    api_key_label,
+                    target: target.clone(),
+                });
+
+                let subgraph_id = Self::subgraph_id_from_target(store.clone(), target)
                     .map_err(|e| {
                         error!(
                             logger,
@@ -142,6 +176,7 @@ where
                     Ok(ws_stream) => {
                         // Obtain the subgraph ID or name that we resolved the request to
                         let subgraph_id = subgraph_id.lock().unwrap().clone().unwrap();
+                        let quota = quota.lock().unwrap().take().unwrap();
 
                         // Get the subgraph schema
                         let schema = match store2.api_schema(&subgraph_id) {
@@ -161,6 +196,7 @@ where
                             schema,
                             ws_stream,
                             graphql_runner.clone(),
+                            quota,
                         );
 
                         graph::spawn_allow_panic(service.into_future().compat());
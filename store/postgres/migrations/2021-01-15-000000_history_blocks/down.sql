@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  drop column history_blocks,
+  drop column earliest_block;
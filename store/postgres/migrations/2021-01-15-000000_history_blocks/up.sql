@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  add column history_blocks int,
+  add column earliest_block int not null default 0;
@@ -0,0 +1 @@
+drop table ethereum_block_traces;
@@ -0,0 +1,11 @@
+-- All traces of a block, as returned by trace_filter, so that they do not
+-- have to be requested from the Ethereum node again
+create table ethereum_block_traces (
+  hash varchar primary key,
+  number bigint not null,
+  network_name varchar not null references ethereum_networks (name),
+  traces jsonb not null
+);
+
+create index ethereum_block_traces_network_number
+    on ethereum_block_traces(network_name, number);
@@ -0,0 +1,17 @@
+-- A log of the reorgs that the block ingestors observed: the chain head
+-- moved from the old head to the new head, and the old head is not an
+-- ancestor of the new head. The depth is the number of blocks of the old
+-- chain that were replaced
+create table ethereum_reorgs (
+  id serial primary key,
+  network_name varchar not null references ethereum_networks (name),
+  old_head_hash varchar not null,
+  old_head_number bigint not null,
+  new_head_hash varchar not null,
+  new_head_number bigint not null,
+  depth bigint not null,
+  created_at timestamptz not null default now()
+);
+
+create index ethereum_reorgs_network_id
+    on ethereum_reorgs(network_name, id);
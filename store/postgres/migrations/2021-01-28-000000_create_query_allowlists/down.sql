@@ -0,0 +1,2 @@
+drop table query_allowlist;
+drop table query_allowlist_deployments;
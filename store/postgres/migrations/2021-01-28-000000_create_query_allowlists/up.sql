@@ -0,0 +1,14 @@
+-- Deployments that only run queries whose shape is in their allow-list
+create table query_allowlist_deployments (
+  deployment varchar primary key
+);
+
+-- The allow-lists of deployments. Queries are identified by their shape
+-- hash; we keep the text of one query with that shape for reference
+create table query_allowlist (
+  deployment varchar not null,
+  shape_hash bigint not null,
+  query text not null,
+  created_at timestamptz not null default now(),
+  primary key (deployment, shape_hash)
+);
@@ -0,0 +1,13 @@
+-- The entity changes that processing a block made to a deployment, and the
+-- reverts of its blocks, in the order in which they happened. The entries
+-- of a deployment are numbered consecutively by their cursor. For a
+-- revert, `changes` is null and the block is the block that the deployment
+-- was reverted to
+create table subgraphs.entity_change_log (
+  deployment varchar not null,
+  cursor bigint not null,
+  block_number int not null,
+  block_hash bytea not null,
+  changes jsonb,
+  primary key (deployment, cursor)
+);
@@ -5,14 +5,17 @@ use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::{insert_into, select, update};
 use graph::ensure;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::sync::Arc;
 
+use graph::data::subgraph::status::ChainReorg;
 use graph::prelude::{
-    serde_json, web3::types::H256, BlockNumber, ChainHeadUpdateListener as _,
-    ChainHeadUpdateStream, Error, EthereumBlock, EthereumBlockPointer, EthereumNetworkIdentifier,
-    Future, LightEthereumBlock, Stream,
+    serde_json,
+    web3::types::{Trace, TransactionReceipt, H256},
+    BlockNumber, ChainHeadUpdateListener as _, ChainHeadUpdateStream, Error, EthereumBlock,
+    EthereumBlockPointer, EthereumNetworkIdentifier, Future, LightEthereumBlock, Stream,
 };
 
 //use web3::types::H256;
@@ -134,33 +137,13 @@ impl ChainStoreTrait for ChainStore {
         B: Stream<Item = EthereumBlock, Error = E> + Send + 'static,
         E: From<Error> + Send + 'static,
     {
-        use crate::db_schema::ethereum_blocks::dsl::*;
-
         let conn = self.conn.clone();
         let net_name = self.network.clone();
         Box::new(blocks.for_each(move |block| {
-            let json_blob = serde_json::to_value(&block).expect("Failed to serialize block");
-            let values = (
-                hash.eq(format!("{:x}", block.block.hash.unwrap())),
-                number.eq(block.block.number.unwrap().as_u64() as i64),
-                parent_hash.eq(format!("{:x}", block.block.parent_hash)),
-                network_name.eq(&net_name),
-                data.eq(json_blob),
-            );
-
-            // Insert blocks.
-            //
-            // If the table already contains a block with the same hash, then overwrite that block
-            // if it may be adding transaction receipts.
-            insert_into(ethereum_blocks)
-                .values(values.clone())
-                .on_conflict(hash)
-                .do_update()
-                .set(values)
-                .execute(&*conn.get().map_err(Error::from)?)
+            conn.get()
                 .map_err(Error::from)
+                .and_then(|conn| upsert_block(&conn, &net_name, &block))
                 .map_err(E::from)
-                .map(|_| ())
         }))
     }
 
@@ -196,6 +179,101 @@ impl ChainStoreTrait for ChainStore {
         Ok(())
     }
 
+    fn upsert_block(&self, block: EthereumBlock) -> Result<(), Error> {
+        upsert_block(&*self.get_conn()?, &self.network, &block)
+    }
+
+    fn transaction_receipts(
+        &self,
+        block_hash: &H256,
+    ) -> Result<Option<Vec<TransactionReceipt>>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Jsonb, Nullable};
+
+        let receipts = ethereum_blocks
+            .select(sql::<Nullable<Jsonb>>("data -> 'transaction_receipts'"))
+            .filter(network_name.eq(&self.network))
+            .filter(hash.eq(format!("{:x}", block_hash)))
+            .first::<Option<serde_json::Value>>(&*self.get_conn()?)
+            .optional()?
+            .flatten();
+
+        // Light blocks are stored with an empty list of receipts; since
+        // blocks without transactions never need their receipts loaded, an
+        // empty list always means that we do not have the receipts
+        match receipts {
+            None => Ok(None),
+            Some(receipts) => {
+                let receipts: Vec<TransactionReceipt> = serde_json::from_value(receipts)?;
+                Ok(Some(receipts).filter(|receipts| !receipts.is_empty()))
+            }
+        }
+    }
+
+    fn block_traces(&self, block_hash: &H256) -> Result<Option<Vec<Trace>>, Error> {
+        use crate::db_schema::ethereum_block_traces::dsl::*;
+
+        ethereum_block_traces
+            .select(traces)
+            .filter(network_name.eq(&self.network))
+            .filter(hash.eq(format!("{:x}", block_hash)))
+            .first::<serde_json::Value>(&*self.get_conn()?)
+            .optional()?
+            .map(|value| serde_json::from_value(value).map_err(Error::from))
+            .transpose()
+    }
+
+    fn set_block_traces(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        block_traces: &[Trace],
+    ) -> Result<(), Error> {
+        use crate::db_schema::ethereum_block_traces::dsl::*;
+
+        let json_blob = serde_json::to_value(block_traces)?;
+        insert_into(ethereum_block_traces)
+            .values((
+                hash.eq(block_ptr.hash_hex()),
+                number.eq(block_ptr.number as i64),
+                network_name.eq(&self.network),
+                traces.eq(json_blob),
+            ))
+            .on_conflict(hash)
+            .do_nothing()
+            .execute(&*self.get_conn()?)?;
+        Ok(())
+    }
+
+    fn block_traces_in_range(&self, from: u64, to: u64) -> Result<Option<Vec<Trace>>, Error> {
+        use crate::db_schema::ethereum_block_traces::dsl::*;
+
+        let blocks = ethereum_block_traces
+            .select((number, traces))
+            .filter(network_name.eq(&self.network))
+            .filter(number.between(from as i64, to as i64))
+            .order(number)
+            .load::<(i64, serde_json::Value)>(&*self.get_conn()?)?;
+
+        // If a number is missing, or we have traces for more than one block
+        // with the same number, we can't tell which traces are on the main
+        // chain without asking the Ethereum node
+        if blocks.len() as u64 != to + 1 - from
+            || blocks
+                .iter()
+                .zip(from..=to)
+                .any(|((block_number, _), expected)| *block_number as u64 != expected)
+        {
+            return Ok(None);
+        }
+
+        let mut range_traces = Vec::new();
+        for (_, value) in blocks {
+            range_traces.extend(serde_json::from_value::<Vec<Trace>>(value)?);
+        }
+        Ok(Some(range_traces))
+    }
+
     fn attempt_chain_head_update(&self, ancestor_count: u64) -> Result<Vec<H256>, Error> {
         // Call attempt_head_update SQL function
         select(attempt_chain_head_update(
@@ -219,6 +297,60 @@ impl ChainStoreTrait for ChainStore {
         .and_then(|r| r.map_err(Error::from))
     }
 
+    fn record_reorg(
+        &self,
+        old_head: EthereumBlockPointer,
+        new_head: EthereumBlockPointer,
+        max_depth: u64,
+    ) -> Result<Option<ChainReorg>, Error> {
+        use crate::db_schema::ethereum_reorgs::dsl::*;
+        use diesel::dsl::sql;
+        use diesel::sql_types::BigInt;
+
+        let conn = self.get_conn()?;
+        let min_number = old_head.number.saturating_sub(max_depth);
+
+        let new_chain = ancestor_hashes(&conn, &new_head, min_number)?;
+        if new_chain.get(&old_head.number) == Some(&old_head.hash_hex()) {
+            // The new head is a descendant of the old head
+            return Ok(None);
+        }
+
+        // The common ancestor is the highest block that is on both chains.
+        // If we do not have the blocks to find it, we can not tell how deep
+        // the reorg was
+        let old_chain = ancestor_hashes(&conn, &old_head, min_number)?;
+        let common_ancestor = old_chain
+            .iter()
+            .filter(|(block_number, block_hash)| new_chain.get(*block_number) == Some(*block_hash))
+            .map(|(block_number, _)| *block_number)
+            .max();
+        let reorg_depth = match common_ancestor {
+            Some(common_ancestor) => old_head.number - common_ancestor,
+            None => return Ok(None),
+        };
+
+        let timestamp = insert_into(ethereum_reorgs)
+            .values((
+                network_name.eq(&self.network),
+                old_head_hash.eq(old_head.hash_hex()),
+                old_head_number.eq(old_head.number as i64),
+                new_head_hash.eq(new_head.hash_hex()),
+                new_head_number.eq(new_head.number as i64),
+                depth.eq(reorg_depth as i64),
+            ))
+            .returning(sql::<BigInt>("extract(epoch from created_at)::bigint"))
+            .get_result::<i64>(&conn)?;
+
+        Ok(Some(ChainReorg {
+            network: self.network.clone(),
+            old_head,
+            new_head,
+            depth: reorg_depth,
+            timestamp: timestamp as u64,
+        }))
+    }
+
     fn chain_head_updates(&self) -> ChainHeadUpdateStream {
         self.chain_head_update_listener
             .subscribe(self.network.to_owned())
@@ -282,6 +414,7 @@ impl ChainStoreTrait for ChainStore {
     }
 
     fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error> {
+        use crate::db_schema::ethereum_block_traces::dsl as tr;
         use crate::db_schema::ethereum_blocks::dsl;
         use diesel::sql_types::{Integer, Text};
 
@@ -341,6 +474,13 @@ impl ChainStoreTrait for ChainStore {
                         .filter(dsl::number.lt(*block as i64))
                         .filter(dsl::number.gt(0))
                         .execute(&conn)
+                        .and_then(|rows| {
+                            diesel::delete(tr::ethereum_block_traces)
+                                .filter(tr::network_name.eq(&self.network))
+                                .filter(tr::number.lt(*block as i64))
+                                .execute(&conn)
+                                .map(|_| rows)
+                        })
                         .map(|rows| (*block, rows))
                 } else {
                     Ok((0, 0))
@@ -366,9 +506,15 @@ impl ChainStoreTrait for ChainStore {
     }
 
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error> {
+        use crate::db_schema::ethereum_block_traces::dsl as tr;
         use crate::db_schema::ethereum_blocks::dsl;
 
         let conn = self.get_conn()?;
+        diesel::delete(tr::ethereum_block_traces)
+            .filter(tr::network_name.eq(&self.network))
+            .filter(tr::number.eq(number as i64))
+            .filter(tr::hash.ne(&format!("{:x}", hash)))
+            .execute(&conn)?;
         diesel::delete(dsl::ethereum_blocks)
             .filter(dsl::network_name.eq(&self.network))
             .filter(dsl::number.eq(number as i64))
@@ -394,3 +540,64 @@ impl ChainStoreTrait for ChainStore {
             .transpose()
     }
 }
+
+/// Insert `block` with its transaction receipts, overwriting a block with
+/// the same hash since it may be a light block without receipts
+fn upsert_block(conn: &PgConnection, net_name: &str, block: &EthereumBlock) -> Result<(), Error> {
+    use crate::db_schema::ethereum_blocks::dsl::*;
+
+    let json_blob = serde_json::to_value(block).expect("Failed to serialize block");
+    let values = (
+        hash.eq(format!("{:x}", block.block.hash.unwrap())),
+        number.eq(block.block.number.unwrap().as_u64() as i64),
+        parent_hash.eq(format!("{:x}", block.block.parent_hash)),
+        network_name.eq(net_name),
+        data.eq(json_blob),
+    );
+
+    insert_into(ethereum_blocks)
+        .values(values.clone())
+        .on_conflict(hash)
+        .do_update()
+        .set(values)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Return the hashes of `head` and its ancestors down to block number
+/// `min_number`, keyed by block number. Stops at the first ancestor that is
+/// not in the store
+fn ancestor_hashes(
+    conn: &PgConnection,
+    head: &EthereumBlockPointer,
+    min_number: u64,
+) -> Result<HashMap<u64, String>, Error> {
+    use diesel::sql_types::{BigInt, Text};
+
+    #[derive(QueryableByName)]
+    struct ChainBlock {
+        #[sql_type = "Text"]
+        hash: String,
+        #[sql_type = "BigInt"]
+        number: i64,
+    };
+
+    let query = "
+        with recursive chain(hash, parent_hash, number) as (
+            select hash, parent_hash, number
+              from ethereum_blocks
+             where hash = $1
+            union all
+            select b.hash, b.parent_hash, b.number
+              from ethereum_blocks b, chain c
+             where b.hash = c.parent_hash
+               and b.number >= $2)
+        select hash, number from chain";
+    Ok(diesel::sql_query(query)
+        .bind::<Text, _>(head.hash_hex())
+        .bind::<BigInt, _>(min_number as i64)
+        .load::<ChainBlock>(conn)?
+        .into_iter()
+        .map(|block| (block.number as u64, block.hash))
+        .collect())
+}
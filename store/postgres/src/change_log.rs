@@ -0,0 +1,287 @@
+//! The entity change log of deployments. When it is enabled, writing a
+//! block appends the entity changes it made to the log, and reverting a
+//! block appends a revert, in the same transaction as the write or the
+//! revert. The entries of a deployment are numbered consecutively by their
+//! cursor, which clients use to resume reading the log where they left off.
+//! Since all writes to a deployment take the deployment lock, cursors are
+//! allocated without any races.
+use diesel::dsl::{delete, insert_into, max};
+use diesel::pg::PgConnection;
+use diesel::prelude::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use lazy_static::lazy_static;
+
+use graph::components::store::EntityType;
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::prelude::{
+    anyhow, serde_json, web3::types::H256, BlockNumber, ChangeLogEntry, ChangeLogOperation,
+    Deserialize, Entity, EntityKey, EntityModification, EthereumBlockPointer, Serialize,
+    StoreError, SubgraphDeploymentId,
+};
+
+use crate::block_range::block_number;
+
+lazy_static! {
+    /// Whether to write the entity change log; it is only needed to stream
+    /// entity changes with the entity changes server
+    pub(crate) static ref ENABLED: bool = std::env::var("GRAPH_ENTITY_CHANGE_LOG").is_ok();
+}
+
+/// The number of entries we copy at once when moving a deployment
+const BATCH_SIZE: i64 = 1_000;
+
+table! {
+    subgraphs.entity_change_log (deployment, cursor) {
+        deployment -> Text,
+        cursor -> BigInt,
+        block_number -> Integer,
+        block_hash -> Binary,
+        changes -> Nullable<Jsonb>,
+    }
+}
+
+use entity_change_log as l;
+
+type Row = (String, i64, BlockNumber, Vec<u8>, Option<serde_json::Value>);
+
+/// How an entity modification is stored in the `changes` of an entry
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum LoggedModification {
+    Insert {
+        entity: String,
+        id: String,
+        data: Entity,
+    },
+    Overwrite {
+        entity: String,
+        id: String,
+        data: Entity,
+    },
+    Remove {
+        entity: String,
+        id: String,
+    },
+}
+
+impl LoggedModification {
+    fn new(modification: &EntityModification) -> Self {
+        use EntityModification::*;
+
+        let key = modification.entity_key();
+        let entity = key.entity_type.as_str().to_owned();
+        let id = key.entity_id.clone();
+        match modification {
+            Insert { data, .. } => LoggedModification::Insert {
+                entity,
+                id,
+                data: data.clone(),
+            },
+            Overwrite { data, .. } => LoggedModification::Overwrite {
+                entity,
+                id,
+                data: data.clone(),
+            },
+            Remove { .. } => LoggedModification::Remove { entity, id },
+        }
+    }
+
+    fn into_modification(self, id: &SubgraphDeploymentId) -> EntityModification {
+        let key = |entity, entity_id| EntityKey {
+            subgraph_id: id.clone(),
+            entity_type: EntityType::data(entity),
+            entity_id,
+        };
+        match self {
+            LoggedModification::Insert { entity, id, data } => EntityModification::Insert {
+                key: key(entity, id),
+                data,
+            },
+            LoggedModification::Overwrite { entity, id, data } => EntityModification::Overwrite {
+                key: key(entity, id),
+                data,
+            },
+            LoggedModification::Remove { entity, id } => EntityModification::Remove {
+                key: key(entity, id),
+            },
+        }
+    }
+}
+
+fn append(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+    changes: Option<serde_json::Value>,
+) -> Result<(), StoreError> {
+    let cursor = l::table
+        .filter(l::deployment.eq(id.as_str()))
+        .select(max(l::cursor))
+        .first::<Option<i64>>(conn)?
+        .unwrap_or(0)
+        + 1;
+    insert_into(l::table)
+        .values((
+            l::deployment.eq(id.as_str()),
+            l::cursor.eq(cursor),
+            l::block_number.eq(block_number(block_ptr)),
+            l::block_hash.eq(block_ptr.hash.as_bytes()),
+            l::changes.eq(changes),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Log the entity modifications `mods` that processing `block_ptr` made.
+/// Modifications of the proof of indexing are not logged, and neither are
+/// blocks that did not change any entities. The caller must hold the
+/// deployment lock
+pub(crate) fn append_changes(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+    mods: &[EntityModification],
+) -> Result<(), StoreError> {
+    let mods: Vec<_> = mods
+        .iter()
+        .filter(|modification| !modification.entity_key().entity_type.is_data(POI_OBJECT))
+        .map(LoggedModification::new)
+        .collect();
+    if mods.is_empty() {
+        return Ok(());
+    }
+    let changes = serde_json::to_value(mods)
+        .map_err(|e| StoreError::Unknown(anyhow!("can not log entity changes: {}", e)))?;
+    append(conn, id, block_ptr, Some(changes))
+}
+
+/// Log that the deployment was reverted to `block_ptr`. The caller must
+/// hold the deployment lock
+pub(crate) fn append_revert(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block_ptr: &EthereumBlockPointer,
+) -> Result<(), StoreError> {
+    append(conn, id, block_ptr, None)
+}
+
+fn entry(id: &SubgraphDeploymentId, row: Row) -> Result<ChangeLogEntry, StoreError> {
+    let (_, cursor, number, hash, changes) = row;
+    let block = EthereumBlockPointer::from((H256::from_slice(&hash), number as u64));
+    let operation = match changes {
+        Some(changes) => {
+            let mods: Vec<LoggedModification> = serde_json::from_value(changes).map_err(|e| {
+                StoreError::Unknown(anyhow!(
+                    "invalid entry {} in the entity change log of `{}`: {}",
+                    cursor,
+                    id,
+                    e
+                ))
+            })?;
+            ChangeLogOperation::Changes(
+                mods.into_iter()
+                    .map(|modification| modification.into_modification(id))
+                    .collect(),
+            )
+        }
+        None => ChangeLogOperation::Revert,
+    };
+    Ok(ChangeLogEntry {
+        cursor,
+        block,
+        operation,
+    })
+}
+
+/// Return at most `limit` entries for the deployment `id` whose cursor is
+/// bigger than `after`. Fails if the entry right after `after` has been
+/// pruned
+pub(crate) fn entries(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    after: i64,
+    limit: usize,
+) -> Result<Vec<ChangeLogEntry>, StoreError> {
+    let rows = l::table
+        .filter(l::deployment.eq(id.as_str()))
+        .filter(l::cursor.gt(after))
+        .order(l::cursor)
+        .limit(limit as i64)
+        .load::<Row>(conn)?;
+    if let Some((_, cursor, _, _, _)) = rows.first() {
+        if *cursor > after + 1 {
+            return Err(StoreError::QueryExecutionError(format!(
+                "the entity change log of `{}` has been pruned and \
+                 starts after cursor {}",
+                id,
+                cursor - 1
+            )));
+        }
+    }
+    rows.into_iter().map(|row| entry(id, row)).collect()
+}
+
+/// Remove the entries for blocks before `earliest_block` from the log of
+/// the deployment `id`, except for the last one so that we can still tell
+/// which cursors have been pruned
+pub(crate) fn prune(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    earliest_block: BlockNumber,
+) -> Result<(), StoreError> {
+    let last = l::table
+        .filter(l::deployment.eq(id.as_str()))
+        .filter(l::block_number.lt(earliest_block))
+        .select(max(l::cursor))
+        .first::<Option<i64>>(conn)?;
+    if let Some(last) = last {
+        delete(
+            l::table
+                .filter(l::deployment.eq(id.as_str()))
+                .filter(l::cursor.lt(last)),
+        )
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Copy the log of the deployment `id` from `src` to `dst`, keeping the
+/// cursors of all entries
+pub(crate) fn copy(
+    src: &PgConnection,
+    dst: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    let mut after = 0;
+    loop {
+        let rows = l::table
+            .filter(l::deployment.eq(id.as_str()))
+            .filter(l::cursor.gt(after))
+            .order(l::cursor)
+            .limit(BATCH_SIZE)
+            .load::<Row>(src)?;
+        let last = match rows.last() {
+            Some((_, cursor, _, _, _)) => *cursor,
+            None => return Ok(()),
+        };
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|(deployment, cursor, number, hash, changes)| {
+                (
+                    l::deployment.eq(deployment),
+                    l::cursor.eq(cursor),
+                    l::block_number.eq(number),
+                    l::block_hash.eq(hash),
+                    l::changes.eq(changes),
+                )
+            })
+            .collect();
+        insert_into(l::table).values(rows).execute(dst)?;
+        after = last;
+    }
+}
+
+/// Remove the log of the deployment `id`
+pub(crate) fn drop(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    delete(l::table.filter(l::deployment.eq(id.as_str()))).execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,391 @@
+//! Copy a deployment into a different shard while it is being indexed.
+//!
+//! The copy happens in three steps: first, all versions of all entities are
+//! copied from a consistent snapshot of the source. The copy then catches
+//! up with the source by applying the changes that the source made since
+//! then, block by block. Finally, the remaining changes and the metadata
+//! of the deployment are copied while the source is locked so that it can
+//! not change. Writing a block to the source also takes that lock, so that
+//! taking it waits for a block that is being written to be finished. Once
+//! the metadata has been copied, it is removed from the source, and any
+//! later attempt to write to the source fails.
+//!
+//! Rows are copied through their JSONB representation, which makes it
+//! possible to copy them without knowing all their columns in advance, and
+//! are inserted in the order of their `vid` in the source. The copies get
+//! new `vid`s in the destination.
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, BigInt, Integer, Jsonb, Text};
+use diesel::{Connection, RunQueryDsl};
+use std::sync::Arc;
+use std::time::Instant;
+
+use graph::prelude::{
+    anyhow::anyhow, info, o, serde_json, warn, BlockNumber, EntityModification, Logger, StoreError,
+    SubgraphDeploymentId, BLOCK_NUMBER_MAX,
+};
+
+use crate::block_range::block_number;
+use crate::primary::{Namespace, Site, METADATA_NAMESPACE};
+use crate::relational::{Layout, SqlName, Table};
+use crate::{change_log, deployment, dynds, store::Store};
+
+/// The number of rows we copy at once
+const BATCH_SIZE: i64 = 10_000;
+
+/// The maximum number of blocks whose changes we copy at once
+const MAX_BLOCKS: BlockNumber = 1_000;
+
+/// The length of the ids of dynamic data sources; metadata entries for a
+/// dynamic data source have ids that start with the id of the data source
+const DDS_ID_LEN: i32 = 40;
+
+#[derive(QueryableByName)]
+struct Row {
+    #[sql_type = "BigInt"]
+    vid: i64,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+#[derive(QueryableByName)]
+struct ColumnName {
+    #[sql_type = "Text"]
+    column_name: String,
+}
+
+/// Return the names of all columns of `table` in `namespace` except `vid`,
+/// in the order in which they appear in the table
+fn columns(conn: &PgConnection, namespace: &str, table: &str) -> Result<Vec<String>, StoreError> {
+    const QUERY: &str = "
+        select column_name::text
+          from information_schema.columns
+         where table_schema = $1 and table_name = $2 and column_name <> 'vid'
+         order by ordinal_position";
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Text, _>(namespace)
+        .bind::<Text, _>(table)
+        .load::<ColumnName>(conn)?
+        .into_iter()
+        .map(|column| format!("\"{}\"", column.column_name))
+        .collect())
+}
+
+/// Copy all versions of the rows in `table` from `src` into the table
+/// with the same name in `dst` and return how many rows were copied. If
+/// `prefixes` is given, only copy rows whose id starts with one of the
+/// prefixes, which all must have the given length
+fn copy_rows(
+    src_conn: &PgConnection,
+    dst_conn: &PgConnection,
+    table: &Table,
+    src: &Namespace,
+    dst: &Namespace,
+    prefixes: Option<(&Vec<String>, i32)>,
+) -> Result<usize, StoreError> {
+    let src_table = SqlName::qualified_name(src, &table.name);
+    let dst_table = SqlName::qualified_name(dst, &table.name);
+    let columns = columns(dst_conn, dst.as_str(), table.name.as_str())?.join(", ");
+    let filter = match prefixes {
+        Some(_) => "and left(c.id, $3) = any($4)",
+        None => "",
+    };
+    let select = format!(
+        "select c.vid, to_jsonb(c.*) as data from {} c where c.vid > $1 {} order by c.vid limit $2",
+        src_table, filter
+    );
+    let insert = format!(
+        "insert into {dst}({columns}) \
+         select {columns} from jsonb_populate_recordset(null::{dst}, $1)",
+        dst = dst_table,
+        columns = columns
+    );
+
+    let mut count = 0;
+    let mut after_vid = -1;
+    loop {
+        let query = diesel::sql_query(&select)
+            .bind::<BigInt, _>(after_vid)
+            .bind::<BigInt, _>(BATCH_SIZE);
+        let rows = match prefixes {
+            Some((prefixes, len)) => query
+                .bind::<Integer, _>(len)
+                .bind::<Array<Text>, _>(prefixes)
+                .load::<Row>(src_conn)?,
+            None => query.load::<Row>(src_conn)?,
+        };
+        let last_vid = match rows.last() {
+            Some(row) => row.vid,
+            None => break,
+        };
+        count += rows.len();
+        let rows = serde_json::Value::Array(rows.into_iter().map(|row| row.data).collect());
+        diesel::sql_query(&insert)
+            .bind::<Jsonb, _>(rows)
+            .execute(dst_conn)?;
+        after_vid = last_vid;
+    }
+    Ok(count)
+}
+
+/// The state of copying a deployment into another shard
+pub(crate) struct DeploymentCopy<'a> {
+    logger: Logger,
+    src: &'a Store,
+    src_site: Arc<Site>,
+    dst: &'a Store,
+    dst_site: Site,
+    /// The layout of the copy; `None` until the data has been copied
+    layout: Option<Layout>,
+    /// The copy contains all changes of the source up to this block
+    block: BlockNumber,
+    /// The head and reorg count of the source when we last read changes
+    src_head: BlockNumber,
+    reorg_count: u32,
+}
+
+impl<'a> DeploymentCopy<'a> {
+    pub fn new(
+        logger: &Logger,
+        src: &'a Store,
+        src_site: Arc<Site>,
+        dst: &'a Store,
+        dst_site: Site,
+    ) -> Self {
+        let logger = logger.new(o!("deployment" => src_site.deployment.to_string()));
+        Self {
+            logger,
+            src,
+            src_site,
+            dst,
+            dst_site,
+            layout: None,
+            block: 0,
+            src_head: 0,
+            reorg_count: 0,
+        }
+    }
+
+    fn deployment(&self) -> &SubgraphDeploymentId {
+        &self.src_site.deployment
+    }
+
+    /// The block up to which the copy contains all changes of the source
+    pub fn block(&self) -> BlockNumber {
+        self.block
+    }
+
+    /// Create the namespace for the copy and copy all entity versions into
+    /// it from a snapshot of the source
+    pub fn copy_data(&mut self) -> Result<(), StoreError> {
+        let start = Instant::now();
+        let src_conn = self.src.get_conn()?;
+        let dst_conn = self.dst.get_conn()?;
+        let id = self.deployment().clone();
+
+        let (layout, head, reorg_count) = src_conn
+            .build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| -> Result<_, StoreError> {
+                let head = deployment::block_ptr(&src_conn, &id)?
+                    .map(|ptr| block_number(&ptr))
+                    .ok_or_else(|| {
+                        StoreError::Unknown(anyhow!(
+                            "deployment `{}` has not started syncing yet",
+                            id
+                        ))
+                    })?;
+                let reorg_count = deployment::reorg_count(&src_conn, &id)?;
+                let src_layout = self.src.layout(&src_conn, &self.src_site.namespace, &id)?;
+                let schema = deployment::schema(&src_conn, id.clone())?;
+
+                let layout = dst_conn.transaction(|| -> Result<_, StoreError> {
+                    let namespace = &self.dst_site.namespace;
+                    dst_conn.batch_execute(&format!("create schema {}", namespace))?;
+                    let layout =
+                        Layout::create_relational_schema(&dst_conn, &schema, namespace.clone())?;
+                    for table in src_layout.tables.values() {
+                        let count = copy_rows(
+                            &src_conn,
+                            &dst_conn,
+                            table,
+                            &self.src_site.namespace,
+                            namespace,
+                            None,
+                        )?;
+                        info!(self.logger, "Copied {} {} versions", count, table.object);
+                    }
+                    Ok(layout)
+                })?;
+                Ok((layout, head, reorg_count))
+            })?;
+
+        info!(self.logger, "Copied entities up to block {}", head;
+              "time_ms" => start.elapsed().as_millis());
+        self.layout = Some(layout);
+        self.block = head;
+        self.src_head = head;
+        self.reorg_count = reorg_count;
+        Ok(())
+    }
+
+    /// Apply the changes that the source made since we last looked at it
+    /// to the copy, and return how many blocks the copy is behind the
+    /// source. The source is read in a consistent snapshot
+    pub fn catch_up(&mut self) -> Result<BlockNumber, StoreError> {
+        let src_conn = self.src.get_conn()?;
+        let dst_conn = self.dst.get_conn()?;
+        src_conn
+            .build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| self.catch_up_with(&src_conn, &dst_conn))
+    }
+
+    fn catch_up_with(
+        &mut self,
+        src_conn: &PgConnection,
+        dst_conn: &PgConnection,
+    ) -> Result<BlockNumber, StoreError> {
+        let id = self.deployment().clone();
+        let layout = self
+            .layout
+            .as_ref()
+            .expect("the data is copied before catching up");
+        let head = deployment::block_ptr(src_conn, &id)?
+            .map(|ptr| block_number(&ptr))
+            .unwrap_or(0);
+        let reorg_count = deployment::reorg_count(src_conn, &id)?;
+
+        let mut block = self.block;
+        dst_conn.transaction(|| -> Result<_, StoreError> {
+            // Each revert moves the source back by exactly one block. If
+            // there were `n` reverts since we last looked, no block up to
+            // `n` blocks before the previous head has been touched, but
+            // anything after that might have been reverted
+            if reorg_count > self.reorg_count {
+                let reverted = self.src_head - (reorg_count - self.reorg_count) as BlockNumber;
+                if reverted < block {
+                    layout.revert_block(dst_conn, &id, reverted + 1)?;
+                    info!(self.logger, "Reverted copy to block {}", reverted);
+                    block = reverted;
+                }
+            }
+
+            let src_layout = self.src.layout(src_conn, &self.src_site.namespace, &id)?;
+            let to = head.min(block + MAX_BLOCKS);
+            if to > block {
+                for changes in src_layout.entity_changes(src_conn, &id, block, to, true)? {
+                    for modification in changes.mods {
+                        match modification {
+                            EntityModification::Insert { key, data } => {
+                                layout.insert(dst_conn, &key, data, changes.block)?
+                            }
+                            EntityModification::Overwrite { key, data } => {
+                                layout.update(dst_conn, &key, data, changes.block)?
+                            }
+                            EntityModification::Remove { key } => {
+                                layout.delete(dst_conn, &key, changes.block).map(|_| ())?
+                            }
+                        }
+                    }
+                }
+                block = to;
+            }
+            Ok(())
+        })?;
+
+        self.block = block;
+        self.src_head = head;
+        self.reorg_count = reorg_count;
+        Ok(head - block)
+    }
+
+    /// Copy the remaining changes and the metadata of the deployment while
+    /// the source is locked, remove the metadata from the source, and call
+    /// `switch` to make the copy the deployment that is used from now on.
+    /// Indexing of the source should have been told to stop before calling
+    /// this; a block that is being written when we try to lock the source
+    /// is waited for and copied, and writes of later blocks to the source
+    /// fail
+    pub fn finish<F>(&mut self, switch: F) -> Result<(), StoreError>
+    where
+        F: FnOnce() -> Result<(), StoreError>,
+    {
+        let start = Instant::now();
+        let src_conn = self.src.get_conn()?;
+        let dst_conn = self.dst.get_conn()?;
+        let id = self.deployment().clone();
+
+        src_conn.transaction(|| -> Result<_, StoreError> {
+            deployment::lock(&src_conn, &id)?;
+            while self.catch_up_with(&src_conn, &dst_conn)? > 0 {}
+
+            dst_conn.transaction(|| -> Result<_, StoreError> {
+                // Metadata entries for the deployment have ids that start
+                // with the deployment id, and entries for dynamic data
+                // sources ids that start with the id of the data source
+                let deployment_prefix = vec![id.to_string()];
+                let dds = dynds::ids(&src_conn, id.as_str(), BLOCK_NUMBER_MAX)?;
+                let mut prefixes = vec![(&deployment_prefix, id.as_str().len() as i32)];
+                if !dds.is_empty() {
+                    prefixes.push((&dds, DDS_ID_LEN));
+                }
+
+                // Remove leftovers from an earlier copy of the deployment in
+                // this shard
+                Layout::drop_metadata(&dst_conn, &id)?;
+                for table in Layout::deployment_metadata_tables() {
+                    for prefixes in &prefixes {
+                        copy_rows(
+                            &src_conn,
+                            &dst_conn,
+                            table,
+                            &METADATA_NAMESPACE,
+                            &METADATA_NAMESPACE,
+                            Some(*prefixes),
+                        )?;
+                    }
+                }
+                change_log::copy(&src_conn, &dst_conn, &id)
+            })?;
+            info!(self.logger, "Copied metadata at block {}", self.block);
+
+            // Without its metadata, nothing can be written to the source
+            // anymore, and nothing gets lost by writing to it by mistake
+            Layout::drop_metadata(&src_conn, &id)?;
+
+            switch()
+        })?;
+
+        // The site now points to the copy, and nothing reads the entities
+        // of the source anymore. The move has succeeded at this point, and
+        // failing to drop the source must not undo it
+        if let Err(e) = deployment::drop_schema(&src_conn, &self.src_site.namespace) {
+            warn!(self.logger, "Failed to drop the source of the copy";
+                  "namespace" => self.src_site.namespace.to_string(),
+                  "error" => e.to_string());
+        }
+
+        info!(self.logger, "Finished copying deployment";
+              "block" => self.block,
+              "time_ms" => start.elapsed().as_millis());
+        Ok(())
+    }
+
+    /// Remove everything that we copied so far. The metadata of the copy is
+    /// only written when the copy is finished and does not need to be
+    /// removed
+    pub fn drop(&mut self) -> Result<(), StoreError> {
+        let dst_conn = self.dst.get_conn()?;
+        dst_conn.batch_execute(&format!(
+            "drop schema if exists {} cascade",
+            self.dst_site.namespace
+        ))?;
+        self.layout = None;
+        Ok(())
+    }
+}
@@ -18,6 +18,28 @@ table! {
     }
 }
 
+table! {
+    ethereum_block_traces (hash) {
+        hash -> Varchar,
+        number -> BigInt,
+        network_name -> Varchar, // REFERENCES ethereum_networks (name),
+        traces -> Jsonb,
+    }
+}
+
+table! {
+    ethereum_reorgs (id) {
+        id -> Integer,
+        network_name -> Varchar, // REFERENCES ethereum_networks (name),
+        old_head_hash -> Varchar,
+        old_head_number -> BigInt,
+        new_head_hash -> Varchar,
+        new_head_number -> BigInt,
+        depth -> BigInt,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     large_notifications(id) {
         id -> Integer,
@@ -61,6 +61,10 @@ table! {
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
         block_range -> Range<Integer>,
+        // The following columns are not part of the GraphQL schema for
+        // the metadata subgraph
+        history_blocks -> Nullable<Integer>,
+        earliest_block -> Integer,
     }
 }
 
@@ -189,13 +193,18 @@ pub fn graft_point(
 }
 
 pub fn schema(conn: &PgConnection, id: SubgraphDeploymentId) -> Result<Schema, StoreError> {
+    let s = raw_schema(conn, &id)?;
+    Schema::parse(s.as_str(), id).map_err(|e| StoreError::Unknown(e))
+}
+
+/// The GraphQL schema of the deployment as it was given in its manifest
+pub fn raw_schema(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<String, StoreError> {
     use subgraph_manifest as sm;
     let manifest_id = SubgraphManifestEntity::id(&id);
-    let s: String = sm::table
+    Ok(sm::table
         .select(sm::schema)
         .filter(sm::id.eq(manifest_id.as_str()))
-        .first(conn)?;
-    Schema::parse(s.as_str(), id).map_err(|e| StoreError::Unknown(e))
+        .first(conn)?)
 }
 
 pub fn manifest_info(
@@ -402,6 +411,95 @@ pub fn state(conn: &PgConnection, id: SubgraphDeploymentId) -> Result<Deployment
     }
 }
 
+/// Lock the metadata of deployment `id` until the end of the current
+/// transaction. Since processing a block updates the block pointer of the
+/// deployment, this keeps anybody from changing the deployment
+pub fn lock(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::id)
+        .for_update()
+        .first::<String>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))?;
+    Ok(())
+}
+
+/// Return the number of blocks that have been reverted for the deployment
+/// `id` over its lifetime
+pub fn reorg_count(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<u32, StoreError> {
+    use subgraph_deployment as d;
+
+    let count = d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::reorg_count)
+        .first::<i32>(conn)?;
+    convert_to_u32(Some(count), "reorg_count", id.as_str())
+}
+
+/// Return the number of blocks of entity history that should be kept for
+/// the deployment `id`. `None` means that the entire history is kept
+pub fn history_blocks(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<BlockNumber>, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::history_blocks)
+        .first::<Option<i32>>(conn)?)
+}
+
+/// Set the number of blocks of entity history that should be kept for the
+/// deployment `id`. Setting this does not remove any history by itself,
+/// that only happens when the deployment gets pruned
+pub fn set_history_blocks(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    history_blocks: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::history_blocks.eq(history_blocks))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Return the earliest block for which the deployment `id` still has the
+/// complete entity history. Queries for, and reverts to, blocks before
+/// that are not possible since pruning has removed the entity versions
+/// they would need
+pub fn earliest_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<BlockNumber, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::id.eq(id.as_str()))
+        .select(d::earliest_block)
+        .first::<i32>(conn)?)
+}
+
+/// Record that the entity history for the deployment `id` has been pruned
+/// and is only complete from `earliest_block` on
+pub fn set_earliest_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    earliest_block: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::earliest_block.eq(earliest_block))
+        .execute(conn)?;
+    Ok(())
+}
+
 /// Mark the deployment `id` as synced
 pub fn set_synced(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
     use subgraph_deployment as d;
@@ -0,0 +1,840 @@
+//! Export the entities of a deployment as they were at a given block, and
+//! restore a deployment from such an export. A dump is a directory with one
+//! file per entity type, a `metadata` subdirectory with one file per
+//! metadata type, and a manifest that describes the block, the schema and
+//! the files of the dump.
+//!
+//! Values are written as follows:
+//!
+//! * `Boolean` and `Int` attributes are written as booleans and numbers
+//! * `BigInt` and `BigDecimal` attributes are written as their decimal
+//!   string representation so that no precision is lost
+//! * `Bytes` attributes are written as binary data in Parquet and as hex
+//!   strings with a `0x` prefix otherwise
+//! * `String` and enum attributes are written as strings
+//! * list attributes are written as JSON arrays; in Parquet and CSV, the
+//!   JSON array is stored as a string
+//!
+//! In CSV files, `null` is written as `\N`. Values that start with a
+//! backslash are escaped with another backslash so that they can be told
+//! apart from `null`; an empty field is an empty string
+use diesel::pg::PgConnection;
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+use parquet::record::Field;
+use parquet::schema::parser::parse_message_type;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use graph::data::store::scalar;
+use graph::data::subgraph::schema::MetadataType;
+use graph::prelude::{
+    anyhow::{anyhow, bail},
+    serde_json, BlockNumber, Deserialize, Entity, EntityKey, Error, Serialize,
+    SubgraphDeploymentId, Value,
+};
+
+use crate::relational::{Column, ColumnType, Layout, Table, METADATA_LAYOUT};
+
+/// The name of the file in a dump directory that describes the dump
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The subdirectory of a dump directory that contains the metadata of the
+/// deployment
+const METADATA_DIR: &str = "metadata";
+
+/// The length of the ids of dynamic data sources; metadata entries for a
+/// dynamic data source have ids that start with the id of the data source
+const DDS_ID_LEN: i32 = 40;
+
+const DDS: MetadataType = MetadataType::DynamicEthereumContractDataSource;
+
+/// How `null` is written in CSV files
+const CSV_NULL: &str = "\\N";
+
+/// The number of entities we read from the database at once. For Parquet
+/// files, each batch becomes one row group
+const BATCH_SIZE: i64 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+    Parquet,
+    Csv,
+    Jsonl,
+}
+
+impl DumpFormat {
+    /// The extension for the files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DumpFormat::Parquet => "parquet",
+            DumpFormat::Csv => "csv",
+            DumpFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+impl FromStr for DumpFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(DumpFormat::Parquet),
+            "csv" => Ok(DumpFormat::Csv),
+            "jsonl" => Ok(DumpFormat::Jsonl),
+            _ => Err(anyhow!(
+                "unknown dump format `{}`; use one of `parquet`, `csv`, or `jsonl`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// The block at which a dump was taken. The `hash` is only known if the
+/// block is still in the block cache of the deployment's network
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpBlock {
+    pub number: BlockNumber,
+    pub hash: Option<String>,
+}
+
+/// An attribute of an entity type in a dump
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpColumn {
+    /// The name of the attribute in the GraphQL schema
+    pub name: String,
+    /// The GraphQL type of the attribute, e.g., `[BigInt!]!`
+    pub field_type: String,
+    /// The type of the values of the attribute
+    pub column_type: String,
+}
+
+/// The file for one entity type in a dump
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpTable {
+    pub entity: String,
+    /// The name of the file relative to the dump directory
+    pub file: String,
+    pub rows: usize,
+    pub columns: Vec<DumpColumn>,
+}
+
+/// The description of a dump that is stored in `MANIFEST_FILE` in the
+/// dump directory
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DumpManifest {
+    pub deployment: String,
+    pub network: Option<String>,
+    pub block: DumpBlock,
+    pub format: DumpFormat,
+    /// The GraphQL schema of the deployment
+    pub schema: String,
+    pub tables: Vec<DumpTable>,
+    /// The metadata of the deployment, including its dynamic data sources
+    pub metadata: Vec<DumpTable>,
+}
+
+impl DumpManifest {
+    pub fn read(dir: &Path) -> Result<Self, Error> {
+        let file = File::open(dir.join(MANIFEST_FILE))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), Error> {
+        let file = File::create(dir.join(MANIFEST_FILE))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// A writer for the file of one entity type
+trait TableWriter {
+    fn write(&mut self, entities: &[Entity]) -> Result<(), Error>;
+
+    fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// Write all entities that are visible at `block` into `dir`, one file per
+/// table in `layout`. The manifest for the dump is not written; that is up
+/// to the caller, which must also make sure that all reads happen in the
+/// same transaction
+pub fn dump(
+    conn: &PgConnection,
+    layout: &Layout,
+    block: BlockNumber,
+    format: DumpFormat,
+    dir: &Path,
+) -> Result<Vec<DumpTable>, Error> {
+    if dir.join(MANIFEST_FILE).exists() {
+        bail!("the directory {} already contains a dump", dir.display());
+    }
+    fs::create_dir_all(dir)?;
+
+    let mut tables: Vec<_> = layout.tables.values().collect();
+    tables.sort_by(|a, b| a.object.cmp(&b.object));
+
+    tables
+        .into_iter()
+        .map(|table| dump_table(conn, layout, table, block, &[None], format, dir, ""))
+        .collect()
+}
+
+/// Write the metadata of `deployment` as it was at `block` into the
+/// `METADATA_DIR` subdirectory of `dir`, one file per metadata table. This
+/// includes the dynamic data sources of the deployment that existed at
+/// `block`
+pub fn dump_metadata(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+    block: BlockNumber,
+    format: DumpFormat,
+    dir: &Path,
+) -> Result<Vec<DumpTable>, Error> {
+    fs::create_dir_all(dir.join(METADATA_DIR))?;
+
+    // Metadata entries for the deployment have ids that start with the
+    // deployment id, and entries for dynamic data sources ids that start
+    // with the id of the data source
+    let deployment_prefix = vec![deployment.to_string()];
+    let dds = crate::dynds::ids(conn, deployment.as_str(), block)?;
+    let mut prefixes = vec![Some((&deployment_prefix, deployment.as_str().len() as i32))];
+    if !dds.is_empty() {
+        prefixes.push(Some((&dds, DDS_ID_LEN)));
+    }
+
+    let mut tables: Vec<_> = Layout::deployment_metadata_tables().collect();
+    tables.sort_by(|a, b| a.object.cmp(&b.object));
+
+    tables
+        .into_iter()
+        .map(|table| {
+            dump_table(
+                conn,
+                &METADATA_LAYOUT,
+                table,
+                block,
+                &prefixes,
+                format,
+                dir,
+                METADATA_DIR,
+            )
+        })
+        .collect()
+}
+
+/// Write the entities in `table` that are visible at `block` into a file
+/// in the subdirectory `subdir` of `dir`. For each entry in `prefixes`,
+/// write the entities that `Layout::dump_batch` returns for that prefix
+fn dump_table(
+    conn: &PgConnection,
+    layout: &Layout,
+    table: &Table,
+    block: BlockNumber,
+    prefixes: &[Option<(&Vec<String>, i32)>],
+    format: DumpFormat,
+    dir: &Path,
+    subdir: &str,
+) -> Result<DumpTable, Error> {
+    let columns: Vec<_> = table
+        .columns
+        .iter()
+        .filter(|column| !column.is_fulltext())
+        .collect();
+    let file = Path::new(subdir)
+        .join(format!("{}.{}", table.name.as_str(), format.extension()))
+        .to_string_lossy()
+        .into_owned();
+    let path = dir.join(&file);
+    let mut writer: Box<dyn TableWriter + '_> = match format {
+        DumpFormat::Parquet => Box::new(ParquetTableWriter::new(&path, table, &columns)?),
+        DumpFormat::Csv => Box::new(CsvTableWriter::new(&path, &columns)?),
+        DumpFormat::Jsonl => Box::new(JsonlTableWriter::new(&path, &columns)?),
+    };
+
+    let mut rows = 0;
+    for prefixes in prefixes {
+        let mut after_vid = -1;
+        loop {
+            let batch =
+                layout.dump_batch(conn, table, block, prefixes.clone(), after_vid, BATCH_SIZE)?;
+            let last_vid = match batch.last() {
+                Some((vid, _)) => *vid,
+                None => break,
+            };
+            let entities: Vec<_> = batch.into_iter().map(|(_, entity)| entity).collect();
+            writer.write(&entities)?;
+            rows += entities.len();
+            after_vid = last_vid;
+        }
+    }
+    writer.finish()?;
+
+    Ok(DumpTable {
+        entity: table.object.clone(),
+        file,
+        rows,
+        columns: columns
+            .iter()
+            .map(|column| DumpColumn {
+                name: column.field.clone(),
+                field_type: column.field_type.to_string(),
+                column_type: column_type_name(&column.column_type).to_owned(),
+            })
+            .collect(),
+    })
+}
+
+/// Insert the metadata from the dump in `dir` for `deployment`. The
+/// `SubgraphDeployment` entry is changed so that the deployment starts
+/// out healthy at the block of the dump, and without a graft base since
+/// the dump contains all the data the deployment needs
+pub fn restore_metadata(
+    conn: &PgConnection,
+    deployment: &SubgraphDeploymentId,
+    manifest: &DumpManifest,
+    dir: &Path,
+) -> Result<(), Error> {
+    if manifest.metadata.is_empty() {
+        bail!("the dump does not contain the metadata for the deployment");
+    }
+    let entity_count: usize = manifest.tables.iter().map(|table| table.rows).sum();
+
+    // Entries for dynamic data sources were created at the block at which
+    // the data source was created. We restore the data sources first so
+    // that we know that block for the other entries of each data source
+    let mut dds_blocks: HashMap<String, BlockNumber> = HashMap::new();
+    let mut metadata: Vec<_> = manifest.metadata.iter().collect();
+    metadata.sort_by_key(|dumped| dumped.entity != DDS.to_string());
+
+    for dumped in metadata {
+        let table = METADATA_LAYOUT.table_for_entity(&dumped.entity)?;
+        let entity_type = MetadataType::from_str(&dumped.entity)
+            .map_err(|_| anyhow!("unknown metadata type `{}`", dumped.entity))?;
+        read_entities(dir, manifest.format, dumped, table, |mut entity| {
+            let key = EntityKey::metadata(deployment.clone(), entity_type.clone(), entity.id()?);
+            match &entity_type {
+                MetadataType::SubgraphDeployment => {
+                    reset_deployment(&mut entity, &manifest.block, entity_count)?;
+                    METADATA_LAYOUT.insert_unversioned(conn, &key, entity)?;
+                }
+                MetadataType::SubgraphError => {
+                    // The restored deployment starts out without errors
+                }
+                MetadataType::DynamicEthereumContractDataSource => {
+                    let block = match entity.get("ethereumBlockNumber") {
+                        Some(Value::BigInt(number)) => number.to_string().parse::<BlockNumber>()?,
+                        _ => bail!("dynamic data source {} has no block number", key.entity_id),
+                    };
+                    dds_blocks.insert(key.entity_id.clone(), block);
+                    METADATA_LAYOUT.insert(conn, &key, entity, block)?;
+                }
+                _ => {
+                    let dds_id = key.entity_id.get(..DDS_ID_LEN as usize);
+                    match dds_id.and_then(|dds_id| dds_blocks.get(dds_id)) {
+                        Some(block) => METADATA_LAYOUT.insert(conn, &key, entity, *block)?,
+                        None => METADATA_LAYOUT.insert_unversioned(conn, &key, entity)?,
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn reset_deployment(
+    entity: &mut Entity,
+    block: &DumpBlock,
+    entity_count: usize,
+) -> Result<(), Error> {
+    let hash = block
+        .hash
+        .as_ref()
+        .ok_or_else(|| anyhow!("the hash of block {} is not known", block.number))?;
+    entity.set("latestEthereumBlockHash", scalar::Bytes::from_str(hash)?);
+    entity.set(
+        "latestEthereumBlockNumber",
+        scalar::BigInt::from(block.number),
+    );
+    entity.set("entityCount", scalar::BigInt::from(entity_count as u64));
+    entity.set("health", "healthy");
+    entity.set("failed", false);
+    entity.set("nonFatalErrors", Value::List(vec![]));
+    for attr in &[
+        "fatalError",
+        "lastHealthyEthereumBlockHash",
+        "lastHealthyEthereumBlockNumber",
+        "graftBase",
+        "graftBlockHash",
+        "graftBlockNumber",
+    ] {
+        entity.remove(*attr);
+    }
+    Ok(())
+}
+
+/// Insert the entities from the dump in `dir` into the tables of `layout`
+/// for `deployment`. All entities are inserted at the block of the dump,
+/// `BATCH_SIZE` entities at a time. Returns the number of entities that
+/// were inserted
+pub fn restore(
+    conn: &PgConnection,
+    layout: &Layout,
+    deployment: &SubgraphDeploymentId,
+    manifest: &DumpManifest,
+    dir: &Path,
+) -> Result<usize, Error> {
+    let block = manifest.block.number;
+    let mut count = 0;
+    for dumped in &manifest.tables {
+        let table = layout.table_for_entity(&dumped.entity)?;
+        let mut batch = Vec::new();
+        count += read_entities(dir, manifest.format, dumped, table, |entity| {
+            let key = EntityKey::data(deployment.clone(), dumped.entity.clone(), entity.id()?);
+            batch.push((key, entity));
+            if batch.len() as i64 >= BATCH_SIZE {
+                layout.insert_many(conn, &dumped.entity, std::mem::take(&mut batch), block)?;
+            }
+            Ok(())
+        })?;
+        if !batch.is_empty() {
+            layout.insert_many(conn, &dumped.entity, batch, block)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Read the entities from the file for `dumped` and call `f` for each of
+/// them. Returns the number of entities that were read
+fn read_entities(
+    dir: &Path,
+    format: DumpFormat,
+    dumped: &DumpTable,
+    table: &Table,
+    mut f: impl FnMut(Entity) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    fn set(entity: &mut Entity, column: &Column, value: Value) {
+        if value != Value::Null {
+            entity.set(column.field.as_str(), value);
+        }
+    }
+
+    let path = dir.join(&dumped.file);
+    let mut count = 0;
+    match format {
+        DumpFormat::Parquet => {
+            let reader = SerializedFileReader::new(File::open(&path)?)?;
+            for row in reader.get_row_iter(None)? {
+                let mut entity = Entity::new();
+                for (field, value) in row.get_column_iter() {
+                    let column = table.column_for_field(field)?;
+                    set(&mut entity, column, parquet_value(column, value)?);
+                }
+                f(entity)?;
+                count += 1;
+            }
+        }
+        DumpFormat::Csv => {
+            let mut reader = csv::Reader::from_path(&path)?;
+            let columns = reader
+                .headers()?
+                .iter()
+                .map(|field| table.column_for_field(field))
+                .collect::<Result<Vec<_>, _>>()?;
+            for record in reader.records() {
+                let record = record?;
+                let mut entity = Entity::new();
+                for (column, text) in columns.iter().zip(record.iter()) {
+                    if let Some(text) = csv_unescape(text) {
+                        set(&mut entity, column, text_to_value(column, text)?);
+                    }
+                }
+                f(entity)?;
+                count += 1;
+            }
+        }
+        DumpFormat::Jsonl => {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_str(&line?)?;
+                let mut entity = Entity::new();
+                for (field, value) in line {
+                    let column = table.column_for_field(&field)?;
+                    set(&mut entity, column, json_to_value(column, value)?);
+                }
+                f(entity)?;
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+fn column_type_name(column_type: &ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Boolean => "Boolean",
+        ColumnType::BigDecimal => "BigDecimal",
+        ColumnType::BigInt => "BigInt",
+        ColumnType::Bytes => "Bytes",
+        ColumnType::Int => "Int",
+        ColumnType::String => "String",
+        ColumnType::TSVector(_) => "TSVector",
+        ColumnType::Enum(_) => "Enum",
+        ColumnType::BytesId => "BytesId",
+    }
+}
+
+fn is_required(column: &Column) -> bool {
+    column.is_primary_key() || !column.is_nullable()
+}
+
+/// The text for a CSV field: `\N` for `null`, and `text` with a leading
+/// backslash escaped otherwise
+fn csv_escape(text: Option<String>) -> String {
+    match text {
+        None => CSV_NULL.to_owned(),
+        Some(text) if text.starts_with('\\') => format!("\\{}", text),
+        Some(text) => text,
+    }
+}
+
+/// Reverse `csv_escape`; returns `None` for `null`
+fn csv_unescape(field: &str) -> Option<&str> {
+    if field == CSV_NULL {
+        None
+    } else if field.starts_with('\\') {
+        Some(&field[1..])
+    } else {
+        Some(field)
+    }
+}
+
+/// The value of `column` in `entity`, or `None` if it is `null`
+fn column_value<'a>(column: &Column, entity: &'a Entity) -> Option<&'a Value> {
+    entity.get(&column.field).filter(|value| !value.is_null())
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    use serde_json::Value as j;
+
+    match value {
+        Value::String(s) => j::String(s.clone()),
+        Value::Int(i) => j::from(*i),
+        Value::Bool(b) => j::Bool(*b),
+        Value::Null => j::Null,
+        Value::List(values) => j::Array(values.iter().map(json_value).collect()),
+        Value::BigDecimal(_) | Value::BigInt(_) | Value::Bytes(_) => j::String(value.to_string()),
+    }
+}
+
+/// The representation of `value` as text for CSV files and text columns
+/// in Parquet files
+fn text_value(value: &Value) -> String {
+    match value {
+        Value::List(_) => json_value(value).to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Parse the text representation of a scalar value of `column`
+fn scalar_from_str(column: &Column, s: &str) -> Result<Value, Error> {
+    Ok(match &column.column_type {
+        ColumnType::Boolean => Value::Bool(bool::from_str(s)?),
+        ColumnType::Int => Value::Int(i32::from_str(s)?),
+        ColumnType::BigInt => Value::BigInt(scalar::BigInt::from_str(s)?),
+        ColumnType::BigDecimal => Value::BigDecimal(scalar::BigDecimal::from_str(s)?),
+        ColumnType::Bytes => Value::Bytes(scalar::Bytes::from_str(s)?),
+        ColumnType::String
+        | ColumnType::Enum(_)
+        | ColumnType::BytesId
+        | ColumnType::TSVector(_) => Value::String(s.to_owned()),
+    })
+}
+
+fn scalar_from_json(column: &Column, json: serde_json::Value) -> Result<Value, Error> {
+    use serde_json::Value as j;
+
+    match json {
+        j::Bool(b) => Ok(Value::Bool(b)),
+        j::Number(number) => number
+            .as_i64()
+            .and_then(|number| i32::try_from(number).ok())
+            .map(Value::Int)
+            .ok_or_else(|| anyhow!("invalid number {} for attribute {}", number, column.field)),
+        j::String(s) => scalar_from_str(column, &s),
+        _ => Err(anyhow!(
+            "unexpected value `{}` for attribute {}",
+            json,
+            column.field
+        )),
+    }
+}
+
+/// Convert a value that was written with `json_value` back into a `Value`
+fn json_to_value(column: &Column, json: serde_json::Value) -> Result<Value, Error> {
+    use serde_json::Value as j;
+
+    match json {
+        j::Null => Ok(Value::Null),
+        j::Array(values) if column.is_list() => values
+            .into_iter()
+            .map(|value| scalar_from_json(column, value))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::List),
+        json if !column.is_list() => scalar_from_json(column, json),
+        json => Err(anyhow!(
+            "unexpected value `{}` for list attribute {}",
+            json,
+            column.field
+        )),
+    }
+}
+
+/// Convert a value that was written with `text_value` back into a `Value`
+fn text_to_value(column: &Column, text: &str) -> Result<Value, Error> {
+    if column.is_list() {
+        json_to_value(column, serde_json::from_str(text)?)
+    } else {
+        scalar_from_str(column, text)
+    }
+}
+
+/// Convert a value that was written by a `ParquetTableWriter` back into a
+/// `Value`
+fn parquet_value(column: &Column, field: &Field) -> Result<Value, Error> {
+    match field {
+        Field::Null => Ok(Value::Null),
+        Field::Bool(b) => Ok(Value::Bool(*b)),
+        Field::Int(i) => Ok(Value::Int(*i)),
+        Field::Str(s) => text_to_value(column, s),
+        Field::Bytes(bytes) => {
+            let bytes = scalar::Bytes::from(bytes.data());
+            match column.column_type {
+                ColumnType::BytesId => Ok(Value::String(bytes.to_string())),
+                _ => Ok(Value::Bytes(bytes)),
+            }
+        }
+        _ => Err(anyhow!(
+            "unexpected value `{}` for attribute {}",
+            field,
+            column.field
+        )),
+    }
+}
+
+struct CsvTableWriter<'a> {
+    writer: csv::Writer<File>,
+    columns: &'a [&'a Column],
+}
+
+impl<'a> CsvTableWriter<'a> {
+    fn new(path: &Path, columns: &'a [&'a Column]) -> Result<Self, Error> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(columns.iter().map(|column| column.field.as_str()))?;
+        Ok(CsvTableWriter { writer, columns })
+    }
+}
+
+impl TableWriter for CsvTableWriter<'_> {
+    fn write(&mut self, entities: &[Entity]) -> Result<(), Error> {
+        for entity in entities {
+            self.writer.write_record(
+                self.columns
+                    .iter()
+                    .map(|column| csv_escape(column_value(column, entity).map(text_value))),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+struct JsonlTableWriter<'a> {
+    writer: BufWriter<File>,
+    columns: &'a [&'a Column],
+}
+
+impl<'a> JsonlTableWriter<'a> {
+    fn new(path: &Path, columns: &'a [&'a Column]) -> Result<Self, Error> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(JsonlTableWriter { writer, columns })
+    }
+}
+
+impl TableWriter for JsonlTableWriter<'_> {
+    fn write(&mut self, entities: &[Entity]) -> Result<(), Error> {
+        for entity in entities {
+            let line: serde_json::Map<_, _> = self
+                .columns
+                .iter()
+                .map(|column| {
+                    let value = column_value(column, entity)
+                        .map(json_value)
+                        .unwrap_or(serde_json::Value::Null);
+                    (column.field.clone(), value)
+                })
+                .collect();
+            writeln!(self.writer, "{}", serde_json::Value::Object(line))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+struct ParquetTableWriter<'a> {
+    writer: SerializedFileWriter<File>,
+    columns: &'a [&'a Column],
+}
+
+impl<'a> ParquetTableWriter<'a> {
+    fn new(path: &Path, table: &Table, columns: &'a [&'a Column]) -> Result<Self, Error> {
+        let schema = Arc::new(parse_message_type(&Self::schema(table, columns))?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+        Ok(ParquetTableWriter { writer, columns })
+    }
+
+    /// The Parquet schema for `table`, for example
+    /// ```text
+    /// message Thing {
+    ///   REQUIRED BYTE_ARRAY id (UTF8);
+    ///   OPTIONAL INT32 count;
+    ///   OPTIONAL BYTE_ARRAY owner;
+    /// }
+    /// ```
+    fn schema(table: &Table, columns: &[&Column]) -> String {
+        let mut schema = format!("message {} {{\n", table.object);
+        for column in columns {
+            let repetition = if is_required(column) {
+                "REQUIRED"
+            } else {
+                "OPTIONAL"
+            };
+            let (physical_type, logical_type) = match (column.is_list(), &column.column_type) {
+                (false, ColumnType::Boolean) => ("BOOLEAN", ""),
+                (false, ColumnType::Int) => ("INT32", ""),
+                (false, ColumnType::Bytes) | (false, ColumnType::BytesId) => ("BYTE_ARRAY", ""),
+                _ => ("BYTE_ARRAY", " (UTF8)"),
+            };
+            schema.push_str(&format!(
+                "  {} {} {}{};\n",
+                repetition, physical_type, column.field, logical_type
+            ));
+        }
+        schema.push_str("}\n");
+        schema
+    }
+
+    fn byte_array(column: &Column, value: &Value) -> Result<ByteArray, Error> {
+        match (column.is_list(), &column.column_type, value) {
+            (false, ColumnType::Bytes, Value::Bytes(bytes)) => Ok(ByteArray::from(bytes.to_vec())),
+            (false, ColumnType::BytesId, Value::String(id)) => {
+                let bytes = scalar::Bytes::from_str(id)
+                    .map_err(|e| anyhow!("invalid id `{}` for {}: {}", id, column.field, e))?;
+                Ok(ByteArray::from(bytes.to_vec()))
+            }
+            _ => Ok(ByteArray::from(text_value(value).into_bytes())),
+        }
+    }
+
+    fn write_column(
+        writer: &mut ColumnWriter,
+        column: &Column,
+        entities: &[Entity],
+    ) -> Result<(), Error> {
+        let values: Vec<_> = entities
+            .iter()
+            .map(|entity| column_value(column, entity))
+            .collect();
+        let def_levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+        let def_levels = if is_required(column) {
+            if values.iter().any(|value| value.is_none()) {
+                bail!("the non-nullable attribute {} has no value", column.field);
+            }
+            None
+        } else {
+            Some(def_levels.as_slice())
+        };
+        let values = values.into_iter().flatten();
+
+        let unexpected = |value: &Value| {
+            anyhow!(
+                "unexpected value `{}` for attribute {} of type {}",
+                value,
+                column.field,
+                column.field_type
+            )
+        };
+        match writer {
+            ColumnWriter::BoolColumnWriter(writer) => {
+                let values = values
+                    .map(|value| match value {
+                        Value::Bool(b) => Ok(*b),
+                        _ => Err(unexpected(value)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                writer.write_batch(&values, def_levels, None)?;
+            }
+            ColumnWriter::Int32ColumnWriter(writer) => {
+                let values = values
+                    .map(|value| match value {
+                        Value::Int(i) => Ok(*i),
+                        _ => Err(unexpected(value)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                writer.write_batch(&values, def_levels, None)?;
+            }
+            ColumnWriter::ByteArrayColumnWriter(writer) => {
+                let values = values
+                    .map(|value| Self::byte_array(column, value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                writer.write_batch(&values, def_levels, None)?;
+            }
+            _ => unreachable!("we only write boolean, int32, and byte array columns"),
+        }
+        Ok(())
+    }
+}
+
+impl TableWriter for ParquetTableWriter<'_> {
+    fn write(&mut self, entities: &[Entity]) -> Result<(), Error> {
+        let mut row_group = self.writer.next_row_group()?;
+        let mut columns = self.columns.iter();
+        while let Some(mut writer) = row_group.next_column()? {
+            let column = columns
+                .next()
+                .ok_or_else(|| anyhow!("the Parquet schema has more columns than the table"))?;
+            Self::write_column(&mut writer, column, entities)?;
+            row_group.close_column(writer)?;
+        }
+        self.writer.close_row_group(row_group)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Error> {
+        self.writer.close()?;
+        Ok(())
+    }
+}
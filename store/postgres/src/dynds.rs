@@ -4,12 +4,13 @@ use std::ops::Bound;
 
 use diesel::pg::PgConnection;
 use diesel::prelude::{ExpressionMethods, JoinOnDsl, QueryDsl, RunQueryDsl};
+use diesel::sql_types::Bool;
 
 use graph::{
     components::store::StoredDynamicDataSource,
     constraint_violation,
     data::subgraph::Source,
-    prelude::{bigdecimal::ToPrimitive, web3::types::H160, BigDecimal, StoreError},
+    prelude::{bigdecimal::ToPrimitive, web3::types::H160, BigDecimal, BlockNumber, StoreError},
 };
 
 use crate::block_range::first_block_in_range;
@@ -103,6 +104,22 @@ fn to_source(
     })
 }
 
+/// Return the ids of the dynamic data sources of deployment `id` that
+/// exist at `block`, in the order in which they were created
+pub fn ids(conn: &PgConnection, id: &str, block: BlockNumber) -> Result<Vec<String>, StoreError> {
+    use dynamic_ethereum_contract_data_source as decds;
+
+    Ok(decds::table
+        .filter(decds::deployment.eq(id))
+        .filter(diesel::dsl::sql::<Bool>(&format!(
+            "block_range @> {}",
+            block
+        )))
+        .select(decds::id)
+        .order_by((decds::ethereum_block_number, decds::vid))
+        .load::<String>(conn)?)
+}
+
 pub fn load(conn: &PgConnection, id: &str) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
     use dynamic_ethereum_contract_data_source as decds;
     use ethereum_contract_source as ecs;
@@ -28,19 +28,22 @@ use diesel::RunQueryDsl;
 use maybe_owned::MaybeOwned;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::TryInto;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+use graph::data::query::SqlTrace;
 use graph::data::subgraph::schema::{MetadataType, POI_OBJECT, POI_TABLE};
 use graph::prelude::{
-    anyhow, info, BlockNumber, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder,
-    EntityRange, EthereumBlockPointer, Logger, QueryExecutionError, StoreError, StoreEvent,
-    SubgraphDeploymentId,
+    anyhow, info, q, BlockNumber, Entity, EntityAggregation, EntityCollection, EntityCursor,
+    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
+    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId,
 };
 use graph::{components::store::EntityType, data::schema::Schema as SubgraphSchema};
 
 use crate::deployment;
+use crate::dump::{DumpFormat, DumpTable};
 use crate::primary::Site;
 use crate::relational::{Catalog, Layout, METADATA_LAYOUT};
 use crate::{block_range::block_number, primary::Namespace};
@@ -116,6 +119,15 @@ impl Connection<'_> {
             let start = Instant::now();
             let base_layout =
                 &Connection::layout(&self.conn, base.namespace.clone(), &base.deployment)?;
+            if block_number(&block) < base_layout.earliest_block {
+                return Err(StoreError::Unknown(anyhow!(
+                    "Can not graft onto `{}` at block {} since its history \
+                     has been pruned and only goes back to block {}",
+                    base.deployment,
+                    block.number,
+                    base_layout.earliest_block
+                )));
+            }
             layout.copy_from(
                 logger,
                 &self.conn,
@@ -199,15 +211,63 @@ impl Connection<'_> {
         collection: EntityCollection,
         filter: Option<EntityFilter>,
         order: EntityOrder,
+        cursor: Option<EntityCursor>,
+        range: EntityRange,
+        block: BlockNumber,
+        query_id: Option<String>,
+        trace: bool,
+    ) -> Result<(Vec<T>, Option<SqlTrace>), QueryExecutionError> {
+        self.data.query_traced(
+            logger,
+            &self.conn,
+            collection,
+            filter,
+            order,
+            cursor,
+            range,
+            block,
+            self.data.earliest_block,
+            query_id,
+            trace,
+        )
+    }
+
+    pub(crate) fn estimate_cost(
+        &self,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        cursor: Option<EntityCursor>,
         range: EntityRange,
         block: BlockNumber,
         query_id: Option<String>,
-    ) -> Result<Vec<T>, QueryExecutionError> {
-        self.data.query(
-            logger, &self.conn, collection, filter, order, range, block, query_id,
+    ) -> Result<f64, QueryExecutionError> {
+        self.data.estimate_cost(
+            &self.conn, collection, filter, order, cursor, range, block, query_id,
         )
     }
 
+    pub(crate) fn aggregate(
+        &self,
+        query: EntityAggregation,
+    ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
+        self.data
+            .aggregate(&self.conn, query, self.data.earliest_block)
+    }
+
+    /// Remove all entity versions that are not needed to answer queries
+    /// for blocks from `earliest_block` on, and remember that the
+    /// deployment only has its full history from that block on
+    pub(crate) fn prune(
+        &self,
+        logger: &Logger,
+        earliest_block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let count = self.data.prune(logger, &self.conn, earliest_block)?;
+        deployment::set_earliest_block(&self.conn, &self.subgraph, earliest_block)?;
+        Ok(count)
+    }
+
     pub(crate) fn conflicting_entity(
         &self,
         entity_id: &String,
@@ -298,6 +358,19 @@ impl Connection<'_> {
         Ok((event, count))
     }
 
+    /// Write the entities that are visible at `block` into `dir`, one
+    /// file per entity type
+    pub(crate) fn dump(
+        &self,
+        block: BlockNumber,
+        format: DumpFormat,
+        dir: &Path,
+    ) -> Result<Vec<DumpTable>, StoreError> {
+        Ok(crate::dump::dump(
+            &self.conn, &self.data, block, format, dir,
+        )?)
+    }
+
     pub(crate) fn update_entity_count(&self, count: i32) -> Result<(), StoreError> {
         if count == 0 {
             return Ok(());
@@ -408,7 +481,11 @@ impl Connection<'_> {
         let subgraph_schema = deployment::schema(conn, subgraph.to_owned())?;
         let has_poi = supports_proof_of_indexing(conn, &namespace)?;
         let catalog = Catalog::new(conn, namespace)?;
-        let layout = Layout::new(&subgraph_schema, catalog, has_poi)?;
+        let mut layout = Layout::new(&subgraph_schema, catalog, has_poi)?;
+        // Remember how far back the history goes so that queries do not
+        // have to look that up every time. Pruning makes everybody forget
+        // their cached layout
+        layout.earliest_block = deployment::earliest_block(conn, subgraph)?;
 
         Ok(layout)
     }
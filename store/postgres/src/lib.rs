@@ -23,10 +23,13 @@ mod block_range;
 mod catalog;
 mod chain_head_listener;
 mod chain_store;
+mod change_log;
 pub mod connection_pool;
+mod copy;
 mod db_schema;
 mod deployment;
 mod detail;
+mod dump;
 mod dynds;
 mod entities;
 mod functions;
@@ -44,13 +47,16 @@ mod store_events;
 
 #[cfg(debug_assertions)]
 pub mod db_schema_for_tests {
+    pub use crate::db_schema::ethereum_block_traces;
     pub use crate::db_schema::ethereum_blocks;
     pub use crate::db_schema::ethereum_networks;
+    pub use crate::db_schema::ethereum_reorgs;
 }
 
 #[cfg(debug_assertions)]
 pub mod layout_for_tests {
     pub use crate::block_range::*;
+    pub use crate::dump::{dump, restore, DumpBlock, DumpFormat, DumpManifest};
     pub use crate::entities::STRING_PREFIX_SIZE;
     pub use crate::primary::{Connection, Namespace, EVENT_TAP, EVENT_TAP_ENABLED};
     pub use crate::relational::*;
@@ -60,7 +66,7 @@ pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
 pub use self::detail::DeploymentDetail;
 pub use self::network_store::NetworkStore;
-pub use self::primary::UnusedDeployment;
+pub use self::primary::{AllowlistedQuery, UnusedDeployment};
 pub use self::sharded_store::{unused, DeploymentPlacer, Shard, ShardedStore, PRIMARY_SHARD};
 pub use self::store::{Store, StoreConfig};
 pub use self::store_events::SubscriptionManager;
@@ -74,6 +80,7 @@ pub mod command_support {
             deployment_schemas, subgraph, subgraph_deployment_assignment, subgraph_version,
         };
     }
+    pub use crate::dump::{DumpBlock, DumpColumn, DumpFormat, DumpManifest, DumpTable};
     pub use crate::entities::Connection;
     pub use crate::primary::Namespace;
     pub use crate::relational::{Catalog, Column, ColumnType, Layout};
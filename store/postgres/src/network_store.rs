@@ -9,11 +9,11 @@ use graph::{
     data::subgraph::status,
     prelude::{
         ethabi,
-        web3::types::{Address, H256},
-        BlockNumber, ChainHeadUpdateStream, ChainStore as ChainStoreTrait, CheapClone, Error,
-        EthereumBlock, EthereumBlockPointer, EthereumCallCache, Future, LightEthereumBlock, NodeId,
-        Schema, Store as StoreTrait, StoreError, Stream, SubgraphDeploymentEntity,
-        SubgraphDeploymentId, SubgraphName, SubgraphVersionSwitchingMode,
+        web3::types::{Address, Trace, TransactionReceipt, H256},
+        BlockNumber, ChainHeadUpdateStream, ChainStore as ChainStoreTrait, ChangeLogEntry,
+        CheapClone, Error, EthereumBlock, EthereumBlockPointer, EthereumCallCache, Future,
+        LightEthereumBlock, NodeId, Schema, Store as StoreTrait, StoreError, Stream,
+        SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphName, SubgraphVersionSwitchingMode,
     },
 };
 
@@ -147,6 +147,15 @@ impl StoreTrait for NetworkStore {
             .revert_block_operations(subgraph_id, block_ptr_to)
     }
 
+    fn entity_change_log(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        self.store.entity_change_log(subgraph_id, after, limit)
+    }
+
     fn deployment_state_from_name(
         &self,
         name: graph::prelude::SubgraphName,
@@ -218,6 +227,14 @@ impl StoreTrait for NetworkStore {
         self.store.status(filter)
     }
 
+    fn chain_reorgs(
+        &self,
+        network: Option<String>,
+        first: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError> {
+        self.store.chain_reorgs(network, first)
+    }
+
     async fn load_dynamic_data_sources(
         &self,
         subgraph_id: SubgraphDeploymentId,
@@ -287,6 +304,14 @@ impl QueryStoreManager for NetworkStore {
             replica,
         )))
     }
+
+    fn query_allowed(
+        &self,
+        id: &SubgraphDeploymentId,
+        shape_hash: u64,
+    ) -> Result<bool, StoreError> {
+        self.store.query_allowed(id, shape_hash)
+    }
 }
 
 impl EthereumCallCache for NetworkStore {
@@ -332,10 +357,46 @@ impl ChainStoreTrait for NetworkStore {
         self.chain_store.upsert_light_blocks(blocks)
     }
 
+    fn upsert_block(&self, block: EthereumBlock) -> Result<(), Error> {
+        self.chain_store.upsert_block(block)
+    }
+
+    fn transaction_receipts(
+        &self,
+        block_hash: &H256,
+    ) -> Result<Option<Vec<TransactionReceipt>>, Error> {
+        self.chain_store.transaction_receipts(block_hash)
+    }
+
+    fn block_traces(&self, block_hash: &H256) -> Result<Option<Vec<Trace>>, Error> {
+        self.chain_store.block_traces(block_hash)
+    }
+
+    fn set_block_traces(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        traces: &[Trace],
+    ) -> Result<(), Error> {
+        self.chain_store.set_block_traces(block_ptr, traces)
+    }
+
+    fn block_traces_in_range(&self, from: u64, to: u64) -> Result<Option<Vec<Trace>>, Error> {
+        self.chain_store.block_traces_in_range(from, to)
+    }
+
     fn attempt_chain_head_update(&self, ancestor_count: u64) -> Result<Vec<H256>, Error> {
         self.chain_store.attempt_chain_head_update(ancestor_count)
     }
 
+    fn record_reorg(
+        &self,
+        old_head: EthereumBlockPointer,
+        new_head: EthereumBlockPointer,
+        max_depth: u64,
+    ) -> Result<Option<status::ChainReorg>, Error> {
+        self.chain_store.record_reorg(old_head, new_head, max_depth)
+    }
+
     fn chain_head_updates(&self) -> ChainHeadUpdateStream {
         self.chain_store.chain_head_updates()
     }
@@ -4,7 +4,7 @@
 use diesel::{
     data_types::PgTimestamp,
     dsl::{any, exists, not},
-    sql_types::{Array, Text},
+    sql_types::{Array, BigInt, Text},
 };
 use diesel::{
     dsl::{delete, insert_into, sql, update},
@@ -24,9 +24,9 @@ use graph::{
     data::subgraph::status,
     prelude::EthereumBlockPointer,
     prelude::{
-        anyhow, bigdecimal::ToPrimitive, entity, lazy_static, serde_json, EntityChange,
-        EntityChangeOperation, MetadataOperation, NodeId, StoreError, SubgraphDeploymentId,
-        SubgraphName, SubgraphVersionSwitchingMode,
+        anyhow, bigdecimal::ToPrimitive, entity, lazy_static, serde_json, BlockNumber,
+        EntityChange, EntityChangeOperation, MetadataOperation, NodeId, StoreError,
+        SubgraphDeploymentId, SubgraphName, SubgraphVersionSwitchingMode,
     },
 };
 use graph::{data::subgraph::schema::generate_entity_id, prelude::StoreEvent};
@@ -46,6 +46,10 @@ use crate::{
     sharded_store::{unused, Shard},
 };
 
+/// The channel on which we notify other processes that a deployment was
+/// moved to a different shard
+pub(crate) const SITE_CHANGES_CHANNEL: &str = "site_changes";
+
 #[cfg(debug_assertions)]
 use std::sync::Mutex;
 #[cfg(debug_assertions)]
@@ -143,12 +147,32 @@ table! {
     }
 }
 
+table! {
+    /// Deployments that only run queries whose shape is in their allow-list
+    query_allowlist_deployments(deployment) {
+        deployment -> Text,
+    }
+}
+
+table! {
+    /// The allow-lists of deployments, keyed by the shape hash of queries
+    query_allowlist(deployment, shape_hash) {
+        deployment -> Text,
+        shape_hash -> BigInt,
+        /// A query with this shape, for reference
+        query -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(
     subgraph,
     subgraph_version,
     subgraph_deployment_assignment,
     deployment_schemas,
     unused_deployments,
+    query_allowlist_deployments,
+    query_allowlist,
 );
 
 /// Information about the database schema that stores the entities for a
@@ -184,6 +208,21 @@ pub struct UnusedDeployment {
     pub synced: bool,
 }
 
+/// An entry in the query allow-list of a deployment
+#[derive(Clone, Queryable, Debug)]
+pub struct AllowlistedQuery {
+    pub deployment: String,
+    shape_hash: i64,
+    pub query: String,
+    pub created_at: PgTimestamp,
+}
+
+impl AllowlistedQuery {
+    pub fn shape_hash(&self) -> u64 {
+        self.shape_hash as u64
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 /// A namespace (schema) in the database
 pub struct Namespace(String);
@@ -226,7 +265,7 @@ impl fmt::Display for Namespace {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 /// Details about a deployment and the shard in which it is stored. We need
 /// the database namespace for the deployment as that information is only
 /// stored in the primary database
@@ -641,6 +680,92 @@ impl Connection {
         })
     }
 
+    /// Allocate a new database namespace without recording it in
+    /// `deployment_schemas`. The namespace is generated in the same way as
+    /// the namespaces for new deployments so that the two never clash
+    pub fn allocate_namespace(&self) -> Result<Namespace, StoreError> {
+        #[derive(QueryableByName)]
+        struct Name {
+            #[sql_type = "Text"]
+            name: String,
+        }
+
+        let name =
+            diesel::sql_query("select 'sgd' || nextval('deployment_schemas_id_seq') as name")
+                .get_result::<Name>(&self.0)?
+                .name;
+        Namespace::new(name).map_err(|name| {
+            constraint_violation!("Generated database schema name {} is invalid", name)
+        })
+    }
+
+    /// Point the entry in `deployment_schemas` for the deployment of `site`
+    /// to the shard and namespace of `site`. Returns the site the entry
+    /// pointed to before
+    pub fn move_site(&self, site: &Site) -> Result<Site, StoreError> {
+        use deployment_schemas as ds;
+
+        let old = self
+            .find_site(&site.deployment)?
+            .ok_or_else(|| StoreError::DeploymentNotFound(site.deployment.to_string()))?;
+        update(ds::table.filter(ds::subgraph.eq(site.deployment.as_str())))
+            .set((
+                ds::shard.eq(site.shard.as_str()),
+                ds::name.eq(site.namespace.as_str()),
+            ))
+            .execute(&self.0)?;
+        Ok(old)
+    }
+
+    /// Remove the assignment of deployment `id` so that the node that is
+    /// indexing it stops
+    pub fn unassign_subgraph(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<EntityChange>, StoreError> {
+        use subgraph_deployment_assignment as a;
+
+        let deleted = delete(a::table.filter(a::id.eq(id.as_str()))).execute(&self.0)?;
+        match deleted {
+            0 => Ok(vec![]),
+            1 => {
+                let key =
+                    MetadataType::SubgraphDeploymentAssignment.key(id.clone(), id.to_string());
+                Ok(vec![MetadataOperation::Remove { key }.into()])
+            }
+            _ => {
+                // `id` is the primary key of the subgraph_deployment_assignment table,
+                // and we can therefore only delete no or one entry
+                unreachable!()
+            }
+        }
+    }
+
+    /// Assign deployment `id`, which must not be assigned to any node yet,
+    /// to `node`
+    pub fn assign_subgraph(
+        &self,
+        id: &SubgraphDeploymentId,
+        node: &NodeId,
+    ) -> Result<Vec<EntityChange>, StoreError> {
+        use subgraph_deployment_assignment as a;
+
+        insert_into(a::table)
+            .values((
+                a::id.eq(id.as_str()),
+                a::node_id.eq(node.as_str()),
+                a::block_range.eq(UNVERSIONED_RANGE),
+                a::cost.eq(sql("1")),
+            ))
+            .execute(&self.0)?;
+        let key = MetadataType::SubgraphDeploymentAssignment.key(id.clone(), id.to_string());
+        let op = MetadataOperation::Set {
+            key,
+            data: entity! { node_id: node.to_string() },
+        };
+        Ok(vec![op.into()])
+    }
+
     /// Remove all subgraph versions and the entry in `deployment_schemas` for
     /// subgraph `id` in a transaction
     pub fn drop_site(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
@@ -728,6 +853,14 @@ impl Connection {
         JsonNotification::send("store_events", &v, &self.0)
     }
 
+    /// Tell all processes that the deployment `id` is now stored in a
+    /// different place, or that its history was pruned, so that they stop
+    /// using what they cached about it
+    pub fn send_site_change(&self, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+        let v = serde_json::json!({ "deployment": id.as_str() });
+        JsonNotification::send(SITE_CHANGES_CHANNEL, &v, &self.0)
+    }
+
     /// Return the name of the node that has the fewest assignments out of the
     /// given `nodes`. If `nodes` is empty, return `None`
     pub fn least_assigned_node(&self, nodes: &Vec<NodeId>) -> Result<Option<NodeId>, StoreError> {
@@ -852,6 +985,47 @@ impl Connection {
         Ok(infos)
     }
 
+    pub fn chain_reorgs(
+        &self,
+        network: Option<String>,
+        first: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError> {
+        use crate::db_schema::ethereum_reorgs as r;
+
+        let mut query = r::table
+            .select((
+                r::network_name,
+                r::old_head_hash,
+                r::old_head_number,
+                r::new_head_hash,
+                r::new_head_number,
+                r::depth,
+                sql::<BigInt>("extract(epoch from created_at)::bigint"),
+            ))
+            .order_by(r::id.desc())
+            .limit(first as i64)
+            .into_boxed();
+        if let Some(network) = network {
+            query = query.filter(r::network_name.eq(network));
+        }
+
+        query
+            .load::<(String, String, i64, String, i64, i64, i64)>(&self.0)?
+            .into_iter()
+            .map(
+                |(network, old_hash, old_number, new_hash, new_number, depth, timestamp)| -> Result<_, StoreError> {
+                    Ok(status::ChainReorg {
+                        network,
+                        old_head: EthereumBlockPointer::try_from((old_hash.as_str(), old_number))?,
+                        new_head: EthereumBlockPointer::try_from((new_hash.as_str(), new_number))?,
+                        depth: depth as u64,
+                        timestamp: timestamp as u64,
+                    })
+                },
+            )
+            .collect()
+    }
+
     pub fn chain_head_block(&self, network: &str) -> Result<Option<u64>, StoreError> {
         use crate::db_schema::ethereum_networks as n;
 
@@ -874,6 +1048,27 @@ impl Connection {
         )
     }
 
+    /// Return the hash of the block `number` on `network` if the block
+    /// cache contains exactly one block with that number. With uncles in
+    /// the cache, we can't tell which of them is on the main chain
+    pub fn block_hash(
+        &self,
+        network: &str,
+        number: BlockNumber,
+    ) -> Result<Option<String>, StoreError> {
+        use crate::db_schema::ethereum_blocks as b;
+
+        let hashes: Vec<String> = b::table
+            .filter(b::network_name.eq(network))
+            .filter(b::number.eq(number as i64))
+            .select(b::hash)
+            .load(&self.0)?;
+        match hashes.as_slice() {
+            [hash] => Ok(Some(hash.clone())),
+            _ => Ok(None),
+        }
+    }
+
     pub(crate) fn deployments_for_subgraph(&self, name: String) -> Result<Vec<String>, StoreError> {
         use subgraph as s;
         use subgraph_version as v;
@@ -1075,4 +1270,105 @@ impl Connection {
             .distinct()
             .load(&self.0)?)
     }
+
+    /// Return the allow-lists of all deployments that only run queries
+    /// from their allow-list as a map from deployment to the shape hashes
+    /// in the allow-list
+    pub fn query_allowlists(&self) -> Result<HashMap<String, HashSet<u64>>, StoreError> {
+        use query_allowlist as a;
+        use query_allowlist_deployments as d;
+
+        let mut allowlists: HashMap<String, HashSet<u64>> = d::table
+            .select(d::deployment)
+            .load::<String>(&self.0)?
+            .into_iter()
+            .map(|deployment| (deployment, HashSet::new()))
+            .collect();
+        let entries = a::table
+            .select((a::deployment, a::shape_hash))
+            .load::<(String, i64)>(&self.0)?;
+        for (deployment, shape_hash) in entries {
+            if let Some(shapes) = allowlists.get_mut(&deployment) {
+                shapes.insert(shape_hash as u64);
+            }
+        }
+        Ok(allowlists)
+    }
+
+    /// Return the allow-list of the deployment `id`, and whether the
+    /// deployment only runs queries from it
+    pub fn query_allowlist(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<(bool, Vec<AllowlistedQuery>), StoreError> {
+        use query_allowlist as a;
+        use query_allowlist_deployments as d;
+
+        let enabled = diesel::select(exists(d::table.filter(d::deployment.eq(id.as_str()))))
+            .get_result::<bool>(&self.0)?;
+        let queries = a::table
+            .filter(a::deployment.eq(id.as_str()))
+            .order_by(a::created_at)
+            .load::<AllowlistedQuery>(&self.0)?;
+        Ok((enabled, queries))
+    }
+
+    /// Add `query` to the allow-list of the deployment `id`. Returns
+    /// `false` if the allow-list already has a query with that shape
+    pub fn add_to_query_allowlist(
+        &self,
+        id: &SubgraphDeploymentId,
+        shape_hash: u64,
+        query: &str,
+    ) -> Result<bool, StoreError> {
+        use query_allowlist as a;
+
+        let rows = insert_into(a::table)
+            .values((
+                a::deployment.eq(id.as_str()),
+                a::shape_hash.eq(shape_hash as i64),
+                a::query.eq(query),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&self.0)?;
+        Ok(rows > 0)
+    }
+
+    /// Remove the query with `shape_hash` from the allow-list of the
+    /// deployment `id`. Returns `false` if there was no such query
+    pub fn remove_from_query_allowlist(
+        &self,
+        id: &SubgraphDeploymentId,
+        shape_hash: u64,
+    ) -> Result<bool, StoreError> {
+        use query_allowlist as a;
+
+        let rows = delete(
+            a::table
+                .filter(a::deployment.eq(id.as_str()))
+                .filter(a::shape_hash.eq(shape_hash as i64)),
+        )
+        .execute(&self.0)?;
+        Ok(rows > 0)
+    }
+
+    /// Set whether the deployment `id` only runs queries from its
+    /// allow-list
+    pub fn set_query_allowlist_enabled(
+        &self,
+        id: &SubgraphDeploymentId,
+        enabled: bool,
+    ) -> Result<(), StoreError> {
+        use query_allowlist_deployments as d;
+
+        if enabled {
+            insert_into(d::table)
+                .values(d::deployment.eq(id.as_str()))
+                .on_conflict_do_nothing()
+                .execute(&self.0)?;
+        } else {
+            delete(d::table.filter(d::deployment.eq(id.as_str()))).execute(&self.0)?;
+        }
+        Ok(())
+    }
 }
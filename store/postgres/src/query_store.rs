@@ -4,6 +4,7 @@ use web3::types::H256;
 
 use crate::store::ReplicaId;
 use graph::components::store::QueryStore as QueryStoreTrait;
+use graph::data::query::SqlTrace;
 use graph::prelude::*;
 
 use crate::primary::Site;
@@ -36,7 +37,7 @@ impl QueryStoreTrait for QueryStore {
     fn find_query_values(
         &self,
         query: EntityQuery,
-    ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
+    ) -> Result<(Vec<BTreeMap<String, q::Value>>, Option<SqlTrace>), QueryExecutionError> {
         assert_eq!(&self.site.deployment, &query.subgraph_id);
         let conn = self
             .store
@@ -45,6 +46,27 @@ impl QueryStoreTrait for QueryStore {
         self.store.execute_query(&conn, query)
     }
 
+    fn aggregate(
+        &self,
+        query: EntityAggregation,
+    ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        let conn = self
+            .store
+            .get_entity_conn(self.site.as_ref(), self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store.execute_aggregation(&conn, query)
+    }
+
+    fn estimate_cost(&self, query: EntityQuery) -> Result<f64, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        let conn = self
+            .store
+            .get_entity_conn(self.site.as_ref(), self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store.estimate_query_cost(&conn, query)
+    }
+
     /// Return true if the deployment with the given id is fully synced,
     /// and return false otherwise. Errors from the store are passed back up
     fn is_deployment_synced(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
@@ -87,6 +109,12 @@ impl QueryStoreTrait for QueryStore {
             .transpose()
     }
 
+    fn chain_head_ptr(&self) -> Result<Option<EthereumBlockPointer>, QueryExecutionError> {
+        self.chain_store
+            .chain_head_ptr()
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))
+    }
+
     fn wait_stats(&self) -> &PoolWaitStats {
         self.store.wait_stats(self.replica_id)
     }
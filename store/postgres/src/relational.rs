@@ -24,9 +24,11 @@ use std::time::{Duration, Instant};
 use crate::{
     primary::{Namespace, METADATA_NAMESPACE},
     relational_queries::{
-        self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
-        DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-        FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+        self as rq, AggregateData, AggregateQuery, ClampRangeQuery, ConflictingEntityQuery,
+        DeleteByPrefixQuery, DeleteDynamicDataSourcesQuery, DeleteQuery, DumpQuery, EntityData,
+        EntityHistoryData, EntityHistoryQuery, EntityVersionData, ExplainQuery, FilterCollection,
+        FilterQuery, FindManyQuery, FindQuery, InsertQuery, PruneQuery, QueryPlan,
+        RevertClampQuery, RevertRemoveQuery, UpdateQuery,
     },
 };
 use graph::components::store::EntityType;
@@ -37,11 +39,13 @@ use graph::data::subgraph::schema::{
 };
 use graph::data::{
     graphql::ext::{DocumentExt, ObjectTypeExt},
+    query::SqlTrace,
     subgraph::schema::MetadataType,
 };
 use graph::prelude::{
-    anyhow, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
+    anyhow, info, BlockModifications, BlockNumber, Entity, EntityAggregation, EntityChange,
+    EntityChangeOperation, EntityCollection, EntityCursor, EntityFilter, EntityKey,
+    EntityModification, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
     QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
     BLOCK_NUMBER_MAX,
 };
@@ -215,6 +219,9 @@ pub struct Layout {
     pub enums: EnumMap,
     /// The query to count all entities
     pub count_query: String,
+    /// The earliest block for which the deployment still has its complete
+    /// history. This is `0` unless the deployment has been pruned
+    pub earliest_block: BlockNumber,
 }
 
 impl Layout {
@@ -334,6 +341,7 @@ impl Layout {
             tables,
             enums,
             count_query,
+            earliest_block: 0,
         })
     }
 
@@ -524,6 +532,18 @@ impl Layout {
             .ok_or_else(|| StoreError::UnknownTable(entity.to_owned()))
     }
 
+    /// Return an error if the history of the deployment at `block` has
+    /// been removed by pruning
+    fn check_block_retained(&self, block: BlockNumber) -> Result<(), StoreError> {
+        if block < self.earliest_block {
+            return Err(QueryExecutionError::BlockNotRetained(block, self.earliest_block).into());
+        }
+        Ok(())
+    }
+
+    /// Find the entity of type `entity` with the given `id` as of `block`.
+    /// Like queries, finding entities at blocks before `earliest_block` is
+    /// rejected since pruning has removed the data needed for that
     pub fn find(
         &self,
         conn: &PgConnection,
@@ -531,6 +551,7 @@ impl Layout {
         id: &str,
         block: BlockNumber,
     ) -> Result<Option<Entity>, StoreError> {
+        self.check_block_retained(block)?;
         let table = self.table_for_entity(entity)?;
         FindQuery::new(table.as_ref(), id, block)
             .get_result::<EntityData>(conn)
@@ -539,12 +560,15 @@ impl Layout {
             .transpose()
     }
 
+    /// Find all entities with the given ids as of `block`. Blocks before
+    /// `earliest_block` are rejected just like they are for `find`
     pub fn find_many<'a>(
         &self,
         conn: &PgConnection,
         ids_for_type: BTreeMap<&str, &Vec<&str>>,
         block: BlockNumber,
     ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        self.check_block_retained(block)?;
         if ids_for_type.is_empty() {
             return Ok(BTreeMap::new());
         }
@@ -582,6 +606,27 @@ impl Layout {
         Ok(())
     }
 
+    /// Insert `entities` of type `entity_type` at `block`, using as few
+    /// queries as Postgres' limit on the number of bind parameters allows
+    pub fn insert_many(
+        &self,
+        conn: &PgConnection,
+        entity_type: &str,
+        entities: Vec<(EntityKey, Entity)>,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        let table = self.table_for_entity(entity_type)?;
+        let chunk_size =
+            (POSTGRES_MAX_PARAMETERS / InsertQuery::parameters_per_entity(table)).max(1);
+        let mut entities = entities;
+        while !entities.is_empty() {
+            let rest = entities.split_off(chunk_size.min(entities.len()));
+            InsertQuery::many(table, entities, block)?.execute(conn)?;
+            entities = rest;
+        }
+        Ok(())
+    }
+
     pub fn insert_unversioned(
         &self,
         conn: &PgConnection,
@@ -607,6 +652,13 @@ impl Layout {
     }
 
     /// order is a tuple (attribute, value_type, direction)
+    ///
+    /// If a `cursor` is given, only entities after or before it in that
+    /// order are returned
+    ///
+    /// The `earliest_block` is the earliest block for which the deployment
+    /// still has its complete history; queries for blocks before that
+    /// are rejected since pruning has removed the data they need
     pub fn query<T: crate::relational_queries::FromEntityData>(
         &self,
         logger: &Logger,
@@ -614,23 +666,48 @@ impl Layout {
         collection: EntityCollection,
         filter: Option<EntityFilter>,
         order: EntityOrder,
+        cursor: Option<EntityCursor>,
         range: EntityRange,
         block: BlockNumber,
+        earliest_block: BlockNumber,
         query_id: Option<String>,
     ) -> Result<Vec<T>, QueryExecutionError> {
-        fn log_query_timing(
-            logger: &Logger,
-            query: &FilterQuery,
-            elapsed: Duration,
-            entity_count: usize,
-        ) {
+        self.query_traced(
+            logger,
+            conn,
+            collection,
+            filter,
+            order,
+            cursor,
+            range,
+            block,
+            earliest_block,
+            query_id,
+            false,
+        )
+        .map(|(values, _)| values)
+    }
+
+    /// Like `query`, but if `trace` is `true`, also return the SQL that
+    /// was run, how long it took and how many rows it returned
+    pub fn query_traced<T: crate::relational_queries::FromEntityData>(
+        &self,
+        logger: &Logger,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        cursor: Option<EntityCursor>,
+        range: EntityRange,
+        block: BlockNumber,
+        earliest_block: BlockNumber,
+        query_id: Option<String>,
+        trace: bool,
+    ) -> Result<(Vec<T>, Option<SqlTrace>), QueryExecutionError> {
+        fn query_text(query: &FilterQuery) -> String {
             // 20kB
             const MAXLEN: usize = 20_480;
 
-            if !*graph::log::LOG_SQL_TIMING {
-                return;
-            }
-
             let mut text = debug_query(&query).to_string().replace("\n", " ");
             // If the query + bind variables is more than MAXLEN, truncate it;
             // this will happen when queries have very large bind variables
@@ -639,20 +716,38 @@ impl Layout {
                 text.truncate(MAXLEN);
                 text.push_str(" ...");
             }
+            text
+        }
+
+        fn log_query_timing(
+            logger: &Logger,
+            query: &FilterQuery,
+            elapsed: Duration,
+            entity_count: usize,
+        ) {
+            if !*graph::log::LOG_SQL_TIMING {
+                return;
+            }
+
             info!(
                 logger,
                 "Query timing (SQL)";
-                "query" => text,
+                "query" => query_text(query),
                 "time_ms" => elapsed.as_millis(),
                 "entity_count" => entity_count
             );
         }
 
-        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref())?;
+        if block < earliest_block {
+            return Err(QueryExecutionError::BlockNotRetained(block, earliest_block));
+        }
+
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
         let query = FilterQuery::new(
             &filter_collection,
             filter.as_ref(),
             order,
+            cursor.as_ref(),
             range,
             block,
             query_id,
@@ -660,21 +755,130 @@ impl Layout {
         let query_clone = query.clone();
 
         let start = Instant::now();
-        let values = query.load::<EntityData>(conn).map_err(|e| {
+        let mut values = query.load::<EntityData>(conn).map_err(|e| {
             QueryExecutionError::ResolveEntitiesError(format!(
                 "{}, query = {:?}",
                 e,
                 debug_query(&query_clone).to_string()
             ))
         })?;
-        log_query_timing(logger, &query_clone, start.elapsed(), values.len());
-        values
+        let elapsed = start.elapsed();
+        log_query_timing(logger, &query_clone, elapsed, values.len());
+        let trace = if trace {
+            Some(SqlTrace {
+                sql: query_text(&query_clone),
+                elapsed,
+                rows: values.len(),
+            })
+        } else {
+            None
+        };
+        // For a `before` cursor, the query returns entities in reverse order
+        if cursor
+            .as_ref()
+            .map(|cursor| cursor.is_before())
+            .unwrap_or(false)
+        {
+            values.reverse();
+        }
+        let values = values
             .into_iter()
             .map(|entity_data| {
                 entity_data
                     .deserialize_with_layout(self)
                     .map_err(|e| e.into())
             })
+            .collect::<Result<_, _>>()?;
+        Ok((values, trace))
+    }
+
+    /// Ask Postgres for its estimate of the cost of running the query
+    /// that `query` with the same arguments would run, without actually
+    /// running it. The cost is in the planner's arbitrary units
+    pub fn estimate_cost(
+        &self,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        cursor: Option<EntityCursor>,
+        range: EntityRange,
+        block: BlockNumber,
+        query_id: Option<String>,
+    ) -> Result<f64, QueryExecutionError> {
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
+        if filter_collection.is_empty() {
+            return Ok(0.0);
+        }
+        let query = FilterQuery::new(
+            &filter_collection,
+            filter.as_ref(),
+            order,
+            cursor.as_ref(),
+            range,
+            block,
+            query_id,
+        )?;
+        let query = ExplainQuery::new(query);
+        let query_clone = query.clone();
+        let plan = query
+            .load::<QueryPlan>(conn)
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&query_clone).to_string()
+                ))
+            })?
+            .pop()
+            .ok_or_else(|| {
+                QueryExecutionError::ResolveEntitiesError(
+                    "explain did not return a query plan".to_string(),
+                )
+            })?;
+        plan.total_cost()
+    }
+
+    /// Compute the aggregates described by `query` at `query.block`
+    pub fn aggregate(
+        &self,
+        conn: &PgConnection,
+        query: EntityAggregation,
+        earliest_block: BlockNumber,
+    ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
+        if query.block < earliest_block {
+            return Err(QueryExecutionError::BlockNotRetained(
+                query.block,
+                earliest_block,
+            ));
+        }
+
+        let table = self.table_for_entity(&query.entity_type)?;
+        let aggregate_query = AggregateQuery::new(
+            self,
+            table,
+            query.filter.as_ref(),
+            &query.aggregates,
+            &query.group_by,
+            query.range,
+            query.block,
+            query.query_id,
+        )?;
+        let query_clone = aggregate_query.clone();
+
+        let values = aggregate_query.load::<AggregateData>(conn).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&query_clone).to_string()
+            ))
+        })?;
+        values
+            .into_iter()
+            .map(|data| {
+                data.deserialize_with_query(&query_clone)
+                    .map_err(|e| e.into())
+            })
             .collect()
     }
 
@@ -796,6 +1000,94 @@ impl Layout {
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Remove all entity versions that are not visible at any block from
+    /// `earliest_block` on. Current versions are always retained. Returns
+    /// the total number of versions that were removed
+    pub fn prune(
+        &self,
+        logger: &Logger,
+        conn: &PgConnection,
+        earliest_block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut total = 0;
+        for table in self.tables.values() {
+            let start = Instant::now();
+            let count = PruneQuery::new(table, earliest_block).execute(conn)?;
+            info!(logger, "Pruned {} {} versions", count, table.object;
+                  "time_ms" => start.elapsed().as_millis());
+            total += count;
+        }
+        Ok(total)
+    }
+
+    /// Reconstruct the entity modifications for the blocks after `after`
+    /// up to and including `to` from the block ranges of the entity
+    /// versions. The result is ordered by block number and only contains
+    /// blocks that changed some entities. Changes to the Proof of Indexing
+    /// are only included if `include_poi` is `true`. The history for these
+    /// blocks must not have been pruned
+    pub fn entity_changes(
+        &self,
+        conn: &PgConnection,
+        subgraph_id: &SubgraphDeploymentId,
+        after: BlockNumber,
+        to: BlockNumber,
+        include_poi: bool,
+    ) -> Result<Vec<BlockModifications>, StoreError> {
+        let mut blocks: BTreeMap<BlockNumber, Vec<EntityModification>> = BTreeMap::new();
+        for table in self.tables.values() {
+            if table.object == POI_OBJECT && !include_poi {
+                continue;
+            }
+            let rows: Vec<EntityHistoryData> =
+                EntityHistoryQuery::new(table, after, to).get_results(conn)?;
+            for row in rows {
+                let block = row.block;
+                let (removed, overwrite) = (row.removed, row.overwrite);
+                let mut data: Entity = row.entity_data().deserialize_with_layout(self)?;
+                data.remove("__typename");
+                let key = EntityKey::data(subgraph_id.clone(), table.object.clone(), data.id()?);
+                let modification = match (removed, overwrite) {
+                    (true, _) => EntityModification::Remove { key },
+                    (false, true) => EntityModification::Overwrite { key, data },
+                    (false, false) => EntityModification::Insert { key, data },
+                };
+                blocks.entry(block).or_default().push(modification);
+            }
+        }
+        Ok(blocks
+            .into_iter()
+            .map(|(block, mods)| BlockModifications { block, mods })
+            .collect())
+    }
+
+    /// Read at most `limit` of the entities in `table` that are visible at
+    /// `block` and whose version has a `vid` greater than `after_vid`. If
+    /// `prefixes` is given, only read entities whose id starts with one of
+    /// the prefixes, which all must have the same given length. The
+    /// entities are returned together with the `vid` of their version and
+    /// in ascending order of `vid`
+    pub fn dump_batch(
+        &self,
+        conn: &PgConnection,
+        table: &Table,
+        block: BlockNumber,
+        prefixes: Option<(&Vec<String>, i32)>,
+        after_vid: i64,
+        limit: i64,
+    ) -> Result<Vec<(i64, Entity)>, StoreError> {
+        DumpQuery::new(table, block, prefixes, after_vid, limit)
+            .get_results::<EntityVersionData>(conn)?
+            .into_iter()
+            .map(|row| {
+                let vid = row.vid;
+                let mut entity: Entity = row.entity_data().deserialize_with_layout(self)?;
+                entity.remove("__typename");
+                Ok((vid, entity))
+            })
+            .collect()
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`. This function can only be called on the `Layout`
     /// for the metadata subgraph.
@@ -850,12 +1142,12 @@ impl Layout {
         Ok(())
     }
 
-    pub fn drop_metadata(
-        conn: &PgConnection,
-        subgraph: &SubgraphDeploymentId,
-    ) -> Result<(), StoreError> {
+    /// The tables of the metadata layout that hold metadata for individual
+    /// deployments, i.e., tables whose entries have ids that start with
+    /// the id of the deployment or the id of a dynamic data source
+    pub fn deployment_metadata_tables() -> impl Iterator<Item = &'static Arc<Table>> {
         lazy_static! {
-            // Tables from which we should not delete entries for `subgraph`
+            // Tables that do not contain entries for a deployment
             // See also: ed42d219c6704a4aab57ce1ea66698e7
             static ref OTHER_TABLES: Vec<String> = vec![
                 // Not deployment specific
@@ -872,21 +1164,27 @@ impl Layout {
             .collect();
         }
 
+        METADATA_LAYOUT
+            .tables
+            .values()
+            .filter(|table| !OTHER_TABLES.contains(&table.object))
+    }
+
+    pub fn drop_metadata(
+        conn: &PgConnection,
+        subgraph: &SubgraphDeploymentId,
+    ) -> Result<(), StoreError> {
         // Revert dynamic data sources to before the genesis block
         METADATA_LAYOUT.revert_metadata(conn, subgraph, BLOCK_UNVERSIONED)?;
 
         // Delete 'static' metadata
-        for table in METADATA_LAYOUT
-            .tables
-            .values()
-            .filter(|table| !OTHER_TABLES.contains(&table.object))
-        {
+        for table in Self::deployment_metadata_tables() {
             let id = subgraph.to_string();
             let prefix_len = id.len() as i32;
             DeleteByPrefixQuery::new(table, &vec![id], prefix_len).get_results(conn)?;
         }
 
-        Ok(())
+        crate::change_log::drop(conn, subgraph)
     }
 
     pub fn is_cacheable(&self) -> bool {
@@ -1188,6 +1486,9 @@ pub(crate) const PRIMARY_KEY_COLUMN: &str = "id";
 /// synthetic primary key. This is the name of the column we use.
 pub(crate) const VID_COLUMN: &str = "vid";
 
+/// The maximum number of bind parameters Postgres allows in a query
+const POSTGRES_MAX_PARAMETERS: usize = u16::MAX as usize;
+
 #[derive(Clone, Debug)]
 pub struct Table {
     /// The name of the GraphQL object type ('Thing')
@@ -9,7 +9,7 @@ use diesel::pg::{Pg, PgConnection};
 use diesel::query_builder::{AstPass, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Range, Text};
 use diesel::Connection;
 use lazy_static::lazy_static;
 use std::collections::{BTreeMap, HashSet};
@@ -21,13 +21,15 @@ use std::str::FromStr;
 
 use graph::data::{schema::FulltextAlgorithm, store::scalar};
 use graph::prelude::{
-    anyhow, q, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
-    EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
-    QueryExecutionError, StoreError, Value,
+    anyhow, q, serde_json, Attribute, BlockNumber, Child, ChildMultiplicity, CursorPosition,
+    Entity, EntityAggregate, EntityCollection, EntityCursor, EntityFilter, EntityKey, EntityLink,
+    EntityOrder, EntityRange, EntityWindow, ParentLink, QueryExecutionError, StoreError, Value,
 };
 
 use crate::entities::STRING_PREFIX_SIZE;
-use crate::relational::{Column, ColumnType, IdType, Layout, SqlName, Table, PRIMARY_KEY_COLUMN};
+use crate::relational::{
+    Column, ColumnType, IdType, Layout, SqlName, Table, PRIMARY_KEY_COLUMN, VID_COLUMN,
+};
 use crate::sql_value::SqlValue;
 use crate::{
     block_range::{
@@ -717,25 +719,51 @@ impl<'a> QueryFragment<Pg> for PrefixComparison<'a> {
 /// the `where` clause of a SQL query. The attributes mentioned in
 /// the `filter` must all come from the given `table`, which is used to
 /// map GraphQL names to column names, and to determine the type of the
-/// column an attribute refers to
+/// column an attribute refers to. The query the filter is used in must
+/// use `c` as the alias for `table`.
+///
+/// Filters on child entities are turned into an `exists` subquery against
+/// the table for the child entity type at `block`; the `layout` is used to
+/// find that table
 #[derive(Debug, Clone)]
 pub struct QueryFilter<'a> {
     filter: &'a EntityFilter,
     table: &'a Table,
+    layout: &'a Layout,
+    block: BlockNumber,
+    /// How deeply this filter is nested in filters on child entities. The
+    /// table the filter applies to has alias `c` at the top level, and
+    /// `c{depth}` in nested subqueries
+    depth: usize,
 }
 
 impl<'a> QueryFilter<'a> {
-    pub fn new(filter: &'a EntityFilter, table: &'a Table) -> Result<Self, StoreError> {
-        Self::valid_attributes(filter, table)?;
-        Ok(QueryFilter { filter, table })
+    pub fn new(
+        filter: &'a EntityFilter,
+        table: &'a Table,
+        layout: &'a Layout,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        Self::valid_attributes(filter, table, layout)?;
+        Ok(QueryFilter {
+            filter,
+            table,
+            layout,
+            block,
+            depth: 0,
+        })
     }
 
-    fn valid_attributes(filter: &'a EntityFilter, table: &'a Table) -> Result<(), StoreError> {
+    fn valid_attributes(
+        filter: &'a EntityFilter,
+        table: &'a Table,
+        layout: &'a Layout,
+    ) -> Result<(), StoreError> {
         use EntityFilter::*;
         match filter {
             And(filters) | Or(filters) => {
                 for filter in filters {
-                    Self::valid_attributes(filter, table)?;
+                    Self::valid_attributes(filter, table, layout)?;
                 }
             }
 
@@ -752,9 +780,26 @@ impl<'a> QueryFilter<'a> {
             | StartsWith(attr, _)
             | NotStartsWith(attr, _)
             | EndsWith(attr, _)
-            | NotEndsWith(attr, _) => {
+            | NotEndsWith(attr, _)
+            | ContainsNoCase(attr, _)
+            | NotContainsNoCase(attr, _)
+            | StartsWithNoCase(attr, _)
+            | NotStartsWithNoCase(attr, _)
+            | EndsWithNoCase(attr, _)
+            | NotEndsWithNoCase(attr, _)
+            | Matches(attr, _) => {
                 table.column_for_field(attr)?;
             }
+
+            Child(child) => {
+                let child_table = layout.table_for_entity(&child.entity_type)?;
+                if child.derived {
+                    child_table.column_for_field(&child.attr)?;
+                } else {
+                    table.column_for_field(&child.attr)?;
+                }
+                Self::valid_attributes(&child.filter, child_table, layout)?;
+            }
         }
         Ok(())
     }
@@ -763,6 +808,9 @@ impl<'a> QueryFilter<'a> {
         QueryFilter {
             filter,
             table: self.table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth,
         }
     }
 
@@ -772,6 +820,80 @@ impl<'a> QueryFilter<'a> {
             .expect("the constructor already checked that all attribute names are valid")
     }
 
+    /// The alias of the table at nesting level `depth`
+    fn alias(depth: usize) -> String {
+        if depth == 0 {
+            "c".to_string()
+        } else {
+            format!("c{}", depth)
+        }
+    }
+
+    /// Generate
+    ///   exists (select 1 from {child_table} c1
+    ///            where c1.block_range @> $block
+    ///              and {link between c and c1}
+    ///              and {child.filter})
+    ///
+    /// The link depends on whether the parent stores the ids of its
+    /// children or the children store the id of their parent, and whether
+    /// the attribute that stores the ids is a list or not
+    fn child(&self, child: &'a Child, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let child_table = self
+            .layout
+            .table_for_entity(&child.entity_type)
+            .expect("the constructor already checked that the child entity type exists")
+            .as_ref();
+        let parent_alias = Self::alias(self.depth);
+        let child_alias = Self::alias(self.depth + 1);
+
+        out.push_sql("exists (select 1 from ");
+        out.push_sql(child_table.qualified_name.as_str());
+        out.push_sql(" ");
+        out.push_sql(&child_alias);
+        out.push_sql(" where ");
+        BlockRangeContainsClause::new(child_table, &format!("{}.", child_alias), self.block)
+            .walk_ast(out.reborrow())?;
+        out.push_sql(" and ");
+
+        // `id_alias` is the table with the id that `attr` in table
+        // `attr_alias` refers to
+        let (attr_alias, attr_column, id_alias) = if child.derived {
+            let column = child_table
+                .column_for_field(&child.attr)
+                .expect("the constructor already checked the child attribute");
+            (&child_alias, column, &parent_alias)
+        } else {
+            (&parent_alias, self.column(&child.attr), &child_alias)
+        };
+        out.push_sql(id_alias);
+        out.push_sql(".");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        if attr_column.is_list() {
+            out.push_sql(" = any(");
+        } else {
+            out.push_sql(" = ");
+        }
+        out.push_sql(attr_alias);
+        out.push_sql(".");
+        out.push_identifier(attr_column.name.as_str())?;
+        if attr_column.is_list() {
+            out.push_sql(")");
+        }
+
+        out.push_sql(" and ");
+        QueryFilter {
+            filter: child.filter.as_ref(),
+            table: child_table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth + 1,
+        }
+        .walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn binary_op(
         &self,
         filters: &Vec<EntityFilter>,
@@ -853,6 +975,84 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    fn contains_nocase(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        negated: bool,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::String(s) => {
+                out.push_identifier(column.name.as_str())?;
+                if negated {
+                    out.push_sql(" not ilike ");
+                } else {
+                    out.push_sql(" ilike ")
+                };
+                if s.starts_with('%') || s.ends_with('%') {
+                    out.push_bind_param::<Text, _>(s)?;
+                } else {
+                    let s = format!("%{}%", s);
+                    out.push_bind_param::<Text, _>(&s)?;
+                }
+            }
+            Value::Bytes(_)
+            | Value::List(_)
+            | Value::Null
+            | Value::BigDecimal(_)
+            | Value::Int(_)
+            | Value::Bool(_)
+            | Value::BigInt(_) => {
+                let filter = match negated {
+                    false => "contains_nocase",
+                    true => "not_contains_nocase",
+                };
+                return Err(UnsupportedFilter {
+                    filter: filter.to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate `attribute ~* pattern`. The GraphQL layer has already
+    /// rejected patterns that are too expensive to match
+    fn matches(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::String(s) => {
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(" ~* ");
+                out.push_bind_param::<Text, _>(s)?;
+            }
+            Value::Bytes(_)
+            | Value::List(_)
+            | Value::Null
+            | Value::BigDecimal(_)
+            | Value::Int(_)
+            | Value::Bool(_)
+            | Value::BigInt(_) => {
+                return Err(UnsupportedFilter {
+                    filter: "matches".to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
     fn equals(
         &self,
         attribute: &Attribute,
@@ -1086,6 +1286,25 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", false, out)?
             }
+
+            ContainsNoCase(attr, value) => self.contains_nocase(attr, value, false, out)?,
+            NotContainsNoCase(attr, value) => self.contains_nocase(attr, value, true, out)?,
+            StartsWithNoCase(attr, value) => {
+                self.starts_or_ends_with(attr, value, " ilike ", true, out)?
+            }
+            NotStartsWithNoCase(attr, value) => {
+                self.starts_or_ends_with(attr, value, " not ilike ", true, out)?
+            }
+            EndsWithNoCase(attr, value) => {
+                self.starts_or_ends_with(attr, value, " ilike ", false, out)?
+            }
+            NotEndsWithNoCase(attr, value) => {
+                self.starts_or_ends_with(attr, value, " not ilike ", false, out)?
+            }
+
+            Matches(attr, value) => self.matches(attr, value, out)?,
+
+            Child(child) => self.child(child, out)?,
         }
         Ok(())
     }
@@ -1187,21 +1406,64 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindManyQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindManyQuery<'a> {}
 
+/// Insert one or more entities into a table. The insert uses the columns
+/// for which at least one of the entities has a value; entities that do not
+/// have a value for one of these columns get `null` for it
 #[derive(Debug, Clone)]
 pub struct InsertQuery<'a> {
     table: &'a Table,
-    key: &'a EntityKey,
-    entity: Entity,
+    entities: Vec<Entity>,
     block: BlockNumber,
 }
 
 impl<'a> InsertQuery<'a> {
     pub fn new(
         table: &'a Table,
-        key: &'a EntityKey,
+        key: &EntityKey,
         entity: Entity,
         block: BlockNumber,
     ) -> Result<InsertQuery<'a>, StoreError> {
+        Ok(InsertQuery {
+            table,
+            entities: vec![Self::prepare(table, key, entity)?],
+            block,
+        })
+    }
+
+    pub fn many(
+        table: &'a Table,
+        entities: Vec<(EntityKey, Entity)>,
+        block: BlockNumber,
+    ) -> Result<InsertQuery<'a>, StoreError> {
+        let entities = entities
+            .into_iter()
+            .map(|(key, entity)| Self::prepare(table, &key, entity))
+            .collect::<Result<_, _>>()?;
+        Ok(InsertQuery {
+            table,
+            entities,
+            block,
+        })
+    }
+
+    /// The largest number of bind parameters the query needs for one
+    /// entity of `table`
+    pub fn parameters_per_entity(table: &Table) -> usize {
+        let columns: usize = table
+            .columns
+            .iter()
+            .map(|column| {
+                column
+                    .fulltext_fields
+                    .as_ref()
+                    .map_or(1, |fields| fields.len())
+            })
+            .sum();
+        // One more for the block range
+        columns + 1
+    }
+
+    fn prepare(table: &Table, key: &EntityKey, entity: Entity) -> Result<Entity, StoreError> {
         let mut entity = entity;
         for column in table.columns.iter() {
             match column.fulltext_fields.as_ref() {
@@ -1226,13 +1488,7 @@ impl<'a> InsertQuery<'a> {
                 )));
             }
         }
-
-        Ok(InsertQuery {
-            table,
-            key,
-            entity,
-            block,
-        })
+        Ok(entity)
     }
 }
 
@@ -1240,32 +1496,50 @@ impl<'a> QueryFragment<Pg> for InsertQuery<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         out.unsafe_to_cache_prepared();
 
+        let columns: Vec<_> = self
+            .table
+            .columns
+            .iter()
+            .filter(|column| {
+                self.entities
+                    .iter()
+                    .any(|entity| entity.contains_key(&column.field))
+            })
+            .collect();
+
         // Construct a query
         //   insert into schema.table(column, ...)
-        //   values ($1, ...)
-        // and convert and bind the entity's values into it
+        //   values ($1, ...), ($n, ...)
+        // and convert and bind the entities' values into it
         out.push_sql("insert into ");
         out.push_sql(self.table.qualified_name.as_str());
 
         out.push_sql("(");
-        for column in self.table.columns.iter() {
-            if self.entity.contains_key(&column.field) {
-                out.push_identifier(column.name.as_str())?;
-                out.push_sql(", ");
-            }
+        for column in &columns {
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(", ");
         }
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
 
-        out.push_sql(")\nvalues(");
-        for column in self.table.columns.iter() {
-            if let Some(value) = self.entity.get(&column.field) {
-                QueryValue(value, &column.column_type).walk_ast(out.reborrow())?;
+        out.push_sql(")\nvalues");
+        let block_range: BlockRange = (self.block..).into();
+        for (i, entity) in self.entities.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(",\n      ");
+            }
+            out.push_sql("(");
+            for column in &columns {
+                match entity.get(&column.field) {
+                    Some(value) => {
+                        QueryValue(value, &column.column_type).walk_ast(out.reborrow())?
+                    }
+                    None => out.push_sql("null"),
+                }
                 out.push_sql(", ");
             }
+            out.push_bind_param::<Range<Integer>, _>(&block_range)?;
+            out.push_sql(")");
         }
-        let block_range: BlockRange = (self.block..).into();
-        out.push_bind_param::<Range<Integer>, _>(&block_range)?;
-        out.push_sql(")");
         Ok(())
     }
 }
@@ -1603,6 +1877,7 @@ impl<'a> FilterWindow<'a> {
         layout: &'a Layout,
         window: EntityWindow,
         query_filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         let EntityWindow {
             child_type,
@@ -1611,7 +1886,7 @@ impl<'a> FilterWindow<'a> {
         } = window;
         let table = layout.table_for_entity(&child_type).map(|rc| rc.as_ref())?;
         let query_filter = query_filter
-            .map(|filter| QueryFilter::new(filter, table))
+            .map(|filter| QueryFilter::new(filter, table, layout, block))
             .transpose()?;
         let link = TableLink::new(table, link)?;
         Ok(FilterWindow {
@@ -1912,6 +2187,7 @@ impl<'a> FilterCollection<'a> {
         layout: &'a Layout,
         collection: EntityCollection,
         filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         match collection {
             EntityCollection::All(entities) => {
@@ -1927,7 +2203,7 @@ impl<'a> FilterCollection<'a> {
                             .map(|rc| rc.as_ref())
                             .and_then(|table| {
                                 filter
-                                    .map(|filter| QueryFilter::new(filter, table))
+                                    .map(|filter| QueryFilter::new(filter, table, layout, block))
                                     .transpose()
                                     .map(|filter| (table, filter))
                             })
@@ -1938,7 +2214,7 @@ impl<'a> FilterCollection<'a> {
             EntityCollection::Window(windows) => {
                 let windows = windows
                     .into_iter()
-                    .map(|window| FilterWindow::new(layout, window, filter))
+                    .map(|window| FilterWindow::new(layout, window, filter, block))
                     .collect::<Result<Vec<_>, _>>()?;
                 let collection = if windows.len() == 1 {
                     let mut windows = windows;
@@ -1962,7 +2238,7 @@ impl<'a> FilterCollection<'a> {
         }
     }
 
-    fn is_empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         match self {
             FilterCollection::All(entities) => entities.is_empty(),
             FilterCollection::SingleWindow(_) => false,
@@ -1972,15 +2248,20 @@ impl<'a> FilterCollection<'a> {
 }
 
 /// Convenience to pass the name of the column to order by around. If `name`
-/// is `None`, the sort key should be ignored
+/// is `None`, the sort key should be ignored. When `reverse` is set, the
+/// order is the exact reverse of the order requested by the query; we need
+/// that to find the entities that come before a cursor
 #[derive(Debug, Clone, Copy)]
 pub enum SortKey<'a> {
     None,
-    Id,
+    Id {
+        reverse: bool,
+    },
     Key {
         column: &'a Column,
         value: Option<&'a str>,
         direction: &'static str,
+        reverse: bool,
     },
 }
 
@@ -1989,6 +2270,7 @@ impl<'a> SortKey<'a> {
         order: EntityOrder,
         table: &'a Table,
         filter: Option<&'a EntityFilter>,
+        reverse: bool,
     ) -> Result<Self, QueryExecutionError> {
         const ASC: &str = "asc";
         const DESC: &str = "desc";
@@ -1998,6 +2280,7 @@ impl<'a> SortKey<'a> {
             attribute: String,
             filter: Option<&'a EntityFilter>,
             direction: &'static str,
+            reverse: bool,
         ) -> Result<SortKey<'a>, QueryExecutionError> {
             let column = table.column_for_field(&attribute)?;
             if column.is_fulltext() {
@@ -2010,6 +2293,7 @@ impl<'a> SortKey<'a> {
                                 column,
                                 value: sort_value,
                                 direction,
+                                reverse,
                             })
                         }
                         _ => unreachable!(),
@@ -2021,14 +2305,15 @@ impl<'a> SortKey<'a> {
                     column,
                     value: None,
                     direction,
+                    reverse,
                 })
             }
         }
 
         match order {
-            EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC),
-            EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC),
-            EntityOrder::Default => Ok(SortKey::Id),
+            EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC, reverse),
+            EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC, reverse),
+            EntityOrder::Default => Ok(SortKey::Id { reverse }),
             EntityOrder::Unordered => Ok(SortKey::None),
         }
     }
@@ -2036,12 +2321,8 @@ impl<'a> SortKey<'a> {
     /// Generate selecting the sort key if it is needed
     fn select(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
-            SortKey::None | SortKey::Id => Ok(()),
-            SortKey::Key {
-                column,
-                value: _,
-                direction: _,
-            } => {
+            SortKey::None | SortKey::Id { .. } => Ok(()),
+            SortKey::Key { column, .. } => {
                 let name = column.name.as_str();
                 if !column.is_primary_key() {
                     out.push_sql(", c.");
@@ -2057,17 +2338,22 @@ impl<'a> SortKey<'a> {
     fn order_by(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
             SortKey::None => Ok(()),
-            SortKey::Id => {
+            SortKey::Id { reverse } => {
                 out.push_sql("order by ");
-                out.push_identifier(PRIMARY_KEY_COLUMN)
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                if *reverse {
+                    out.push_sql(" desc");
+                }
+                Ok(())
             }
             SortKey::Key {
                 column,
                 value,
                 direction,
+                reverse,
             } => {
                 out.push_sql("order by ");
-                SortKey::sort_expr(column, value, direction, out)
+                SortKey::sort_expr(column, value, direction, *reverse, out)
             }
         }
     }
@@ -2077,29 +2363,44 @@ impl<'a> SortKey<'a> {
     fn order_by_parent(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
             SortKey::None => Ok(()),
-            SortKey::Id => {
+            SortKey::Id { reverse } => {
                 out.push_sql("order by g$parent_id, ");
-                out.push_identifier(PRIMARY_KEY_COLUMN)
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                if *reverse {
+                    out.push_sql(" desc");
+                }
+                Ok(())
             }
             SortKey::Key {
                 column,
                 value,
                 direction,
+                reverse,
             } => {
                 out.push_sql("order by g$parent_id, ");
-                SortKey::sort_expr(column, value, direction, out)
+                SortKey::sort_expr(column, value, direction, *reverse, out)
             }
         }
     }
 
     /// Generate
     ///   [name direction,] id
+    /// or, if `reverse` is set,
+    ///   [name reverse(direction) nulls first,] id desc
     fn sort_expr(
         column: &Column,
         value: &Option<&str>,
         direction: &str,
+        reverse: bool,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
+        let (direction, nulls, id_direction) = if reverse {
+            let direction = if direction == "asc" { "desc" } else { "asc" };
+            (direction, " nulls first", " desc")
+        } else {
+            (direction, " nulls last", "")
+        };
+        let name = column.name.as_str();
         match &column.column_type {
             ColumnType::TSVector(config) => {
                 let algorithm = match config.algorithm {
@@ -2107,30 +2408,151 @@ impl<'a> SortKey<'a> {
                     FulltextAlgorithm::ProximityRank => "ts_rank_cd(",
                 };
                 out.push_sql(algorithm);
-                let name = column.name.as_str();
                 out.push_identifier(name)?;
                 out.push_sql(", to_tsquery(");
 
                 out.push_bind_param::<Text, _>(&String::from(value.unwrap()))?;
                 out.push_sql(")) ");
-                out.push_sql(direction);
-                out.push_sql(" nulls last");
-                if name != PRIMARY_KEY_COLUMN {
-                    out.push_sql(", ");
-                    out.push_identifier(PRIMARY_KEY_COLUMN)?;
-                }
-                Ok(())
             }
             _ => {
-                let name = column.name.as_str();
                 out.push_identifier(name)?;
                 out.push_sql(" ");
-                out.push_sql(direction);
-                out.push_sql(" nulls last");
-                if name != PRIMARY_KEY_COLUMN {
-                    out.push_sql(", ");
-                    out.push_identifier(PRIMARY_KEY_COLUMN)?;
+            }
+        }
+        out.push_sql(direction);
+        out.push_sql(nulls);
+        if name != PRIMARY_KEY_COLUMN {
+            out.push_sql(", ");
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+            out.push_sql(id_direction);
+        }
+        Ok(())
+    }
+
+    /// Check that `value` can be compared to the values of the scalar
+    /// `column` in the keyset condition generated by `after`
+    fn accepts(column: &Column, value: &Value) -> bool {
+        match (value, &column.column_type) {
+            (Value::Null, _) => true,
+            (Value::String(_), ColumnType::String)
+            | (Value::String(_), ColumnType::Enum(_))
+            | (Value::String(_), ColumnType::Bytes)
+            | (Value::String(_), ColumnType::BytesId)
+            | (Value::Bytes(_), ColumnType::Bytes)
+            | (Value::Bytes(_), ColumnType::BytesId)
+            | (Value::Int(_), ColumnType::Int)
+            | (Value::BigInt(_), ColumnType::BigInt)
+            | (Value::BigDecimal(_), ColumnType::BigDecimal)
+            | (Value::Bool(_), ColumnType::Boolean) => true,
+            (_, _) => false,
+        }
+    }
+
+    /// Generate a condition that only lets rows from `table` through that
+    /// come strictly after `position` in the order given by this sort key.
+    /// Since rows are ordered by `name direction nulls last, id`, we
+    /// generate, for an ascending order, the keyset condition
+    ///   ((c.name, c.id) > ($value, $id) or c.name is null)
+    /// and the corresponding conditions for descending and reversed orders
+    /// or when the value at the position is `null`
+    fn after(
+        &self,
+        table: &Table,
+        position: &CursorPosition,
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()> {
+        fn push_column(name: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+            out.push_sql("c.");
+            out.push_identifier(name)
+        }
+
+        fn push_id_cmp(
+            op: &str,
+            id: &Value,
+            id_type: &ColumnType,
+            out: &mut AstPass<Pg>,
+        ) -> QueryResult<()> {
+            push_column(PRIMARY_KEY_COLUMN, out)?;
+            out.push_sql(op);
+            QueryValue(id, id_type).walk_ast(out.reborrow())
+        }
+
+        let id = Value::String(position.id.clone());
+        let id_type = &table.primary_key().column_type;
+
+        match self {
+            SortKey::None => Ok(()),
+            SortKey::Id { reverse } => {
+                let op = if *reverse { " < " } else { " > " };
+                push_id_cmp(op, &id, id_type, out)
+            }
+            SortKey::Key {
+                column,
+                value: _,
+                direction,
+                reverse,
+            } => {
+                let name = column.name.as_str();
+                let op = if (*direction == "asc") != *reverse {
+                    " > "
+                } else {
+                    " < "
+                };
+                if name == PRIMARY_KEY_COLUMN {
+                    return push_id_cmp(op, &id, id_type, out);
                 }
+                let id_op = if *reverse { " < " } else { " > " };
+                let nulls_last = !*reverse;
+
+                out.push_sql("(");
+                match &position.value {
+                    None | Some(Value::Null) => {
+                        // Rows with a null value form one block, either at
+                        // the end or at the beginning of the result
+                        push_column(name, out)?;
+                        if nulls_last {
+                            out.push_sql(" is null and ");
+                        } else {
+                            out.push_sql(" is not null or ");
+                        }
+                        push_id_cmp(id_op, &id, id_type, out)?;
+                    }
+                    Some(value) => {
+                        let value = QueryValue(value, &column.column_type);
+                        if op == id_op {
+                            // The column and the id are sorted in the same
+                            // direction, and we can use a row comparison
+                            out.push_sql("(");
+                            push_column(name, out)?;
+                            out.push_sql(", ");
+                            push_column(PRIMARY_KEY_COLUMN, out)?;
+                            out.push_sql(")");
+                            out.push_sql(op);
+                            out.push_sql("(");
+                            value.walk_ast(out.reborrow())?;
+                            out.push_sql(", ");
+                            QueryValue(&id, id_type).walk_ast(out.reborrow())?;
+                            out.push_sql(")");
+                        } else {
+                            push_column(name, out)?;
+                            out.push_sql(op);
+                            value.walk_ast(out.reborrow())?;
+                            out.push_sql(" or (");
+                            push_column(name, out)?;
+                            out.push_sql(" = ");
+                            value.walk_ast(out.reborrow())?;
+                            out.push_sql(" and ");
+                            push_id_cmp(id_op, &id, id_type, out)?;
+                            out.push_sql(")");
+                        }
+                        if nulls_last {
+                            out.push_sql(" or ");
+                            push_column(name, out)?;
+                            out.push_sql(" is null");
+                        }
+                    }
+                }
+                out.push_sql(")");
                 Ok(())
             }
         }
@@ -2164,6 +2586,7 @@ impl QueryFragment<Pg> for FilterRange {
 pub struct FilterQuery<'a> {
     collection: &'a FilterCollection<'a>,
     sort_key: SortKey<'a>,
+    cursor: Option<&'a CursorPosition>,
     range: FilterRange,
     block: BlockNumber,
     query_id: Option<String>,
@@ -2174,6 +2597,7 @@ impl<'a> FilterQuery<'a> {
         collection: &'a FilterCollection,
         filter: Option<&'a EntityFilter>,
         order: EntityOrder,
+        cursor: Option<&'a EntityCursor>,
         range: EntityRange,
         block: BlockNumber,
         query_id: Option<String>,
@@ -2185,11 +2609,45 @@ impl<'a> FilterQuery<'a> {
         let first_table = collection
             .first_table()
             .expect("an entity query always contains at least one entity type/table");
-        let sort_key = SortKey::new(order, first_table, filter)?;
+        // To get the entities before a cursor, we walk the rows in reverse
+        // order starting at the cursor; the caller has to reverse the
+        // result to get it back into the requested order
+        let reverse = cursor.map(|cursor| cursor.is_before()).unwrap_or(false);
+        let sort_key = SortKey::new(order, first_table, filter, reverse)?;
+
+        if let Some(cursor) = cursor {
+            match (collection, &sort_key) {
+                (FilterCollection::All(_), SortKey::Id { .. }) => { /* ok */ }
+                (FilterCollection::All(_), SortKey::Key { column, .. })
+                    if !column.is_fulltext() && !column.is_list() =>
+                { /* ok */ }
+                (FilterCollection::All(_), _) => {
+                    return Err(QueryExecutionError::InvalidCursor(
+                        "cursors can not be used with this order".to_string(),
+                    ))
+                }
+                (_, _) => {
+                    return Err(QueryExecutionError::InvalidCursor(
+                        "cursors can only be used for top-level collections".to_string(),
+                    ))
+                }
+            }
+            if let (Some(value), SortKey::Key { column, .. }) =
+                (&cursor.position().value, &sort_key)
+            {
+                if !column.is_primary_key() && !SortKey::accepts(column, value) {
+                    return Err(QueryExecutionError::InvalidCursor(format!(
+                        "the value {} can not be used for attribute `{}`",
+                        value, column.field
+                    )));
+                }
+            }
+        }
 
         Ok(FilterQuery {
             collection,
             sort_key,
+            cursor: cursor.map(|cursor| cursor.position()),
             range: FilterRange(range),
             block,
             query_id,
@@ -2217,6 +2675,10 @@ impl<'a> FilterQuery<'a> {
             out.push_sql(" and ");
             filter.walk_ast(out.reborrow())?;
         }
+        if let Some(position) = self.cursor {
+            out.push_sql(" and ");
+            self.sort_key.after(table, position, &mut out)?;
+        }
         out.push_sql("\n");
         Ok(())
     }
@@ -2485,6 +2947,354 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
+/// Helper struct for retrieving the plan that `ExplainQuery` produces
+#[derive(QueryableByName)]
+pub struct QueryPlan {
+    #[sql_type = "Text"]
+    #[column_name = "QUERY PLAN"]
+    pub plan: String,
+}
+
+impl QueryPlan {
+    /// The planner's estimate of the total cost of running the query. The
+    /// plan is the JSON output of `explain (format json)` which looks like
+    /// `[{ "Plan": { "Total Cost": 123.45, .. } }]`
+    pub fn total_cost(&self) -> Result<f64, QueryExecutionError> {
+        let plan: serde_json::Value = serde_json::from_str(&self.plan).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!("invalid query plan: {}", e))
+        })?;
+        plan.get(0)
+            .and_then(|plan| plan.get("Plan"))
+            .and_then(|plan| plan.get("Total Cost"))
+            .and_then(|cost| cost.as_f64())
+            .ok_or_else(|| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "query plan has no total cost: {}",
+                    self.plan
+                ))
+            })
+    }
+}
+
+/// Ask Postgres for the plan of a `FilterQuery` without running it
+#[derive(Debug, Clone, Constructor)]
+pub struct ExplainQuery<'a> {
+    query: FilterQuery<'a>,
+}
+
+impl<'a> QueryFragment<Pg> for ExplainQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+        out.push_sql("explain (format json) ");
+        self.query.walk_ast(out)
+    }
+}
+
+impl<'a> QueryId for ExplainQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, QueryPlan> for ExplainQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<QueryPlan>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExplainQuery<'a> {}
+
+/// Helper struct for retrieving the result of an `AggregateQuery`. Each row
+/// contains the values of all aggregates and group-by columns for one group
+/// as a JSONB object
+#[derive(QueryableByName)]
+pub struct AggregateData {
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+impl AggregateData {
+    /// Map the `AggregateData` to GraphQL values. Aggregates are returned
+    /// as strings from the database so that we do not lose precision when
+    /// passing them through JSON
+    pub fn deserialize_with_query(
+        self,
+        query: &AggregateQuery,
+    ) -> Result<BTreeMap<String, q::Value>, StoreError> {
+        use serde_json::Value as j;
+
+        let mut map = match self.data {
+            j::Object(map) => map,
+            _ => unreachable!(
+                "we use `jsonb_build_object` in our queries, and will therefore always get an object back"
+            ),
+        };
+
+        let mut out = BTreeMap::new();
+        for (aggregate, column, name) in &query.aggregates {
+            let value = match (map.remove(name.as_str()), column) {
+                (None, _) | (Some(j::Null), _) => q::Value::Null,
+                (Some(j::Number(number)), None) => number
+                    .as_i64()
+                    .and_then(|count| i32::try_from(count).ok())
+                    .map(|count| q::Value::Int(count.into()))
+                    .ok_or_else(|| {
+                        StoreError::Unknown(anyhow!("failed to convert count {} to Int", number))
+                    })?,
+                (Some(j::String(s)), Some(column)) => match (aggregate, &column.column_type) {
+                    (EntityAggregate::Min(_), ColumnType::Int)
+                    | (EntityAggregate::Max(_), ColumnType::Int) => i32::from_str(&s)
+                        .map(|i| q::Value::Int(i.into()))
+                        .map_err(|e| {
+                            StoreError::Unknown(anyhow!("failed to convert {} to Int: {}", s, e))
+                        })?,
+                    (EntityAggregate::Avg(_), _) | (_, ColumnType::BigDecimal) => {
+                        scalar::BigDecimal::from_str(&s)
+                            .map(q::Value::from_big_decimal)
+                            .map_err(|e| {
+                                StoreError::Unknown(anyhow!(
+                                    "failed to convert {} to BigDecimal: {}",
+                                    s,
+                                    e
+                                ))
+                            })?
+                    }
+                    (_, _) => q::Value::String(s),
+                },
+                (Some(json), _) => {
+                    return Err(StoreError::Unknown(anyhow!(
+                        "unexpected value {} for aggregate {}",
+                        json,
+                        name
+                    )))
+                }
+            };
+            out.insert(name.clone(), value);
+        }
+        for column in &query.group_by {
+            let json = map.remove(column.field.as_str()).unwrap_or(j::Null);
+            let value = q::Value::from_column_value(&column.column_type, json)?;
+            out.insert(column.field.clone(), value);
+        }
+        Ok(out)
+    }
+}
+
+/// Compute aggregates over the entities in `table` that are visible at
+/// `block` and match `filter`, optionally grouped by some columns.
+///
+/// Generates a query
+///
+///   select jsonb_build_object('count', count(*),
+///                             'amount_sum', sum(c."amount")::text,
+///                             'owner', c."owner") as data
+///     from table c
+///    where block_range @> $block
+///      and query_filter
+///    group by c."owner"
+///    order by c."owner"
+///    limit {first} offset {skip}
+#[derive(Debug, Clone)]
+pub struct AggregateQuery<'a> {
+    table: &'a Table,
+    filter: Option<QueryFilter<'a>>,
+    /// The aggregates together with the column they are computed over and
+    /// the name under which they are returned
+    aggregates: Vec<(&'a EntityAggregate, Option<&'a Column>, String)>,
+    group_by: Vec<&'a Column>,
+    range: FilterRange,
+    block: BlockNumber,
+    query_id: Option<String>,
+}
+
+impl<'a> AggregateQuery<'a> {
+    /// The maximum number of arguments that Postgres allows for a function
+    /// call is 100; since each entry in `jsonb_build_object` uses two
+    /// arguments, we combine chunks of this many entries with `||`
+    const BUILD_OBJECT_CHUNK_SIZE: usize = 50;
+
+    pub fn new(
+        layout: &'a Layout,
+        table: &'a Table,
+        filter: Option<&'a EntityFilter>,
+        aggregates: &'a [EntityAggregate],
+        group_by: &'a [String],
+        range: EntityRange,
+        block: BlockNumber,
+        query_id: Option<String>,
+    ) -> Result<Self, StoreError> {
+        let filter = filter
+            .map(|filter| QueryFilter::new(filter, table, layout, block))
+            .transpose()?;
+
+        let aggregates = aggregates
+            .iter()
+            .map(|aggregate| {
+                let column = match aggregate.attribute() {
+                    None => None,
+                    Some(attr) => {
+                        let column = table.column_for_field(attr)?;
+                        let numeric = match column.column_type {
+                            ColumnType::Int | ColumnType::BigInt | ColumnType::BigDecimal => true,
+                            _ => false,
+                        };
+                        if !numeric || column.is_list() {
+                            return Err(StoreError::QueryExecutionError(format!(
+                                "can not compute `{}` since `{}.{}` is not a numeric attribute",
+                                aggregate.name(),
+                                table.object,
+                                attr
+                            )));
+                        }
+                        Some(column)
+                    }
+                };
+                Ok((aggregate, column, aggregate.name()))
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let group_by = group_by
+            .iter()
+            .map(|attr| {
+                let column = table.column_for_field(attr)?;
+                if column.is_list() || column.is_fulltext() {
+                    return Err(StoreError::QueryExecutionError(format!(
+                        "can not group by `{}.{}` since it is not a scalar attribute",
+                        table.object, attr
+                    )));
+                }
+                Ok(column)
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        Ok(AggregateQuery {
+            table,
+            filter,
+            aggregates,
+            group_by,
+            range: FilterRange(range),
+            block,
+            query_id,
+        })
+    }
+
+    fn aggregate(
+        aggregate: &EntityAggregate,
+        column: Option<&Column>,
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let function = match aggregate {
+            EntityAggregate::Count => {
+                out.push_sql("count(*)");
+                return Ok(());
+            }
+            EntityAggregate::Sum(_) => "sum",
+            EntityAggregate::Min(_) => "min",
+            EntityAggregate::Max(_) => "max",
+            EntityAggregate::Avg(_) => "avg",
+        };
+        let column = column.expect("all aggregates except count have a column");
+        out.push_sql(function);
+        out.push_sql("(c.");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(")::text");
+        Ok(())
+    }
+}
+
+impl<'a> QueryFragment<Pg> for AggregateQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        if let Some(qid) = &self.query_id {
+            out.push_sql("/* qid: ");
+            out.push_sql(qid);
+            out.push_sql(" */\n");
+        }
+
+        // Every entry in the resulting object is a (name, expression) pair
+        enum Entry<'b> {
+            Aggregate(&'b EntityAggregate, Option<&'b Column>, &'b String),
+            Group(&'b Column),
+        }
+        let entries: Vec<_> = self
+            .aggregates
+            .iter()
+            .map(|(aggregate, column, name)| Entry::Aggregate(*aggregate, *column, name))
+            .chain(self.group_by.iter().map(|column| Entry::Group(*column)))
+            .collect();
+
+        out.push_sql("select ");
+        if entries.is_empty() {
+            out.push_sql("'{}'::jsonb");
+        }
+        for (i, chunk) in entries.chunks(Self::BUILD_OBJECT_CHUNK_SIZE).enumerate() {
+            if i > 0 {
+                out.push_sql(" || ");
+            }
+            out.push_sql("jsonb_build_object(");
+            for (j, entry) in chunk.iter().enumerate() {
+                if j > 0 {
+                    out.push_sql(", ");
+                }
+                match entry {
+                    Entry::Aggregate(aggregate, column, name) => {
+                        out.push_bind_param::<Text, _>(*name)?;
+                        out.push_sql(", ");
+                        Self::aggregate(aggregate, *column, &mut out)?;
+                    }
+                    Entry::Group(column) => {
+                        out.push_bind_param::<Text, _>(&column.field)?;
+                        out.push_sql(", c.");
+                        out.push_identifier(column.name.as_str())?;
+                    }
+                }
+            }
+            out.push_sql(")");
+        }
+        out.push_sql(" as data");
+
+        out.push_sql("\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c");
+        out.push_sql("\n where ");
+        BlockRangeContainsClause::new(&self.table, "c.", self.block).walk_ast(out.reborrow())?;
+        if let Some(filter) = &self.filter {
+            out.push_sql(" and ");
+            filter.walk_ast(out.reborrow())?;
+        }
+
+        if !self.group_by.is_empty() {
+            for keyword in &["\n group by ", "\n order by "] {
+                out.push_sql(keyword);
+                for (i, column) in self.group_by.iter().enumerate() {
+                    if i > 0 {
+                        out.push_sql(", ");
+                    }
+                    out.push_sql("c.");
+                    out.push_identifier(column.name.as_str())?;
+                }
+            }
+            self.range.walk_ast(out.reborrow())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for AggregateQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, AggregateData> for AggregateQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<AggregateData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for AggregateQuery<'a> {}
+
 /// Reduce the upper bound of the current entry's block range to `block` as
 /// long as that does not result in an empty block range
 #[derive(Debug, Clone, Constructor)]
@@ -2665,6 +3475,270 @@ impl<'a> LoadQuery<PgConnection, RevertEntityData> for RevertClampQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for RevertClampQuery<'a> {}
 
+/// A query that removes all versions whose block range ends before
+/// `earliest_block`, i.e., versions that are not visible at any block
+/// from `earliest_block` on. Current versions are never removed
+#[derive(Debug, Clone, Constructor)]
+pub struct PruneQuery<'a> {
+    table: &'a Table,
+    earliest_block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for PruneQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where coalesce(upper(block_range), INTMAX) <= $earliest_block
+        //
+        // Versions with an unbounded upper end have an upper bound of
+        // `null`, and the coalesce makes sure we keep them
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where coalesce(upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), 2147483647) <= ");
+        out.push_bind_param::<Integer, _>(&self.earliest_block)?;
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneQuery<'a> {}
+
+/// Helper struct for retrieving the history of entities. Each row
+/// describes a change to an entity at `block`; rows for removed entities
+/// contain the last version of the entity before it was removed
+#[derive(QueryableByName)]
+pub struct EntityHistoryData {
+    #[sql_type = "Integer"]
+    pub block: BlockNumber,
+    #[sql_type = "Bool"]
+    pub removed: bool,
+    #[sql_type = "Bool"]
+    pub overwrite: bool,
+    #[sql_type = "Text"]
+    entity: String,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+impl EntityHistoryData {
+    pub fn entity_data(self) -> EntityData {
+        EntityData {
+            entity: self.entity,
+            data: self.data,
+        }
+    }
+}
+
+/// A query that reconstructs the changes made to entities in `table` in
+/// the blocks after `after` up to and including `to` from the block ranges
+/// of the entity versions
+#[derive(Debug, Clone, Constructor)]
+pub struct EntityHistoryQuery<'a> {
+    table: &'a Table,
+    after: BlockNumber,
+    to: BlockNumber,
+}
+
+impl<'a> EntityHistoryQuery<'a> {
+    /// Generate `{func}(c.block_range) > $after and {func}(c.block_range) <= $to`
+    fn block_bounds(&self, func: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql(func);
+        out.push_sql("(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") > ");
+        out.push_bind_param::<Integer, _>(&self.after)?;
+        out.push_sql(" and ");
+        out.push_sql(func);
+        out.push_sql("(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") <= ");
+        out.push_bind_param::<Integer, _>(&self.to)
+    }
+
+    /// Generate `exists (select 1 from table o where o.id = c.id
+    ///                   and {other}(o.block_range) = {this}(c.block_range))`
+    fn adjacent_version(&self, other: &str, this: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("exists (select 1 from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" o where o.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" = c.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" and ");
+        out.push_sql(other);
+        out.push_sql("(o.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") = ");
+        out.push_sql(this);
+        out.push_sql("(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("))");
+        Ok(())
+    }
+}
+
+impl<'a> QueryFragment<Pg> for EntityHistoryQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   select lower(c.block_range) as block, false as removed,
+        //          exists (select 1 from table o
+        //                   where o.id = c.id
+        //                     and upper(o.block_range) = lower(c.block_range))
+        //            as overwrite,
+        //          $object as entity, to_jsonb(c.*) as data
+        //     from table c
+        //    where lower(c.block_range) > $after and lower(c.block_range) <= $to
+        //   union all
+        //   select upper(c.block_range), true, false, $object, to_jsonb(c.*)
+        //     from table c
+        //    where upper(c.block_range) > $after and upper(c.block_range) <= $to
+        //      and not exists (select 1 from table o
+        //                       where o.id = c.id
+        //                         and lower(o.block_range) = upper(c.block_range))
+        //    order by block
+        //
+        // A version that starts at a block where a previous version ended
+        // is an update, all other versions that start at a block are
+        // inserts. A version that ends at a block without a successor was
+        // removed at that block
+        out.push_sql("select lower(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") as block, false as removed, ");
+        self.adjacent_version("upper", "lower", &mut out)?;
+        out.push_sql(" as overwrite, ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(" as entity, to_jsonb(c.*) as data\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where ");
+        self.block_bounds("lower", &mut out)?;
+        out.push_sql("\nunion all\nselect upper(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), true, false, ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(", to_jsonb(c.*)\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where ");
+        self.block_bounds("upper", &mut out)?;
+        out.push_sql(" and not ");
+        self.adjacent_version("lower", "upper", &mut out)?;
+        out.push_sql("\n order by block");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for EntityHistoryQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityHistoryData> for EntityHistoryQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityHistoryData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for EntityHistoryQuery<'a> {}
+
+/// Helper struct for retrieving entity versions together with their `vid`
+#[derive(QueryableByName)]
+pub struct EntityVersionData {
+    #[sql_type = "BigInt"]
+    pub vid: i64,
+    #[sql_type = "Text"]
+    entity: String,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+impl EntityVersionData {
+    pub fn entity_data(self) -> EntityData {
+        EntityData {
+            entity: self.entity,
+            data: self.data,
+        }
+    }
+}
+
+/// A query that reads the versions of the entities in `table` that are
+/// visible at `block`. The versions are ordered by `vid`, and at most
+/// `limit` versions with a `vid` greater than `after_vid` are returned so
+/// that large tables can be read in batches. If `prefixes` is given, only
+/// entities whose id starts with one of the prefixes, all of which must
+/// have the given length, are returned
+#[derive(Debug, Clone, Constructor)]
+pub struct DumpQuery<'a> {
+    table: &'a Table,
+    block: BlockNumber,
+    prefixes: Option<(&'a Vec<String>, i32)>,
+    after_vid: i64,
+    limit: i64,
+}
+
+impl<'a> QueryFragment<Pg> for DumpQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select c.vid, $object as entity, to_jsonb(c.*) as data
+        //      from schema.table c
+        //     where c.block_range @> $block and c.vid > $after_vid
+        //       [and left(c.id, $prefix_len) = any($prefixes)]
+        //     order by c.vid
+        //     limit $limit
+        out.push_sql("select c.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(", ");
+        out.push_bind_param::<Text, _>(&self.table.object)?;
+        out.push_sql(" as entity, to_jsonb(c.*) as data\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where ");
+        BlockRangeContainsClause::new(&self.table, "c.", self.block).walk_ast(out.reborrow())?;
+        out.push_sql(" and c.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" > ");
+        out.push_bind_param::<BigInt, _>(&self.after_vid)?;
+        if let Some((prefixes, prefix_len)) = &self.prefixes {
+            out.push_sql(" and left(c.");
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+            out.push_sql(", ");
+            out.push_bind_param::<Integer, _>(prefix_len)?;
+            out.push_sql(") = any(");
+            out.push_bind_param::<Array<Text>, _>(prefixes)?;
+            out.push_sql(")");
+        }
+        out.push_sql("\n order by c.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql("\n limit ");
+        out.push_bind_param::<BigInt, _>(&self.limit)
+    }
+}
+
+impl<'a> QueryId for DumpQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityVersionData> for DumpQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityVersionData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DumpQuery<'a> {}
+
 #[test]
 fn block_number_max_is_i32_max() {
     // The code in RevertClampQuery::walk_ast embeds i32::MAX
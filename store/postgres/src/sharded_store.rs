@@ -1,7 +1,9 @@
 use diesel::Connection;
 use std::fmt;
-use std::sync::RwLock;
-use std::{collections::BTreeMap, collections::HashMap, sync::Arc};
+use std::path::Path;
+use std::sync::{Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+use std::{collections::BTreeMap, collections::HashMap, collections::HashSet, sync::Arc};
 
 use graph::{
     components::{
@@ -16,21 +18,34 @@ use graph::{
     prelude::StoreEvent,
     prelude::SubgraphDeploymentEntity,
     prelude::{
-        lazy_static, web3::types::Address, ApiSchema, DeploymentState, DynTryFuture, Entity,
-        EntityKey, EntityModification, EntityQuery, Error, EthereumBlockPointer, EthereumCallCache,
-        Logger, MetadataOperation, NodeId, QueryExecutionError, Schema, StopwatchMetrics,
-        Store as StoreTrait, StoreError, SubgraphDeploymentId, SubgraphName,
-        SubgraphVersionSwitchingMode,
+        anyhow::anyhow, futures03, info, lazy_static, o, q, shape_hash, warn, web3::types::Address,
+        ApiSchema, BlockNumber, ChangeLogEntry, DeploymentState, DynTryFuture, Entity, EntityKey,
+        EntityModification, EntityQuery, Error, EthereumBlockPointer, EthereumCallCache,
+        EventProducer, Logger, MetadataOperation, NodeId, QueryExecutionError, Schema,
+        StopwatchMetrics, Store as StoreTrait, StoreError, Stream01CompatExt, SubgraphDeploymentId,
+        SubgraphName, SubgraphVersionSwitchingMode, TryStreamExt,
     },
 };
 use store::StoredDynamicDataSource;
 
-use crate::{deployment, primary, primary::Site};
 use crate::{
+    copy::DeploymentCopy,
     detail::DeploymentDetail,
-    primary::UnusedDeployment,
+    dump::{DumpFormat, DumpManifest},
+    notification_listener::{NotificationListener, SafeChannelName},
+    primary::{AllowlistedQuery, UnusedDeployment, SITE_CHANGES_CHANNEL},
     store::{ReplicaId, Store},
 };
+use crate::{deployment, primary, primary::Site};
+
+/// How many blocks a copy made by `move_deployment` may be behind the
+/// deployment before we stop indexing the deployment to finish the copy
+const MOVE_MAX_LAG: BlockNumber = 10;
+
+/// How long we use the query allow-lists we loaded from the database
+/// before loading them again. Changes to allow-lists made by other
+/// processes take effect after at most this long
+const ALLOWLIST_TTL: Duration = Duration::from_secs(30);
 
 /// The name of a database shard; valid names must match `[a-z0-9_]+`
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -104,7 +119,13 @@ pub struct ShardedStore {
     stores: HashMap<Shard, Arc<Store>>,
     /// Cache for the mapping from deployment id to shard/namespace/id
     sites: RwLock<HashMap<SubgraphDeploymentId, Arc<Site>>>,
+    /// Cache for the query allow-lists of deployments, together with the
+    /// time when we loaded them
+    allowlists: RwLock<Option<(Instant, Arc<HashMap<String, HashSet<u64>>>)>>,
     placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
+    /// Listens for deployments that were moved to another shard; kept here
+    /// so that it lives as long as the store
+    site_listener: Mutex<Option<NotificationListener>>,
 }
 
 impl ShardedStore {
@@ -121,7 +142,54 @@ impl ShardedStore {
             primary,
             stores,
             sites,
+            allowlists: RwLock::new(None),
             placer,
+            site_listener: Mutex::new(None),
+        }
+    }
+
+    /// Listen for notifications that deployments were moved to a different
+    /// shard or pruned, possibly by another process, and forget what we
+    /// cached about them so that we use their new location and earliest
+    /// block from then on
+    pub fn listen_for_site_changes(self: &Arc<Self>, logger: &Logger, postgres_url: String) {
+        let logger = logger.new(o!("component" => "SiteChangeListener"));
+        let mut listener = NotificationListener::new(
+            &logger,
+            postgres_url,
+            SafeChannelName::i_promise_this_is_safe(SITE_CHANGES_CHANNEL),
+        );
+
+        let store: Weak<Self> = Arc::downgrade(self);
+        graph::spawn(listener.take_event_stream().unwrap().compat().try_for_each(
+            move |notification| {
+                let id = notification
+                    .payload
+                    .get("deployment")
+                    .and_then(|id| id.as_str())
+                    .and_then(|id| SubgraphDeploymentId::new(id).ok());
+                match (id, store.upgrade()) {
+                    (Some(id), Some(store)) => store.forget_site(&id),
+                    (None, _) => warn!(
+                        logger,
+                        "invalid site change received from database: {:?}", notification.payload
+                    ),
+                    (_, None) => (),
+                }
+                futures03::future::ok(())
+            },
+        ));
+        listener.start();
+
+        *self.site_listener.lock().unwrap() = Some(listener);
+    }
+
+    /// Remove everything we cached about where and how the deployment `id`
+    /// is stored
+    fn forget_site(&self, id: &SubgraphDeploymentId) {
+        self.sites.write().unwrap().remove(id);
+        for store in self.stores.values() {
+            store.layout_cache.lock().unwrap().remove(id);
         }
     }
 
@@ -132,6 +200,7 @@ impl ShardedStore {
             store.layout_cache.lock().unwrap().clear();
         }
         self.sites.write().unwrap().clear();
+        *self.allowlists.write().unwrap() = None;
     }
 
     fn site(&self, id: &SubgraphDeploymentId) -> Result<Arc<Site>, StoreError> {
@@ -354,6 +423,7 @@ impl ShardedStore {
         for store in self.stores.values() {
             let conn = store.get_conn()?;
             conn.batch_execute(query)?;
+            conn.batch_execute("delete from subgraphs.entity_change_log;")?;
             conn.batch_execute("delete from deployment_schemas;")?;
         }
         self.clear_caches();
@@ -436,6 +506,85 @@ impl ShardedStore {
         self.primary_conn()?.list_unused_deployments(filter)
     }
 
+    /// Check whether a query with `shape_hash` may run against the
+    /// deployment `id`. Deployments in allow-list mode only run queries
+    /// whose shape is in their allow-list; all other deployments run any
+    /// query
+    pub(crate) fn query_allowed(
+        &self,
+        id: &SubgraphDeploymentId,
+        shape_hash: u64,
+    ) -> Result<bool, StoreError> {
+        if let Some((loaded_at, allowlists)) = &*self.allowlists.read().unwrap() {
+            if loaded_at.elapsed() < ALLOWLIST_TTL {
+                return Ok(allowlists
+                    .get(id.as_str())
+                    .map_or(true, |shapes| shapes.contains(&shape_hash)));
+            }
+        }
+
+        let allowlists = Arc::new(self.primary_conn()?.query_allowlists()?);
+        let allowed = allowlists
+            .get(id.as_str())
+            .map_or(true, |shapes| shapes.contains(&shape_hash));
+        *self.allowlists.write().unwrap() = Some((Instant::now(), allowlists));
+        Ok(allowed)
+    }
+
+    /// Return the query allow-list of the deployment `id`, and whether the
+    /// deployment is in allow-list mode
+    pub fn query_allowlist(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<(bool, Vec<AllowlistedQuery>), StoreError> {
+        self.site(id)?;
+        self.primary_conn()?.query_allowlist(id)
+    }
+
+    /// Add `query` to the allow-list of the deployment `id`, and return
+    /// the shape hash under which it was added. Returns `None` if the
+    /// allow-list already has a query with the same shape
+    pub fn add_to_query_allowlist(
+        &self,
+        id: &SubgraphDeploymentId,
+        query: &q::Document,
+    ) -> Result<Option<u64>, StoreError> {
+        self.site(id)?;
+        let shape_hash = shape_hash(query);
+        let added =
+            self.primary_conn()?
+                .add_to_query_allowlist(id, shape_hash, &query.to_string())?;
+        *self.allowlists.write().unwrap() = None;
+        Ok(Some(shape_hash).filter(|_| added))
+    }
+
+    /// Remove the query with `shape_hash` from the allow-list of the
+    /// deployment `id`. Returns `false` if the allow-list has no such query
+    pub fn remove_from_query_allowlist(
+        &self,
+        id: &SubgraphDeploymentId,
+        shape_hash: u64,
+    ) -> Result<bool, StoreError> {
+        let removed = self
+            .primary_conn()?
+            .remove_from_query_allowlist(id, shape_hash)?;
+        *self.allowlists.write().unwrap() = None;
+        Ok(removed)
+    }
+
+    /// Put the deployment `id` into allow-list mode, or take it out of it
+    pub fn set_query_allowlist_enabled(
+        &self,
+        id: &SubgraphDeploymentId,
+        enabled: bool,
+    ) -> Result<(), StoreError> {
+        self.site(id)?;
+        self.primary_conn()?
+            .set_query_allowlist_enabled(id, enabled)?;
+        *self.allowlists.write().unwrap() = None;
+        Ok(())
+    }
+
     /// Remove a deployment, i.e., all its data and metadata. This is only permissible
     /// if the deployment is unused in the sense that it is neither the current nor
     /// pending version of any subgraph, and is not currently assigned to any node
@@ -472,6 +621,189 @@ impl ShardedStore {
         Ok(())
     }
 
+    /// Remove entity versions from the deployment `id` that are more than
+    /// `history_blocks` blocks older than the deployment's head. If
+    /// `history_blocks` is `None`, use the setting stored for the
+    /// deployment. Returns the earliest block for which the deployment
+    /// still has its complete history, and the number of entity versions
+    /// that were removed
+    pub fn prune(
+        &self,
+        logger: &Logger,
+        id: &SubgraphDeploymentId,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<(BlockNumber, usize), StoreError> {
+        let (store, site) = self.store(id)?;
+        let (earliest_block, count) = store.prune(logger, &site, history_blocks)?;
+        // The cached layouts of the deployment might still have the old
+        // earliest block; make sure everybody reloads them
+        self.forget_site(id);
+        self.primary_conn()?.send_site_change(id)?;
+        Ok((earliest_block, count))
+    }
+
+    /// Write the entities of the deployment `id` as they were at `block`
+    /// into the directory `dir`, one file per entity type in the given
+    /// `format`, together with a manifest that describes the dump. If
+    /// `block` is `None`, dump the current head of the deployment
+    pub fn dump(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: Option<BlockNumber>,
+        format: DumpFormat,
+        dir: &Path,
+    ) -> Result<DumpManifest, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.dump(&self.primary_conn()?, &site, block, format, dir)
+    }
+
+    /// Move the deployment `id` into `shard`. The deployment is copied
+    /// while it is being indexed; once the copy has caught up with the
+    /// deployment, the deployment is unassigned so that indexing stops.
+    /// We then wait for the block that is being written, if any, copy the
+    /// remaining changes, and make the copy the deployment that is used
+    /// from then on. The deployment is then assigned to the same node as
+    /// before again, and all processes that listen for site changes are
+    /// told to use the copy. The entities and the metadata of the old copy
+    /// of the deployment are removed. Returns the site of the old copy
+    pub fn move_deployment(
+        &self,
+        logger: &Logger,
+        id: &SubgraphDeploymentId,
+        shard: Shard,
+    ) -> Result<Site, StoreError> {
+        let (src, src_site) = self.store(id)?;
+        if src_site.shard == shard {
+            return Err(StoreError::Unknown(anyhow!(
+                "deployment `{}` is already in shard `{}`",
+                id,
+                shard
+            )));
+        }
+        let dst = self
+            .stores
+            .get(&shard)
+            .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+        let node = self.primary_conn()?.assigned_node(id)?;
+        let dst_site = Site {
+            deployment: id.clone(),
+            shard,
+            namespace: self.primary_conn()?.allocate_namespace()?,
+        };
+
+        let mut copy = DeploymentCopy::new(logger, src, src_site.clone(), dst, dst_site.clone());
+        let mut unassigned = false;
+        let result = (|| -> Result<(), StoreError> {
+            copy.copy_data()?;
+            while copy.catch_up()? > MOVE_MAX_LAG {}
+
+            if node.is_some() {
+                let pconn = self.primary_conn()?;
+                pconn.transaction(|| -> Result<_, StoreError> {
+                    let changes = pconn.unassign_subgraph(id)?;
+                    pconn.send_store_event(&StoreEvent::new(changes))
+                })?;
+                unassigned = true;
+                info!(logger, "Waiting for indexing to stop"; "block" => copy.block());
+            }
+
+            copy.finish(|| {
+                let pconn = self.primary_conn()?;
+                pconn.transaction(|| -> Result<_, StoreError> {
+                    pconn.move_site(&dst_site)?;
+                    pconn.send_site_change(id)?;
+                    let changes = match &node {
+                        Some(node) => pconn.assign_subgraph(id, node)?,
+                        None => vec![],
+                    };
+                    pconn.send_store_event(&StoreEvent::new(changes))
+                })
+            })
+        })();
+
+        if let Err(e) = result {
+            copy.drop()?;
+            if let (true, Some(node)) = (unassigned, &node) {
+                let pconn = self.primary_conn()?;
+                pconn.transaction(|| -> Result<_, StoreError> {
+                    let changes = pconn.assign_subgraph(id, node)?;
+                    pconn.send_store_event(&StoreEvent::new(changes))
+                })?;
+            }
+            return Err(e);
+        }
+
+        self.forget_site(id);
+        Ok(src_site.as_ref().clone())
+    }
+
+    /// Create a new deployment in `shard` from the dump in `dir` and make it
+    /// the current version of the subgraph `name`, assigned to `node_id`.
+    /// The deployment continues indexing from the block at which the dump
+    /// was taken
+    pub fn restore(
+        &self,
+        dir: &Path,
+        shard: Shard,
+        name: SubgraphName,
+        node_id: NodeId,
+    ) -> Result<DumpManifest, StoreError> {
+        let manifest = DumpManifest::read(dir)?;
+        let id = SubgraphDeploymentId::new(manifest.deployment.clone()).map_err(|id| {
+            StoreError::Unknown(anyhow!("the dump has an invalid deployment id `{}`", id))
+        })?;
+        let network = manifest.network.clone().ok_or_else(|| {
+            StoreError::Unknown(anyhow!("the dump does not record the network of `{}`", id))
+        })?;
+        if manifest.block.hash.is_none() {
+            return Err(StoreError::Unknown(anyhow!(
+                "the dump does not record the hash of block {}, and indexing can therefore \
+                 not resume from it",
+                manifest.block.number
+            )));
+        }
+        if let Some(site) = self.primary_conn()?.find_site(&id)? {
+            return Err(StoreError::Unknown(anyhow!(
+                "deployment `{}` already exists in shard `{}`",
+                id,
+                site.shard
+            )));
+        }
+
+        let store = self
+            .stores
+            .get(&shard)
+            .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+        let site = self
+            .primary_conn()?
+            .allocate_site(shard.clone(), &id, &network)?;
+        if let Err(e) = store.restore(&site, &manifest, dir) {
+            // Do not leave a site without a deployment behind
+            self.primary_conn()?.drop_site(&id)?;
+            return Err(e);
+        }
+
+        let exists_and_synced = |id: &SubgraphDeploymentId| {
+            let (store, _) = self.store(id)?;
+            let conn = store.get_conn()?;
+            deployment::exists_and_synced(&conn, id.as_str())
+        };
+
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            let changes = pconn.create_subgraph_version(
+                name,
+                &id,
+                node_id,
+                SubgraphVersionSwitchingMode::Instant,
+                exists_and_synced,
+            )?;
+            pconn.send_store_event(&StoreEvent::new(changes))?;
+            Ok(())
+        })?;
+        Ok(manifest)
+    }
+
     #[cfg(debug_assertions)]
     pub fn error_count(&self, id: &SubgraphDeploymentId) -> Result<usize, StoreError> {
         let (store, _) = self.store(id)?;
@@ -565,6 +897,16 @@ impl StoreTrait for ShardedStore {
         self.send_store_event(&event)
     }
 
+    fn entity_change_log(
+        &self,
+        id: &SubgraphDeploymentId,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.entity_change_log(site.as_ref(), after, limit)
+    }
+
     fn deployment_state_from_name(
         &self,
         name: SubgraphName,
@@ -586,7 +928,11 @@ impl StoreTrait for ShardedStore {
         logger: &Logger,
         id: &SubgraphDeploymentId,
     ) -> Result<(), StoreError> {
+        // The deployment might have been moved to a different shard since
+        // we cached its site
+        self.sites.write().unwrap().remove(id);
         let (store, site) = self.store(id)?;
+        store.layout_cache.lock().unwrap().remove(id);
 
         let graft_base = match store.graft_pending(id)? {
             Some((base_id, base_ptr)) => {
@@ -708,6 +1054,14 @@ impl StoreTrait for ShardedStore {
         Ok(infos)
     }
 
+    fn chain_reorgs(
+        &self,
+        network: Option<String>,
+        first: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError> {
+        self.primary_conn()?.chain_reorgs(network, first)
+    }
+
     async fn load_dynamic_data_sources(
         &self,
         id: SubgraphDeploymentId,
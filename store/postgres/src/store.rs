@@ -6,6 +6,7 @@ use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::{insert_into, update};
 use futures03::FutureExt as _;
 use graph::components::store::{EntityType, StoredDynamicDataSource};
+use graph::data::query::SqlTrace;
 use graph::data::subgraph::status;
 use graph::prelude::{
     error, CancelGuard, CancelHandle, CancelToken, CancelableError, PoolWaitStats,
@@ -17,6 +18,7 @@ use rand::{seq::SliceRandom, thread_rng};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use std::time::Instant;
 use std::{
@@ -29,21 +31,24 @@ use graph::components::store::EntityCollection;
 use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::data::subgraph::schema::{SubgraphError, POI_OBJECT};
 use graph::prelude::{
-    anyhow, debug, ethabi, futures03, info, o, tiny_keccak, tokio, trace, web3, ApiSchema,
-    BlockNumber, CheapClone, DeploymentState, DynTryFuture, Entity, EntityKey, EntityModification,
-    EntityOrder, EntityQuery, EntityRange, Error, EthereumBlockPointer, EthereumCallCache, Logger,
-    MetadataOperation, MetricsRegistry, QueryExecutionError, Schema, StopwatchMetrics, StoreError,
-    StoreEvent, SubgraphDeploymentId, Value, BLOCK_NUMBER_MAX,
+    anyhow, debug, ethabi, futures03, info, o, q, tiny_keccak, tokio, trace, web3, ApiSchema,
+    BlockNumber, ChangeLogEntry, CheapClone, DeploymentState, DynTryFuture, Entity,
+    EntityAggregation, EntityKey, EntityModification, EntityOrder, EntityQuery, EntityRange, Error,
+    EthereumBlockPointer, EthereumCallCache, Logger, MetadataOperation, MetricsRegistry,
+    QueryExecutionError, Schema, StopwatchMetrics, StoreError, StoreEvent, SubgraphDeploymentId,
+    Value, BLOCK_NUMBER_MAX,
 };
 
 use graph_graphql::prelude::api_schema;
 use web3::types::{Address, H256};
 
+use crate::block_range::block_number;
+use crate::dump::{DumpBlock, DumpFormat, DumpManifest};
 use crate::primary::Site;
 use crate::relational::{Layout, METADATA_LAYOUT};
 use crate::relational_queries::FromEntityData;
+use crate::{change_log, deployment, primary, primary::Namespace};
 use crate::{connection_pool::ConnectionPool, detail, entities as e};
-use crate::{deployment, primary::Namespace};
 
 lazy_static! {
     static ref CONNECTION_LIMITER: Semaphore = {
@@ -313,7 +318,7 @@ impl Store {
         &self,
         conn: &e::Connection,
         query: EntityQuery,
-    ) -> Result<Vec<T>, QueryExecutionError> {
+    ) -> Result<(Vec<T>, Option<SqlTrace>), QueryExecutionError> {
         // Process results; deserialize JSON data
         let logger = query.logger.unwrap_or(self.logger.clone());
         conn.query(
@@ -321,12 +326,38 @@ impl Store {
             query.collection,
             query.filter,
             query.order,
+            query.cursor,
             query.range,
             query.block,
             query.query_id,
+            query.trace,
         )
     }
 
+    pub(crate) fn estimate_query_cost(
+        &self,
+        conn: &e::Connection,
+        query: EntityQuery,
+    ) -> Result<f64, QueryExecutionError> {
+        conn.estimate_cost(
+            query.collection,
+            query.filter,
+            query.order,
+            query.cursor,
+            query.range,
+            query.block,
+            query.query_id,
+        )
+    }
+
+    pub(crate) fn execute_aggregation(
+        &self,
+        conn: &e::Connection,
+        query: EntityAggregation,
+    ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
+        conn.aggregate(query)
+    }
+
     fn check_interface_entity_uniqueness(
         &self,
         conn: &e::Connection,
@@ -859,13 +890,16 @@ impl Store {
                                 EntityCollection::All(vec![POI_OBJECT.to_owned()]),
                                 None,
                                 EntityOrder::Default,
+                                None,
                                 EntityRange {
                                     first: None,
                                     skip: 0,
                                 },
                                 block.number.try_into().unwrap(),
                                 None,
+                                false,
                             )
+                            .map(|(entities, _)| entities)
                             .map_err(anyhow::Error::from)?;
 
                         Ok(Some(entities))
@@ -940,6 +974,7 @@ impl Store {
             .get_entity_conn(site, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
         self.execute_query(&conn, query)
+            .map(|(entities, _)| entities)
     }
 
     pub(crate) fn find_one(
@@ -953,7 +988,7 @@ impl Store {
             .get_entity_conn(site, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
 
-        let mut results = self.execute_query(&conn, query)?;
+        let (mut results, _) = self.execute_query(&conn, query)?;
         match results.len() {
             0 | 1 => Ok(results.pop()),
             n => panic!("find_one query found {} results", n),
@@ -1002,6 +1037,11 @@ impl Store {
         let econn = self.get_entity_conn(site, ReplicaId::Main)?;
 
         let event = econn.transaction(|| -> Result<_, StoreError> {
+            // Moving the deployment to another shard takes this lock to
+            // wait for the last block to be written, and removes the
+            // deployment from this shard before releasing it
+            deployment::lock(&econn.conn, &site.deployment)?;
+
             let block_ptr_from = Self::block_ptr_with_conn(&site.deployment, &econn)?;
             if let Some(ref block_ptr_from) = block_ptr_from {
                 if block_ptr_from.number >= block_ptr_to.number {
@@ -1018,6 +1058,10 @@ impl Store {
             // for longer than we have to
             let event: StoreEvent = mods.iter().collect();
 
+            if *change_log::ENABLED {
+                change_log::append_changes(&econn.conn, &site.deployment, &block_ptr_to, &mods)?;
+            }
+
             // Make the changes
             let section = stopwatch.start_section("apply_entity_modifications");
             self.apply_entity_modifications(&econn, mods, Some(&block_ptr_to), stopwatch)?;
@@ -1047,6 +1091,8 @@ impl Store {
         let econn = self.get_entity_conn(site, ReplicaId::Main)?;
 
         let event = econn.transaction(|| -> Result<_, StoreError> {
+            deployment::lock(&econn.conn, &site.deployment)?;
+
             // Unwrap: If we are reverting then the block ptr is not `None`.
             let block_ptr_from = Self::block_ptr_with_conn(&site.deployment, &econn)?.unwrap();
 
@@ -1071,17 +1117,227 @@ impl Store {
                 }
             }
 
+            // Don't revert past the block to which the history was pruned
+            let earliest_block = deployment::earliest_block(&econn.conn, &site.deployment)?;
+            if (earliest_block as u64) > block_ptr_to.number {
+                return Err(anyhow!(
+                    "Can not revert subgraph `{}` to block {} as its history \
+                    has been pruned and only goes back to block {}",
+                    site.deployment.clone(),
+                    block_ptr_to.number,
+                    earliest_block
+                )
+                .into());
+            }
+
             let metadata_event =
                 deployment::revert_block_ptr(&econn.conn, &site.deployment, block_ptr_to)?;
 
             let (event, count) = econn.revert_block(&block_ptr_from)?;
             econn.update_entity_count(count)?;
+
+            if *change_log::ENABLED {
+                change_log::append_revert(&econn.conn, &site.deployment, &block_ptr_to)?;
+            }
             Ok(event.extend(metadata_event))
         })?;
 
         Ok(event)
     }
 
+    pub(crate) fn entity_change_log(
+        &self,
+        site: &Site,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        let conn = self.get_conn()?;
+        change_log::entries(&conn, &site.deployment, after, limit)
+    }
+
+    /// Write the entities of the deployment `site` as they were at `block`
+    /// into `dir`, together with a manifest that describes the dump. If
+    /// `block` is `None`, use the current head of the deployment. All data
+    /// is read from the same snapshot
+    pub(crate) fn dump(
+        &self,
+        primary: &primary::Connection,
+        site: &Site,
+        block: Option<BlockNumber>,
+        format: DumpFormat,
+        dir: &Path,
+    ) -> Result<DumpManifest, StoreError> {
+        let econn = self.get_entity_conn(site, ReplicaId::Main)?;
+
+        econn
+            .conn
+            .build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| {
+                let head =
+                    deployment::block_ptr(&econn.conn, &site.deployment)?.ok_or_else(|| {
+                        anyhow!(
+                            "deployment `{}` has not started syncing yet",
+                            site.deployment
+                        )
+                    })?;
+                let head_number = block_number(&head);
+                let earliest_block = deployment::earliest_block(&econn.conn, &site.deployment)?;
+                let block = block.unwrap_or(head_number);
+                if block > head_number || block < earliest_block {
+                    return Err(anyhow!(
+                        "can not dump deployment `{}` at block {} since it only has data \
+                         for blocks {} to {}",
+                        site.deployment,
+                        block,
+                        earliest_block,
+                        head_number
+                    )
+                    .into());
+                }
+
+                let network = deployment::network(&econn.conn, &site.deployment)?;
+                // The block cache lives in the primary, not in the shard
+                // of the deployment
+                let hash = if block == head_number {
+                    Some(format!("{:x}", head.hash))
+                } else if let Some(network) = &network {
+                    primary.block_hash(network, block)?
+                } else {
+                    None
+                };
+                let hash = hash.ok_or_else(|| {
+                    anyhow!(
+                        "can not dump deployment `{}` at block {} since the hash of that block \
+                         is not known; restoring the dump could not resume indexing",
+                        site.deployment,
+                        block
+                    )
+                })?;
+                let schema = deployment::raw_schema(&econn.conn, &site.deployment)?;
+
+                let tables = econn.dump(block, format, dir)?;
+                let metadata =
+                    crate::dump::dump_metadata(&econn.conn, &site.deployment, block, format, dir)?;
+                let manifest = DumpManifest {
+                    deployment: site.deployment.to_string(),
+                    network,
+                    block: DumpBlock {
+                        number: block,
+                        hash: Some(hash),
+                    },
+                    format,
+                    schema,
+                    tables,
+                    metadata,
+                };
+                manifest.write(dir)?;
+                Ok(manifest)
+            })
+    }
+
+    /// Create the deployment `site` from the dump in `dir`. The site must
+    /// have been allocated already, but the deployment can not have any
+    /// metadata or data yet. The deployment will continue indexing from
+    /// the block at which the dump was taken
+    pub(crate) fn restore(
+        &self,
+        site: &Site,
+        manifest: &DumpManifest,
+        dir: &Path,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        // As in `create_deployment`, we only use the metadata layout until
+        // the metadata, and with it the schema, has been restored
+        let econn = e::Connection::new(
+            conn.into(),
+            METADATA_LAYOUT.clone(),
+            site.deployment.clone(),
+        );
+        econn.transaction(|| -> Result<_, StoreError> {
+            if deployment::exists(&econn.conn, &site.deployment)? {
+                return Err(StoreError::Unknown(anyhow!(
+                    "deployment `{}` already exists in shard `{}`",
+                    site.deployment,
+                    site.shard
+                )));
+            }
+            crate::dump::restore_metadata(&econn.conn, &site.deployment, manifest, dir)?;
+
+            let schema = deployment::schema(&econn.conn, site.deployment.clone())?;
+            econn.create_schema(site.namespace.clone(), &schema, None)?;
+            let layout =
+                e::Connection::layout(&econn.conn, site.namespace.clone(), &site.deployment)?;
+            crate::dump::restore(&econn.conn, &layout, &site.deployment, manifest, dir)?;
+
+            // We do not have the history before the block of the dump, and
+            // can therefore not revert past it
+            deployment::set_earliest_block(&econn.conn, &site.deployment, manifest.block.number)?;
+            Ok(())
+        })
+    }
+
+    /// Remove entity versions for the deployment `site` that are older than
+    /// `history_blocks` blocks before the deployment's current head. If
+    /// `history_blocks` is given, it also becomes the deployment's setting
+    /// for how much history to retain; otherwise, the stored setting is
+    /// used. Returns the earliest block that is still fully retained and
+    /// the number of entity versions that were removed
+    pub(crate) fn prune(
+        &self,
+        logger: &Logger,
+        site: &Site,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<(BlockNumber, usize), StoreError> {
+        let econn = self.get_entity_conn(site, ReplicaId::Main)?;
+
+        econn.transaction(|| -> Result<_, StoreError> {
+            let history_blocks = match history_blocks {
+                Some(history_blocks) => {
+                    deployment::set_history_blocks(&econn.conn, &site.deployment, history_blocks)?;
+                    history_blocks
+                }
+                None => {
+                    deployment::history_blocks(&econn.conn, &site.deployment)?.ok_or_else(|| {
+                        anyhow!(
+                            "deployment `{}` does not have a setting for the number of \
+                             blocks of history to retain",
+                            site.deployment
+                        )
+                    })?
+                }
+            };
+            if history_blocks <= 0 {
+                return Err(anyhow!(
+                    "the number of blocks of history to retain must be positive, but is {}",
+                    history_blocks
+                )
+                .into());
+            }
+
+            let head = Self::block_ptr_with_conn(&site.deployment, &econn)?
+                .map(|ptr| crate::block_range::block_number(&ptr))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "deployment `{}` has not started syncing yet",
+                        site.deployment
+                    )
+                })?;
+            let earliest_block = deployment::earliest_block(&econn.conn, &site.deployment)?;
+            let new_earliest_block = head - history_blocks;
+            if new_earliest_block <= earliest_block {
+                // Nothing to do; the history has already been pruned at
+                // least that far
+                return Ok((earliest_block, 0));
+            }
+
+            let count = econn.prune(logger, new_earliest_block)?;
+            change_log::prune(&econn.conn, &site.deployment, new_earliest_block)?;
+            Ok((new_earliest_block, count))
+        })
+    }
+
     pub(crate) fn deployment_state_from_id(
         &self,
         id: SubgraphDeploymentId,
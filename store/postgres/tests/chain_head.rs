@@ -5,13 +5,14 @@ use futures::future::IntoFuture;
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use graph::prelude::{Future01CompatExt, SubgraphDeploymentId};
+use graph::prelude::{Future01CompatExt, Store as _, SubgraphDeploymentId};
 use graph::{components::store::ChainStore, prelude::QueryStoreManager};
 use graph_store_postgres::NetworkStore as DieselStore;
 
 use test_store::block_store::{
     Chain, FakeBlock, BLOCK_FIVE, BLOCK_FOUR, BLOCK_ONE, BLOCK_ONE_NO_PARENT, BLOCK_ONE_SIBLING,
-    BLOCK_THREE, BLOCK_THREE_NO_PARENT, BLOCK_TWO, BLOCK_TWO_NO_PARENT, GENESIS_BLOCK, NO_PARENT,
+    BLOCK_THREE, BLOCK_THREE_NO_PARENT, BLOCK_THREE_REORG, BLOCK_TWO, BLOCK_TWO_NO_PARENT,
+    BLOCK_TWO_REORG, GENESIS_BLOCK, NO_PARENT,
 };
 use test_store::*;
 
@@ -221,3 +222,182 @@ fn block_hashes_by_number() {
         Ok(())
     })
 }
+
+#[test]
+fn block_traces() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_TWO_NO_PARENT,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        assert!(store
+            .block_traces(&BLOCK_TWO.block_hash())
+            .unwrap()
+            .is_none());
+
+        store.set_block_traces(BLOCK_TWO.block_ptr(), &[]).unwrap();
+        store
+            .set_block_traces(BLOCK_TWO_NO_PARENT.block_ptr(), &[])
+            .unwrap();
+        assert_eq!(
+            Some(0),
+            store
+                .block_traces(&BLOCK_TWO.block_hash())
+                .unwrap()
+                .map(|traces| traces.len())
+        );
+
+        // Confirming a block also removes the traces of its siblings
+        store
+            .confirm_block_hash(2, &BLOCK_TWO.block_hash())
+            .unwrap();
+        assert!(store
+            .block_traces(&BLOCK_TWO.block_hash())
+            .unwrap()
+            .is_some());
+        assert!(store
+            .block_traces(&BLOCK_TWO_NO_PARENT.block_hash())
+            .unwrap()
+            .is_none());
+        Ok(())
+    })
+}
+
+#[test]
+fn block_traces_in_range() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_TWO_NO_PARENT,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        let cached = |from, to| store.block_traces_in_range(from, to).unwrap().is_some();
+
+        store.set_block_traces(BLOCK_TWO.block_ptr(), &[]).unwrap();
+        assert!(!cached(1, 2), "the traces of block one are missing");
+        assert!(cached(2, 2));
+
+        store.set_block_traces(BLOCK_ONE.block_ptr(), &[]).unwrap();
+        assert!(cached(1, 2));
+
+        // With the traces of two blocks for number two, we can't tell
+        // which ones to use
+        store
+            .set_block_traces(BLOCK_TWO_NO_PARENT.block_ptr(), &[])
+            .unwrap();
+        assert!(!cached(1, 2));
+        assert!(cached(1, 1));
+
+        store
+            .confirm_block_hash(2, &BLOCK_TWO.block_hash())
+            .unwrap();
+        assert!(cached(1, 2));
+        Ok(())
+    })
+}
+
+#[test]
+fn transaction_receipts() {
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE];
+    run_test(chain, move |store| -> Result<(), ()> {
+        // Only full blocks have receipts
+        assert!(store
+            .transaction_receipts(&BLOCK_ONE.block_hash())
+            .unwrap()
+            .is_none());
+        assert!(store
+            .transaction_receipts(&BLOCK_TWO.block_hash())
+            .unwrap()
+            .is_none());
+        Ok(())
+    })
+}
+
+#[test]
+fn record_reorg() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO_REORG,
+        &*BLOCK_THREE_REORG,
+        &*BLOCK_THREE_NO_PARENT,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        // Moving to a descendant of the old head is not a reorg
+        let reorg = store
+            .record_reorg(
+                BLOCK_TWO.block_ptr(),
+                BLOCK_THREE.block_ptr(),
+                ANCESTOR_COUNT,
+            )
+            .unwrap();
+        assert_eq!(None, reorg);
+
+        // Without the parent of the new head, we can not find the common
+        // ancestor
+        let reorg = store
+            .record_reorg(
+                BLOCK_TWO.block_ptr(),
+                BLOCK_THREE_NO_PARENT.block_ptr(),
+                ANCESTOR_COUNT,
+            )
+            .unwrap();
+        assert_eq!(None, reorg);
+
+        // The common ancestor of the two chains is the genesis block
+        let reorg = store
+            .record_reorg(
+                BLOCK_TWO.block_ptr(),
+                BLOCK_THREE_REORG.block_ptr(),
+                ANCESTOR_COUNT,
+            )
+            .unwrap()
+            .expect("moving to another chain is a reorg");
+        assert_eq!(NETWORK_NAME, reorg.network);
+        assert_eq!(BLOCK_TWO.block_ptr(), reorg.old_head);
+        assert_eq!(BLOCK_THREE_REORG.block_ptr(), reorg.new_head);
+        assert_eq!(2, reorg.depth);
+
+        // Reorgs deeper than the maximum depth are not detected
+        let reorg = store
+            .record_reorg(BLOCK_TWO.block_ptr(), BLOCK_THREE_REORG.block_ptr(), 1)
+            .unwrap();
+        assert_eq!(None, reorg);
+
+        let reorg = store
+            .record_reorg(
+                BLOCK_THREE_REORG.block_ptr(),
+                BLOCK_THREE.block_ptr(),
+                ANCESTOR_COUNT,
+            )
+            .unwrap()
+            .expect("moving back to the first chain is a reorg");
+        assert_eq!(3, reorg.depth);
+
+        let reorgs = store
+            .chain_reorgs(Some(NETWORK_NAME.to_owned()), 10)
+            .unwrap();
+        assert_eq!(
+            vec![
+                (BLOCK_THREE_REORG.block_ptr(), BLOCK_THREE.block_ptr(), 3),
+                (BLOCK_TWO.block_ptr(), BLOCK_THREE_REORG.block_ptr(), 2)
+            ],
+            reorgs
+                .into_iter()
+                .map(|reorg| (reorg.old_head, reorg.new_head, reorg.depth))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, store.chain_reorgs(None, 1).unwrap().len());
+        assert!(store
+            .chain_reorgs(Some("no-such-network".to_owned()), 10)
+            .unwrap()
+            .is_empty());
+        Ok(())
+    })
+}
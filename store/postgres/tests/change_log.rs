@@ -0,0 +1,123 @@
+//! Tests for the entity change log. They live in their own file since
+//! `GRAPH_ENTITY_CHANGE_LOG` is only read once per process, and would
+//! otherwise make all other tests write the log
+use graph::prelude::{
+    ChangeLogEntry, ChangeLogOperation, Entity, EntityKey, EntityModification, EntityOperation,
+    Store as _, SubgraphDeploymentId, Value,
+};
+use test_store::*;
+
+const SCHEMA: &str = "type Thing @entity { id: ID!, value: Int! }";
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("changeLog").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SCHEMA);
+    id
+}
+
+fn set(id: &SubgraphDeploymentId, thing: &str, value: i32) -> EntityOperation {
+    EntityOperation::Set {
+        key: EntityKey::data(id.clone(), "Thing".to_owned(), thing.to_owned()),
+        data: Entity::from(vec![
+            ("id", Value::from(thing)),
+            ("value", Value::from(value)),
+        ]),
+    }
+}
+
+fn remove(id: &SubgraphDeploymentId, thing: &str) -> EntityOperation {
+    EntityOperation::Remove {
+        key: EntityKey::data(id.clone(), "Thing".to_owned(), thing.to_owned()),
+    }
+}
+
+/// Summarize `entry` as its cursor, block number, and the operation and
+/// id of each modification, sorted by id. Reverts have no modifications
+fn describe(entry: &ChangeLogEntry) -> (i64, u64, Option<Vec<(&'static str, String)>>) {
+    let mods = match &entry.operation {
+        ChangeLogOperation::Changes(mods) => {
+            let mut mods: Vec<_> = mods
+                .iter()
+                .map(|modification| match modification {
+                    EntityModification::Insert { key, .. } => ("insert", key.entity_id.clone()),
+                    EntityModification::Overwrite { key, .. } => {
+                        ("overwrite", key.entity_id.clone())
+                    }
+                    EntityModification::Remove { key } => ("remove", key.entity_id.clone()),
+                })
+                .collect();
+            mods.sort_by(|a, b| a.1.cmp(&b.1));
+            Some(mods)
+        }
+        ChangeLogOperation::Revert => None,
+    };
+    (entry.cursor, entry.block.number, mods)
+}
+
+#[test]
+fn change_log_records_changes_and_reverts() {
+    std::env::set_var("GRAPH_ENTITY_CHANGE_LOG", "true");
+
+    run_test_sequentially(setup, |store, id| async move {
+        let write = |block: usize, ops| {
+            transact_entity_operations(&store, id.clone(), BLOCKS[block].clone(), ops).unwrap()
+        };
+
+        write(0, vec![set(&id, "a", 1), set(&id, "b", 1)]);
+        write(1, vec![set(&id, "a", 2), remove(&id, "b")]);
+        store
+            .revert_block_operations(id.clone(), BLOCKS[0].clone())
+            .unwrap();
+        write(1, vec![set(&id, "c", 1)]);
+
+        let entries = store.entity_change_log(&id, 0, 100).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    1,
+                    0,
+                    Some(vec![("insert", "a".to_owned()), ("insert", "b".to_owned())])
+                ),
+                (
+                    2,
+                    1,
+                    Some(vec![
+                        ("overwrite", "a".to_owned()),
+                        ("remove", "b".to_owned())
+                    ])
+                ),
+                (3, 0, None),
+                (4, 1, Some(vec![("insert", "c".to_owned())])),
+            ],
+            entries.iter().map(describe).collect::<Vec<_>>()
+        );
+        assert_eq!(BLOCKS[0], entries[2].block);
+        match &entries[1].operation {
+            ChangeLogOperation::Changes(mods) => {
+                let overwrite = mods
+                    .iter()
+                    .find_map(|modification| match modification {
+                        EntityModification::Overwrite { data, .. } => Some(data),
+                        _ => None,
+                    })
+                    .unwrap();
+                assert_eq!(Some(&Value::Int(2)), overwrite.get("value"));
+            }
+            ChangeLogOperation::Revert => panic!("expected changes but got a revert"),
+        }
+
+        // A client that resumes after the first two entries sees the revert
+        // that happened in the meantime
+        let cursors: Vec<_> = store
+            .entity_change_log(&id, 2, 100)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.cursor)
+            .collect();
+        assert_eq!(vec![3, 4], cursors);
+
+        assert_eq!(1, store.entity_change_log(&id, 0, 1).unwrap().len());
+        assert!(store.entity_change_log(&id, 4, 100).unwrap().is_empty());
+    })
+}
@@ -0,0 +1,165 @@
+//! Dump a deployment with the `ShardedStore` and create a new deployment
+//! from that dump
+use std::str::FromStr;
+use std::sync::Arc;
+
+use graph::data::store::scalar;
+use graph::data::subgraph::schema::DynamicEthereumContractDataSourceEntity;
+use graph::data::subgraph::{Mapping, Source};
+use graph::prelude::web3::types::{Address, H256};
+use graph::prelude::*;
+use graph_store_postgres::layout_for_tests::DumpFormat;
+use graph_store_postgres::PRIMARY_SHARD;
+use test_store::*;
+
+const SCHEMA: &str = "
+    type Token @entity {
+        id: ID!,
+        name: String,
+        supply: BigInt!
+    }
+";
+
+fn block_ptr(number: u64) -> EthereumBlockPointer {
+    (H256::from_low_u64_be(number + 1), number).into()
+}
+
+fn token(id: &str, name: Option<&str>, supply: u64) -> EntityOperation {
+    let mut data = Entity::new();
+    data.set("id", id);
+    data.set("name", name.map(Value::from).unwrap_or(Value::Null));
+    data.set("supply", scalar::BigInt::from(supply));
+    EntityOperation::Set {
+        key: EntityKey::data(
+            SubgraphDeploymentId::new("dumpRestore").unwrap(),
+            "Token".to_owned(),
+            id.to_owned(),
+        ),
+        data,
+    }
+}
+
+fn data_source() -> DataSource {
+    DataSource {
+        kind: "ethereum/contract".to_owned(),
+        name: "Token".to_owned(),
+        network: Some(NETWORK_NAME.to_owned()),
+        source: Source {
+            address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            abi: "Token".to_owned(),
+            start_block: 0,
+        },
+        mapping: Mapping {
+            kind: "ethereum/events".to_owned(),
+            api_version: "0.0.4".to_owned(),
+            language: "wasm/assemblyscript".to_owned(),
+            entities: vec!["Token".to_owned()],
+            abis: vec![],
+            event_handlers: vec![],
+            call_handlers: vec![],
+            block_handlers: vec![],
+            link: Link::from("link".to_owned()),
+            runtime: Arc::new(Vec::new()),
+        },
+        context: None,
+        creation_block: None,
+    }
+}
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("dumpRestore").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(&id, SCHEMA);
+    id
+}
+
+/// Write a few blocks with entities and a dynamic data source, dump the
+/// deployment in `format`, remove it, and check that restoring the dump
+/// brings back the entities, the dynamic data source, and the block
+/// pointer
+fn dump_and_restore(format: DumpFormat) {
+    run_test_sequentially(setup, move |store, id| async move {
+        let write = |number, ops| {
+            transact_entity_operations(&store, id.clone(), block_ptr(number), ops)
+                .expect("writing block succeeds")
+        };
+        write(0, vec![token("a", Some("A"), 1), token("b", Some(""), 1)]);
+        let mut ops = vec![token("c", None, 1)];
+        ops.extend(
+            DynamicEthereumContractDataSourceEntity::from((&id, &data_source(), &block_ptr(1)))
+                .write_entity_operations(&id, &DynamicEthereumContractDataSourceEntity::make_id()),
+        );
+        write(1, ops);
+        write(2, vec![token("a", Some("A"), 2)]);
+
+        let dir =
+            std::env::temp_dir().join(format!("sharded-dump-{}-{}", format, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let manifest = store
+            .store()
+            .dump(&id, None, format, &dir)
+            .expect("dumping succeeds");
+        assert_eq!(2, manifest.block.number);
+        assert!(manifest.block.hash.is_some());
+        assert!(!manifest.metadata.is_empty());
+
+        remove_subgraph(&id);
+        assert!(primary_connection().find_site(&id).unwrap().is_none());
+
+        let name = SubgraphName::new("dumpRestore").unwrap();
+        let node = NodeId::new("test").unwrap();
+        let restored = store
+            .store()
+            .restore(&dir, PRIMARY_SHARD.clone(), name, node);
+        std::fs::remove_dir_all(&dir).ok();
+        restored.expect("restoring succeeds");
+
+        assert_eq!(Some(block_ptr(2)), store.block_ptr(&id).unwrap());
+
+        let get = |entity_id: &str| {
+            store
+                .get(EntityKey::data(
+                    id.clone(),
+                    "Token".to_owned(),
+                    entity_id.to_owned(),
+                ))
+                .unwrap()
+                .unwrap_or_else(|| panic!("Token[{}] was restored", entity_id))
+        };
+        let a = get("a");
+        assert_eq!(Some(&Value::from("A")), a.get("name"));
+        assert_eq!(
+            Some(&Value::BigInt(scalar::BigInt::from(2u64))),
+            a.get("supply")
+        );
+        assert_eq!(Some(&Value::from("")), get("b").get("name"));
+        assert_eq!(None, get("c").get("name"));
+
+        let data_sources = store
+            .load_dynamic_data_sources(id.clone())
+            .await
+            .expect("loading dynamic data sources succeeds");
+        assert_eq!(1, data_sources.len());
+        assert_eq!("Token", data_sources[0].name);
+        assert_eq!(Some(1), data_sources[0].creation_block);
+
+        // The restored deployment continues indexing where the dump ended
+        write(3, vec![token("d", Some("D"), 1)]);
+        assert_eq!(Some(block_ptr(3)), store.block_ptr(&id).unwrap());
+    })
+}
+
+#[test]
+fn dump_and_restore_parquet() {
+    dump_and_restore(DumpFormat::Parquet);
+}
+
+#[test]
+fn dump_and_restore_csv() {
+    dump_and_restore(DumpFormat::Csv);
+}
+
+#[test]
+fn dump_and_restore_jsonl() {
+    dump_and_restore(DumpFormat::Jsonl);
+}
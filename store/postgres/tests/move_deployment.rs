@@ -0,0 +1,172 @@
+use graph::prelude::{
+    web3::types::H256, Entity, EntityKey, EntityOperation, EthereumBlockPointer, Store as _,
+    StoreError, SubgraphDeploymentId, Value,
+};
+use graph_store_postgres::NetworkStore;
+use std::sync::Arc;
+use test_store::*;
+
+const SCHEMA: &str = "
+    type Block @entity {
+        id: ID!,
+        number: Int!,
+        rewritten: Boolean!
+    }
+
+    type Counter @entity {
+        id: ID!,
+        value: Int!
+    }
+";
+
+/// The blocks that are written while the deployment is being moved
+const HEAD: i32 = 60;
+
+/// Every block whose number is a multiple of this is reverted and written
+/// again
+const REVERT_EVERY: i32 = 7;
+
+fn block_ptr(number: i32) -> EthereumBlockPointer {
+    (H256::from_low_u64_be(number as u64 + 1), number as u64).into()
+}
+
+fn key(id: &SubgraphDeploymentId, entity_type: &str, entity_id: String) -> EntityKey {
+    EntityKey::data(id.clone(), entity_type.to_owned(), entity_id)
+}
+
+/// Write block `number`, retrying if the write raced with moving the
+/// deployment and went to the old copy. The store knows about the new copy
+/// once it has been moved, and the retry writes to that
+fn write(store: &Arc<NetworkStore>, id: &SubgraphDeploymentId, number: i32, rewritten: bool) {
+    let mut block = Entity::new();
+    block.set("id", number.to_string());
+    block.set("number", number);
+    block.set("rewritten", rewritten);
+    let mut counter = Entity::new();
+    counter.set("id", "c".to_owned());
+    counter.set("value", number);
+    let ops = vec![
+        EntityOperation::Set {
+            key: key(id, "Block", number.to_string()),
+            data: block,
+        },
+        EntityOperation::Set {
+            key: key(id, "Counter", "c".to_owned()),
+            data: counter,
+        },
+    ];
+
+    loop {
+        match transact_entity_operations(store, id.clone(), block_ptr(number), ops.clone()) {
+            Ok(()) => return,
+            Err(StoreError::DeploymentNotFound(_)) => continue,
+            Err(e) => panic!("writing block {} failed: {}", number, e),
+        }
+    }
+}
+
+/// Revert block `number`, retrying like `write`
+fn revert(store: &Arc<NetworkStore>, id: &SubgraphDeploymentId, number: i32) {
+    loop {
+        match store.revert_block_operations(id.clone(), block_ptr(number - 1)) {
+            Ok(()) => return,
+            Err(StoreError::DeploymentNotFound(_)) => continue,
+            Err(e) => panic!("reverting block {} failed: {}", number, e),
+        }
+    }
+}
+
+#[test]
+fn move_deployment_while_indexing() {
+    fn setup() -> SubgraphDeploymentId {
+        let id = SubgraphDeploymentId::new("moveDeployment").unwrap();
+        remove_subgraphs();
+        create_test_subgraph(&id, SCHEMA);
+        id
+    }
+
+    run_test_sequentially(setup, |store, id| async move {
+        let src = primary_connection()
+            .find_site(&id)
+            .unwrap()
+            .expect("the deployment exists")
+            .shard;
+        let dst = match shards().into_iter().find(|shard| shard != &src) {
+            Some(dst) => dst,
+            // If the test config only has one shard, there is nowhere to
+            // move the deployment to. This will happen when the tests do
+            // not use a configuration file
+            None => return,
+        };
+
+        // The deployment needs to have started syncing before it can be
+        // moved
+        for number in 0..5 {
+            write(&store, &id, number, false);
+        }
+
+        let writer = {
+            let store = store.clone();
+            let id = id.clone();
+            std::thread::spawn(move || {
+                for number in 5..=HEAD {
+                    write(&store, &id, number, false);
+                    if number % REVERT_EVERY == 0 {
+                        revert(&store, &id, number);
+                        write(&store, &id, number, true);
+                    }
+                }
+            })
+        };
+        let old = store
+            .store()
+            .move_deployment(&*LOGGER, &id, dst.clone())
+            .expect("moving the deployment succeeds");
+        writer.join().expect("writing blocks succeeds");
+
+        assert_eq!(src, old.shard);
+        let site = primary_connection().find_site(&id).unwrap().unwrap();
+        assert_eq!(dst, site.shard);
+        assert_ne!(old.namespace, site.namespace);
+        assert!(
+            !namespace_exists(&old.shard, old.namespace.as_str()),
+            "the entities of the old copy were removed"
+        );
+        assert!(namespace_exists(&site.shard, site.namespace.as_str()));
+        assert_eq!(
+            Some("test".to_owned()),
+            store
+                .assigned_node(&id)
+                .unwrap()
+                .map(|node| node.to_string())
+        );
+
+        assert_eq!(Some(block_ptr(HEAD)), store.block_ptr(&id).unwrap());
+        for number in 0..=HEAD {
+            let block = store
+                .get(key(&id, "Block", number.to_string()))
+                .unwrap()
+                .unwrap_or_else(|| panic!("block {} was copied", number));
+            assert_eq!(Some(&Value::Int(number)), block.get("number"));
+            assert_eq!(
+                Some(&Value::Bool(number >= 5 && number % REVERT_EVERY == 0)),
+                block.get("rewritten"),
+                "block {}",
+                number
+            );
+        }
+        let counter = store
+            .get(key(&id, "Counter", "c".to_owned()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(&Value::Int(HEAD)), counter.get("value"));
+
+        // The copy can be reverted like any other deployment
+        revert(&store, &id, HEAD);
+        let counter = store
+            .get(key(&id, "Counter", "c".to_owned()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(&Value::Int(HEAD - 1)), counter.get("value"));
+    })
+}
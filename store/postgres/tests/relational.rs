@@ -7,10 +7,14 @@ use std::str::FromStr;
 
 use graph::data::store::scalar::{BigDecimal, BigInt, Bytes};
 use graph::prelude::{
-    web3::types::H256, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
-    EntityRange, Schema, SubgraphDeploymentId, Value, ValueType, BLOCK_NUMBER_MAX,
+    q, serde_json, web3::types::H256, BlockNumber, CursorPosition, Entity, EntityAggregate,
+    EntityAggregation, EntityCollection, EntityCursor, EntityFilter, EntityKey, EntityModification,
+    EntityOrder, EntityQuery, EntityRange, QueryExecutionError, Schema, StoreError,
+    SubgraphDeploymentId, Value, ValueType, BLOCK_NUMBER_MAX,
+};
+use graph_store_postgres::layout_for_tests::{
+    dump, restore, DumpBlock, DumpFormat, DumpManifest, Layout, Namespace, STRING_PREFIX_SIZE,
 };
-use graph_store_postgres::layout_for_tests::{Layout, Namespace, STRING_PREFIX_SIZE};
 
 use test_store::*;
 
@@ -520,11 +524,13 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
             collection,
             Some(filter),
             EntityOrder::Default,
+            None,
             EntityRange {
                 first: None,
                 skip: 0,
             },
             BLOCK_NUMBER_MAX,
+            0,
             None,
         )
         .expect("Count query failed")
@@ -557,6 +563,348 @@ fn delete() {
     });
 }
 
+#[test]
+fn prune() {
+    fn scalar_query(
+        conn: &PgConnection,
+        layout: &Layout,
+        block: BlockNumber,
+        earliest_block: BlockNumber,
+    ) -> Result<Vec<Entity>, QueryExecutionError> {
+        layout.query::<Entity>(
+            &*LOGGER,
+            &conn,
+            EntityCollection::All(vec!["Scalar".to_owned()]),
+            None,
+            EntityOrder::Default,
+            None,
+            EntityRange::first(100),
+            block,
+            earliest_block,
+            None,
+        )
+    }
+
+    run_test(|conn, layout| {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+        let key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Scalar".to_owned(),
+            "one".to_owned(),
+        );
+        for block in 1..5 {
+            let mut entity = SCALAR_ENTITY.clone();
+            entity.set("int", block);
+            layout
+                .update(&conn, &key, entity, block)
+                .expect("Failed to update");
+        }
+
+        // Versions valid at blocks 0, 1, and 2 are not visible at block 3
+        let count = layout.prune(&*LOGGER, &conn, 3).expect("Failed to prune");
+        assert_eq!(3, count);
+
+        let entities = scalar_query(conn, layout, 3, 3).expect("block 3 can be queried");
+        assert_eq!(1, entities.len());
+        assert_eq!(Some(&Value::Int(3)), entities[0].get("int"));
+        let entities =
+            scalar_query(conn, layout, BLOCK_NUMBER_MAX, 3).expect("the head can be queried");
+        assert_eq!(Some(&Value::Int(4)), entities[0].get("int"));
+
+        match scalar_query(conn, layout, 2, 3) {
+            Err(QueryExecutionError::BlockNotRetained(2, 3)) => { /* expected */ }
+            other => panic!("expected BlockNotRetained error but got {:?}", other),
+        }
+
+        // Finding entities is subject to the same restriction; the store
+        // remembers the earliest block when it loads the layout
+        let layout = Layout {
+            earliest_block: 3,
+            ..layout.clone()
+        };
+        let entity = layout
+            .find(conn, "Scalar", "one", 3)
+            .expect("block 3 can be searched")
+            .unwrap();
+        assert_eq!(Some(&Value::Int(3)), entity.get("int"));
+        let ids = vec!["one"];
+        let entities = layout
+            .find_many(conn, vec![("Scalar", &ids)].into_iter().collect(), 3)
+            .expect("block 3 can be searched");
+        assert_eq!(1, entities["Scalar"].len());
+
+        let expected = QueryExecutionError::BlockNotRetained(2, 3).to_string();
+        match layout.find(conn, "Scalar", "one", 2) {
+            Err(StoreError::QueryExecutionError(msg)) if msg == expected => { /* expected */ }
+            other => panic!("expected BlockNotRetained error but got {:?}", other),
+        }
+        match layout.find_many(conn, vec![("Scalar", &ids)].into_iter().collect(), 2) {
+            Err(StoreError::QueryExecutionError(msg)) if msg == expected => { /* expected */ }
+            other => panic!("expected BlockNotRetained error but got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn entity_changes() {
+    fn describe(modification: &EntityModification) -> (&'static str, String) {
+        match modification {
+            EntityModification::Insert { key, .. } => ("insert", key.entity_id.clone()),
+            EntityModification::Overwrite { key, .. } => ("overwrite", key.entity_id.clone()),
+            EntityModification::Remove { key } => ("remove", key.entity_id.clone()),
+        }
+    }
+
+    run_test(|conn, layout| {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+        let mut key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Scalar".to_owned(),
+            "one".to_owned(),
+        );
+        let mut entity = SCALAR_ENTITY.clone();
+        entity.set("int", 2);
+        layout
+            .update(&conn, &key, entity.clone(), 2)
+            .expect("Failed to update");
+        layout.delete(&conn, &key, 3).expect("Failed to delete");
+        let mut two = SCALAR_ENTITY.clone();
+        two.set("id", "two");
+        key.entity_id = "two".to_owned();
+        layout
+            .insert(&conn, &key, two, 3)
+            .expect("Failed to insert");
+
+        let blocks = layout
+            .entity_changes(&conn, &*THINGS_SUBGRAPH_ID, 0, 3, false)
+            .expect("Failed to read entity changes");
+        assert_eq!(
+            vec![2, 3],
+            blocks.iter().map(|b| b.block).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![("overwrite", "one".to_owned())],
+            blocks[0].mods.iter().map(describe).collect::<Vec<_>>()
+        );
+        match &blocks[0].mods[0] {
+            EntityModification::Overwrite { data, .. } => {
+                assert_eq!(Some(&Value::Int(2)), data.get("int"))
+            }
+            other => panic!("expected an overwrite but got {:?}", other),
+        }
+
+        let mut mods = blocks[1].mods.iter().map(describe).collect::<Vec<_>>();
+        mods.sort();
+        assert_eq!(
+            vec![("insert", "two".to_owned()), ("remove", "one".to_owned())],
+            mods
+        );
+
+        // Changes outside of the requested range are not returned
+        let blocks = layout
+            .entity_changes(&conn, &*THINGS_SUBGRAPH_ID, 2, 2, false)
+            .expect("Failed to read entity changes");
+        assert!(blocks.is_empty());
+    });
+}
+
+#[test]
+fn dump_jsonl() {
+    run_test(|conn, layout| {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+        let mut entity = SCALAR_ENTITY.clone();
+        entity.set("int", 1);
+        update_entity(&conn, &layout, "Scalar", entity);
+
+        // Dump the version of the entity from before the update
+        let dir = std::env::temp_dir().join(format!("relational-dump-{}", std::process::id()));
+        let tables = dump(conn, layout, 0, DumpFormat::Jsonl, &dir).expect("Failed to dump");
+        let scalar = tables
+            .iter()
+            .find(|table| table.entity == "Scalar")
+            .expect("the dump contains Scalar");
+        assert_eq!(1, scalar.rows);
+        assert_eq!("scalar.jsonl", scalar.file);
+
+        let text = std::fs::read_to_string(dir.join(&scalar.file)).expect("Failed to read dump");
+        std::fs::remove_dir_all(&dir).ok();
+        let line: serde_json::Value = serde_json::from_str(text.trim()).expect("valid JSON");
+        assert_eq!("one", line["id"]);
+        assert_eq!(std::i32::MAX, line["int"]);
+        assert_eq!(
+            serde_json::json!(["left", "right", "middle"]),
+            line["strings"]
+        );
+        assert_eq!("yellow", line["color"]);
+    });
+}
+
+#[test]
+fn dump_restore() {
+    for format in &[DumpFormat::Parquet, DumpFormat::Csv, DumpFormat::Jsonl] {
+        run_test(|conn, layout| {
+            // Strings that the CSV format needs to tell apart from `null`
+            let mut entities = vec![SCALAR_ENTITY.clone()];
+            for (id, string) in &[
+                ("empty", Value::from("")),
+                ("null", Value::Null),
+                ("null-text", Value::from("\\N")),
+                ("backslash", Value::from("\\")),
+            ] {
+                let mut entity = SCALAR_ENTITY.clone();
+                entity.set("id", *id);
+                entity.set("string", string.clone());
+                entities.push(entity);
+            }
+            for entity in &entities {
+                insert_entity(&conn, &layout, "Scalar", entity.clone());
+            }
+
+            let dir = std::env::temp_dir().join(format!(
+                "relational-restore-{}-{}",
+                format,
+                std::process::id()
+            ));
+            let tables = dump(conn, layout, 0, *format, &dir).expect("Failed to dump");
+            let manifest = DumpManifest {
+                deployment: THINGS_SUBGRAPH_ID.to_string(),
+                network: None,
+                block: DumpBlock {
+                    number: 0,
+                    hash: None,
+                },
+                format: *format,
+                schema: THINGS_GQL.to_owned(),
+                tables,
+                metadata: vec![],
+            };
+
+            remove_test_data(conn);
+            let layout = insert_test_data(conn);
+            let count = restore(conn, &layout, &THINGS_SUBGRAPH_ID, &manifest, &dir);
+            std::fs::remove_dir_all(&dir).ok();
+            assert_eq!(entities.len(), count.expect("Failed to restore"));
+
+            for expected in &entities {
+                let id = expected.id().unwrap();
+                let entity = layout
+                    .find(conn, "Scalar", &id, BLOCK_NUMBER_MAX)
+                    .expect("Failed to read Scalar")
+                    .unwrap_or_else(|| panic!("Scalar[{}] was restored from {}", id, format));
+                assert_entity_eq!(scrub(expected), entity);
+            }
+        });
+    }
+}
+
+#[test]
+fn aggregate() {
+    fn user_aggregation(
+        aggregates: Vec<EntityAggregate>,
+        group_by: Vec<&str>,
+        filter: Option<EntityFilter>,
+    ) -> EntityAggregation {
+        EntityAggregation {
+            subgraph_id: THINGS_SUBGRAPH_ID.clone(),
+            block: BLOCK_NUMBER_MAX,
+            entity_type: "User".to_owned(),
+            filter,
+            aggregates,
+            group_by: group_by.into_iter().map(|attr| attr.to_owned()).collect(),
+            range: EntityRange::first(100),
+            logger: None,
+            query_id: None,
+        }
+    }
+
+    fn int(i: i32) -> q::Value {
+        q::Value::Int(i.into())
+    }
+
+    fn string(s: &str) -> q::Value {
+        q::Value::String(s.to_owned())
+    }
+
+    run_test(|conn, layout| {
+        use EntityAggregate::*;
+
+        insert_users(conn, layout);
+
+        let aggregation = user_aggregation(
+            vec![
+                Count,
+                Sum("age".to_owned()),
+                Min("age".to_owned()),
+                Max("age".to_owned()),
+                Avg("age".to_owned()),
+                Sum("seconds_age".to_owned()),
+            ],
+            vec![],
+            None,
+        );
+        let values = layout
+            .aggregate(conn, aggregation, 0)
+            .expect("aggregation succeeds");
+        assert_eq!(1, values.len());
+        let value = &values[0];
+        assert_eq!(Some(&int(3)), value.get("count"));
+        assert_eq!(Some(&string("138")), value.get("age_sum"));
+        assert_eq!(Some(&int(28)), value.get("age_min"));
+        assert_eq!(Some(&int(67)), value.get("age_max"));
+        assert_eq!(Some(&string("46")), value.get("age_avg"));
+        assert_eq!(Some(&string("4354948800")), value.get("seconds_age_sum"));
+
+        // Group by a column, and apply a filter
+        let aggregation = user_aggregation(
+            vec![Count, Sum("age".to_owned())],
+            vec!["coffee"],
+            Some(EntityFilter::GreaterThan("age".to_owned(), Value::Int(30))),
+        );
+        let values = layout
+            .aggregate(conn, aggregation, 0)
+            .expect("aggregation succeeds");
+        assert_eq!(2, values.len());
+        assert_eq!(Some(&q::Value::Boolean(false)), values[0].get("coffee"));
+        assert_eq!(Some(&int(1)), values[0].get("count"));
+        assert_eq!(Some(&string("67")), values[0].get("age_sum"));
+        assert_eq!(Some(&q::Value::Boolean(true)), values[1].get("coffee"));
+        assert_eq!(Some(&int(1)), values[1].get("count"));
+        assert_eq!(Some(&string("43")), values[1].get("age_sum"));
+
+        // Aggregates over non-numeric attributes are rejected
+        let aggregation = user_aggregation(vec![Sum("name".to_owned())], vec![], None);
+        assert!(layout.aggregate(conn, aggregation, 0).is_err());
+    });
+}
+
+#[test]
+fn estimate_cost() {
+    run_test(|conn, layout| {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+
+        let estimate = |entity_types: Vec<&str>| {
+            layout.estimate_cost(
+                conn,
+                EntityCollection::All(entity_types.into_iter().map(str::to_owned).collect()),
+                Some(EntityFilter::Equal("bool".into(), true.into())),
+                EntityOrder::Default,
+                None,
+                EntityRange::first(100),
+                BLOCK_NUMBER_MAX,
+                None,
+            )
+        };
+
+        // Postgres plans the query with `explain` without running it
+        let cost = estimate(vec!["Scalar"]).expect("the query can be explained");
+        assert!(cost > 0.0 && cost.is_finite(), "unexpected cost {}", cost);
+
+        // Queries for no entity types are not sent to the database
+        assert_eq!(0.0, estimate(vec![]).expect("empty queries have no cost"));
+    });
+}
+
 #[test]
 fn conflicting_entity() {
     run_test(|conn, layout| {
@@ -626,8 +974,10 @@ impl<'a> QueryChecker<'a> {
                 query.collection,
                 query.filter,
                 query.order,
+                query.cursor,
                 query.range,
                 BLOCK_NUMBER_MAX,
+                0,
                 None,
             )
             .expect("layout.query failed to execute query");
@@ -813,6 +1163,45 @@ fn check_find() {
                     .filter(EntityFilter::NotEndsWith("name".to_owned(), "ini".into()))
                     .desc("name"),
             )
+            .check(
+                vec!["2"],
+                user_query().filter(EntityFilter::ContainsNoCase("name".into(), "IND".into())),
+            )
+            .check(
+                vec!["3", "1"],
+                user_query()
+                    .filter(EntityFilter::NotContainsNoCase("name".into(), "IND".into()))
+                    .desc("name"),
+            )
+            .check(
+                vec!["1"],
+                user_query().filter(EntityFilter::StartsWithNoCase("name".into(), "jO".into())),
+            )
+            .check(
+                vec!["3", "2"],
+                user_query()
+                    .filter(EntityFilter::NotStartsWithNoCase(
+                        "name".into(),
+                        "jO".into(),
+                    ))
+                    .desc("name"),
+            )
+            .check(
+                vec!["2"],
+                user_query().filter(EntityFilter::EndsWithNoCase("name".into(), "INI".into())),
+            )
+            .check(
+                vec!["3", "1"],
+                user_query()
+                    .filter(EntityFilter::NotEndsWithNoCase("name".into(), "INI".into()))
+                    .desc("name"),
+            )
+            .check(
+                vec!["1", "2"],
+                user_query()
+                    .filter(EntityFilter::Matches("name".into(), "^(c|j).*[io]$".into()))
+                    .desc("name"),
+            )
             .check(
                 vec!["1"],
                 user_query()
@@ -1161,6 +1550,87 @@ fn check_find() {
     })
 }
 
+#[test]
+fn check_cursor() {
+    fn after(value: Option<Value>, id: &str) -> EntityCursor {
+        EntityCursor::After(CursorPosition {
+            value,
+            id: id.to_owned(),
+        })
+    }
+
+    fn before(value: Option<Value>, id: &str) -> EntityCursor {
+        EntityCursor::Before(CursorPosition {
+            value,
+            id: id.to_owned(),
+        })
+    }
+
+    run_test(move |conn, layout| {
+        // Users ordered by age are "3" (28), "2" (43), and "1" (67)
+        QueryChecker::new(conn, layout)
+            .check(
+                vec!["2", "1"],
+                user_query()
+                    .asc("age")
+                    .cursor(after(Some(Value::Int(28)), "3")),
+            )
+            .check(
+                vec!["3", "2"],
+                user_query()
+                    .asc("age")
+                    .cursor(before(Some(Value::Int(67)), "1")),
+            )
+            .check(
+                vec!["2"],
+                user_query()
+                    .asc("age")
+                    .cursor(before(Some(Value::Int(67)), "1"))
+                    .first(1),
+            )
+            .check(
+                vec!["2", "3"],
+                user_query()
+                    .desc("age")
+                    .cursor(after(Some(Value::Int(67)), "1")),
+            )
+            .check(
+                vec!["1", "2"],
+                user_query()
+                    .desc("age")
+                    .cursor(before(Some(Value::Int(28)), "3")),
+            )
+            .check(vec!["2", "3"], user_query().cursor(after(None, "1")))
+            .check(vec!["1", "2"], user_query().cursor(before(None, "3")))
+            .check(vec!["1"], user_query().desc("id").cursor(after(None, "2")))
+            // Colors sort as yellow ("1"), red ("2"), and null ("3")
+            .check(
+                vec!["2", "3"],
+                user_query()
+                    .asc("favorite_color")
+                    .cursor(after(Some(Value::from("yellow")), "1")),
+            )
+            .check(
+                vec![],
+                user_query()
+                    .asc("favorite_color")
+                    .cursor(after(Some(Value::Null), "3")),
+            )
+            .check(
+                vec!["1", "2"],
+                user_query()
+                    .asc("favorite_color")
+                    .cursor(before(Some(Value::Null), "3")),
+            )
+            .check(
+                vec!["1", "3"],
+                user_query()
+                    .desc("favorite_color")
+                    .cursor(after(Some(Value::from("red")), "2")),
+            );
+    });
+}
+
 // We call our test strings aN so that
 //   aN = "a" * (STRING_PREFIX_SIZE - 2 + N)
 // chosen so that they straddle the boundary between strings that fit into
@@ -1197,8 +1667,10 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
                 query.collection,
                 query.filter,
                 query.order,
+                query.cursor,
                 query.range,
                 BLOCK_NUMBER_MAX,
+                0,
                 None,
             )
             .expect("layout.query failed to execute query");
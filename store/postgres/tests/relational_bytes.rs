@@ -7,9 +7,9 @@ use std::collections::BTreeMap;
 
 use graph::data::store::scalar::{BigDecimal, BigInt};
 use graph::prelude::{
-    web3::types::H256, ChildMultiplicity, Entity, EntityCollection, EntityKey, EntityLink,
-    EntityOrder, EntityRange, EntityWindow, ParentLink, Schema, SubgraphDeploymentId, Value,
-    WindowAttribute, BLOCK_NUMBER_MAX,
+    web3::types::H256, Child, ChildMultiplicity, Entity, EntityCollection, EntityFilter, EntityKey,
+    EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink, Schema, SubgraphDeploymentId,
+    Value, WindowAttribute, BLOCK_NUMBER_MAX,
 };
 use graph_store_postgres::layout_for_tests::{Layout, Namespace};
 
@@ -363,8 +363,10 @@ fn query() {
                 coll,
                 None,
                 EntityOrder::Default,
+                None,
                 EntityRange::first(10),
                 BLOCK_NUMBER_MAX,
+                0,
                 None,
             )
             .expect("the query succeeds")
@@ -468,3 +470,81 @@ fn query() {
         assert_eq!(vec![ROOT, ROOT], things);
     });
 }
+
+#[test]
+fn query_with_child_filter() {
+    fn fetch(conn: &PgConnection, layout: &Layout, filter: EntityFilter) -> Vec<String> {
+        layout
+            .query::<Entity>(
+                &*LOGGER,
+                conn,
+                EntityCollection::All(vec!["Thing".to_owned()]),
+                Some(filter),
+                EntityOrder::Default,
+                None,
+                EntityRange::first(10),
+                BLOCK_NUMBER_MAX,
+                0,
+                None,
+            )
+            .expect("the query succeeds")
+            .into_iter()
+            .map(|e| e.id().expect("entities have an id"))
+            .collect::<Vec<_>>()
+    }
+
+    fn child(attr: &str, derived: bool, filter: EntityFilter) -> EntityFilter {
+        EntityFilter::Child(Child {
+            attr: attr.to_owned(),
+            entity_type: "Thing".to_owned(),
+            filter: Box::new(filter),
+            derived,
+        })
+    }
+
+    fn name(name: &str) -> EntityFilter {
+        EntityFilter::Equal("name".to_owned(), name.into())
+    }
+
+    run_test(|conn, layout| {
+        make_thing_tree(conn, layout);
+
+        //   things(where: { parent_: { name: "child1" } }) { id }
+        let things = fetch(conn, layout, child("parent", false, name("child1")));
+        assert_eq!(vec![GRANDCHILD1], things);
+
+        //   things(where: { children_: { name: "child2" } }) { id }
+        let things = fetch(conn, layout, child("children", false, name("child2")));
+        assert_eq!(vec![ROOT], things);
+
+        //   things(where: { children_: { name: "grandchild1" } }) { id }
+        let things = fetch(conn, layout, child("children", false, name("grandchild1")));
+        assert_eq!(vec![CHILD1, CHILD2], things);
+
+        // Things that some other thing names as their parent; that is what
+        // a field `kids: [Thing!]! @derivedFrom(field: "parent")` would
+        // give us
+        //   things(where: { kids_: { name: "grandchild2" } }) { id }
+        let things = fetch(conn, layout, child("parent", true, name("grandchild2")));
+        assert_eq!(vec![CHILD2], things);
+
+        //   things(where: { parent_: { parent_: { name: "root" } } }) { id }
+        let things = fetch(
+            conn,
+            layout,
+            child("parent", false, child("parent", false, name("root"))),
+        );
+        assert_eq!(vec![GRANDCHILD1, GRANDCHILD2], things);
+
+        // Child filters can be combined with other filters
+        let things = fetch(
+            conn,
+            layout,
+            EntityFilter::And(vec![
+                child("parent", false, child("parent", false, name("root"))),
+                name("grandchild2"),
+            ]),
+        );
+        assert_eq!(vec![GRANDCHILD2], things);
+    });
+}
@@ -1843,6 +1843,39 @@ fn find_at_block() {
     shaqueeena_at_block(7000, "teeko@email.com");
 }
 
+#[test]
+fn find_after_prune() {
+    run_test(|store| async move {
+        let shaqueeena_at_block = |block: BlockNumber| {
+            let mut query = user_query()
+                .filter(EntityFilter::Equal("name".to_owned(), "Shaqueeena".into()))
+                .desc("name");
+            query.block = block;
+            store.find(query)
+        };
+
+        // Querying caches the layout of the deployment, which must be
+        // forgotten when the deployment is pruned
+        assert!(shaqueeena_at_block(0).unwrap().is_empty());
+
+        let (earliest_block, _) = store
+            .store()
+            .prune(&*LOGGER, &TEST_SUBGRAPH_ID, Some(1))
+            .expect("pruning succeeds");
+        assert_eq!(1, earliest_block);
+
+        match shaqueeena_at_block(0) {
+            Err(QueryExecutionError::BlockNotRetained(0, 1)) => { /* expected */ }
+            other => panic!("expected BlockNotRetained error but got {:?}", other),
+        }
+        let entities = shaqueeena_at_block(1).expect("block 1 can be queried");
+        assert_eq!(
+            Some(&Value::from("queensha@email.com")),
+            entities[0].get("email")
+        );
+    })
+}
+
 #[test]
 fn cleanup_cached_blocks() {
     run_test(|store| async move {
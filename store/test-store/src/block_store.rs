@@ -93,13 +93,17 @@ lazy_static! {
     pub static ref BLOCK_THREE_NO_PARENT: FakeBlock = FakeBlock::make_no_parent(3, "fa9ebe3f74de4c56908b49f5c4044e85825f7350f3fa08a19151de82a82a7313");
     pub static ref BLOCK_FOUR: FakeBlock = BLOCK_THREE.make_child("7cce080f5a49c2997a6cc65fc1cee9910fd8fc3721b7010c0b5d0873e2ac785e");
     pub static ref BLOCK_FIVE: FakeBlock = BLOCK_FOUR.make_child("7b0ea919e258eb2b119eb32de56b85d12d50ac6a9f7c5909f843d6172c8ba196");
+    pub static ref BLOCK_TWO_REORG: FakeBlock = BLOCK_ONE_SIBLING.make_child("d212305aa1167abc4d3213fe811bc0fc5d91a5ea32d25b69a2da154bcac708b3");
+    pub static ref BLOCK_THREE_REORG: FakeBlock = BLOCK_TWO_REORG.make_child("ab8ac7de777c15ea9edf85d100da0f68191966941d38bbbde76ad01470da4b29");
     pub static ref BLOCK_SIX_NO_PARENT: FakeBlock = FakeBlock::make_no_parent(6, "6b834521bb753c132fdcf0e1034803ed9068e324112f8750ba93580b393a986b");
 }
 
 /// Removes all networks and blocks from the database
 pub fn remove() {
+    use db_schema::ethereum_block_traces as t;
     use db_schema::ethereum_blocks as b;
     use db_schema::ethereum_networks as n;
+    use db_schema::ethereum_reorgs as r;
 
     crate::store::remove_subgraphs();
 
@@ -107,6 +111,12 @@ pub fn remove() {
         .get()
         .expect("Failed to connect to Postgres");
 
+    diesel::delete(r::table)
+        .execute(&conn)
+        .expect("Failed to delete ethereum_reorgs");
+    diesel::delete(t::table)
+        .execute(&conn)
+        .expect("Failed to delete ethereum_block_traces");
     diesel::delete(b::table)
         .execute(&conn)
         .expect("Failed to delete ethereum_blocks");
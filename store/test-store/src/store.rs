@@ -1,5 +1,5 @@
-use diesel::{self, PgConnection};
-use graph::data::graphql::effort::LoadManager;
+use diesel::{self, Connection as _, PgConnection, RunQueryDsl};
+use graph::data::graphql::{effort::LoadManager, quota::ApiKeys};
 use graph::data::query::QueryResults;
 use graph::data::query::QueryTarget;
 use graph::data::subgraph::schema::SubgraphError;
@@ -42,6 +42,7 @@ lazy_static! {
     pub static ref LOAD_MANAGER: Arc<LoadManager> = Arc::new(LoadManager::new(
         &*LOGGER,
         Vec::new(),
+        ApiKeys::default(),
         Arc::new(MockMetricsRegistry::new()),
         CONN_POOL_SIZE as usize
     ));
@@ -139,6 +140,28 @@ pub fn place(name: &str) -> Result<Option<(Shard, Vec<NodeId>)>, String> {
     CONFIG.deployment.place(name, NETWORK_NAME)
 }
 
+/// The names of all shards in the test configuration
+pub fn shards() -> Vec<Shard> {
+    CONFIG
+        .stores
+        .keys()
+        .map(|name| Shard::new(name.clone()).expect("shard names have been validated"))
+        .collect()
+}
+
+/// Whether the database of `shard` has the schema `namespace`
+pub fn namespace_exists(shard: &Shard, namespace: &str) -> bool {
+    let url = &CONFIG.stores[shard.as_str()].connection;
+    let conn = PgConnection::establish(url).expect("we can connect to the shard");
+    let query = format!(
+        "exists (select 1 from pg_namespace where nspname = '{}')",
+        namespace
+    );
+    diesel::select(diesel::dsl::sql::<diesel::sql_types::Bool>(&query))
+        .get_result(&conn)
+        .expect("we can look up the namespace")
+}
+
 fn create_subgraph(
     subgraph_id: &SubgraphDeploymentId,
     schema: &str,
@@ -395,6 +418,7 @@ fn execute_subgraph_query_internal(
                 load_manager: LOAD_MANAGER.clone(),
                 max_first: std::u32::MAX,
                 max_skip: std::u32::MAX,
+                max_cost: None,
             },
             false,
         )))
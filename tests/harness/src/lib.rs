@@ -0,0 +1,10 @@
+extern crate futures;
+extern crate graph;
+
+mod memory_store;
+mod subgraph_test;
+
+pub use self::memory_store::MemoryStore;
+pub use self::subgraph_test::{
+    FixtureBlock, FixtureCall, FixtureEthCall, FixtureLog, SubgraphTest,
+};
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use graph::components::{server::index_node::VersionInfo, store::StoredDynamicDataSource};
+use graph::data::subgraph::schema::SubgraphError;
+use graph::prelude::*;
+use graph::{components::store::EntityType, data::subgraph::status};
+use web3::types::Address;
+
+/// A store that keeps the entities of a single deployment in memory. It
+/// only supports what is needed to run the mappings of a subgraph, which
+/// makes it possible to test mappings without a database. Operations that
+/// need history or subgraph metadata, like reverting blocks or managing
+/// subgraph names, fail with an error.
+pub struct MemoryStore {
+    schema: Arc<Schema>,
+    network: String,
+    entities: RwLock<HashMap<EntityKey, Entity>>,
+    block_ptr: RwLock<Option<EthereumBlockPointer>>,
+    synced: AtomicBool,
+}
+
+impl MemoryStore {
+    pub fn new(schema: Arc<Schema>, network: String) -> Self {
+        MemoryStore {
+            schema,
+            network,
+            entities: RwLock::new(HashMap::new()),
+            block_ptr: RwLock::new(None),
+            synced: AtomicBool::new(false),
+        }
+    }
+
+    /// Apply the changes for one block and advance the block pointer
+    pub fn apply(&self, block_ptr: EthereumBlockPointer, mods: Vec<EntityModification>) {
+        use EntityModification::*;
+
+        let mut entities = self.entities.write().unwrap();
+        for modification in mods {
+            match modification {
+                Insert { key, data } | Overwrite { key, data } => {
+                    entities.insert(key, data);
+                }
+                Remove { key } => {
+                    entities.remove(&key);
+                }
+            }
+        }
+        *self.block_ptr.write().unwrap() = Some(block_ptr);
+    }
+
+    /// All entities of type `entity_type`, sorted by their id
+    pub fn entities(&self, entity_type: &str) -> Vec<Entity> {
+        let entity_type = EntityType::data(entity_type.to_owned());
+        let mut entities: Vec<_> = self
+            .entities
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.entity_type == entity_type)
+            .map(|(key, entity)| (key.entity_id.clone(), entity.clone()))
+            .collect();
+        entities.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entities.into_iter().map(|(_, entity)| entity).collect()
+    }
+}
+
+fn unsupported(operation: &str) -> StoreError {
+    StoreError::Unknown(anyhow!(
+        "the in-memory store does not support {}",
+        operation
+    ))
+}
+
+impl EthereumCallCache for MemoryStore {
+    fn get_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(None)
+    }
+
+    fn set_call(
+        &self,
+        _contract_address: Address,
+        _encoded_call: &[u8],
+        _block: EthereumBlockPointer,
+        _return_value: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    fn block_ptr(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<EthereumBlockPointer>, Error> {
+        Ok(self.block_ptr.read().unwrap().clone())
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        Ok(self.entities.read().unwrap().get(&key).cloned())
+    }
+
+    fn get_many(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+    ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+        let entities = self.entities.read().unwrap();
+        let mut result = BTreeMap::new();
+        for (entity_type, ids) in ids_for_type {
+            let found: Vec<_> = ids
+                .into_iter()
+                .filter_map(|id| {
+                    let key = EntityKey {
+                        subgraph_id: subgraph_id.clone(),
+                        entity_type: entity_type.clone(),
+                        entity_id: id.to_owned(),
+                    };
+                    entities.get(&key).cloned()
+                })
+                .collect();
+            if !found.is_empty() {
+                result.insert(entity_type.clone(), found);
+            }
+        }
+        Ok(result)
+    }
+
+    fn supports_proof_of_indexing<'a>(
+        self: Arc<Self>,
+        _subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, bool> {
+        Box::pin(async { Ok(false) })
+    }
+
+    fn get_proof_of_indexing<'a>(
+        self: Arc<Self>,
+        _subgraph_id: &'a SubgraphDeploymentId,
+        _indexer: &'a Option<Address>,
+        _block: EthereumBlockPointer,
+    ) -> DynTryFuture<'a, Option<[u8; 32]>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Find entities of the types in an `EntityCollection::All`, ordered by
+    /// id. Filters, cursors, windows and ordering by attributes are not
+    /// supported
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        let entity_types = match query.collection {
+            EntityCollection::All(entity_types) => entity_types,
+            EntityCollection::Window(_) => {
+                return Err(QueryExecutionError::NotSupported(
+                    "windowed queries against the in-memory store".to_owned(),
+                ))
+            }
+        };
+        if query.filter.is_some() || query.cursor.is_some() {
+            return Err(QueryExecutionError::NotSupported(
+                "filters and cursors in queries against the in-memory store".to_owned(),
+            ));
+        }
+        match query.order {
+            EntityOrder::Default | EntityOrder::Unordered => (),
+            EntityOrder::Ascending(..) | EntityOrder::Descending(..) => {
+                return Err(QueryExecutionError::NotSupported(
+                    "ordering by attributes in the in-memory store".to_owned(),
+                ))
+            }
+        }
+
+        let mut entities: Vec<_> = entity_types
+            .iter()
+            .flat_map(|entity_type| self.entities(entity_type))
+            .collect();
+        entities.sort_by(|a, b| a.id().ok().cmp(&b.id().ok()));
+
+        let entities = entities.into_iter().skip(query.range.skip as usize);
+        Ok(match query.range.first {
+            Some(first) => entities.take(first as usize).collect(),
+            None => entities.collect(),
+        })
+    }
+
+    fn find_one(&self, mut query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        query.range = EntityRange::first(1);
+        Ok(self.find(query)?.into_iter().next())
+    }
+
+    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
+        Ok(None)
+    }
+
+    fn transact_block_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        block_ptr_to: EthereumBlockPointer,
+        mods: Vec<EntityModification>,
+        _stopwatch: StopwatchMetrics,
+        _deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<(), StoreError> {
+        self.apply(block_ptr_to, mods);
+        Ok(())
+    }
+
+    fn revert_block_operations(
+        &self,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        Err(unsupported("reverting blocks"))
+    }
+
+    fn entity_change_log(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _after: i64,
+        _limit: usize,
+    ) -> Result<Vec<ChangeLogEntry>, StoreError> {
+        Err(unsupported("reading the entity change log"))
+    }
+
+    fn deployment_state_from_name(
+        &self,
+        name: SubgraphName,
+    ) -> Result<DeploymentState, StoreError> {
+        Err(StoreError::DeploymentNotFound(name.to_string()))
+    }
+
+    fn deployment_state_from_id(
+        &self,
+        id: SubgraphDeploymentId,
+    ) -> Result<DeploymentState, StoreError> {
+        Ok(DeploymentState {
+            id,
+            reorg_count: 0,
+            max_reorg_depth: 0,
+            latest_ethereum_block_number: 0,
+        })
+    }
+
+    async fn fail_subgraph(
+        &self,
+        _: SubgraphDeploymentId,
+        _: SubgraphError,
+    ) -> Result<(), StoreError> {
+        Err(unsupported("recording subgraph failures"))
+    }
+
+    fn create_subgraph_deployment(
+        &self,
+        _: SubgraphName,
+        _: &Schema,
+        _: SubgraphDeploymentEntity,
+        _: NodeId,
+        _: String,
+        _: SubgraphVersionSwitchingMode,
+    ) -> Result<(), StoreError> {
+        Err(unsupported("creating deployments"))
+    }
+
+    fn create_subgraph(&self, _: SubgraphName) -> Result<String, StoreError> {
+        Err(unsupported("creating subgraphs"))
+    }
+
+    fn remove_subgraph(&self, _: SubgraphName) -> Result<(), StoreError> {
+        Err(unsupported("removing subgraphs"))
+    }
+
+    fn reassign_subgraph(&self, _: &SubgraphDeploymentId, _: &NodeId) -> Result<(), StoreError> {
+        Err(unsupported("reassigning subgraphs"))
+    }
+
+    fn start_subgraph_deployment(
+        &self,
+        _logger: &Logger,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn is_deployment_synced(&self, _: &SubgraphDeploymentId) -> Result<bool, Error> {
+        Ok(self.synced.load(Ordering::SeqCst))
+    }
+
+    fn deployment_synced(&self, _: &SubgraphDeploymentId) -> Result<(), Error> {
+        self.synced.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn status(&self, _: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+        Err(unsupported("indexing status"))
+    }
+
+    /// Fixture blocks always form a single chain, so there never are any
+    /// reorgs
+    fn chain_reorgs(
+        &self,
+        _: Option<String>,
+        _: usize,
+    ) -> Result<Vec<status::ChainReorg>, StoreError> {
+        Ok(vec![])
+    }
+
+    /// Data sources created from templates only live in the subgraph
+    /// instance and are never written to this store
+    async fn load_dynamic_data_sources(
+        &self,
+        _: SubgraphDeploymentId,
+    ) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+        Ok(vec![])
+    }
+
+    fn assigned_node(&self, _: &SubgraphDeploymentId) -> Result<Option<NodeId>, StoreError> {
+        Ok(None)
+    }
+
+    fn assignments(&self, _: &NodeId) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+        Ok(vec![])
+    }
+
+    fn subgraph_exists(&self, _: &SubgraphName) -> Result<bool, StoreError> {
+        Ok(false)
+    }
+
+    fn input_schema(&self, _: &SubgraphDeploymentId) -> Result<Arc<Schema>, StoreError> {
+        Ok(self.schema.cheap_clone())
+    }
+
+    fn api_schema(&self, _: &SubgraphDeploymentId) -> Result<Arc<ApiSchema>, StoreError> {
+        Err(unsupported("API schemas"))
+    }
+
+    fn network_name(&self, _: &SubgraphDeploymentId) -> Result<Option<String>, StoreError> {
+        Ok(Some(self.network.clone()))
+    }
+
+    fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError> {
+        Err(StoreError::DeploymentNotFound(version_id.to_owned()))
+    }
+
+    fn versions_for_subgraph_id(
+        &self,
+        _: &str,
+    ) -> Result<(Option<String>, Option<String>), StoreError> {
+        Ok((None, None))
+    }
+}
@@ -0,0 +1,594 @@
+//! Run the mappings of a subgraph against blocks described in a fixture
+//! file, without an Ethereum node, IPFS or a database. The manifest, schema,
+//! ABIs and WASM modules are read from the local filesystem, and entities
+//! are written to a `MemoryStore` where tests can inspect them.
+//!
+//! See `docs/subgraph-tests.md` for the format of fixture files.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use futures::future;
+
+use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+use graph::data::subgraph::Link;
+use graph::mock::MockEthereumAdapter;
+use graph::prelude::{SubgraphInstance as _, *};
+use graph::util::lfu_cache::LfuCache;
+use graph_chain_arweave::adapter::ArweaveAdapter;
+use graph_chain_ethereum::TriggerProcessor;
+use graph_core::{three_box::ThreeBoxAdapter, SubgraphInstance};
+use graph_mock::MockMetricsRegistry;
+use graph_runtime_wasm::RuntimeHostBuilder;
+use web3::types::{Address, Bytes, Log, Transaction, H256, U256, U64};
+
+use crate::MemoryStore;
+
+/// The deployment id under which fixture subgraphs run. It is never used
+/// to look anything up and only needs to be valid
+const TEST_DEPLOYMENT_ID: &str = "QmTestSubgraphDeploymentIdxxxxxxxxxxxxxxxxxxxx";
+
+/// A block in a fixture file together with the logs, calls and `eth_call`
+/// results that the mappings should see for it
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureBlock {
+    pub number: u64,
+    pub hash: Option<H256>,
+    pub parent_hash: Option<H256>,
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub logs: Vec<FixtureLog>,
+    #[serde(default)]
+    pub calls: Vec<FixtureCall>,
+    #[serde(default)]
+    pub eth_calls: Vec<FixtureEthCall>,
+}
+
+/// An event emitted in a fixture block
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+    pub transaction_hash: Option<H256>,
+    #[serde(default)]
+    pub from: Address,
+}
+
+/// A contract call made in a fixture block, as it would appear in a trace
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureCall {
+    pub from: Address,
+    pub to: Address,
+    #[serde(default)]
+    pub value: U256,
+    pub input: Bytes,
+    #[serde(default)]
+    pub output: Bytes,
+    pub transaction_hash: Option<H256>,
+}
+
+/// The result of an `eth_call` that a mapping makes while processing a
+/// fixture block. The call matches if it goes to `address` and its encoded
+/// input starts with `input`, which makes it possible to only give the
+/// function selector
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureEthCall {
+    pub address: Address,
+    pub input: Bytes,
+    #[serde(default)]
+    pub output: Bytes,
+    pub revert: Option<String>,
+}
+
+impl FixtureBlock {
+    /// Read a JSON array of blocks from `path`
+    pub fn load_all(path: impl AsRef<Path>) -> Result<Vec<FixtureBlock>, Error> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open fixture file {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to parse fixture file {}", path.display()))
+    }
+
+    fn hash(&self) -> H256 {
+        self.hash.unwrap_or_else(|| synthetic_hash(self.number))
+    }
+
+    fn parent_hash(&self) -> H256 {
+        self.parent_hash.unwrap_or_else(|| match self.number {
+            0 => H256::zero(),
+            n => synthetic_hash(n - 1),
+        })
+    }
+
+    fn ptr(&self) -> EthereumBlockPointer {
+        EthereumBlockPointer {
+            hash: self.hash(),
+            number: self.number,
+        }
+    }
+
+    /// The hash of the transaction for the log or call at `position` in
+    /// this block if it does not specify one. Logs come before calls
+    fn transaction_hash(&self, position: usize) -> H256 {
+        H256::from(tiny_keccak::keccak256(
+            format!("tx-{}-{}", self.number, position).as_bytes(),
+        ))
+    }
+
+    /// Build the block that is passed to mappings. Every log and call is put
+    /// into a transaction of its own unless it names a transaction
+    /// explicitly; transactions are ordered by when they are first
+    /// mentioned, logs before calls
+    fn light_block(&self) -> LightEthereumBlock {
+        let mut block = LightEthereumBlock::default();
+        block.hash = Some(self.hash());
+        block.parent_hash = self.parent_hash();
+        block.number = Some(U64::from(self.number));
+        block.timestamp = U256::from(self.timestamp);
+
+        let senders = self
+            .logs
+            .iter()
+            .map(|log| (log.transaction_hash, log.from, None, U256::zero(), None))
+            .chain(self.calls.iter().map(|call| {
+                (
+                    call.transaction_hash,
+                    call.from,
+                    Some(call.to),
+                    call.value,
+                    Some(call.input.clone()),
+                )
+            }));
+        for (position, (hash, from, to, value, input)) in senders.enumerate() {
+            let hash = hash.unwrap_or_else(|| self.transaction_hash(position));
+            if block.transactions.iter().any(|tx| tx.hash == hash) {
+                continue;
+            }
+            let index = block.transactions.len() as u64;
+            block.transactions.push(Transaction {
+                hash,
+                block_hash: Some(self.hash()),
+                block_number: Some(U64::from(self.number)),
+                transaction_index: Some(U64::from(index)),
+                from,
+                to,
+                value,
+                input: input.unwrap_or_default(),
+                ..Default::default()
+            });
+        }
+        block
+    }
+
+    /// All triggers in this block. Which data source handles which trigger
+    /// is decided by the subgraph instance, exactly like for blocks from a
+    /// block stream
+    fn triggers(&self, block: &LightEthereumBlock) -> Vec<EthereumTrigger> {
+        let tx_index = |hash: H256| {
+            block
+                .transactions
+                .iter()
+                .position(|tx| tx.hash == hash)
+                .unwrap() as u64
+        };
+
+        let mut position = 0;
+        let mut tx_hash = |hash: Option<H256>| {
+            let hash = hash.unwrap_or_else(|| self.transaction_hash(position));
+            position += 1;
+            hash
+        };
+
+        let mut triggers = Vec::new();
+        for (log_index, log) in self.logs.iter().enumerate() {
+            let hash = tx_hash(log.transaction_hash);
+            let index = tx_index(hash);
+            triggers.push(EthereumTrigger::Log(Log {
+                address: log.address,
+                topics: log.topics.clone(),
+                data: log.data.clone(),
+                block_hash: Some(self.hash()),
+                block_number: Some(U64::from(self.number)),
+                transaction_hash: Some(hash),
+                transaction_index: Some(U64::from(index)),
+                log_index: Some(U256::from(log_index as u64)),
+                transaction_log_index: Some(U256::from(log_index as u64)),
+                log_type: None,
+                removed: Some(false),
+            }));
+        }
+
+        let mut callees = BTreeSet::new();
+        for call in &self.calls {
+            let hash = tx_hash(call.transaction_hash);
+            let index = tx_index(hash);
+            callees.insert(call.to);
+            triggers.push(EthereumTrigger::Call(EthereumCall {
+                from: call.from,
+                to: call.to,
+                value: call.value,
+                gas_used: U256::zero(),
+                input: call.input.clone(),
+                output: call.output.clone(),
+                block_number: self.number,
+                block_hash: self.hash(),
+                transaction_hash: Some(hash),
+                transaction_index: index,
+            }));
+        }
+
+        triggers.push(EthereumTrigger::Block(
+            self.ptr(),
+            EthereumBlockTriggerType::Every,
+        ));
+        for to in callees {
+            triggers.push(EthereumTrigger::Block(
+                self.ptr(),
+                EthereumBlockTriggerType::WithCallTo(to),
+            ));
+        }
+
+        triggers.sort();
+        triggers
+    }
+}
+
+fn synthetic_hash(number: u64) -> H256 {
+    H256::from(tiny_keccak::keccak256(
+        format!("block-{}", number).as_bytes(),
+    ))
+}
+
+/// Resolves links in a manifest as paths relative to the directory that
+/// contains the manifest
+struct FileLinkResolver {
+    base: PathBuf,
+}
+
+impl FileLinkResolver {
+    fn path(&self, link: &Link) -> PathBuf {
+        self.base.join(link.link.trim_start_matches("/ipfs/"))
+    }
+}
+
+#[async_trait]
+impl LinkResolver for FileLinkResolver {
+    fn with_timeout(self, _timeout: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let path = self.path(link);
+        std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let contents = String::from_utf8(self.cat(logger, link).await?)?;
+        let values: Vec<_> = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(line, text)| {
+                serde_json::from_str(text)
+                    .map(|value| JsonStreamValue { value, line })
+                    .map_err(Error::from)
+            })
+            .collect();
+        Ok(Box::pin(futures03::stream::iter(values)))
+    }
+}
+
+/// Rewrite every `file: <path>` in the manifest into the `file: { /: <path> }`
+/// form that a manifest has after `graph build` uploaded it to IPFS
+fn rewrite_file_links(value: &mut serde_yaml::Value) {
+    use serde_yaml::Value;
+
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                if key.as_str() == Some("file") && value.is_string() {
+                    let mut link = serde_yaml::Mapping::new();
+                    link.insert(Value::from("/"), value.clone());
+                    *value = Value::Mapping(link);
+                } else {
+                    rewrite_file_links(value);
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter_mut().for_each(rewrite_file_links),
+        _ => {}
+    }
+}
+
+/// Answer `eth_call`s from the fixture of the block that is being processed
+fn mock_ethereum_adapter(eth_calls: Arc<RwLock<Vec<FixtureEthCall>>>) -> MockEthereumAdapter {
+    let mut adapter = MockEthereumAdapter::new();
+    adapter
+        .expect_contract_call()
+        .returning(move |_, call: EthereumContractCall, _| {
+            let result = call
+                .function
+                .encode_input(&call.args)
+                .map_err(EthereumContractCallError::EncodingError)
+                .and_then(|input| {
+                    let eth_calls = eth_calls.read().unwrap();
+                    let fixture = eth_calls
+                        .iter()
+                        .find(|fixture| {
+                            fixture.address == call.address && input.starts_with(&fixture.input.0)
+                        })
+                        .ok_or_else(|| {
+                            EthereumContractCallError::Revert(format!(
+                                "no eth_call fixture for call to {} with input 0x{} in block {}",
+                                call.address,
+                                hex::encode(&input),
+                                call.block_ptr.number
+                            ))
+                        })?;
+                    match &fixture.revert {
+                        Some(reason) => Err(EthereumContractCallError::Revert(reason.clone())),
+                        None => call
+                            .function
+                            .decode_output(&fixture.output.0)
+                            .map_err(EthereumContractCallError::from),
+                    }
+                });
+            Box::new(future::result(result))
+        });
+    adapter
+}
+
+/// A subgraph loaded from disk whose mappings can be run against fixture
+/// blocks
+pub struct SubgraphTest {
+    logger: Logger,
+    deployment: SubgraphDeploymentId,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    instance: SubgraphInstance<RuntimeHostBuilder<MemoryStore>>,
+    host_metrics: Arc<HostMetrics>,
+    store: Arc<MemoryStore>,
+    eth_calls: Arc<RwLock<Vec<FixtureEthCall>>>,
+}
+
+impl SubgraphTest {
+    /// Load the subgraph whose `subgraph.yaml` is at `manifest_path`. All
+    /// files that the manifest references are resolved relative to the
+    /// directory the manifest is in
+    pub async fn load(logger: &Logger, manifest_path: impl AsRef<Path>) -> Result<Self, Error> {
+        let manifest_path = manifest_path.as_ref();
+        let base = manifest_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let contents = std::fs::read_to_string(manifest_path)
+            .with_context(|| format!("failed to read manifest {}", manifest_path.display()))?;
+        let mut raw: serde_yaml::Value = serde_yaml::from_str(&contents)?;
+        rewrite_file_links(&mut raw);
+
+        let resolver = Arc::new(FileLinkResolver { base });
+        let manifest = SubgraphManifest::resolve_from_raw(
+            TEST_DEPLOYMENT_ID.to_owned(),
+            manifest_path.display().to_string(),
+            raw,
+            resolver.as_ref(),
+            logger,
+        )
+        .await?;
+
+        let deployment = manifest.id.clone();
+        let network = manifest.network_name();
+        let logger = logger.new(o!("subgraph_id" => deployment.to_string()));
+
+        let store = Arc::new(MemoryStore::new(
+            Arc::new(manifest.schema.clone()),
+            network.clone(),
+        ));
+        let eth_calls = Arc::new(RwLock::new(Vec::new()));
+        let mut eth_networks = EthereumNetworks::new();
+        eth_networks.insert(
+            network.clone(),
+            NodeCapabilities {
+                archive: true,
+                traces: true,
+            },
+            Arc::new(mock_ethereum_adapter(eth_calls.cheap_clone())),
+        );
+        let host_builder = RuntimeHostBuilder::new(
+            eth_networks,
+            resolver,
+            HashMap::from_iter(Some((network, store.cheap_clone()))),
+            Arc::new(ArweaveAdapter::new("https://arweave.net".to_owned())),
+            Arc::new(ThreeBoxAdapter::new("https://ipfs.3box.io/".to_owned())),
+        );
+
+        let registry = Arc::new(MockMetricsRegistry::new());
+        let stopwatch = StopwatchMetrics::new(logger.clone(), deployment.clone(), registry.clone());
+        let host_metrics = Arc::new(HostMetrics::new(registry, deployment.as_str(), stopwatch));
+
+        let templates = Arc::new(manifest.templates.clone());
+        let instance =
+            SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+
+        Ok(SubgraphTest {
+            logger,
+            deployment,
+            templates,
+            instance,
+            host_metrics,
+            store,
+            eth_calls,
+        })
+    }
+
+    /// Process the blocks in the fixture file at `path` in order
+    pub async fn run_fixture(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        for block in FixtureBlock::load_all(path)? {
+            self.process_block(&block).await?;
+        }
+        Ok(())
+    }
+
+    /// Run all handlers that match the triggers in `block` and write the
+    /// resulting entity changes to the store. Handler errors, including
+    /// deterministic ones, fail processing of the block
+    pub async fn process_block(&mut self, block: &FixtureBlock) -> Result<(), Error> {
+        let logger = self
+            .logger
+            .new(o!("block_number" => block.number, "block_hash" => block.hash().to_string()));
+        *self.eth_calls.write().unwrap() = block.eth_calls.clone();
+
+        let light_block = Arc::new(block.light_block());
+        let triggers = block.triggers(&light_block);
+
+        let mut block_state = BlockState::new(self.store.clone(), LfuCache::new());
+        for trigger in triggers.iter() {
+            block_state = self
+                .instance
+                .process_trigger(
+                    &TriggerProcessor,
+                    &logger,
+                    &light_block,
+                    trigger,
+                    block_state,
+                    None,
+                )
+                .await
+                .map_err(mapping_error)?;
+        }
+
+        // Instantiate data sources created from templates and run the
+        // triggers of this block through them until no more are created
+        while block_state.has_created_data_sources() {
+            let mut hosts = Vec::new();
+            for info in block_state.drain_created_data_sources() {
+                let data_source = DataSource::try_from(info)?;
+                if let Some(host) = self.instance.add_dynamic_data_source(
+                    &logger,
+                    data_source,
+                    self.templates.clone(),
+                    self.host_metrics.clone(),
+                )? {
+                    hosts.push(host);
+                }
+            }
+
+            for trigger in triggers.iter() {
+                block_state =
+                    SubgraphInstance::<RuntimeHostBuilder<MemoryStore>>::process_trigger_in_hosts(
+                        &TriggerProcessor,
+                        &logger,
+                        &hosts,
+                        &light_block,
+                        trigger,
+                        block_state,
+                        None,
+                    )
+                    .await
+                    .map_err(mapping_error)?;
+            }
+        }
+
+        if let Some(error) = block_state.deterministic_errors.into_iter().next() {
+            return Err(anyhow!(
+                "handler {} failed in block {}: {}",
+                error.handler.as_deref().unwrap_or("unknown"),
+                block.number,
+                error.message
+            ));
+        }
+
+        let mods = block_state
+            .entity_cache
+            .as_modifications(self.store.as_ref())?
+            .modifications;
+        self.store.apply(block.ptr(), mods);
+        Ok(())
+    }
+
+    /// The entity of type `entity_type` with the given `id`
+    pub fn entity(&self, entity_type: &str, id: &str) -> Result<Option<Entity>, Error> {
+        let key = EntityKey::data(
+            self.deployment.clone(),
+            entity_type.to_owned(),
+            id.to_owned(),
+        );
+        Ok(self.store.get(key)?)
+    }
+
+    /// All entities of type `entity_type`, sorted by their id
+    pub fn entities(&self, entity_type: &str) -> Vec<Entity> {
+        self.store.entities(entity_type)
+    }
+
+    pub fn store(&self) -> Arc<MemoryStore> {
+        self.store.cheap_clone()
+    }
+}
+
+fn mapping_error(e: MappingError) -> Error {
+    match e {
+        MappingError::PossibleReorg(e) | MappingError::Unknown(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixture_triggers() {
+        let blocks: Vec<FixtureBlock> = serde_json::from_str(
+            r#"[{
+              "number": 3,
+              "logs": [{
+                "address": "0x0000000000000000000000000000000000000001",
+                "topics": ["0x0000000000000000000000000000000000000000000000000000000000000002"]
+              }],
+              "calls": [{
+                "from": "0x0000000000000000000000000000000000000003",
+                "to": "0x0000000000000000000000000000000000000001",
+                "input": "0x12345678"
+              }]
+            }]"#,
+        )
+        .unwrap();
+        let block = &blocks[0];
+        let light_block = block.light_block();
+
+        assert_eq!(Some(synthetic_hash(3)), light_block.hash);
+        assert_eq!(synthetic_hash(2), light_block.parent_hash);
+        assert_eq!(2, light_block.transactions.len());
+
+        let triggers = block.triggers(&light_block);
+        assert_eq!(4, triggers.len());
+        match (&triggers[0], &triggers[1]) {
+            (EthereumTrigger::Log(log), EthereumTrigger::Call(call)) => {
+                assert_eq!(
+                    light_block.transactions[0].hash,
+                    log.transaction_hash.unwrap()
+                );
+                assert_eq!(1, call.transaction_index);
+                assert_eq!(Some(call.to), light_block.transactions[1].to);
+            }
+            _ => panic!("expected a log and a call trigger, got {:?}", triggers),
+        }
+        assert_eq!(
+            EthereumTrigger::Block(block.ptr(), EthereumBlockTriggerType::Every),
+            triggers[2]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+;; A block handler that stores a `Block` entity for every block, keyed by
+;; the block hash. There is no AssemblyScript toolchain in this repository's
+;; test setup, so the module is written by hand; the tests compile it to
+;; `mapping.wasm` before loading the subgraph.
+;;
+;; Static data uses the AssemblyScript memory layout that the runtime
+;; expects: strings are a u32 length followed by UTF-16 code units, an
+;; `Entity` is a map with a pointer to an `Array` of entries, and an array
+;; points to an `ArrayBuffer` with an 8 byte header.
+(module
+  (import "index" "store.set" (func $store_set (param i32 i32 i32)))
+  (import "index" "typeConversion.bytesToHex" (func $bytes_to_hex (param i32) (result i32)))
+
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; "Block"
+  (data (i32.const 8) "\05\00\00\00B\00l\00o\00c\00k\00")
+  ;; "hash"
+  (data (i32.const 32) "\04\00\00\00h\00a\00s\00h\00")
+  ;; "number"
+  (data (i32.const 48) "\06\00\00\00n\00u\00m\00b\00e\00r\00")
+  ;; Value { kind: BYTES, payload } and Value { kind: BIGINT, payload }; the
+  ;; payloads are filled in by the handler
+  (data (i32.const 64) "\06\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00")
+  (data (i32.const 80) "\07\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00")
+  ;; Map entries ("hash", 64) and ("number", 80)
+  (data (i32.const 96) "\20\00\00\00\40\00\00\00\30\00\00\00\50\00\00\00")
+  ;; ArrayBuffer with the pointers to the two entries
+  (data (i32.const 112) "\08\00\00\00\00\00\00\00\60\00\00\00\68\00\00\00")
+  ;; Array { buffer: 112, length: 2 } and Entity { entries: 128 }
+  (data (i32.const 128) "\70\00\00\00\02\00\00\00\80\00\00\00")
+
+  ;; A bump allocator; memory is never freed since every trigger runs in a
+  ;; fresh instance
+  (func (export "memory.allocate") (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap
+      (i32.and
+        (i32.add (i32.add (local.get $ptr) (local.get $size)) (i32.const 7))
+        (i32.const -8)))
+    (block $done
+      (loop $grow
+        (br_if $done
+          (i32.le_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536))))
+        (drop (memory.grow (i32.const 1)))
+        (br $grow)))
+    (local.get $ptr))
+
+  ;; `block` points to an `ethereum.Block`, whose first field is the hash
+  ;; and whose eighth field is the number
+  (func (export "handleBlock") (param $block i32)
+    (i64.store (i32.const 72) (i64.extend_i32_u (i32.load (local.get $block))))
+    (i64.store (i32.const 88) (i64.extend_i32_u (i32.load offset=28 (local.get $block))))
+    (call $store_set
+      (i32.const 8)
+      (call $bytes_to_hex (i32.load (local.get $block)))
+      (i32.const 136))))
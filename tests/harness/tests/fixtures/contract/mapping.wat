@@ -0,0 +1,132 @@
+;; An event handler that stores a `Transfer` entity together with the
+;; balance of the recipient, which it looks up with an `eth_call`, and a
+;; call handler that stores a `ValueSet` entity for every call to
+;; `setValue`. Both entities are keyed by the transaction hash. The tests
+;; compile this module to `mapping.wasm` before loading the subgraph.
+;;
+;; The layout of static data is the same as in `../blocks/mapping.wat`. The
+;; handlers fill in the pointers that depend on the trigger before they
+;; hand the data to the host.
+(module
+  (import "index" "store.set" (func $store_set (param i32 i32 i32)))
+  (import "index" "ethereum.call" (func $ethereum_call (param i32) (result i32)))
+  (import "index" "typeConversion.bytesToHex" (func $bytes_to_hex (param i32) (result i32)))
+
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; "Transfer"
+  (data (i32.const 8) "\08\00\00\00T\00r\00a\00n\00s\00f\00e\00r\00")
+  ;; "ValueSet"
+  (data (i32.const 32) "\08\00\00\00V\00a\00l\00u\00e\00S\00e\00t\00")
+  ;; "to"
+  (data (i32.const 56) "\02\00\00\00t\00o\00")
+  ;; "value"
+  (data (i32.const 64) "\05\00\00\00v\00a\00l\00u\00e\00")
+  ;; "balance"
+  (data (i32.const 80) "\07\00\00\00b\00a\00l\00a\00n\00c\00e\00")
+  ;; "from"
+  (data (i32.const 104) "\04\00\00\00f\00r\00o\00m\00")
+  ;; "Contract"
+  (data (i32.const 120) "\08\00\00\00C\00o\00n\00t\00r\00a\00c\00t\00")
+  ;; "balanceOf"
+  (data (i32.const 144) "\09\00\00\00b\00a\00l\00a\00n\00c\00e\00O\00f\00")
+  ;; "balanceOf(address):(uint256)"
+  (data (i32.const 168) "\1c\00\00\00b\00a\00l\00a\00n\00c\00e\00O\00f\00\28\00a\00d\00d\00r\00e\00s\00s\00\29\00\3a\00\28\00u\00i\00n\00t\002\005\006\00\29\00")
+
+  ;; The `Transfer` entity: the values `to` (BYTES), `value` and `balance`
+  ;; (both BIGINT), the entries ("to", 232), ("value", 248) and
+  ;; ("balance", 264), the ArrayBuffer and Array of the entries, and the
+  ;; Entity at 336
+  (data (i32.const 232) "\06\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\07\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\07\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00")
+  (data (i32.const 280) "\38\00\00\00\e8\00\00\00\40\00\00\00\f8\00\00\00\50\00\00\00\08\01\00\00")
+  (data (i32.const 304) "\0c\00\00\00\00\00\00\00\18\01\00\00\20\01\00\00\28\01\00\00")
+  (data (i32.const 328) "\30\01\00\00\03\00\00\00\48\01\00\00")
+
+  ;; The `ValueSet` entity: the values `from` (BYTES) and `value` (BIGINT),
+  ;; the entries ("from", 344) and ("value", 360), the ArrayBuffer and Array
+  ;; of the entries, and the Entity at 416
+  (data (i32.const 344) "\06\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\07\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00")
+  (data (i32.const 376) "\68\00\00\00\58\01\00\00\40\00\00\00\68\01\00\00")
+  (data (i32.const 392) "\08\00\00\00\00\00\00\00\78\01\00\00\80\01\00\00")
+  (data (i32.const 408) "\88\01\00\00\02\00\00\00\98\01\00\00")
+
+  ;; The call `Contract.balanceOf(to)`: the ArrayBuffer and Array of its
+  ;; single argument, and the call itself at 448 with the contract name,
+  ;; contract address, function name, function signature and arguments
+  (data (i32.const 424) "\04\00\00\00\00\00\00\00\00\00\00\00")
+  (data (i32.const 440) "\a8\01\00\00\01\00\00\00")
+  (data (i32.const 448) "\78\00\00\00\00\00\00\00\90\00\00\00\a8\00\00\00\b8\01\00\00")
+
+  ;; A bump allocator; memory is never freed since every trigger runs in a
+  ;; fresh instance
+  (func (export "memory.allocate") (param $size i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap
+      (i32.and
+        (i32.add (i32.add (local.get $ptr) (local.get $size)) (i32.const 7))
+        (i32.const -8)))
+    (block $done
+      (loop $grow
+        (br_if $done
+          (i32.le_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536))))
+        (drop (memory.grow (i32.const 1)))
+        (br $grow)))
+    (local.get $ptr))
+
+  ;; The value of the parameter at `index` in the `Array<ethereum.EventParam>`
+  ;; at `params`; the value is an `ethereum.Value`
+  (func $param (param $params i32) (param $index i32) (result i32)
+    (i32.load offset=4
+      (i32.load offset=8
+        (i32.add
+          (i32.load (local.get $params))
+          (i32.mul (local.get $index) (i32.const 4))))))
+
+  ;; The pointer in the payload of the `ethereum.Value` at `value`
+  (func $payload (param $value i32) (result i32)
+    (i32.load offset=8 (local.get $value)))
+
+  ;; The first `ethereum.Value` in the `Array<ethereum.Value>` at `values`
+  (func $first_value (param $values i32) (result i32)
+    (i32.load offset=8 (i32.load (local.get $values))))
+
+  ;; `event` points to an `ethereum.Event` with the fields address,
+  ;; logIndex, transactionLogIndex, logType, block, transaction and params
+  (func (export "handleTransfer") (param $event i32)
+    (local $params i32)
+    (local $to i32)
+    (local $result i32)
+    (local.set $params (i32.load offset=24 (local.get $event)))
+    (local.set $to (call $param (local.get $params) (i32.const 0)))
+
+    (i64.store (i32.const 240) (i64.extend_i32_u (call $payload (local.get $to))))
+    (i64.store (i32.const 256)
+      (i64.extend_i32_u (call $payload (call $param (local.get $params) (i32.const 1)))))
+
+    ;; balanceOf(to) on the contract that emitted the event; a call that
+    ;; reverts returns null
+    (i32.store (i32.const 432) (local.get $to))
+    (i32.store (i32.const 452) (i32.load (local.get $event)))
+    (local.set $result (call $ethereum_call (i32.const 448)))
+    (if (i32.eqz (local.get $result)) (then (unreachable)))
+    (i64.store (i32.const 272)
+      (i64.extend_i32_u (call $payload (call $first_value (local.get $result)))))
+
+    (call $store_set
+      (i32.const 8)
+      (call $bytes_to_hex (i32.load (i32.load offset=20 (local.get $event))))
+      (i32.const 336)))
+
+  ;; `call` points to an `ethereum.Call` with the fields to, from, block,
+  ;; transaction, inputValues and outputValues
+  (func (export "handleSetValue") (param $call i32)
+    (i64.store (i32.const 352) (i64.extend_i32_u (i32.load offset=4 (local.get $call))))
+    (i64.store (i32.const 368)
+      (i64.extend_i32_u
+        (call $payload (call $param (i32.load offset=16 (local.get $call)) (i32.const 0)))))
+    (call $store_set
+      (i32.const 32)
+      (call $bytes_to_hex (i32.load (i32.load offset=12 (local.get $call))))
+      (i32.const 416))))
@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use graph::data::store::scalar;
+use graph::log::logger;
+use graph::prelude::*;
+use graph_harness::SubgraphTest;
+
+/// Copy the fixture subgraph in `tests/fixtures/<name>` to a temporary
+/// directory and compile its `.wat` modules into the `.wasm` files that
+/// the manifest references. Returns the directory with the copy
+fn fixture(name: &str) -> PathBuf {
+    let source = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    let dir = std::env::temp_dir().join(format!("subgraph-test-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    for entry in std::fs::read_dir(&source).unwrap() {
+        let path = entry.unwrap().path();
+        let target = dir.join(path.file_name().unwrap());
+        if path.extension() == Some("wat".as_ref()) {
+            let wasm = wat::parse_file(&path)
+                .unwrap_or_else(|e| panic!("failed to compile {}: {}", path.display(), e));
+            std::fs::write(target.with_extension("wasm"), wasm).unwrap();
+        } else {
+            std::fs::copy(&path, &target).unwrap();
+        }
+    }
+    dir
+}
+
+fn bytes(hex: &str) -> scalar::Bytes {
+    scalar::Bytes::from_str(hex).unwrap()
+}
+
+fn block(hash: &str, number: u64) -> Entity {
+    let mut entity = Entity::new();
+    entity.set("id", hash.to_owned());
+    entity.set("hash", bytes(hash));
+    entity.set("number", BigInt::from(number));
+    entity
+}
+
+#[tokio::test]
+async fn block_handler_stores_entities() {
+    const HASH1: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+    const HASH2: &str = "0x2222222222222222222222222222222222222222222222222222222222222222";
+
+    let dir = fixture("blocks");
+    let logger = logger(false);
+    let mut test = SubgraphTest::load(&logger, dir.join("subgraph.yaml"))
+        .await
+        .expect("the fixture subgraph can be loaded");
+    test.run_fixture(dir.join("blocks.json"))
+        .await
+        .expect("the fixture blocks can be processed");
+
+    assert_eq!(Some(block(HASH1, 1)), test.entity("Block", HASH1).unwrap());
+    assert_eq!(
+        vec![block(HASH1, 1), block(HASH2, 2)],
+        test.entities("Block")
+    );
+}
+
+fn transfer(tx: &str, to: &str, value: u64, balance: u64) -> Entity {
+    let mut entity = Entity::new();
+    entity.set("id", tx.to_owned());
+    entity.set("to", bytes(to));
+    entity.set("value", BigInt::from(value));
+    entity.set("balance", BigInt::from(balance));
+    entity
+}
+
+#[tokio::test]
+async fn event_and_call_handlers_store_entities() {
+    const TX1: &str = "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const TX2: &str = "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    const TX3: &str = "0xcccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc";
+
+    let dir = fixture("contract");
+    let logger = logger(false);
+    let mut test = SubgraphTest::load(&logger, dir.join("subgraph.yaml"))
+        .await
+        .expect("the fixture subgraph can be loaded");
+    test.run_fixture(dir.join("blocks.json"))
+        .await
+        .expect("the fixture blocks can be processed");
+
+    // The balances come from the `eth_call`s in the fixture; in block 2,
+    // the call for each recipient matches the entry with its address
+    assert_eq!(
+        vec![
+            transfer(TX1, "0x0000000000000000000000000000000000000002", 100, 250),
+            transfer(TX2, "0x0000000000000000000000000000000000000004", 7, 7),
+        ],
+        test.entities("Transfer")
+    );
+
+    let mut value_set = Entity::new();
+    value_set.set("id", TX3.to_owned());
+    value_set.set("from", bytes("0x0000000000000000000000000000000000000003"));
+    value_set.set("value", BigInt::from(42u64));
+    assert_eq!(Some(value_set), test.entity("ValueSet", TX3).unwrap());
+}